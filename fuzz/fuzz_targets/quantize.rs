@@ -0,0 +1,125 @@
+//! arbitrary images, palettes and options through [`qtizer::quantize`] and
+//! [`qtizer::remap`], which return errors instead of panicking
+//! - `arbitrary_inputs` of the library tests keeps found cases as regressions
+
+#![no_main]
+
+use clap::ValueEnum;
+use image::{ColorType, DynamicImage, GenericImageView, ImageBuffer};
+use libfuzzer_sys::arbitrary::{Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use qtizer::colors::Color;
+use qtizer::quantizer::Sample;
+use qtizer::{AlphaPolicy, Options, RemapOptions, colorspace};
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let Ok((img, options, palette, remap_options)) = inputs(&mut u) else {
+        return;
+    };
+
+    if let Ok(palette) = qtizer::quantize(&img, &options) {
+        assert!(palette.colors.len() <= options.k);
+    }
+    if let Ok(remapped) = qtizer::remap(&img, &palette, &remap_options) {
+        assert_eq!(remapped.dimensions(), img.dimensions());
+    }
+});
+
+/// an image, options of both calls and a palette to remap to
+fn inputs(u: &mut Unstructured) -> Result<(DynamicImage, Options, Vec<Color>, RemapOptions)> {
+    let img = image(u)?;
+    let options = Options {
+        k: u.int_in_range(0..=16)?,
+        algorithm: pick(u)?,
+        iterations: u.int_in_range(0..=4)?,
+        alpha: u.arbitrary()?,
+        colorspace: pick(u)?,
+        parameters: parameters(u)?,
+        tolerance: number(u, 10.0)?,
+        rounding: pick(u)?,
+        sample: match u.int_in_range(0..=2)? {
+            0 => Some(Sample::Fraction(number(u, 1.0)?)),
+            1 => Some(Sample::Count(u.int_in_range(0..=64)?)),
+            _ => None,
+        },
+        exact: u.arbitrary()?,
+        seed: u.arbitrary()?,
+        tonemap: pick(u)?,
+        gamut: u.arbitrary::<Option<&str>>()?.and_then(|s| s.parse().ok()),
+        protect_detail: u
+            .arbitrary::<bool>()?
+            .then(|| number(u, 100.0))
+            .transpose()?,
+    };
+    let palette = colors(u)?;
+    let remap_options = RemapOptions {
+        dither: pick(u)?,
+        colorspace: pick(u)?,
+        parameters: parameters(u)?,
+        alpha_policy: *u.choose(&[
+            AlphaPolicy::Ignore,
+            AlphaPolicy::Remap,
+            AlphaPolicy::Preserve,
+            AlphaPolicy::ScreenDoor,
+        ])?,
+    };
+    Ok((img, options, palette, remap_options))
+}
+
+/// any variant of an enum
+fn pick<T: ValueEnum + Clone>(u: &mut Unstructured) -> Result<T> {
+    u.choose(T::value_variants()).cloned()
+}
+
+/// a number of up to `max`, or any float (including negatives, nan and infinity)
+fn number(u: &mut Unstructured, max: f64) -> Result<f64> {
+    Ok(match u.arbitrary()? {
+        true => u.int_in_range(0..=1000)? as f64 / 1000.0 * max,
+        false => u.arbitrary()?,
+    })
+}
+
+/// color space parameters, with viewing conditions parsed from arbitrary text
+fn parameters(u: &mut Unstructured) -> Result<colorspace::Parameters> {
+    Ok(colorspace::Parameters {
+        luma_weight: number(u, 4.0)?,
+        viewing_conditions: (u.arbitrary::<&str>()?.parse()).unwrap_or_default(),
+    })
+}
+
+/// an image of up to 32 by 32 pixels (possibly empty), of any supported color type
+fn image(u: &mut Unstructured) -> Result<DynamicImage> {
+    let (width, height) = (u.int_in_range(0..=32)?, u.int_in_range(0..=32)?);
+    let pixels = width as usize * height as usize;
+    let image = match u.int_in_range(0..=4)? {
+        0 => ImageBuffer::from_raw(width, height, u.bytes(pixels)?.to_vec())
+            .map(DynamicImage::ImageLuma8),
+        1 => ImageBuffer::from_raw(width, height, u.bytes(pixels * 2)?.to_vec())
+            .map(DynamicImage::ImageLumaA8),
+        2 => ImageBuffer::from_raw(width, height, u.bytes(pixels * 3)?.to_vec())
+            .map(DynamicImage::ImageRgb8),
+        3 => ImageBuffer::from_raw(width, height, u.bytes(pixels * 4)?.to_vec())
+            .map(DynamicImage::ImageRgba8),
+        _ => {
+            let values = (0..pixels * 3)
+                .map(|_| u.arbitrary::<f32>())
+                .collect::<Result<Vec<_>>>()?;
+            ImageBuffer::from_raw(width, height, values).map(DynamicImage::ImageRgb32F)
+        }
+    };
+    Ok(image.expect("pixels fill the image"))
+}
+
+/// possibly malformed colors (eg. with missing or extra channels)
+fn colors(u: &mut Unstructured) -> Result<Vec<Color>> {
+    (0..u.int_in_range(0..=8)?)
+        .map(|_| {
+            let channels = u.int_in_range(0..=5)?;
+            Ok(Color {
+                color_type: *u.choose(&[ColorType::Rgb8, ColorType::Rgba8])?,
+                data: u.bytes(channels)?.to_vec(),
+            })
+        })
+        .collect()
+}
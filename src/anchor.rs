@@ -0,0 +1,70 @@
+use std::str::FromStr;
+
+use qtizer::colors::{self, Color};
+use qtizer::messages;
+
+/// colors that the darkest and lightest palette entries are replaced with, see
+/// `--anchor-extremes`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anchors {
+    pub dark: Color,
+    pub light: Color,
+}
+
+impl FromStr for Anchors {
+    type Err = String;
+
+    /// parse `<dark>,<light>` color codes, eg. `#0d0d0d,#f2f2f2`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // commas within parentheses separate channels, eg. of `rgb(13, 13, 13)`
+        let mut depth = 0i32;
+        let separator = s.char_indices().find(|&(_, c)| {
+            depth += match c {
+                '(' => 1,
+                ')' => -1,
+                _ => 0,
+            };
+            c == ',' && depth == 0
+        });
+        let Some((i, _)) = separator else {
+            return Err(messages::text("invalid-anchors", &[("anchors", &s)]));
+        };
+        Ok(Anchors {
+            dark: s[..i].parse()?,
+            light: s[i + 1..].parse()?,
+        })
+    }
+}
+
+impl Anchors {
+    /// replace the darkest (by L*) palette entry with the dark anchor and the lightest with
+    /// the light anchor, keeping their alpha
+    /// - fully transparent entries are never anchored, and palettes of fewer than 2 other
+    ///   entries are left as they are
+    pub fn apply(&self, clusters: &[Color]) -> Vec<Color> {
+        let mut anchored = clusters.to_vec();
+        let visible = (0..clusters.len())
+            .filter(|&i| clusters[i].alpha() != Some(0))
+            .collect::<Vec<_>>();
+        if visible.len() < 2 {
+            return anchored;
+        }
+
+        let lightness = |i: &&usize| colors::to_lab(&clusters[**i])[0];
+        let by_lightness = |a: &&usize, b: &&usize| lightness(a).total_cmp(&lightness(b));
+        let darkest = *visible.iter().min_by(by_lightness).unwrap_or(&visible[0]);
+        let lightest = *(visible.iter())
+            .filter(|&&i| i != darkest)
+            .max_by(by_lightness)
+            .unwrap_or(&visible[1]);
+        for (i, anchor) in [(darkest, &self.dark), (lightest, &self.light)] {
+            let mut color = anchor.with_alpha(false);
+            if let Some(alpha) = clusters[i].alpha() {
+                color.data.push(alpha);
+                color.color_type = image::ColorType::Rgba8;
+            }
+            anchored[i] = color;
+        }
+        anchored
+    }
+}
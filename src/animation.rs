@@ -0,0 +1,125 @@
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::cli::Args;
+use crate::colors::Color;
+use crate::indexed;
+use crate::io;
+use crate::messages;
+use crate::palette;
+use crate::quantizer::Thumbnail;
+use crate::sequence::SequenceFormat;
+
+/// delay between frames, in hundredths of a second
+const FRAME_DELAY: u16 = 40;
+
+/// delay of the last frame (the final palette), before the animation repeats
+const FINAL_DELAY: u16 = 200;
+
+/// maximum number of pixels of frames showing the image, which is scaled down to fit
+/// (see [`Thumbnail`])
+const FRAME_PIXELS: usize = 1 << 16;
+
+/// side length of the squares of frames showing swatches, in pixels
+const SWATCH_SIZE: usize = 32;
+
+/// frame of an animation, as (palette, width, height, assignments)
+pub type Frame = (Vec<Color>, usize, usize, Vec<usize>);
+
+/// write frames (see [`frames`]) as an animated gif, see `--animate-iterations`
+/// - palettes have at most 256 colors, each frame has its own gif palette
+pub fn write(path: &Path, frames: &[Frame]) -> Result<(), String> {
+    let error = |err: gif::EncodingError| messages::text("write-animation", &[("error", &err)]);
+
+    let (width, height) = frames.first().map_or((1, 1), |&(_, w, h, _)| (w, h));
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(messages::text("gif-size", &[("max", &u16::MAX)]));
+    };
+
+    let file = std::fs::File::create(path)
+        .map_err(|err| messages::text("create-animation", &[("error", &err)]))?;
+    let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &[]).map_err(error)?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(error)?;
+
+    for (i, (palette, _, _, assignments)) in frames.iter().enumerate() {
+        let indices = assignments.iter().map(|&i| i as u8).collect::<Vec<_>>();
+        let transparent = indexed::transparent_index(palette);
+        let mut frame = gif::Frame::from_indexed_pixels(width, height, indices, transparent);
+        frame.palette = Some(palette.iter().flat_map(Color::rgb).collect());
+        frame.delay = match i + 1 == frames.len() {
+            true => FINAL_DELAY,
+            false => FRAME_DELAY,
+        };
+        encoder.write_frame(&frame).map_err(error)?;
+    }
+
+    Ok(())
+}
+
+/// write frames (see [`frames`]) as numbered images into a directory instead of a gif,
+/// see `--sequence-format`
+/// - frames are written in the format of the extension of the pattern
+pub fn write_sequence(
+    dir: &Path,
+    sequence: &SequenceFormat,
+    start: usize,
+    frames: &[Frame],
+) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|err| messages::text("create-output-dir", &[("error", &err)]))?;
+
+    for (i, (palette, width, height, assignments)) in frames.iter().enumerate() {
+        let path = dir.join(sequence.name(start + i));
+        let (width, height) = (*width as u32, *height as u32);
+        io::encode(
+            &path,
+            width,
+            height,
+            palette,
+            assignments,
+            &Default::default(),
+        )
+        .map_err(|err| err.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// the frames of the palettes of successive k-means iterations
+/// - frames show a thumbnail of the image remapped to each palette, or a strip of
+///   swatches of each palette (sorted by brightness) with `swatches`
+pub fn frames(
+    pixels: &[Color],
+    width: usize,
+    palettes: &[Vec<Color>],
+    args: &Args,
+    swatches: bool,
+) -> Vec<Frame> {
+    match swatches {
+        true => palettes.iter().map(|p| swatch_frame(p)).collect(),
+        false => {
+            let height = pixels.len() / width.max(1);
+            let thumbnail = Thumbnail::pixels(pixels, width, FRAME_PIXELS);
+            let (width, height) = Thumbnail::dimensions(width, height, FRAME_PIXELS);
+            (palettes.iter())
+                .map(|palette| {
+                    let assignments =
+                        (args.colorspace).assign(&thumbnail, palette, &args.parameters());
+                    (palette.clone(), width, height, assignments)
+                })
+                .collect()
+        }
+    }
+}
+
+/// frame of a strip of swatches
+fn swatch_frame(palette: &[Color]) -> Frame {
+    let mut sorted = palette.to_vec();
+    palette::sort_by_luminance(&mut sorted);
+
+    let (width, height) = (SWATCH_SIZE * sorted.len().max(1), SWATCH_SIZE);
+    let assignments = (0..width * height)
+        .map(|i| i % width / SWATCH_SIZE)
+        .collect();
+    (sorted, width, height, assignments)
+}
@@ -0,0 +1,196 @@
+use image::*;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::cli::{self, Args};
+use crate::dedupe::Dedupe;
+use crate::indexed;
+use crate::logger::{self, Failure};
+use crate::messages;
+use crate::temporal::Temporal;
+
+/// outcome of processing a single input
+struct Job<'a> {
+    input: &'a Path,
+    output: PathBuf,
+    result: Result<(), Failure>,
+    elapsed: Duration,
+    /// earlier input whose palette was reused, see `--dedupe-palettes`
+    reused: Option<PathBuf>,
+}
+
+/// process all inputs concurrently, writing outputs into `output_dir`
+/// - the `--jobs` workers are shared between inputs and their clustering
+/// - with `--temporal-smoothing`, inputs are processed one after another, in order
+/// - with `--dedupe-palettes`, inputs reuse nearly identical palettes of finished inputs
+/// - prints a status line per finished input, and a status table at the end
+/// - returns whether all inputs were processed successfully
+pub fn run(args: &Args, output_dir: &Path, seed: u64) -> bool {
+    if let Err(err) = std::fs::create_dir_all(output_dir) {
+        cli::err_exit(
+            clap::error::ErrorKind::Io,
+            messages::text("create-output-dir", &[("error", &err)]),
+        );
+    }
+
+    let inputs = args.inputs();
+    let palette_output = args.as_palette || args.format.is_some() || args.porcelain.is_some();
+    if args.palette.is_some() && args.indexed != Some(false) && !palette_output {
+        note_unindexed(args, output_dir, inputs);
+    }
+
+    let finished = AtomicUsize::new(0);
+    let width = inputs.len().to_string().len();
+
+    let temporal = args
+        .temporal_smoothing
+        .map(|d| Mutex::new(Temporal::new(d)));
+    let dedupe = args.dedupe_palettes.map(Dedupe::new);
+    let process = |i: usize| {
+        let input = &inputs[i];
+        let output = output_path(args, output_dir, i, input);
+
+        let start = Instant::now();
+        let mut previous =
+            (temporal.as_ref()).map(|t| t.lock().unwrap_or_else(PoisonError::into_inner));
+        let previous = previous.as_deref_mut();
+        let result = crate::quantize_file(
+            args,
+            seed,
+            input,
+            Some(&output),
+            false,
+            previous,
+            dedupe.as_ref(),
+        )
+        .map(drop);
+        let elapsed = start.elapsed();
+        let reused = dedupe.as_ref().and_then(|dedupe| dedupe.source(input));
+
+        // single `eprintln!` per job, so lines never interleave
+        let n = finished.fetch_add(1, Ordering::Relaxed) + 1;
+        let status = match result.is_ok() {
+            true => messages::text("status-done", &[]),
+            false => messages::text("status-failed", &[]),
+        };
+        if let Err(err) = &result {
+            logger::input_error(input, err);
+        }
+        let total = inputs.len();
+        eprintln!("[{n:>width$}/{total}] {status:<6} {}", input.display());
+
+        Job {
+            input,
+            output,
+            result,
+            elapsed,
+            reused,
+        }
+    };
+    let jobs = match args.temporal_smoothing {
+        Some(_) => (0..inputs.len()).map(process).collect::<Vec<_>>(),
+        None => (0..inputs.len()).into_par_iter().map(process).collect(),
+    };
+
+    print_status_table(&jobs);
+
+    let reused = jobs.iter().filter(|job| job.reused.is_some()).count();
+    if reused > 0 {
+        let message = messages::text(
+            "deduplicated-inputs",
+            &[("reused", &reused), ("inputs", &inputs.len())],
+        );
+        logger::note("deduplicated", message);
+    }
+
+    jobs.iter().all(|job| job.result.is_ok())
+}
+
+/// note outputs that are not written as indexed images with a shared `--palette`, whose
+/// indices are the same for every input (eg. for palette swaps)
+/// - outputs keep the format of their input, unless given by `--output-format`
+fn note_unindexed(args: &Args, output_dir: &Path, inputs: &[PathBuf]) {
+    let unindexed = (inputs.iter().enumerate())
+        .filter(|&(i, input)| {
+            let output = output_path(args, output_dir, i, input);
+            !ImageFormat::from_path(&output).is_ok_and(|f| indexed::supported(f, 1))
+        })
+        .count();
+    if unindexed > 0 {
+        let message = messages::text(
+            "unindexed-output",
+            &[("unindexed", &unindexed), ("outputs", &inputs.len())],
+        );
+        logger::note("unindexed-output", message);
+    }
+}
+
+/// output path for the `i`th input: same file name inside `output_dir`, with `--suffix`
+/// (and with the extension of the palette format when writing palettes, or of
+/// `--output-format`)
+/// - or numbered by `--sequence-format`, keeping its extension if it has one
+pub fn output_path(args: &Args, output_dir: &Path, i: usize, input: &Path) -> PathBuf {
+    let stem = input.file_stem().unwrap_or(input.as_os_str());
+    let suffix = args.suffix.as_deref().unwrap_or_default();
+
+    let extension = match args.output_format {
+        Some(format) => format.extensions_str()[0].into(),
+        None if args.format.is_some() || ImageFormat::from_path(input).is_err() => {
+            args.format.unwrap_or_default().extension().into()
+        }
+        None => input.extension().unwrap_or_default().to_string_lossy(),
+    };
+
+    if let Some(sequence) = &args.sequence_format {
+        let name = sequence.name(args.sequence_start + i);
+        return match sequence.has_extension() {
+            true => output_dir.join(name),
+            false => output_dir.join(format!("{name}.{extension}")),
+        };
+    }
+
+    // the stem is kept as is, so file names need not be valid utf-8
+    let mut name = stem.to_os_string();
+    name.push(format!("{suffix}.{extension}"));
+    output_dir.join(name)
+}
+
+/// print a table of per-input outcomes, in input order
+fn print_status_table(jobs: &[Job]) {
+    println!(
+        "{:<6}  {:>8}  {}",
+        messages::text("column-status", &[]),
+        messages::text("column-time", &[]),
+        messages::text("column-file", &[])
+    );
+
+    for job in jobs {
+        let secs = job.elapsed.as_secs_f64();
+        match &job.result {
+            Ok(()) => match &job.reused {
+                Some(earlier) => println!(
+                    "{:<6}  {secs:>7.2}s  {} -> {} ({})",
+                    messages::text("status-ok", &[]),
+                    job.input.display(),
+                    job.output.display(),
+                    messages::text("status-reused", &[("earlier", &earlier.display())])
+                ),
+                None => println!(
+                    "{:<6}  {secs:>7.2}s  {} -> {}",
+                    messages::text("status-ok", &[]),
+                    job.input.display(),
+                    job.output.display()
+                ),
+            },
+            Err(err) => println!(
+                "{:<6}  {secs:>7.2}s  {} ({})",
+                messages::text("status-failed", &[]),
+                job.input.display(),
+                err.message.lines().next().unwrap_or_default()
+            ),
+        }
+    }
+}
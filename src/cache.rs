@@ -0,0 +1,161 @@
+use image::{ColorType, DynamicImage};
+use std::path::Path;
+
+use qtizer::colors::Color;
+
+use crate::cli::Args;
+use crate::messages;
+
+/// first line of cached clustering results, changed whenever their layout changes
+const MAGIC: &[u8] = b"qtizer-cache v1\n";
+
+/// 64-bit fnv-1a hash over little endian words, stable across platforms and releases
+/// (unlike `std::hash::DefaultHasher`)
+pub struct Hasher(u64);
+
+impl Hasher {
+    pub fn new() -> Self {
+        Hasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        let mut words = bytes.chunks_exact(8);
+        for word in words.by_ref() {
+            self.mix(u64::from_le_bytes(
+                word.try_into().expect("chunks of 8 bytes"),
+            ));
+        }
+        for &byte in words.remainder() {
+            self.mix(byte as u64);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn mix(&mut self, value: u64) {
+        self.0 = (self.0 ^ value).wrapping_mul(0x0100_0000_01b3);
+    }
+}
+
+/// cache key of clustering an image: hashes of the decoded image and of all options
+/// affecting the result (with the version, since clustering may change between versions)
+/// - runs without `--seed` leave the seed out, so they reuse any cached result
+pub fn key(args: &Args, img: &DynamicImage, alpha: bool, iterations: usize) -> String {
+    let mut input = Hasher::new();
+    input.write(&img.width().to_le_bytes());
+    input.write(&img.height().to_le_bytes());
+    input.write(format!("{:?}", img.color()).as_bytes());
+    input.write(img.as_bytes());
+
+    let mut options = Hasher::new();
+    let description = format!(
+        "{} k={} algorithm={:?} iterations={iterations} alpha={alpha} alpha_dither={} \
+         colorspace={:?} viewing={:?} luma={:?} gamut={:?} detail={:?} tolerance={} rounding={:?} sample={:?} exact={} \
+         seed={:?}",
+        env!("CARGO_PKG_VERSION"),
+        args.number,
+        args.algorithm,
+        args.alpha_dither,
+        args.colorspace,
+        args.viewing_conditions,
+        args.luma_weight,
+        args.gamut,
+        args.protect_detail,
+        args.tolerance,
+        args.rounding,
+        args.sample,
+        args.exact,
+        args.seed,
+    );
+    options.write(description.as_bytes());
+
+    format!("{:016x}-{:016x}", input.finish(), options.finish())
+}
+
+/// read a cache entry (`None` if missing or unreadable)
+pub fn read(dir: &Path, key: &str) -> Option<Vec<u8>> {
+    std::fs::read(dir.join(key)).ok()
+}
+
+/// write a cache entry, replacing it atomically so concurrent readers never see partial entries
+pub fn write(dir: &Path, key: &str, bytes: &[u8]) -> Result<(), String> {
+    let error =
+        |err: std::io::Error| messages::text("write-cache", &[("key", &key), ("error", &err)]);
+    std::fs::create_dir_all(dir).map_err(error)?;
+
+    let partial = dir.join(format!(".{key}.{}", std::process::id()));
+    std::fs::write(&partial, bytes).map_err(error)?;
+    std::fs::rename(&partial, dir.join(key)).map_err(error)
+}
+
+/// read cached clusters and assignments of `pixels` pixels
+/// - entries that do not match (eg. corrupt, or from another layout) are ignored
+pub fn load(dir: &Path, key: &str, pixels: usize) -> Option<(Vec<Color>, Vec<usize>)> {
+    let bytes = read(dir, key)?;
+    let mut rest = bytes.strip_prefix(MAGIC)?;
+
+    let mut take = |n: usize| {
+        let (head, tail) = rest.split_at_checked(n)?;
+        rest = tail;
+        Some(head)
+    };
+    let count = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+    let channels = take(1)?[0] as usize;
+    let width = take(1)?[0] as usize;
+    if !matches!(channels, 3 | 4) || !matches!(width, 1 | 2 | 4) || count == 0 {
+        return None;
+    }
+
+    let color_type = match channels {
+        3 => ColorType::Rgb8,
+        _ => ColorType::Rgba8,
+    };
+    let clusters = (take(count * channels)?.chunks(channels))
+        .map(|data| Color {
+            color_type,
+            data: data.to_vec(),
+        })
+        .collect::<Vec<_>>();
+
+    let assignments = (take(pixels * width)?.chunks(width))
+        .map(|index| {
+            let mut bytes = [0; 4];
+            bytes[..width].copy_from_slice(index);
+            u32::from_le_bytes(bytes) as usize
+        })
+        .collect::<Vec<_>>();
+
+    let valid = rest.is_empty() && assignments.iter().all(|&i| i < count);
+    valid.then_some((clusters, assignments))
+}
+
+/// cache clusters and assignments, with indices as small as the number of clusters allows
+pub fn store(
+    dir: &Path,
+    key: &str,
+    clusters: &[Color],
+    assignments: &[usize],
+) -> Result<(), String> {
+    let channels = clusters.first().map_or(3, |c| c.data.len());
+    let width = match clusters.len() {
+        0..=0x100 => 1,
+        0x101..=0x1_0000 => 2,
+        _ => 4,
+    };
+
+    let mut bytes =
+        Vec::with_capacity(MAGIC.len() + 6 + clusters.len() * 4 + assignments.len() * width);
+    bytes.extend(MAGIC);
+    bytes.extend((clusters.len() as u32).to_le_bytes());
+    bytes.extend([channels as u8, width as u8]);
+    for cluster in clusters {
+        bytes.extend(&cluster.data);
+    }
+    for &index in assignments {
+        bytes.extend(&(index as u32).to_le_bytes()[..width]);
+    }
+
+    write(dir, key, &bytes)
+}
@@ -0,0 +1,255 @@
+use std::str::FromStr;
+
+use image::ColorType;
+
+use crate::colors::{self, Color};
+use crate::messages;
+
+/// cone response matrix of cam16, from xyz
+const M16: [[f64; 3]; 3] = [
+    [0.401288, 0.650173, -0.051461],
+    [-0.250268, 1.204414, 0.045854],
+    [-0.002079, 0.048952, 0.953127],
+];
+
+/// inverse of [`M16`]
+const M16_INV: [[f64; 3]; 3] = [
+    [1.86206786, -1.01125463, 0.14918677],
+    [0.38752654, 0.62144744, -0.00897398],
+    [-0.01584150, -0.03412294, 1.04996444],
+];
+
+/// d65 white point in xyz, with luminance 100
+const WHITE: [f64; 3] = [95.047, 100.0, 108.883];
+
+/// luminance of the surroundings of a viewed color, relative to the background
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Surround {
+    /// eg. a display in a lit room, or a printed page
+    #[default]
+    Average,
+    /// eg. a display in a dim room
+    Dim,
+    /// eg. a projector in a dark room
+    Dark,
+}
+
+/// viewing conditions of the cam16 color appearance model
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewingConditions {
+    /// luminance of the adapting field, in cd/m²
+    pub luminance: f64,
+    /// luminance of the background, relative to white in percent
+    pub background: f64,
+    pub surround: Surround,
+}
+
+/// typical conditions of viewing a display: 200 lux, on a background of L* 50 (Y 18.4%)
+impl Default for ViewingConditions {
+    fn default() -> Self {
+        ViewingConditions {
+            luminance: 200.0 / std::f64::consts::PI * 0.184,
+            background: 18.4,
+            surround: Surround::Average,
+        }
+    }
+}
+
+impl FromStr for ViewingConditions {
+    type Err = String;
+
+    /// parse comma-separated `key=value` pairs of `luminance`, `background` and `surround`
+    /// (`average`, `dim` or `dark`), eg. `luminance=200,surround=dim`
+    /// - omitted keys keep their defaults
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut conditions = ViewingConditions::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let invalid = || messages::text("invalid-viewing-condition", &[("pair", &pair)]);
+            let (key, value) = pair.split_once('=').ok_or_else(invalid)?;
+            let number = || match value.trim().parse::<f64>() {
+                Ok(v) if v.is_finite() && v > 0.0 => Ok(v),
+                _ => Err(invalid()),
+            };
+            match key.trim() {
+                "luminance" => conditions.luminance = number()?,
+                "background" => conditions.background = number()?.min(100.0),
+                "surround" => {
+                    conditions.surround = match value.trim() {
+                        "average" => Surround::Average,
+                        "dim" => Surround::Dim,
+                        "dark" => Surround::Dark,
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => {
+                    return Err(messages::text(
+                        "unknown-viewing-condition",
+                        &[("key", &key)],
+                    ));
+                }
+            }
+        }
+        Ok(conditions)
+    }
+}
+
+/// cam16 with values derived from viewing conditions
+#[derive(Clone, Copy, Debug)]
+pub struct Model {
+    /// impact of the surround, and chromatic induction factor
+    c: f64,
+    nc: f64,
+    /// luminance level adaptation factor
+    fl: f64,
+    /// background induction factors
+    n: f64,
+    z: f64,
+    nbb: f64,
+    /// degree of adaptation to the white point, per cone response
+    adaptation: [f64; 3],
+    /// achromatic response of white
+    aw: f64,
+}
+
+impl Model {
+    /// derive the model of viewing conditions
+    pub fn new(conditions: ViewingConditions) -> Self {
+        let ViewingConditions {
+            luminance,
+            background,
+            surround,
+        } = conditions;
+        let (f, c, nc) = match surround {
+            Surround::Average => (1.0, 0.69, 1.0),
+            Surround::Dim => (0.9, 0.59, 0.9),
+            Surround::Dark => (0.8, 0.525, 0.8),
+        };
+
+        let k = 1.0 / (5.0 * luminance + 1.0);
+        let k4 = k.powi(4);
+        let fl = k4 * luminance + 0.1 * (1.0 - k4).powi(2) * (5.0 * luminance).cbrt();
+        let n = background / WHITE[1];
+        let z = 1.48 + n.sqrt();
+        let nbb = 0.725 / n.powf(0.2);
+
+        let degree = (f * (1.0 - ((-luminance - 42.0) / 92.0).exp() / 3.6)).clamp(0.0, 1.0);
+        let white = multiply(&M16, WHITE);
+        let adaptation = white.map(|w| degree * WHITE[1] / w + 1.0 - degree);
+
+        let mut model = Model {
+            c,
+            nc,
+            fl,
+            n,
+            z,
+            nbb,
+            adaptation,
+            aw: 0.0,
+        };
+        let [r, g, b] = model.compress(std::array::from_fn(|i| white[i] * adaptation[i]));
+        model.aw = (2.0 * r + g + 0.05 * b) * nbb;
+        model
+    }
+
+    /// post-adaptation nonlinear compression of cone responses
+    fn compress(&self, rgb: [f64; 3]) -> [f64; 3] {
+        rgb.map(|v| {
+            let f = (self.fl * v.abs() / 100.0).powf(0.42);
+            v.signum() * 400.0 * f / (f + 27.13)
+        })
+    }
+
+    /// inverse of [`Model::compress`]
+    fn expand(&self, rgb: [f64; 3]) -> [f64; 3] {
+        rgb.map(|v| {
+            let base = (27.13 * v.abs() / (400.0 - v.abs())).max(0.0);
+            v.signum() * 100.0 / self.fl * base.powf(1.0 / 0.42)
+        })
+    }
+
+    /// eccentricity of a hue (in radians)
+    fn eccentricity(hue: f64) -> f64 {
+        0.25 * ((hue + 2.0).cos() + 3.8)
+    }
+
+    /// convert a color to cam16-ucs (J', a', b'), ignoring alpha
+    pub fn to_ucs(&self, color: &Color) -> [f64; 3] {
+        let [r, g, b] = color.rgb().map(colors::linear);
+        let xyz = [
+            (0.4124 * r + 0.3576 * g + 0.1805 * b) * 100.0,
+            (0.2126 * r + 0.7152 * g + 0.0722 * b) * 100.0,
+            (0.0193 * r + 0.1192 * g + 0.9505 * b) * 100.0,
+        ];
+
+        let cones = multiply(&M16, xyz);
+        let [ra, ga, ba] = self.compress(std::array::from_fn(|i| cones[i] * self.adaptation[i]));
+
+        // opponent responses
+        let a = (11.0 * ra - 12.0 * ga + ba) / 11.0;
+        let b = (ra + ga - 2.0 * ba) / 9.0;
+        let u = (20.0 * ra + 20.0 * ga + 21.0 * ba) / 20.0;
+        let p2 = (40.0 * ra + 20.0 * ga + ba) / 20.0;
+        let hue = b.atan2(a);
+
+        let achromatic = p2 * self.nbb;
+        let j = 100.0 * (achromatic / self.aw).max(0.0).powf(self.c * self.z);
+        let p1 = 50000.0 / 13.0 * Self::eccentricity(hue) * self.nc * self.nbb;
+        let t = p1 * a.hypot(b) / (u + 0.305);
+        let alpha = t.powf(0.9) * (1.64 - 0.29f64.powf(self.n)).powf(0.73);
+        let chroma = alpha * (j / 100.0).sqrt();
+        let colorfulness = chroma * self.fl.powf(0.25);
+
+        // uniform color space
+        let j = 1.7 * j / (1.0 + 0.007 * j);
+        let m = (1.0 + 0.0228 * colorfulness).ln() / 0.0228;
+        [j, m * hue.cos(), m * hue.sin()]
+    }
+
+    /// convert cam16-ucs (J', a', b') to an rgb color, clamping out of gamut values
+    pub fn from_ucs(&self, [j, a, b]: [f64; 3]) -> Color {
+        let colorfulness = ((a.hypot(b) * 0.0228).exp() - 1.0) / 0.0228;
+        let hue = b.atan2(a);
+        let j = j / (1.7 - 0.007 * j);
+
+        let chroma = colorfulness / self.fl.powf(0.25);
+        let alpha = match j > 0.0 {
+            true => chroma / (j / 100.0).sqrt(),
+            false => 0.0,
+        };
+        let t = (alpha / (1.64 - 0.29f64.powf(self.n)).powf(0.73)).powf(1.0 / 0.9);
+
+        let achromatic = self.aw * (j.max(0.0) / 100.0).powf(1.0 / (self.c * self.z));
+        let p1 = Self::eccentricity(hue) * 50000.0 / 13.0 * self.nc * self.nbb;
+        let p2 = achromatic / self.nbb;
+        let (sin, cos) = hue.sin_cos();
+        let gamma = 23.0 * (p2 + 0.305) * t / (23.0 * p1 + 11.0 * t * cos + 108.0 * t * sin);
+        let (a, b) = (gamma * cos, gamma * sin);
+
+        let compressed = [
+            (460.0 * p2 + 451.0 * a + 288.0 * b) / 1403.0,
+            (460.0 * p2 - 891.0 * a - 261.0 * b) / 1403.0,
+            (460.0 * p2 - 220.0 * a - 6300.0 * b) / 1403.0,
+        ];
+        let cones = self.expand(compressed);
+        let [x, y, z] = multiply(
+            &M16_INV,
+            std::array::from_fn(|i| cones[i] / self.adaptation[i]),
+        )
+        .map(|v| v / 100.0);
+
+        // xyz -> linear srgb
+        let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+        let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+        let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+
+        Color {
+            color_type: ColorType::Rgb8,
+            data: vec![colors::gamma(r), colors::gamma(g), colors::gamma(b)],
+        }
+    }
+}
+
+/// product of a 3x3 matrix and a vector
+fn multiply(matrix: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
@@ -0,0 +1,293 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::messages;
+
+/// source of the frames of `--live`, instead of netpbm images read from the input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capture {
+    /// the screen of the linux framebuffer, or a region of it
+    Screen(Option<Region>),
+}
+
+/// rectangle of a screen, in pixels from its top left corner
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Capture {
+    type Err = String;
+
+    /// parse `screen`, or `screen:<width>x<height>+<x>+<y>` for a region of the screen,
+    /// eg. `screen:640x360+0+0`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || messages::text("invalid-capture", &[("source", &s)]);
+        let (source, region) = match s.trim().split_once(':') {
+            Some((source, region)) => (source, Some(region)),
+            None => (s.trim(), None),
+        };
+        if source != "screen" {
+            return Err(invalid());
+        }
+        let Some(region) = region else {
+            return Ok(Capture::Screen(None));
+        };
+
+        let (size, offset) = region.split_once('+').ok_or_else(invalid)?;
+        let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let (x, y) = offset.split_once('+').ok_or_else(invalid)?;
+        let number = |n: &str| n.trim().parse::<u32>().map_err(|_| invalid());
+        let region = Region {
+            x: number(x)?,
+            y: number(y)?,
+            width: number(width)?,
+            height: number(height)?,
+        };
+        if region.width == 0 || region.height == 0 {
+            return Err(invalid());
+        }
+        Ok(Capture::Screen(Some(region)))
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Region {
+            x,
+            y,
+            width,
+            height,
+        } = self;
+        write!(f, "{width}x{height}+{x}+{y}")
+    }
+}
+
+#[cfg(feature = "capture")]
+pub use screen::Screen;
+
+/// screens cannot be captured on other platforms, see [`Screen::open`]
+#[cfg(all(feature = "capture", not(target_os = "linux")))]
+mod screen {
+    use image::DynamicImage;
+
+    use super::Capture;
+    use crate::messages;
+
+    pub enum Screen {}
+
+    impl Screen {
+        pub fn open(_: Capture) -> Result<Self, String> {
+            Err(messages::text("capture-platform", &[]))
+        }
+
+        pub fn frame(&self) -> Result<DynamicImage, String> {
+            match *self {}
+        }
+    }
+}
+
+/// screen capture from the linux framebuffer (eg. of a console, kiosk or embedded display)
+#[cfg(all(feature = "capture", target_os = "linux"))]
+mod screen {
+    use std::fs::File;
+
+    use image::{DynamicImage, RgbImage};
+
+    use super::{Capture, Region};
+    use crate::messages;
+
+    /// framebuffer device captured by `--capture screen`
+    const DEVICE: &str = "/dev/fb0";
+
+    // the structs below are written by the kernel, and only some of their fields are read
+
+    /// position and length of a color channel within a pixel, see `struct fb_bitfield`
+    #[allow(dead_code)]
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, Default)]
+    struct Bitfield {
+        offset: u32,
+        length: u32,
+        msb_right: u32,
+    }
+
+    /// variable screen information of a framebuffer, see `struct fb_var_screeninfo`
+    #[allow(dead_code)]
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, Default)]
+    struct VarScreenInfo {
+        xres: u32,
+        yres: u32,
+        xres_virtual: u32,
+        yres_virtual: u32,
+        xoffset: u32,
+        yoffset: u32,
+        bits_per_pixel: u32,
+        grayscale: u32,
+        red: Bitfield,
+        green: Bitfield,
+        blue: Bitfield,
+        transp: Bitfield,
+        nonstd: u32,
+        activate: u32,
+        height: u32,
+        width: u32,
+        accel_flags: u32,
+        /// pixclock, margins, sync lengths, sync, vmode, rotate and colorspace
+        timing: [u32; 11],
+        reserved: [u32; 4],
+    }
+
+    /// fixed screen information of a framebuffer, see `struct fb_fix_screeninfo`
+    #[allow(dead_code)]
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, Default)]
+    struct FixScreenInfo {
+        id: [u8; 16],
+        smem_start: std::ffi::c_ulong,
+        smem_len: u32,
+        kind: u32,
+        type_aux: u32,
+        visual: u32,
+        xpanstep: u16,
+        ypanstep: u16,
+        ywrapstep: u16,
+        line_length: u32,
+        mmio_start: std::ffi::c_ulong,
+        mmio_len: u32,
+        accel: u32,
+        capabilities: u16,
+        reserved: [u16; 2],
+    }
+
+    /// a region of the screen, read row by row from the framebuffer
+    pub struct Screen {
+        file: File,
+        /// visible region of the framebuffer, in bytes from its start
+        origin: u64,
+        /// bytes per row of the framebuffer
+        stride: u64,
+        bytes_per_pixel: usize,
+        channels: [Bitfield; 3],
+        region: Region,
+    }
+
+    impl Screen {
+        /// open the framebuffer of a capture source
+        /// - the region must lie within the visible screen
+        /// - pixels are 16, 24 or 32 bits of truecolor (not palette indices)
+        pub fn open(capture: Capture) -> Result<Self, String> {
+            let Capture::Screen(region) = capture;
+            let file = File::open(DEVICE).map_err(|err| {
+                messages::text("capture-open", &[("device", &DEVICE), ("error", &err)])
+            })?;
+            let (var, fix) = info(&file).map_err(|err| {
+                messages::text("capture-info", &[("device", &DEVICE), ("error", &err)])
+            })?;
+
+            let bits = var.bits_per_pixel;
+            if !matches!(bits, 16 | 24 | 32) {
+                return Err(messages::text("capture-depth", &[("bits", &bits)]));
+            }
+
+            let screen = Region {
+                x: 0,
+                y: 0,
+                width: var.xres,
+                height: var.yres,
+            };
+            let region = region.unwrap_or(screen);
+            let fits = |start: u32, length: u32, size: u32| {
+                start.checked_add(length).is_some_and(|end| end <= size)
+            };
+            if !fits(region.x, region.width, var.xres) || !fits(region.y, region.height, var.yres) {
+                return Err(messages::text(
+                    "capture-region",
+                    &[
+                        ("region", &region),
+                        ("width", &var.xres),
+                        ("height", &var.yres),
+                    ],
+                ));
+            }
+
+            let bytes_per_pixel = bits as usize / 8;
+            let stride = fix.line_length as u64;
+            Ok(Screen {
+                file,
+                origin: var.yoffset as u64 * stride
+                    + (var.xoffset as usize * bytes_per_pixel) as u64,
+                stride,
+                bytes_per_pixel,
+                channels: [var.red, var.green, var.blue],
+                region,
+            })
+        }
+
+        /// the current contents of the region
+        pub fn frame(&self) -> Result<DynamicImage, String> {
+            use std::os::unix::fs::FileExt;
+
+            let Region {
+                x,
+                y,
+                width,
+                height,
+            } = self.region;
+            let mut row = vec![0; width as usize * self.bytes_per_pixel];
+            let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+            for r in y..y + height {
+                let offset =
+                    self.origin + r as u64 * self.stride + x as u64 * self.bytes_per_pixel as u64;
+                (self.file.read_exact_at(&mut row, offset))
+                    .map_err(|err| messages::text("capture-read", &[("error", &err)]))?;
+                for pixel in row.chunks_exact(self.bytes_per_pixel) {
+                    let mut value = [0; 4];
+                    value[..pixel.len()].copy_from_slice(pixel);
+                    let value = u32::from_le_bytes(value);
+                    rgb.extend(self.channels.map(|channel| scale(value, channel)));
+                }
+            }
+            let image = RgbImage::from_raw(width, height, rgb).expect("rows fill the region");
+            Ok(DynamicImage::ImageRgb8(image))
+        }
+    }
+
+    /// a channel of a pixel, scaled to 8 bits
+    fn scale(value: u32, channel: Bitfield) -> u8 {
+        let length = channel.length.min(16);
+        let max = (1u32 << length) - 1;
+        let v = value.checked_shr(channel.offset).unwrap_or(0) & max;
+        match length {
+            0 => 0,
+            _ => (v * 255 / max) as u8,
+        }
+    }
+
+    /// screen information of an opened framebuffer device
+    fn info(file: &File) -> std::io::Result<(VarScreenInfo, FixScreenInfo)> {
+        use std::os::fd::AsRawFd;
+
+        // requests of `linux/fb.h`
+        const FBIOGET_VSCREENINFO: u32 = 0x4600;
+        const FBIOGET_FSCREENINFO: u32 = 0x4602;
+
+        let (mut var, mut fix) = (VarScreenInfo::default(), FixScreenInfo::default());
+        let fd = file.as_raw_fd();
+        // SAFETY: both requests write exactly the structs given, which match `linux/fb.h`
+        let result = unsafe {
+            match libc::ioctl(fd, FBIOGET_VSCREENINFO as _, &mut var) {
+                0 => libc::ioctl(fd, FBIOGET_FSCREENINFO as _, &mut fix),
+                failed => failed,
+            }
+        };
+        match result {
+            0 => Ok((var, fix)),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    }
+}
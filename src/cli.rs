@@ -1,103 +1,2039 @@
+use clap::builder::TypedValueParser;
 use clap::*;
 use image::*;
+use std::path::{Path, PathBuf};
 
-use crate::colors::ColorCodeFormat;
+use crate::anchor::Anchors;
+use crate::cam16::ViewingConditions;
+use crate::capture::Capture;
+use crate::colors::Color;
+use crate::colorspace::{ColorSpace, Parameters};
+use crate::dither::Dither;
+use crate::gamut::Gamut;
+use crate::io;
+use crate::kmeans::Rounding;
+use crate::logger::{self, Diagnostics};
+use crate::messages;
+use crate::palette::{ColorCodeFormat, CssSyntax, Layout, PaletteFormat, Porcelain};
+use crate::profile;
+use crate::quantizer::{Algorithm, Detail, Sample};
+use crate::sequence::SequenceFormat;
+use crate::tonemap::Tonemap;
+use crate::zones::{ZoneFormat, Zones};
+
+/// path standing for stdin (as input) or stdout (as output)
+pub const STDIO: &str = "-";
+
+/// placeholder in output paths for the number of colors of each `-k` level
+const LEVEL: &str = "{k}";
+
+/// whether a path is [`STDIO`]
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == STDIO
+}
+
+/// whether a path has a `{k}` placeholder, see [`with_level`]
+pub fn has_level(path: &Path) -> bool {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    bytes.windows(LEVEL.len()).any(|w| w == LEVEL.as_bytes())
+}
+
+/// a path with its `{k}` placeholders replaced by a number of colors
+/// - paths need not be valid utf-8 (on unix)
+pub fn with_level(path: &Path, k: usize) -> PathBuf {
+    let level = k.to_string();
+    match path.to_str() {
+        Some(path) => path.replace(LEVEL, &level).into(),
+        #[cfg(unix)]
+        None => {
+            use std::os::unix::ffi::{OsStrExt, OsStringExt};
+            let bytes = path.as_os_str().as_bytes();
+            let mut replaced = Vec::with_capacity(bytes.len());
+            let mut i = 0;
+            while i < bytes.len() {
+                match bytes[i..].starts_with(LEVEL.as_bytes()) {
+                    true => {
+                        replaced.extend_from_slice(level.as_bytes());
+                        i += LEVEL.len();
+                    }
+                    false => {
+                        replaced.push(bytes[i]);
+                        i += 1;
+                    }
+                }
+            }
+            std::ffi::OsString::from_vec(replaced).into()
+        }
+        #[cfg(not(unix))]
+        None => path.to_string_lossy().replace(LEVEL, &level).into(),
+    }
+}
 
 /// Quantization/palette-generation tool using k-means clustering on pixel dataI
-#[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[derive(Parser, Clone, Debug)]
+#[command(
+    author,
+    version,
+    about,
+    override_usage = "qtizer [OPTIONS] <input> [output]\n       \
+                      qtizer [OPTIONS] --output-dir <dir> <input>...\n       \
+                      qtizer <COMMAND>",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    disable_version_flag = true
+)]
 pub struct Args {
-    /// Input file path
-    #[arg(index = 1, value_name = "input")]
-    pub file_path: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Input file path, optionally followed by the output file path
+    /// - With `--output-dir`, all paths are inputs
+    /// - `-` reads the input from stdin (requires `--input-format`)
+    /// - `-` as output writes the quantized image to stdout
+    #[arg(
+        index = 1,
+        value_name = "input",
+        num_args = 1..,
+        required_unless_present_any = ["version", "listen_fifo", "capture"],
+        verbatim_doc_comment
+    )]
+    pub paths: Vec<PathBuf>,
 
     /// Number of colors to quantize to
-    #[arg(short = 'k', default_value_t = 8, value_name = "count")]
+    /// - A comma-separated list (eg. `4,8,16`) quantizes to each number of colors, decoding
+    ///   the image only once, and writes each to the output path with `{k}` replaced
+    #[arg(
+        short = 'k',
+        default_value = "8",
+        value_name = "count",
+        value_delimiter = ',',
+        env = "QTIZER_COLORS",
+        verbatim_doc_comment
+    )]
+    pub levels: Vec<usize>,
+
+    /// Quantize to a range of numbers of colors, instead of `-k`
+    /// - `2..64` quantizes to 2, 4, 8, 16, 32 and 64 colors (doubling, up to and including 64)
+    /// - See `--sheet` to compare the results side by side
+    #[arg(long = "sweep", value_name = "range", verbatim_doc_comment)]
+    pub sweep: Option<Sweep>,
+
+    /// number of colors of the level being quantized (the first of `-k`, until then)
+    #[arg(skip)]
     pub number: usize,
 
+    /// Algorithm computing the palette
+    #[arg(
+        long = "algorithm",
+        default_value = "kmeans",
+        value_name = "name",
+        env = "QTIZER_ALGORITHM"
+    )]
+    pub algorithm: Algorithm,
+
     /// Number of k-means iterations to perform
-    #[arg(short = 'n', default_value_t = 5, value_name = "count")]
+    #[arg(
+        short = 'n',
+        default_value_t = 5,
+        value_name = "count",
+        env = "QTIZER_ITERATIONS"
+    )]
     pub iterations: usize,
 
+    /// Choose the number of iterations per image, based on its size and colors
+    #[arg(long = "auto-iterations", default_value_t = false)]
+    pub auto_iterations: bool,
+
+    /// Stop iterating once no color moves further than this distance
+    #[arg(
+        long = "tolerance",
+        default_value_t = 0.0,
+        value_name = "dist",
+        env = "QTIZER_TOLERANCE"
+    )]
+    pub tolerance: f64,
+
+    /// Rounding of cluster means to rgb values
+    #[arg(
+        long = "rounding",
+        default_value = "half-even",
+        value_name = "mode",
+        env = "QTIZER_ROUNDING"
+    )]
+    pub rounding: Rounding,
+
+    /// Compute the palette from a random subset of pixels, then assign all pixels to it
+    /// - A fraction (`0.1`), percentage (`10%`) or pixel count (`50000`)
+    /// - Faster for large images, at a small cost in palette quality
+    /// - Replaces the thumbnail of large images (see `--exact`)
+    #[arg(
+        long = "sample",
+        value_name = "amount",
+        env = "QTIZER_SAMPLE",
+        verbatim_doc_comment
+    )]
+    pub sample: Option<Sample>,
+
+    /// Compute the palette from all pixels, instead of a downscaled thumbnail
+    /// - By default, large images are clustered on a thumbnail of up to 512x512 pixels,
+    ///   and only the final assignment of colors touches every pixel
+    /// - See `--stats` for the quality cost of the thumbnail
+    #[arg(long = "exact", default_value_t = false, verbatim_doc_comment)]
+    pub exact: bool,
+
+    /// Color space to cluster in
+    #[arg(
+        long = "colorspace",
+        default_value = "srgb",
+        value_name = "space",
+        env = "QTIZER_COLORSPACE"
+    )]
+    pub colorspace: ColorSpace,
+
+    /// Viewing conditions of `--colorspace cam16`, as comma-separated `key=value` pairs
+    /// - luminance: of the adapting field in cd/m² (default: 11.7, 200 lux)
+    /// - background: luminance relative to white in percent (default: 18.4)
+    /// - surround: average, dim or dark (default: average)
+    #[arg(
+        long = "viewing-conditions",
+        value_name = "conditions",
+        verbatim_doc_comment
+    )]
+    pub viewing_conditions: Option<ViewingConditions>,
+
+    /// Weight of luma over chroma differences in `--colorspace ycbcr` (eg. `2`)
+    /// - Above 1, clusters preserve brightness over hue, which the eye is more sensitive to
+    /// - Squared luma differences weigh `<weight>` times as much, as with `--preserve-chroma`
+    #[arg(
+        long = "luma-weight",
+        value_name = "weight",
+        value_parser = parse_luma_weight,
+        verbatim_doc_comment
+    )]
+    pub luma_weight: Option<f64>,
+
+    /// Keep palette colors within a gamut, moving clusters into it after each iteration
+    /// - A mask of CIE L*C*h* ranges: `l` lightness (0-100), `c` chroma, `h` hue in degrees
+    ///   (repeatable, wrapping through 0), eg. `l:70-100,c:0-40` for pastels only
+    /// - Or a palette file, spanning its lightness, chroma and hues (within 15 degrees),
+    ///   eg. `brand.gpl` for brand hues only
+    #[arg(
+        long = "gamut",
+        value_name = "palette|mask",
+        value_parser = builder::OsStringValueParser::new().try_map(parse_gamut),
+        conflicts_with_all = ["palette", "edit", "pipeline", "live", "zones"],
+        verbatim_doc_comment
+    )]
+    pub gamut: Option<Gamut>,
+
+    /// Weight pixels of edges and texture up to `1 + <strength>` times while clustering,
+    /// so small details (eg. text or logos) keep accurate colors at low `-k`
+    /// - Strengths range up to 1000
+    #[arg(
+        long = "protect-detail",
+        value_name = "strength",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "8",
+        value_parser = parse_strength,
+        conflicts_with_all = ["palette", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub protect_detail: Option<f64>,
+
+    /// Replace the darkest and lightest palette colors with near-black and near-white
+    /// anchors, eg. for themes and print
+    /// - As `<dark>,<light>` color codes (default: `#000000,#ffffff`)
+    /// - Pixels are remapped to the anchored palette
+    #[arg(
+        long = "anchor-extremes",
+        value_name = "dark,light",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "#000000,#ffffff",
+        conflicts_with_all = ["palette", "pipeline", "live", "zones"],
+        verbatim_doc_comment
+    )]
+    pub anchor_extremes: Option<Anchors>,
+
+    /// Remap pixels to palette colors by hue and chroma first, and lightness second
+    /// - Squared differences of hue and chroma weigh `<weight>` times as much as those of
+    ///   lightness (in Oklab), so saturated details do not collapse into gray colors
+    #[arg(
+        long = "preserve-chroma",
+        value_name = "weight",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "4",
+        value_parser = parse_chroma_weight,
+        conflicts_with_all = ["dither", "pipeline", "live", "zones"],
+        verbatim_doc_comment
+    )]
+    pub preserve_chroma: Option<f64>,
+
+    /// Tone mapping of high dynamic range inputs (eg. exr or radiance hdr) before clustering
+    /// - By default, light brighter than white is clipped
+    #[arg(long = "tonemap", value_name = "operator", verbatim_doc_comment)]
+    pub tonemap: Option<Tonemap>,
+
+    /// Remap to the colors of a palette file instead of clustering
+    /// - Any palette written by `--format` or `--porcelain` (eg. color codes, GIMP, JSON)
+    /// - `-k` and `-n` do not apply
+    /// - Indexed images keep the order of the palette file, so each index means the same
+    ///   color in every output (eg. for palette swaps of sprites, see `--output-dir`)
+    #[arg(
+        long = "palette",
+        value_name = "file",
+        conflicts_with_all = ["auto_iterations", "stream", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub palette: Option<PathBuf>,
+
     /// Include alpha channel
-    #[arg(short = 'a', long = "with-alpha", default_value_t = false)]
+    #[arg(
+        short = 'a',
+        long = "with-alpha",
+        default_value_t = false,
+        env = "QTIZER_WITH_ALPHA"
+    )]
     pub alpha: bool,
 
+    /// Cluster and remap only rgb, copying the alpha channel of the input to quantized
+    /// images unchanged (eg. for sprites with soft edges)
+    /// - Images are written with full color, not indexed
+    #[arg(
+        long = "preserve-alpha",
+        default_value_t = false,
+        conflicts_with_all = ["as_palette", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub preserve_alpha: bool,
+
+    /// Dither partial alpha into a pattern of opaque and transparent pixels before
+    /// clustering with `-a`, instead of thresholding it (eg. gif transparency)
+    /// - Soft edges of sprites keep their look as a share of visible pixels
+    #[arg(
+        long = "alpha-dither",
+        default_value_t = false,
+        conflicts_with = "preserve_alpha",
+        verbatim_doc_comment
+    )]
+    pub alpha_dither: bool,
+
     /// Optional RNG seed for reproducible results
-    #[arg(short = 's', long = "seed", value_name = "number")]
+    #[arg(short = 's', long = "seed", value_name = "number", env = "QTIZER_SEED")]
     pub seed: Option<u64>,
 
     /// Output file path
     /// - If not provided, outputs to stdout
     /// - With image file extensions, outputs an image file
+    /// - `-` writes the image to stdout, in the format of the input (or png)
     #[arg(
         short = 'o',
         long = "output",
-        conflicts_with = "output_positional",
+        conflicts_with = "output_dir",
         value_name = "output",
         verbatim_doc_comment
     )]
-    pub output: Option<String>,
+    pub output: Option<PathBuf>,
 
-    /// Output file path
-    #[arg(index = 2, conflicts_with = "output", value_name = "output")]
-    pub output_positional: Option<String>,
+    /// Cache clustering results in a directory, keyed by image contents and options
+    /// - Repeated runs on identical images skip clustering
+    /// - Runs without `--seed` reuse cached results of any seed
+    #[arg(
+        long = "cache-dir",
+        value_name = "dir",
+        env = "QTIZER_CACHE_DIR",
+        verbatim_doc_comment
+    )]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Output directory for processing multiple inputs
+    /// - Outputs are named after their inputs
+    /// - With `--format`, palettes are written as files of that format
+    #[arg(long = "output-dir", value_name = "dir", verbatim_doc_comment)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Suffix appended to output file names in `--output-dir`, eg. `-quantized`
+    #[arg(
+        long = "suffix",
+        value_name = "text",
+        requires = "output_dir",
+        allow_hyphen_values = true
+    )]
+    pub suffix: Option<String>,
+
+    /// Name outputs by number instead, eg. `frame_%04d.png` for `frame_0001.png`, ...
+    /// - Outputs of `--output-dir` are numbered in the order of their inputs
+    /// - With `--animate-iterations <dir>`, each iteration is written to the directory as
+    ///   an image, instead of a frame of an animated gif
+    /// - `%d` is the number, `%0<digits>d` pads it with zeros, and `%%` is a `%`
+    /// - Names without an extension get the extension outputs would otherwise have
+    #[arg(
+        long = "sequence-format",
+        value_name = "pattern",
+        conflicts_with = "suffix",
+        verbatim_doc_comment
+    )]
+    pub sequence_format: Option<SequenceFormat>,
+
+    /// Number of the first output of `--sequence-format`
+    #[arg(
+        long = "sequence-start",
+        value_name = "number",
+        default_value_t = 1,
+        requires = "sequence_format"
+    )]
+    pub sequence_start: usize,
+
+    /// Keep the palettes of consecutive inputs of `--output-dir` coherent, eg. of the frames
+    /// of a video, against flicker
+    /// - Inputs are quantized in order, each clustering starts from the previous palette
+    /// - Palette entries move at most `<distance>` (in rgb) from one input to the next
+    #[arg(
+        long = "temporal-smoothing",
+        value_name = "distance",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "16",
+        value_parser = parse_distance,
+        requires = "output_dir",
+        conflicts_with_all = ["palette", "edit"],
+        verbatim_doc_comment
+    )]
+    pub temporal_smoothing: Option<f64>,
+
+    /// Reuse the palette of an earlier input of `--output-dir` for inputs whose palettes
+    /// would be nearly identical, eg. of near-duplicate screenshots
+    /// - Palettes are compared by previews clustered from small thumbnails, which may differ
+    ///   by at most `<threshold>` (the mean delta-e of their colors)
+    /// - Inputs reuse palettes of inputs that finished before them
+    #[arg(
+        long = "dedupe-palettes",
+        value_name = "threshold",
+        value_parser = parse_distance,
+        requires = "output_dir",
+        conflicts_with_all = ["palette", "temporal_smoothing"],
+        verbatim_doc_comment
+    )]
+    pub dedupe_palettes: Option<f64>,
+
+    /// Quantize the rows of truncated or partially corrupt inputs that decoded, instead of
+    /// failing them
+    /// - The decoded region is reported, and quantized images only cover it
+    /// - Jpeg inputs are always decoded as far as possible, with missing parts filled in
+    #[arg(
+        long = "allow-partial",
+        default_value_t = false,
+        conflicts_with = "live",
+        verbatim_doc_comment
+    )]
+    pub allow_partial: bool,
+
+    /// Image format of the inputs, instead of detecting it (eg. `png`)
+    #[arg(long = "input-format", value_name = "fmt", value_parser = parse_image_format)]
+    pub input_format: Option<ImageFormat>,
+
+    /// Image format of the outputs, instead of inferring it from their extension (eg. `webp`)
+    /// - Writes images to paths without image extensions, and to stdout (`-`) in this format
+    /// - With `--output-dir`, outputs are named with an extension of this format
+    #[arg(
+        long = "output-format",
+        value_name = "fmt",
+        value_parser = parse_output_format,
+        conflicts_with = "pipeline",
+        verbatim_doc_comment
+    )]
+    pub output_format: Option<ImageFormat>,
+
+    /// Color profile of output images, which quantized colors are converted to
+    /// - `srgb`, `display-p3`, `adobe-rgb`, or the path of an icc profile file
+    /// - By default, images keep the profile of the input, and colors clustered in a
+    ///   perceptual `--colorspace` are tagged as srgb
+    /// - Requires a png, jpeg or webp output, which the profile is embedded in
+    #[arg(
+        long = "output-profile",
+        value_name = "profile",
+        conflicts_with = "pipeline",
+        verbatim_doc_comment
+    )]
+    pub output_profile: Option<String>,
+
+    /// Gamut of the palette and output images, which colors are mapped into
+    /// - Color codes and palette files hold the values of the gamut
+    /// - Images are tagged with its color profile (see `--output-profile`)
+    #[arg(
+        long = "output-gamut",
+        value_name = "gamut",
+        conflicts_with_all = ["output_profile", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub output_gamut: Option<profile::Gamut>,
+
+    /// Write the palette to the output, regardless of its extension
+    /// - With `--format` or `--porcelain`, as a palette file of that format
+    /// - Otherwise, outputs with image extensions receive a swatch image of the palette
+    #[arg(
+        long = "as-palette",
+        conflicts_with_all = ["as_image", "dither", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub as_palette: bool,
+
+    /// Write the quantized image to the output, failing if its image format is unknown
+    /// - The format is inferred from the extension, or given by `--output-format`
+    #[arg(
+        long = "as-image",
+        conflicts_with_all = ["porcelain", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub as_image: bool,
 
     /// Palette output format
-    #[arg(short = 'f', long = "format", value_name = "fmt")]
-    pub format: Option<ColorCodeFormat>,
+    #[arg(
+        short = 'f',
+        long = "format",
+        value_name = "fmt",
+        env = "QTIZER_FORMAT"
+    )]
+    pub format: Option<PaletteFormat>,
+
+    /// Write color codes on a single line, separated by a delimiter (eg. `--inline=,`)
+    #[arg(
+        long = "inline",
+        value_name = "delim",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = " "
+    )]
+    pub inline: Option<String>,
+
+    /// Embed a tiny thumbnail of the image and the share of each color in json palettes
+    /// - Palettes become objects of `colors`, `order` (indices of colors by descending
+    ///   share) and `thumbnail` (a png data url), so palette browsers can show where a
+    ///   palette came from without the original image
+    #[arg(
+        long = "embed-thumbnail",
+        conflicts_with = "porcelain",
+        verbatim_doc_comment
+    )]
+    pub embed_thumbnail: bool,
+
+    /// Write rgb color codes with alpha in valid CSS syntax
+    /// - legacy: `rgba(r, g, b, 0.67)`
+    /// - modern: `rgb(r g b / 67%)`
+    #[arg(
+        long = "css-compat",
+        value_name = "syntax",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "legacy",
+        verbatim_doc_comment
+    )]
+    pub css_compat: Option<CssSyntax>,
+
+    /// Stable output for scripts, in a versioned format (never colored)
+    /// - v1: a `qtizer-porcelain v1` header, then one line per color with
+    ///   `#rrggbb`, red, green, blue, alpha and share of pixels (tab-separated)
+    #[arg(
+        long = "porcelain",
+        value_name = "version",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "v1",
+        conflicts_with_all = ["inline", "blocks", "css_compat", "stream", "output_dir", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub porcelain: Option<Porcelain>,
+
+    /// Terminate colors (or porcelain records) with null bytes instead of newlines
+    #[arg(
+        long = "print0",
+        default_value_t = false,
+        conflicts_with_all = ["inline", "blocks", "stream", "output_dir", "pipeline"]
+    )]
+    pub print0: bool,
+
+    /// Print the palette as a row of colored blocks above the codes (on terminals)
+    #[arg(
+        long = "blocks",
+        value_name = "width",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "2",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub blocks: Option<u32>,
+
+    /// Dithering of quantized image output
+    #[arg(
+        short = 'd',
+        long = "dither",
+        default_value = "none",
+        value_name = "method"
+    )]
+    pub dither: Dither,
+
+    /// Add seeded luminance grain to quantized images, masking banding in flat regions
+    /// - Brightens or darkens each pixel by up to this many rgb levels (eg. `3`)
+    /// - Adds colors beyond the palette, so images are not written indexed
+    #[arg(
+        long = "grain",
+        value_name = "amount",
+        value_parser = parse_grain,
+        conflicts_with_all = ["dither", "as_palette"],
+        verbatim_doc_comment
+    )]
+    pub grain: Option<f64>,
+
+    /// Write png and gif images as indexed images, with the palette and one index per pixel
+    /// - By default, whenever the palette has at most 256 colors
+    /// - `--indexed=false` writes full color images instead
+    #[arg(
+        long = "indexed",
+        value_name = "bool",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub indexed: Option<bool>,
+
+    /// Keep palette indices 0 to N-1 of indexed images free of pixels, eg. for colors
+    /// substituted at runtime by games or user interfaces
+    /// - Reserved slots are black, unless given by `--reserve-color`
+    #[arg(
+        long = "reserve",
+        value_name = "count",
+        value_parser = clap::value_parser!(u32).range(1..=255),
+        conflicts_with_all = ["grain", "preserve_alpha", "as_palette", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub reserve: Option<u32>,
+
+    /// Colors of the slots of `--reserve`, in order (comma-separated or repeated)
+    #[arg(
+        long = "reserve-color",
+        value_name = "color",
+        value_delimiter = ',',
+        requires = "reserve"
+    )]
+    pub reserve_colors: Vec<Color>,
+
+    // TODO: add palette scripting hooks with embedded rhai (behind a `script` feature)
+    //       the script receives the palette between clustering and export, and may
+    //       filter, rename, reorder or recolor entries.
+    //       blocked on adding the `rhai` dependency, so the argument is hidden and
+    //       rejected by `validate` until then
+    /// Transform the palette with a rhai script before output
+    #[arg(
+        long = "script",
+        value_name = "file",
+        conflicts_with = "pipeline",
+        hide = true
+    )]
+    pub script: Option<PathBuf>,
 
     // TODO: add input alpha policy for opaque output images
     // /// Transparency policy when input has alpha but output does not
     // #[arg(short = 'p', long = "alpha-policy", value_name = "policy",)]
     // pub alpha_policy: AlphaPolicy,
+    /// Print provisional palettes after each iteration
+    /// - On terminals, the palette is updated in place
+    /// - Otherwise, palettes are appended as JSON lines
+    #[arg(
+        long = "stream",
+        default_value_t = false,
+        conflicts_with = "output_dir",
+        verbatim_doc_comment
+    )]
+    pub stream: bool,
 
-    // TODO: implement multi-threading
-    // /// Number of workers to use [default: core count]
-    // #[arg(short = 'j', long = "jobs",
-    //       value_parser = clap::value_parser!(u32).range(1..),
-    //       value_name = "count")]
-    // pub jobs: usize,
-}
+    /// Write the palette of each k-means iteration as a frame of an animated gif
+    /// - Frames show the image (scaled down) in the colors of each palette,
+    ///   or swatches of the palettes with `--as-palette`
+    /// - With `--sequence-format`, frames are written as images into a directory
+    #[arg(
+        long = "animate-iterations",
+        value_name = "file",
+        conflicts_with_all = ["output_dir", "pipeline", "palette"],
+        verbatim_doc_comment
+    )]
+    pub animate_iterations: Option<PathBuf>,
 
-/// semantic validation of arguments
-/// - `--format` cannot be specified when outputting an image file
-/// - some image formats do not support alpha (eg. jpg)
-pub fn semantically_validate(args: &Args) {
-    // check if `--format` is specified AND output has image file extension
-    if args.format.is_some()
-        && (args.output.clone())
-            .or(args.output_positional.clone())
-            .is_some_and(|p| ImageFormat::from_path(p).is_ok())
-    {
-        err_exit(
-            clap::error::ErrorKind::ArgumentConflict,
-            "cannot specify color-code format when outputting an image file.",
-        );
-    }
+    /// Write a contact sheet of the image quantized to each number of colors (eg. of `--sweep`)
+    /// - Each image is scaled down, and labeled with its number of colors and mean error
+    ///   (see `--stats`)
+    #[arg(
+        long = "sheet",
+        value_name = "file",
+        conflicts_with_all = ["output_dir", "pipeline", "palette"],
+        verbatim_doc_comment
+    )]
+    pub sheet: Option<PathBuf>,
 
-    // check if output image format supports alpha channel
-    let output_opt = args.output.clone().or(args.output_positional.clone());
-    if args.alpha && output_opt.is_some() {
-        let output_file = output_opt.unwrap();
-        let filetype = ImageFormat::from_path(&output_file);
+    /// Write a binary mask per cluster to a directory, showing which pixels belong to it
+    /// - Masks are png images named by cluster index and color, eg. `0-1a2b3c.png`
+    /// - Pixels are assigned without dithering, segmenting the image by color
+    #[arg(
+        long = "cluster-masks",
+        value_name = "dir",
+        conflicts_with_all = ["output_dir", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub cluster_masks: Option<PathBuf>,
 
-        use ImageFormat::*;
-        if matches!(filetype, Ok(Jpeg | Bmp | Pnm | Tiff)) {
-            err_exit(
-                clap::error::ErrorKind::ArgumentConflict,
-                format!(
-                    "the `{:?}` image format does not support alpha.",
-                    filetype.unwrap(),
-                ),
-            );
-        }
-    }
-}
+    /// Write the image with the boundaries of cluster regions drawn over it
+    /// - Pixels are assigned without dithering, as with `--cluster-masks`
+    #[arg(
+        long = "overlay",
+        value_name = "file",
+        conflicts_with_all = ["output_dir", "pipeline"],
+        verbatim_doc_comment
+    )]
+    pub overlay: Option<PathBuf>,
 
-/// shorthand for `Args::command().error(...).exit()`
-pub fn err_exit(kind: clap::error::ErrorKind, message: impl std::fmt::Display) {
-    Args::command().error(kind, message).exit()
-}
+    /// Label the regions of `--overlay` with their cluster index
+    #[arg(long = "overlay-labels", default_value_t = false, requires = "overlay")]
+    pub overlay_labels: bool,
+
+    /// Interactively edit the palette before output
+    #[arg(long = "edit", default_value_t = false, conflicts_with = "output_dir")]
+    pub edit: bool,
+
+    /// Run the steps described in a pipeline file instead
+    /// - Steps: resize, cluster, merge-similar, harmonize, export, undo
+    #[arg(
+        long = "pipeline",
+        value_name = "file",
+        conflicts_with_all = ["output", "output_dir", "stream", "edit"],
+        verbatim_doc_comment
+    )]
+    pub pipeline: Option<PathBuf>,
+
+    /// Validate inputs and print the resolved configuration without processing
+    #[arg(long = "dry-run", default_value_t = false)]
+    pub dry_run: bool,
+
+    /// Compare the palette against a recorded palette file (eg. of `--format json`),
+    /// exiting with status 1 if they differ (eg. to gate asset pipelines in ci)
+    /// - Palettes match if they have as many colors, and each color of either is within
+    ///   `--verify-tolerance` of the other (or equal, with `--deterministic`)
+    /// - Requires `--seed`, so k-means starts from the same clusters as when recorded
+    #[arg(
+        long = "verify",
+        value_name = "expected",
+        requires = "seed",
+        conflicts_with_all = ["output_dir", "pipeline", "live", "zones", "listen_fifo", "edit"],
+        verbatim_doc_comment
+    )]
+    pub verify: Option<PathBuf>,
+
+    /// Largest delta-e of colors of palettes that match with `--verify`
+    #[arg(
+        long = "verify-tolerance",
+        value_name = "delta-e",
+        default_value_t = 1.0,
+        value_parser = parse_distance,
+        requires = "verify",
+        conflicts_with = "deterministic"
+    )]
+    pub verify_tolerance: f64,
+
+    /// Require palettes to be equal with `--verify`, instead of within a tolerance
+    #[arg(long = "deterministic", requires = "verify")]
+    pub deterministic: bool,
+
+    /// Stay resident, running jobs read from a named pipe (create it with `mkfifo`)
+    /// - Each line is a job: the arguments of a run, eg. `in.png -k 4 out.png`
+    /// - Each job is followed by `qtizer-job ok` or `qtizer-job error <message>` on stdout
+    /// - Jobs take defaults from `QTIZER_*` environment variables, not from other flags
+    #[arg(
+        long = "listen-fifo",
+        value_name = "path",
+        conflicts_with_all = ["paths", "output", "output_dir", "pipeline", "dry_run", "edit", "stream"],
+        verbatim_doc_comment
+    )]
+    pub listen_fifo: Option<PathBuf>,
+
+    /// Quantize a live stream of frames, printing the palette of each frame
+    /// - The input is a stream of binary netpbm images (ppm, pgm or pam), eg. a screen
+    ///   or camera capture piped from `ffmpeg ... -f image2pipe -c:v ppm -`, or frames
+    ///   captured by `--capture`
+    /// - The clusters of each frame start from the palette of the previous frame
+    /// - Frames arriving faster than `--fps` are skipped, so palettes follow the source
+    /// - On terminals, the palette is updated in place, otherwise palettes are appended
+    ///   as JSON lines
+    #[arg(
+        long = "live",
+        default_value_t = false,
+        conflicts_with_all = [
+            "output", "output_dir", "pipeline", "listen_fifo", "dry_run", "palette", "edit", "stream",
+            "sample", "animate_iterations", "sheet", "cluster_masks", "overlay",
+        ],
+        verbatim_doc_comment
+    )]
+    pub live: bool,
+
+    /// Capture the frames of `--live` instead of reading them from the input
+    /// - `screen` captures the linux framebuffer (`/dev/fb0`), eg. of a console or kiosk
+    /// - `screen:<width>x<height>+<x>+<y>` captures a region of it, eg. `screen:640x360+0+0`
+    /// - Frames are captured at `--fps`
+    #[arg(
+        long = "capture",
+        value_name = "source",
+        requires = "live",
+        conflicts_with = "input_format",
+        verbatim_doc_comment
+    )]
+    pub capture: Option<Capture>,
+
+    /// Frames quantized per second by `--live`, at most [default: 10]
+    #[arg(
+        long = "fps",
+        value_name = "rate",
+        value_parser = parse_fps,
+        requires = "live"
+    )]
+    pub fps: Option<f64>,
+
+    /// Print the dominant color of each zone along the border of an `<rows>x<columns>` grid
+    /// over the image, eg. for leds around a display (with `--live`, of each frame)
+    /// - Zones are ordered clockwise from the top left, as seen from the front
+    /// - Each zone is quantized to `-k` colors, and shows the color of most of its pixels
+    #[arg(
+        long = "zones",
+        value_name = "RxC",
+        conflicts_with_all = [
+            "output", "output_dir", "pipeline", "listen_fifo", "palette", "edit", "stream",
+            "as_palette", "animate_iterations", "sheet", "cluster_masks", "overlay",
+        ],
+        verbatim_doc_comment
+    )]
+    pub zones: Option<Zones>,
+
+    /// Format of the colors of `--zones`
+    #[arg(
+        long = "zones-format",
+        value_name = "format",
+        default_value = "codes",
+        requires = "zones"
+    )]
+    pub zones_format: ZoneFormat,
 
-// TODO: implement static logger functionality for parsed arguments
+    /// Also send the colors of `--zones` to a WLED controller, eg. `wled.local:21324`
+    /// - Packets use the DNRGB realtime protocol, one led per zone
+    #[arg(
+        long = "zones-udp",
+        value_name = "host:port",
+        requires = "zones",
+        verbatim_doc_comment
+    )]
+    pub zones_udp: Option<String>,
+
+    /// Report time spent in each processing phase to stderr
+    #[arg(long = "timing", default_value_t = false)]
+    pub timing: bool,
+
+    /// Append a line of json per run to a file, with the arguments, seed, outcome and
+    /// timings of each phase (for analyzing your own usage, nothing is ever uploaded)
+    #[arg(long = "session-log", value_name = "file", env = "QTIZER_SESSION_LOG")]
+    pub session_log: Option<PathBuf>,
+
+    /// Report the quality of each palette to stderr
+    /// - The mean error is the root mean square distance of pixels to their colors
+    /// - The refinement gain is how much one k-means iteration over all pixels would
+    ///   lower it, compare with `--exact` to judge the cost of the thumbnail
+    #[arg(long = "stats", default_value_t = false, verbatim_doc_comment)]
+    pub stats: bool,
+
+    /// Do not print warnings about likely mistaken arguments
+    #[arg(long = "no-warnings", default_value_t = false)]
+    pub no_warnings: bool,
+
+    /// Format of warnings, notes and errors on stderr
+    #[arg(
+        long = "diagnostics",
+        value_name = "format",
+        default_value = "human",
+        env = "QTIZER_DIAGNOSTICS"
+    )]
+    pub diagnostics: Diagnostics,
+
+    /// Number of threads for clustering and batch processing [default: core count]
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        value_parser = clap::value_parser!(u32).range(1..),
+        value_name = "count",
+        env = "QTIZER_JOBS"
+    )]
+    pub jobs: Option<u32>,
+
+    /// Print version
+    #[arg(short = 'V', long = "version", default_value_t = false)]
+    pub version: bool,
+
+    /// With `--version`, also print build and runtime details
+    #[arg(
+        short = 'v',
+        long = "verbose",
+        default_value_t = false,
+        requires = "version"
+    )]
+    pub verbose: bool,
+}
+
+/// subcommands, used instead of the default quantization mode
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Rank images in a directory by how much they contain the given colors
+    Find(FindArgs),
+
+    /// Reduce the colors of a palette file to k representative colors
+    Reduce(ReduceArgs),
+
+    /// Expand a palette file to k colors, interpolating ramps between its colors
+    Expand(ExpandArgs),
+
+    /// Map the luminance of an image onto a gradient of colors
+    Colorize(ColorizeArgs),
+
+    /// Apply a colormap to a single-channel data image
+    #[command(name = "apply-lut")]
+    ApplyLut(ApplyLutArgs),
+
+    /// Remap an image onto the palette of another image
+    Swap(SwapArgs),
+
+    /// Score how well an image conforms to a reference palette
+    Score(ScoreArgs),
+
+    /// Serve an HTTP API for quantization (and a web UI with `--static`)
+    Serve(ServeArgs),
+
+    /// Generate shell completions
+    Completions(CompletionsArgs),
+
+    /// Generate manpages
+    Manpage(ManpageArgs),
+}
+
+/// arguments of the `find` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct FindArgs {
+    /// Directory to search for images
+    #[arg(index = 1, value_name = "dir")]
+    pub dir: PathBuf,
+
+    /// Color(s) to search for, eg. `#d35400` (comma-separated or repeated)
+    #[arg(
+        short = 'c',
+        long = "color",
+        value_name = "color",
+        value_delimiter = ',',
+        required = true
+    )]
+    pub colors: Vec<Color>,
+
+    /// How images are ranked
+    #[arg(
+        short = 'm',
+        long = "mode",
+        default_value = "contain",
+        value_name = "mode"
+    )]
+    pub mode: FindMode,
+
+    /// Maximum distance of a pixel to a searched color to count as a match
+    #[arg(
+        short = 't',
+        long = "tolerance",
+        default_value_t = 32.0,
+        value_name = "dist"
+    )]
+    pub tolerance: f64,
+
+    /// Search subdirectories recursively
+    #[arg(short = 'r', long = "recursive", default_value_t = false)]
+    pub recursive: bool,
+
+    /// Maximum number of results to print
+    #[arg(short = 'l', long = "limit", value_name = "count")]
+    pub limit: Option<usize>,
+}
+
+/// arguments of the `reduce` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct ReduceArgs {
+    /// Palette file with one color code per line, or a GIMP palette (`.gpl`)
+    /// - Entries may be followed by a usage count to weight them by
+    #[arg(index = 1, value_name = "palette", verbatim_doc_comment)]
+    pub palette: PathBuf,
+
+    /// Number of colors to reduce to
+    #[arg(short = 'k', default_value_t = 8, value_name = "count")]
+    pub number: usize,
+
+    /// Algorithm computing the palette
+    #[arg(
+        long = "algorithm",
+        default_value = "kmeans",
+        value_name = "name",
+        env = "QTIZER_ALGORITHM"
+    )]
+    pub algorithm: Algorithm,
+
+    /// Number of k-means iterations to perform
+    #[arg(short = 'n', default_value_t = 5, value_name = "count")]
+    pub iterations: usize,
+
+    /// Optional RNG seed for reproducible results
+    #[arg(short = 's', long = "seed", value_name = "number")]
+    pub seed: Option<u64>,
+
+    /// Output file path [default: stdout]
+    #[arg(short = 'o', long = "output", value_name = "output")]
+    pub output: Option<PathBuf>,
+
+    /// Palette output format
+    #[arg(short = 'f', long = "format", value_name = "fmt")]
+    pub format: Option<PaletteFormat>,
+}
+
+/// arguments of the `expand` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct ExpandArgs {
+    /// Palette file with one color code per line, or a GIMP palette (`.gpl`)
+    #[arg(index = 1, value_name = "palette")]
+    pub palette: PathBuf,
+
+    /// Number of colors to expand to
+    #[arg(short = 'k', default_value_t = 32, value_name = "count")]
+    pub number: usize,
+
+    /// Output file path [default: stdout]
+    #[arg(short = 'o', long = "output", value_name = "output")]
+    pub output: Option<PathBuf>,
+
+    /// Palette output format
+    #[arg(short = 'f', long = "format", value_name = "fmt")]
+    pub format: Option<PaletteFormat>,
+}
+
+/// arguments of the `score` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct ScoreArgs {
+    /// Image to score
+    #[arg(index = 1, value_name = "image")]
+    pub image: PathBuf,
+
+    /// Reference palette file, in any format written by `--format` (eg. a GIMP palette)
+    #[arg(short = 'a', long = "against", value_name = "palette", required = true)]
+    pub against: PathBuf,
+
+    /// Delta-E (CIE76) beyond which a pixel is outside of the reference palette
+    #[arg(
+        short = 't',
+        long = "tolerance",
+        default_value_t = 5.0,
+        value_name = "delta-e"
+    )]
+    pub tolerance: f64,
+
+    /// Exit with status 1 if more than this percentage of pixels is outside of the palette
+    #[arg(long = "max-outside", value_name = "percent")]
+    pub max_outside: Option<f64>,
+}
+
+/// arguments of the `colorize` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct ColorizeArgs {
+    /// Input image (color images are colorized by their luminance)
+    #[arg(index = 1, value_name = "input")]
+    pub input: PathBuf,
+
+    /// Output image file path
+    #[arg(index = 2, value_name = "output")]
+    pub output: PathBuf,
+
+    /// Gradient colors from dark to light (comma-separated or repeated)
+    #[arg(
+        short = 'c',
+        long = "color",
+        value_name = "color",
+        value_delimiter = ',',
+        required_unless_present = "palette",
+        conflicts_with = "palette"
+    )]
+    pub colors: Vec<Color>,
+
+    /// Palette file with the gradient colors from dark to light
+    #[arg(short = 'p', long = "palette", value_name = "file")]
+    pub palette: Option<PathBuf>,
+
+    /// Map to the nearest gradient color instead of interpolating between them
+    #[arg(short = 'd', long = "discrete", default_value_t = false)]
+    pub discrete: bool,
+}
+
+/// arguments of the `apply-lut` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct ApplyLutArgs {
+    /// Input image (color images are mapped by their luminance)
+    #[arg(index = 1, value_name = "input")]
+    pub input: PathBuf,
+
+    /// Output image file path
+    #[arg(index = 2, value_name = "output")]
+    pub output: PathBuf,
+
+    /// Built-in colormap (viridis, magma, inferno, plasma, gray) or palette file
+    #[arg(short = 'l', long = "lut", value_name = "lut")]
+    pub lut: PathBuf,
+
+    /// Stretch the range of input values to the full colormap
+    #[arg(long = "normalize", default_value_t = false)]
+    pub normalize: bool,
+}
+
+/// arguments of the `swap` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct SwapArgs {
+    /// Image to remap
+    #[arg(index = 1, value_name = "source")]
+    pub source: PathBuf,
+
+    /// Image to take the palette from
+    #[arg(index = 2, value_name = "target")]
+    pub target: PathBuf,
+
+    /// Output image file path
+    #[arg(index = 3, value_name = "output")]
+    pub output: PathBuf,
+
+    /// Number of colors of both palettes
+    #[arg(short = 'k', default_value_t = 8, value_name = "count")]
+    pub number: usize,
+
+    /// Algorithm computing the palette
+    #[arg(
+        long = "algorithm",
+        default_value = "kmeans",
+        value_name = "name",
+        env = "QTIZER_ALGORITHM"
+    )]
+    pub algorithm: Algorithm,
+
+    /// Number of k-means iterations to perform
+    #[arg(short = 'n', default_value_t = 5, value_name = "count")]
+    pub iterations: usize,
+
+    /// Optional RNG seed for reproducible results
+    #[arg(short = 's', long = "seed", value_name = "number")]
+    pub seed: Option<u64>,
+
+    /// How source pixels are matched to target palette colors
+    #[arg(
+        short = 'm',
+        long = "match",
+        default_value = "nearest",
+        value_name = "mode"
+    )]
+    pub mode: SwapMode,
+
+    /// Transfer colors statistically instead of remapping onto a palette
+    /// - reinhard: match mean and deviation of each L*a*b* channel
+    /// - histogram: match the histogram of each rgb channel
+    #[arg(
+        short = 't',
+        long = "transfer",
+        value_name = "method",
+        conflicts_with_all = ["number", "iterations", "seed", "mode"],
+        verbatim_doc_comment
+    )]
+    pub transfer: Option<Transfer>,
+}
+
+/// arguments of the `serve` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(
+        long = "listen",
+        default_value = "127.0.0.1:8080",
+        value_name = "addr",
+        env = "QTIZER_LISTEN"
+    )]
+    pub listen: String,
+
+    /// Also serve the files of a directory, with a bundled web UI as fallback `index.html`
+    #[arg(long = "static", value_name = "dir")]
+    pub static_dir: Option<PathBuf>,
+
+    /// Cache responses in a directory, keyed by image contents and parameters
+    #[arg(long = "cache-dir", value_name = "dir", env = "QTIZER_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Number of quantization jobs running at once
+    #[arg(
+        long = "workers",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u32).range(1..),
+        value_name = "count"
+    )]
+    pub workers: u32,
+
+    /// Number of jobs waiting for a worker, before further jobs are rejected
+    #[arg(long = "queue", default_value_t = 16, value_name = "count")]
+    pub queue: usize,
+
+    /// Number of open connections, before further connections are rejected
+    #[arg(
+        long = "connections",
+        default_value_t = 64,
+        value_parser = clap::value_parser!(u32).range(1..),
+        value_name = "count"
+    )]
+    pub connections: u32,
+
+    /// Maximum seconds a job may take, including its time in the queue
+    /// - Jobs may request a shorter timeout (`timeout` parameter)
+    #[arg(
+        long = "timeout",
+        default_value_t = 60.0,
+        value_name = "secs",
+        verbatim_doc_comment
+    )]
+    pub timeout: f64,
+}
+
+/// arguments of the `completions` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct CompletionsArgs {
+    /// Shell to generate completions for
+    #[arg(index = 1, value_name = "shell")]
+    pub shell: Shell,
+
+    /// Directory to write the completion file to [default: stdout]
+    #[arg(index = 2, value_name = "dir")]
+    pub dir: Option<PathBuf>,
+}
+
+/// arguments of the `manpage` subcommand
+#[derive(clap::Args, Clone, Debug)]
+pub struct ManpageArgs {
+    /// Directory to write manpages of all commands to [default: stdout]
+    #[arg(index = 1, value_name = "dir")]
+    pub dir: Option<PathBuf>,
+}
+
+/// shells supported by the `completions` subcommand
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// color matching mode of the `swap` subcommand
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum SwapMode {
+    /// Nearest target palette color of each pixel
+    Nearest,
+    /// Target palette color of the same luminance rank as the pixel's source palette color
+    Luminance,
+}
+
+/// statistical color transfer method of the `swap` subcommand
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Transfer {
+    /// Match mean and standard deviation in L*a*b* (Reinhard et al.)
+    Reinhard,
+    /// Match the cumulative histogram of each channel
+    Histogram,
+}
+
+/// ranking mode of the `find` subcommand
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum FindMode {
+    /// Share of pixels close to the searched colors
+    Contain,
+    /// Similarity of the image's colors to the searched colors as a palette
+    Match,
+}
+
+/// range of numbers of colors to quantize to, see `--sweep`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sweep {
+    pub from: usize,
+    pub to: usize,
+}
+
+impl Sweep {
+    /// numbers of colors of the range, doubling from its start up to its end
+    pub fn levels(self) -> Vec<usize> {
+        let mut levels = std::iter::successors(Some(self.from), |&k| k.checked_mul(2))
+            .take_while(|&k| k < self.to)
+            .collect::<Vec<_>>();
+        levels.push(self.to);
+        levels
+    }
+}
+
+impl std::str::FromStr for Sweep {
+    type Err = String;
+
+    /// parse a range of numbers of colors, eg. `2..64`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || messages::text("invalid-sweep", &[("sweep", &s)]);
+        let (from, to) = s.split_once("..").ok_or_else(invalid)?;
+        let (from, to) = (from.trim().parse::<usize>(), to.trim().parse::<usize>());
+        match (from, to) {
+            (Ok(from), Ok(to)) if 1 <= from && from <= to => Ok(Sweep { from, to }),
+            (Ok(_), Ok(_)) => Err(messages::text("empty-sweep", &[("sweep", &s)])),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// parse arguments, layered as: built-in defaults < `QTIZER_*` environment variables < flags
+/// - environment values that conflict with the requested output are ignored,
+///   since they are meant as defaults (eg. `QTIZER_FORMAT` when outputting an image)
+pub fn parse() -> Args {
+    parse_from(std::env::args_os()).unwrap_or_else(|err| {
+        // arguments failed to parse, so json diagnostics are detected from the raw arguments
+        let args = std::env::args().collect::<Vec<_>>();
+        let json = (args
+            .windows(2)
+            .any(|pair| pair == ["--diagnostics", "json"]))
+            || args.iter().any(|arg| arg == "--diagnostics=json")
+            || std::env::var("QTIZER_DIAGNOSTICS").is_ok_and(|v| v == "json");
+        if json {
+            logger::set_diagnostics(Diagnostics::Json);
+        }
+        logger::exit(err)
+    })
+}
+
+/// parse arguments from an iterator (starting with the binary name), see [`parse`]
+pub fn parse_from<I, T>(iter: I) -> Result<Args, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let matches = Args::command().try_get_matches_from(iter)?;
+    let mut args = Args::from_arg_matches(&matches)?;
+
+    let from_env = |id: &str| matches.value_source(id) == Some(parser::ValueSource::EnvVariable);
+    let from_cli = |id: &str| matches.value_source(id) == Some(parser::ValueSource::CommandLine);
+
+    // checked here rather than by clap, so that `QTIZER_COLORS` does not conflict
+    if let Some(sweep) = args.sweep {
+        if from_cli("levels") {
+            return Err(error(
+                clap::error::ErrorKind::ArgumentConflict,
+                messages::text(
+                    "argument-conflict",
+                    &[("argument", &"--sweep <range>"), ("other", &"-k <count>")],
+                ),
+            ));
+        }
+        args.levels = sweep.levels();
+    }
+    args.number = args.levels[0];
+    let output_format = args.output_image_format();
+
+    if from_env("format") && output_format.is_some() {
+        args.format = None;
+    }
+
+    if from_env("alpha") && output_format.is_some_and(|f| !io::supports_alpha(f)) {
+        args.alpha = false;
+    }
+
+    // checked here rather than by clap, so that `QTIZER_*` variables do not conflict
+    if args.auto_iterations && from_cli("iterations") {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text(
+                "argument-conflict",
+                &[("argument", &"--auto-iterations"), ("other", &"-n <count>")],
+            ),
+        ));
+    }
+
+    if args.porcelain.is_some() {
+        if from_cli("format") {
+            return Err(error(
+                clap::error::ErrorKind::ArgumentConflict,
+                messages::text(
+                    "argument-conflict",
+                    &[
+                        ("argument", &"--porcelain[=<version>]"),
+                        ("other", &"--format <fmt>"),
+                    ],
+                ),
+            ));
+        }
+        args.format = None;
+    }
+
+    if args.preserve_alpha {
+        if from_cli("alpha") {
+            return Err(error(
+                clap::error::ErrorKind::ArgumentConflict,
+                messages::text(
+                    "argument-conflict",
+                    &[
+                        ("argument", &"--preserve-alpha"),
+                        ("other", &"--with-alpha"),
+                    ],
+                ),
+            ));
+        }
+        args.alpha = false;
+    }
+
+    for (id, flag) in [
+        ("levels", "-k <count>"),
+        ("sweep", "--sweep <range>"),
+        ("iterations", "-n <count>"),
+        ("sample", "--sample <amount>"),
+        ("exact", "--exact"),
+    ] {
+        if args.palette.is_some() && from_cli(id) {
+            return Err(error(
+                clap::error::ErrorKind::ArgumentConflict,
+                messages::text(
+                    "argument-conflict",
+                    &[("argument", &"--palette <file>"), ("other", &flag)],
+                ),
+            ));
+        }
+    }
+
+    Ok(args)
+}
+
+/// parse a grain amount in rgb levels, within `(0, 255]`
+fn parse_grain(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(amount) if amount > 0.0 && amount <= u8::MAX as f64 => Ok(amount),
+        Ok(_) => Err(messages::text("grain-out-of-range", &[("grain", &s)])),
+        Err(_) => Err(messages::text("invalid-grain", &[("grain", &s)])),
+    }
+}
+
+/// parse a luma weight of ycbcr distances, a positive number
+fn parse_luma_weight(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(weight) if weight.is_finite() && weight > 0.0 => Ok(weight),
+        Ok(_) => Err(messages::text("luma-weight-not-positive", &[("value", &s)])),
+        Err(_) => Err(messages::text("invalid-luma-weight", &[("value", &s)])),
+    }
+}
+
+/// parse a detail strength of `--protect-detail`, a positive number of at most
+/// [`Detail::MAX_STRENGTH`]
+fn parse_strength(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(strength) if strength > 0.0 && strength <= Detail::MAX_STRENGTH => Ok(strength),
+        Ok(strength) if strength > 0.0 => Err(messages::text(
+            "strength-too-large",
+            &[("value", &s), ("max", &Detail::MAX_STRENGTH)],
+        )),
+        Ok(_) => Err(messages::text("strength-not-positive", &[("value", &s)])),
+        Err(_) => Err(messages::text("invalid-strength", &[("value", &s)])),
+    }
+}
+
+/// parse a chroma weight of `--preserve-chroma`, a positive number
+fn parse_chroma_weight(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(weight) if weight.is_finite() && weight > 0.0 => Ok(weight),
+        Ok(_) => Err(messages::text(
+            "chroma-weight-not-positive",
+            &[("value", &s)],
+        )),
+        Err(_) => Err(messages::text("invalid-chroma-weight", &[("value", &s)])),
+    }
+}
+
+/// parse an rgb distance, a positive number
+fn parse_distance(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(distance) if distance.is_finite() && distance > 0.0 => Ok(distance),
+        Ok(_) => Err(messages::text("distance-not-positive", &[("value", &s)])),
+        Err(_) => Err(messages::text("invalid-distance", &[("value", &s)])),
+    }
+}
+
+/// parse a frame rate, a positive number
+fn parse_fps(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(fps) if fps.is_finite() && fps > 0.0 => Ok(fps),
+        Ok(_) => Err(messages::text("fps-not-positive", &[("value", &s)])),
+        Err(_) => Err(messages::text("invalid-fps", &[("value", &s)])),
+    }
+}
+
+/// parse the gamut of `--gamut`, a mask of ranges or the path of a palette file
+fn parse_gamut(s: std::ffi::OsString) -> Result<Gamut, String> {
+    let path = Path::new(&s);
+    match s.to_str() {
+        Some(mask) if !path.is_file() && mask.contains(':') => mask.parse(),
+        _ => match crate::palette::Palette::from_file(path) {
+            Ok(palette) if palette.colors.is_empty() => Err(messages::text(
+                "empty-gamut-palette",
+                &[("path", &path.display())],
+            )),
+            Ok(palette) => Ok(Gamut::from_palette(&palette.colors)),
+            Err(err) => Err(format!("{}: {err}", path.display())),
+        },
+    }
+}
+
+/// parse an image format by name or extension, eg. `png` or `jpg`
+fn parse_image_format(name: &str) -> Result<ImageFormat, String> {
+    match ImageFormat::from_extension(name) {
+        Some(format) if io::decodable(format) => Ok(format),
+        Some(format) if !io::compiled(format) => Err(io::Error::MissingCodec(format).to_string()),
+        Some(format) if format.writing_enabled() => Err(io::Error::EncodeOnly(format).to_string()),
+        _ => Err(messages::text("unsupported-format", &[("format", &name)])),
+    }
+}
+
+/// parse an image format that can be written, by name or extension, eg. `png` or `jpg`
+fn parse_output_format(name: &str) -> Result<ImageFormat, String> {
+    match ImageFormat::from_extension(name) {
+        Some(format) if format.writing_enabled() => Ok(format),
+        Some(format) if !io::compiled(format) => Err(io::Error::MissingCodec(format).to_string()),
+        _ => Err(messages::text(
+            "unsupported-output-format",
+            &[("format", &name)],
+        )),
+    }
+}
+
+impl Args {
+    /// input file paths
+    pub fn inputs(&self) -> &[PathBuf] {
+        match (&self.output_dir, &self.paths[..]) {
+            (None, [input, _]) => std::slice::from_ref(input),
+            _ => &self.paths,
+        }
+    }
+
+    /// output file path, given either by `--output` or as second positional argument
+    pub fn output_path(&self) -> Option<PathBuf> {
+        match (&self.output_dir, &self.paths[..]) {
+            (None, [_, output]) => Some(output.clone()),
+            _ => self.output.clone(),
+        }
+    }
+
+    /// image format of the output, or `None` if it receives a palette file
+    /// - given by `--output-format`, otherwise inferred from the output path
+    ///   (see [`io::output_format`])
+    /// - with `--as-palette`, the image is a swatch of the palette (see [`io::encode_swatch`])
+    pub fn output_image_format(&self) -> Option<ImageFormat> {
+        if self.palette_file_output() {
+            return None;
+        }
+        match self.output_path() {
+            Some(path) => io::output_format(&path, self.output_format, self.input_format),
+            None => self.output_format,
+        }
+    }
+
+    /// numbers of colors to quantize to, given by `-k`
+    /// (a single level with `--palette`, which replaces clustering)
+    pub fn quantize_levels(&self) -> &[usize] {
+        match self.palette {
+            Some(_) => &self.levels[..1],
+            None => &self.levels,
+        }
+    }
+
+    /// whether outputs receive palette files regardless of their extension, given by
+    /// `--as-palette` with `--format` or `--porcelain`
+    pub fn palette_file_output(&self) -> bool {
+        self.as_palette && (self.format.is_some() || self.porcelain.is_some())
+    }
+
+    /// parameters of color spaces, given by `--luma-weight` and `--viewing-conditions`
+    pub fn parameters(&self) -> Parameters {
+        Parameters {
+            luma_weight: self.luma_weight.unwrap_or(1.0),
+            viewing_conditions: self.viewing_conditions.unwrap_or_default(),
+        }
+    }
+
+    /// format of single color codes, given by `--format` and `--css-compat`
+    pub fn color_codes(&self) -> ColorCodeFormat {
+        (self.format.unwrap_or_default()).css_color_codes(self.css_compat)
+    }
+
+    /// arrangement of color codes, given by `--inline` and `--print0`
+    pub fn layout(&self) -> Layout<'_> {
+        match (&self.inline, self.print0) {
+            (Some(delimiter), _) => Layout::Inline(delimiter),
+            (None, true) => Layout::Null,
+            (None, false) => Layout::Lines,
+        }
+    }
+
+    /// number of workers to use (defaults to core count)
+    pub fn jobs(&self) -> usize {
+        self.jobs.map_or_else(default_jobs, |j| j as usize)
+    }
+}
+
+/// default number of workers (core count)
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// semantic validation of arguments
+/// - multiple inputs require `--output-dir`
+/// - pipelines take no output file
+/// - output file can only be given once
+/// - `--format` cannot be specified when outputting an image file
+///
+/// note: alpha support of output formats is checked after decoding,
+/// since alpha is dropped for fully opaque inputs
+pub fn semantically_validate(args: &Args) {
+    validate(args).unwrap_or_else(|err| {
+        logger::set_diagnostics(args.diagnostics);
+        logger::exit(err)
+    })
+}
+
+/// semantic validation of arguments, see [`semantically_validate`]
+pub fn validate(args: &Args) -> Result<(), clap::Error> {
+    // flags of cargo features that this build was compiled without, see `Cargo.toml`
+    let ase = messages::text("ase-format", &[]);
+    let features = [
+        (
+            ase.as_str(),
+            "ase",
+            cfg!(feature = "ase"),
+            args.format == Some(PaletteFormat::Ase),
+        ),
+        ("`--edit`", "edit", cfg!(feature = "edit"), args.edit),
+        ("`--live`", "pnm", cfg!(feature = "pnm"), args.live),
+        (
+            "`--capture`",
+            "capture",
+            cfg!(feature = "capture"),
+            args.capture.is_some(),
+        ),
+        (
+            "`--zones-udp`",
+            "net",
+            cfg!(feature = "net"),
+            args.zones_udp.is_some(),
+        ),
+        (
+            "`--animate-iterations`",
+            "gif",
+            cfg!(feature = "gif"),
+            args.animate_iterations.is_some(),
+        ),
+    ];
+    if let Some((flag, feature, ..)) =
+        (features.iter()).find(|&&(.., compiled, used)| used && !compiled)
+    {
+        return Err(error(
+            clap::error::ErrorKind::InvalidValue,
+            qtizer::missing_feature(flag, feature),
+        ));
+    }
+
+    if args.script.is_some() {
+        return Err(error(
+            clap::error::ErrorKind::InvalidValue,
+            messages::text("script-unavailable", &[]),
+        ));
+    }
+
+    // check that positional arguments are `<input> [output]` unless batch processing
+    if args.output_dir.is_none() && args.paths.len() > 2 {
+        return Err(error(
+            clap::error::ErrorKind::TooManyValues,
+            messages::text("multiple-inputs", &[]),
+        ));
+    }
+
+    if args.pipeline.is_some() && args.paths.len() > 1 {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("pipeline-outputs", &[]),
+        ));
+    }
+
+    if args.output.is_some() && args.output_dir.is_none() && args.paths.len() == 2 {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("output-twice", &[]),
+        ));
+    }
+
+    if args.output_dir.is_some() && args.paths.iter().any(|p| is_stdio(p)) {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("stdin-output-dir", &[]),
+        ));
+    }
+
+    if args.inputs().iter().any(|p| is_stdio(p)) {
+        if args.input_format.is_none() && !args.live {
+            return Err(error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                messages::text("stdin-input-format", &[]),
+            ));
+        }
+        if args.edit {
+            return Err(error(
+                clap::error::ErrorKind::ArgumentConflict,
+                messages::text("stdin-edit", &[]),
+            ));
+        }
+    }
+
+    let codes = args.format.is_none_or(PaletteFormat::is_color_codes);
+    if args.inline.is_some() && !codes {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("inline-codes", &[]),
+        ));
+    }
+
+    if args.css_compat.is_some() && args.format != Some(PaletteFormat::Rgb) {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("css-compat-rgb", &[]),
+        ));
+    }
+
+    if args.embed_thumbnail && args.format != Some(PaletteFormat::Json) {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("embed-thumbnail-json", &[]),
+        ));
+    }
+
+    if args.print0 && args.porcelain.is_none() && !codes {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("print0-codes", &[]),
+        ));
+    }
+
+    if args.stream && args.algorithm != Algorithm::Kmeans {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("stream-kmeans", &[]),
+        ));
+    }
+
+    if args.output_format.is_some() && args.output_dir.is_none() && args.output_path().is_none() {
+        return Err(error(
+            clap::error::ErrorKind::MissingRequiredArgument,
+            messages::text("output-format-path", &[]),
+        ));
+    }
+
+    if args.viewing_conditions.is_some() && args.colorspace != ColorSpace::Cam16 {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("viewing-conditions-cam16", &[]),
+        ));
+    }
+
+    if args.live {
+        let invalid = match () {
+            _ if args.capture.is_some() && !args.paths.is_empty() => {
+                Some(messages::text("live-capture", &[]))
+            }
+            _ if args.paths.len() > 1 => Some(messages::text("live-output", &[])),
+            _ if args.levels.len() > 1 => Some(messages::text("live-levels", &[])),
+            _ if args.input_format.is_some_and(|f| f != ImageFormat::Pnm) => {
+                Some(messages::text("live-format", &[]))
+            }
+            _ => None,
+        };
+        if let Some(msg) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, msg));
+        }
+    }
+
+    if args.gamut.is_some() && args.algorithm != Algorithm::Kmeans {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("gamut-kmeans", &[]),
+        ));
+    }
+
+    if args.protect_detail.is_some() && args.algorithm != Algorithm::Kmeans {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("protect-detail-kmeans", &[]),
+        ));
+    }
+
+    if args.temporal_smoothing.is_some() {
+        let invalid = match () {
+            _ if args.algorithm != Algorithm::Kmeans => {
+                Some(messages::text("temporal-kmeans", &[]))
+            }
+            _ if args.levels.len() > 1 => Some(messages::text("temporal-levels", &[])),
+            _ => None,
+        };
+        if let Some(msg) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, msg));
+        }
+    }
+
+    if args.zones.is_some() {
+        let invalid = match () {
+            _ if args.paths.len() > 1 => Some(messages::text("zones-output", &[])),
+            _ if args.levels.len() > 1 => Some(messages::text("zones-levels", &[])),
+            _ => None,
+        };
+        if let Some(msg) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, msg));
+        }
+    }
+
+    if args.alpha_dither && !args.alpha {
+        return Err(error(
+            clap::error::ErrorKind::MissingRequiredArgument,
+            messages::text("alpha-dither-alpha", &[]),
+        ));
+    }
+
+    if args.luma_weight.is_some() && args.colorspace != ColorSpace::Ycbcr {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("luma-weight-ycbcr", &[]),
+        ));
+    }
+
+    if let Some(sequence) = &args.sequence_format {
+        let frames = args.animate_iterations.is_some();
+        let invalid = match () {
+            _ if args.output_dir.is_none() && !frames => {
+                Some(messages::text("sequence-output", &[]))
+            }
+            _ if frames && ImageFormat::from_path(sequence.name(0)).is_err() => {
+                Some(messages::text("sequence-image", &[]))
+            }
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, message));
+        }
+    }
+
+    if args.verify.is_some() && args.quantize_levels().len() > 1 {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("verify-levels", &[]),
+        ));
+    }
+
+    if let Some(path) = &args.animate_iterations {
+        let gif = args.sequence_format.is_none();
+        let invalid = match () {
+            _ if args.algorithm != Algorithm::Kmeans => Some(messages::text("animate-kmeans", &[])),
+            _ if gif && ImageFormat::from_path(path).ok() != Some(ImageFormat::Gif) => {
+                Some(messages::text("animate-gif", &[]))
+            }
+            _ if gif && args.levels.iter().any(|&k| k > 256) => {
+                Some(messages::text("animate-colors", &[]))
+            }
+            _ if args.levels.len() > 1 && !has_level(path) => {
+                Some(messages::text("animate-levels", &[]))
+            }
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, message));
+        }
+    }
+
+    if args.preserve_alpha {
+        let invalid = match args.output_image_format() {
+            _ if args.indexed == Some(true) => Some(messages::text("preserve-alpha-indexed", &[])),
+            Some(format) if !io::supports_alpha(format) => {
+                Some(messages::text("preserve-alpha-format", &[]))
+            }
+            None if args.output_dir.is_none() => Some(messages::text("preserve-alpha-output", &[])),
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, message));
+        }
+    }
+
+    if let Some(count) = args.reserve {
+        let count = count as usize;
+        let invalid = match args.output_image_format() {
+            _ if args.indexed == Some(false) => Some(messages::text("reserve-unindexed", &[])),
+            _ if args.reserve_colors.len() > count => Some(messages::text(
+                "reserve-colors",
+                &[("colors", &args.reserve_colors.len()), ("count", &count)],
+            )),
+            _ if args.levels.iter().any(|&k| k + count > 256) => Some(messages::text(
+                "reserve-colors-exceeded",
+                &[("count", &count), ("k", &(256 - count))],
+            )),
+            Some(format) if !matches!(format, ImageFormat::Png | ImageFormat::Gif) => Some(
+                messages::text("reserve-format", &[("format", &format!("{format:?}"))]),
+            ),
+            None if args.output_dir.is_none() => Some(messages::text("reserve-output", &[])),
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, message));
+        }
+    }
+
+    if args.grain.is_some() {
+        let invalid = match () {
+            _ if args.indexed == Some(true) => Some(messages::text("grain-indexed", &[])),
+            _ if args.output_dir.is_none() && args.output_image_format().is_none() => {
+                Some(messages::text("grain-output", &[]))
+            }
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, message));
+        }
+    }
+
+    if args.output_profile.is_some() || args.output_gamut.is_some() {
+        let flag = match args.output_gamut {
+            Some(_) => "--output-gamut",
+            None => "--output-profile",
+        };
+        let invalid = match args.output_image_format() {
+            Some(format) if !io::supports_icc(format) => Some(messages::text(
+                "profile-format",
+                &[("flag", &flag), ("format", &format!("{format:?}"))],
+            )),
+            None if args.output_gamut.is_none() && args.output_dir.is_none() => {
+                Some(messages::text("output-profile-output", &[]))
+            }
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, message));
+        }
+    }
+
+    if let Some(path) = &args.sheet
+        && !ImageFormat::from_path(path).is_ok_and(|f| f.writing_enabled())
+    {
+        return Err(error(
+            clap::error::ErrorKind::InvalidValue,
+            messages::text("sheet-format", &[("path", &path.display())]),
+        ));
+    }
+
+    if let Some(dir) = &args.cluster_masks
+        && args.levels.len() > 1
+        && !has_level(dir)
+    {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("masks-levels", &[]),
+        ));
+    }
+
+    if let Some(path) = &args.overlay {
+        let invalid = match () {
+            _ if !ImageFormat::from_path(path).is_ok_and(|f| f.writing_enabled()) => Some(
+                messages::text("overlay-format", &[("path", &path.display())]),
+            ),
+            _ if args.levels.len() > 1 && !has_level(path) => {
+                Some(messages::text("overlay-levels", &[]))
+            }
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(clap::error::ErrorKind::InvalidValue, message));
+        }
+    }
+
+    // each level needs an output of its own
+    if args.levels.len() > 1 && args.palette.is_none() {
+        let invalid = match (&args.output_dir, args.output_path()) {
+            _ if args.pipeline.is_some() => Some(messages::text("pipeline-levels", &[])),
+            (Some(_), _) if !args.suffix.as_ref().is_some_and(|s| s.contains("{k}")) => {
+                Some(messages::text("suffix-levels", &[]))
+            }
+            (None, Some(path)) if !has_level(&path) => Some(messages::text("output-levels", &[])),
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, message));
+        }
+    }
+
+    if args.as_image {
+        let invalid = match args.output_path() {
+            None if args.output_dir.is_none() => Some(messages::text("as-image-output", &[])),
+            Some(path) if args.output_image_format().is_none() => Some(messages::text(
+                "as-image-format",
+                &[("path", &path.display())],
+            )),
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(clap::error::ErrorKind::ArgumentConflict, message));
+        }
+    }
+
+    // check if `--format` is specified AND output has image file extension
+    if args.format.is_some() && args.output_image_format().is_some() {
+        return Err(error(
+            clap::error::ErrorKind::ArgumentConflict,
+            messages::text("format-image-output", &[]),
+        ));
+    }
+
+    if args.indexed == Some(true) {
+        if args
+            .output_image_format()
+            .is_some_and(|f| !matches!(f, ImageFormat::Png | ImageFormat::Gif))
+        {
+            return Err(error(
+                clap::error::ErrorKind::ArgumentConflict,
+                messages::text("indexed-format", &[]),
+            ));
+        }
+        if args.levels.iter().any(|&k| k > 256) && args.palette.is_none() {
+            return Err(error(
+                clap::error::ErrorKind::ArgumentConflict,
+                messages::text("indexed-colors", &[]),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// shorthand for `error(...).exit()`
+pub fn err_exit(kind: clap::error::ErrorKind, message: impl std::fmt::Display) -> ! {
+    logger::exit(error(kind, message))
+}
+
+/// shorthand for `Args::command().error(...)`
+pub fn error(kind: clap::error::ErrorKind, message: impl std::fmt::Display) -> clap::Error {
+    Args::command().error(kind, message)
+}
@@ -0,0 +1,162 @@
+use image::*;
+
+use crate::cli::{ApplyLutArgs, ColorizeArgs};
+use crate::colors::{self, Color};
+use crate::io;
+use crate::logger;
+use crate::messages;
+use crate::palette::Palette;
+
+/// built-in colormaps, as evenly spaced gradient colors from low to high values
+const LUTS: &[(&str, &[&str])] = &[
+    (
+        "viridis",
+        &[
+            "#440154", "#482878", "#3e4989", "#31688e", "#26828e", "#1f9e89", "#35b779", "#6ece58",
+            "#b5de2b", "#fde725",
+        ],
+    ),
+    (
+        "magma",
+        &[
+            "#000004", "#180f3d", "#440f76", "#721f81", "#9e2f7f", "#cd4071", "#f1605d", "#fd9668",
+            "#feca8d", "#fcfdbf",
+        ],
+    ),
+    (
+        "inferno",
+        &[
+            "#000004", "#1b0c41", "#4a0c6b", "#781c6d", "#a52c60", "#cf4446", "#ed6925", "#fb9b06",
+            "#f7d13d", "#fcffa4",
+        ],
+    ),
+    (
+        "plasma",
+        &[
+            "#0d0887", "#41049d", "#6a00a8", "#8f0da4", "#b12a90", "#cc4778", "#e16462", "#f2844b",
+            "#fca636", "#fcce25", "#f0f921",
+        ],
+    ),
+    ("gray", &["#000000", "#ffffff"]),
+];
+
+/// map the luminance of an image onto an ordered gradient of colors
+///
+/// every luminance level is assigned a gradient color, and pixels are
+/// remapped to the color of their level like quantized images
+pub fn colorize(args: &ColorizeArgs) -> Result<(), String> {
+    let stops = match &args.palette {
+        Some(palette_file) => Palette::from_file(palette_file)?.colors,
+        None => args.colors.clone(),
+    };
+
+    let img = io::decode(&args.input, None)?.image;
+
+    let levels = gradient(&stops, args.discrete);
+    let assignments = img
+        .to_luma8()
+        .pixels()
+        .map(|p| p.0[0] as usize)
+        .collect::<Vec<_>>();
+
+    let (width, height) = img.dimensions();
+    let options = io::EncodeOptions::default();
+    io::encode(&args.output, width, height, &levels, &assignments, &options)?;
+    println!(
+        "{}",
+        messages::text("saved-colorized", &[("path", &args.output.display())])
+    );
+
+    Ok(())
+}
+
+/// apply a built-in or palette file colormap to a single-channel data image
+pub fn apply_lut(args: &ApplyLutArgs) -> Result<(), String> {
+    let stops = match LUTS
+        .iter()
+        .find(|(name, _)| args.lut.to_str() == Some(*name))
+    {
+        Some((_, codes)) => codes
+            .iter()
+            .map(|code| code.parse().expect("built-in colormaps are valid"))
+            .collect(),
+        None if args.lut.is_file() => Palette::from_file(&args.lut)?.colors,
+        None => {
+            let supported = LUTS.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            return Err(messages::text(
+                "unknown-colormap",
+                &[
+                    ("colormap", &args.lut.display()),
+                    ("supported", &supported.join(", ")),
+                ],
+            ));
+        }
+    };
+
+    let img = io::decode(&args.input, None)?.image;
+    if img.color().has_color() {
+        logger::note(
+            "luminance-mapped",
+            messages::text("luminance-mapped", &[("input", &args.input.display())]),
+        );
+    }
+
+    // keep 16-bit precision until values are mapped to levels
+    let values = img.to_luma16().pixels().map(|p| p.0[0]).collect::<Vec<_>>();
+    let (min, max) = match args.normalize {
+        true => (
+            values.iter().copied().min().unwrap_or(0),
+            values.iter().copied().max().unwrap_or(u16::MAX),
+        ),
+        false => (0, u16::MAX),
+    };
+
+    let range = (max - min).max(1) as u32;
+    let assignments = values
+        .iter()
+        .map(|&v| ((v - min) as u32 * u8::MAX as u32 / range) as usize)
+        .collect::<Vec<_>>();
+
+    let levels = gradient(&stops, false);
+    let (width, height) = img.dimensions();
+    let options = io::EncodeOptions::default();
+    io::encode(&args.output, width, height, &levels, &assignments, &options)?;
+    println!(
+        "{}",
+        messages::text("saved-colormapped", &[("path", &args.output.display())])
+    );
+
+    Ok(())
+}
+
+/// color of each of the 256 luminance levels along a gradient
+/// - the first color is used for black, the last for white
+/// - discrete gradients use the nearest color instead of interpolating
+fn gradient(stops: &[Color], discrete: bool) -> Vec<Color> {
+    // colors are only mixed with equal color types
+    let stops = match stops.iter().any(|c| c.color_type == ColorType::Rgba8) {
+        true => stops
+            .iter()
+            .map(|c| Color {
+                color_type: ColorType::Rgba8,
+                data: c.data.iter().copied().chain([u8::MAX]).take(4).collect(),
+            })
+            .collect::<Vec<_>>(),
+        false => stops.to_vec(),
+    };
+
+    let segments = (stops.len() - 1) as f64;
+    (0..=u8::MAX)
+        .map(|level| {
+            let position = level as f64 / u8::MAX as f64 * segments;
+            match (discrete, position.fract()) {
+                (true, _) => stops[position.round() as usize].clone(),
+                (false, 0.0) => stops[position as usize].clone(),
+                (false, fraction) => {
+                    let lower = position.floor() as usize;
+                    colors::mix(&stops[lower], &stops[lower + 1], 1.0 - fraction, fraction)
+                }
+            }
+        })
+        .collect()
+}
@@ -1,6 +1,9 @@
 use image::*;
 
-use crate::kmeans::Kmeansable;
+use rayon::prelude::*;
+
+use crate::kmeans::{self, Kmeansable, Rounding};
+use crate::messages;
 
 /// marker trait for usable color types
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -9,8 +12,10 @@ pub struct Color {
     pub data: Vec<u8>,
 }
 
+/// channel sums are u64, since u32 sums overflow for clusters of more than ~16.8M
+/// bright pixels (eg. the background of a large scan)
 impl Kmeansable for Color {
-    type Sum = Vec<u32>;
+    type Sum = Vec<u64>;
 
     fn zero() -> Self::Sum {
         vec![0; 4]
@@ -19,131 +24,631 @@ impl Kmeansable for Color {
     /// distance function, according to which clustering is performed
     /// (impl avoids sqrt for performance -- uses squared distance)
     fn distance(&self, other: &Self) -> f64 {
-        self.data
-            .iter()
-            .zip(other.data.iter())
-            .map(|(a, b)| ((*a as f64) - (*b as f64)).powi(2))
-            .sum::<f64>()
+        squared_distance(self, other) as f64
     }
 
     fn add(sum: &Self::Sum, other: &Self) -> Self::Sum {
         sum.iter()
             .zip(&other.data)
-            .map(|(a, b)| a + *b as u32)
+            .map(|(a, b)| a + *b as u64)
             .collect()
     }
 
-    fn div(sum: &Self::Sum, count: usize) -> Self {
+    fn add_weighted(sum: &Self::Sum, other: &Self, weight: u32) -> Self::Sum {
+        sum.iter()
+            .zip(&other.data)
+            .map(|(a, b)| a + *b as u64 * weight as u64)
+            .collect()
+    }
+
+    fn merge(sum: &Self::Sum, other: &Self::Sum) -> Self::Sum {
+        sum.iter().zip(other).map(|(a, b)| a + b).collect()
+    }
+
+    fn div(sum: &Self::Sum, count: usize, rounding: Rounding) -> Self {
         let data = sum
             .iter()
-            .map(|v| (v / count as u32) as u8)
+            .map(|&v| rounding.div(v, count as u64) as u8)
             .collect::<Vec<u8>>();
 
         Color {
             color_type: match data.len() {
-                3 => ColorType::Rgb8,
                 4 => ColorType::Rgba8,
-                _ => unreachable!(
-                    "invalid color length. only rgb or rgba colors should ever be used here."
-                ),
+                _ => ColorType::Rgb8,
             },
             data,
         }
     }
 }
 
-/// calculate the rgba brightness (luminance)
-pub fn brightness(color: &Color) -> u32 {
-    let &[r, g, b, ..] = &color.data[..] else {
-        unreachable!("invalid color type. only rgb or rgba colors should ever be used here.");
-    };
-    ((0.299 * r as f32) + (0.587 * g as f32) + (0.114 * b as f32)) as u32
-}
-
-/// color code output format
-#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
-pub enum ColorCodeFormat {
-    /// `#rrggbb` or `#rrggbbaa`
-    #[default]
-    Hex,
-    /// `rgb(r, g, b)` or `rgba(r, g, b, a)`
-    Rgb,
-}
-
-impl ColorCodeFormat {
-    /// pretty print a color code in the format
-    /// when writing to terminals, uses ansi escape codes for color preview
-    pub fn pretty_print_color_code<W>(format: &ColorCodeFormat, writer: &mut W, color: &Color)
-    where
-        W: std::io::Write,
-    {
-        match format {
-            ColorCodeFormat::Hex => Self::colored_with_format(writer, color, Self::hex_color_code),
-            ColorCodeFormat::Rgb => Self::colored_with_format(writer, color, Self::rgb_color_code),
+/// color with a fixed number of 8-bit channels (3 for rgb, 4 for rgba), for clustering
+/// - unlike [`Color`], distances and sums unroll over the channels without any heap data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pixel<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> Pixel<N> {
+    /// channels of a color (missing channels of malformed colors are opaque or 0)
+    pub fn new(color: &Color) -> Self {
+        match <[u8; N]>::try_from(color.data.as_slice()) {
+            Ok(channels) => Pixel(channels),
+            Err(_) => Pixel(std::array::from_fn(|i| {
+                (color.data.get(i).copied()).unwrap_or(if i == 3 { u8::MAX } else { 0 })
+            })),
         }
     }
 
-    /// pretty print wrapper that colors output
-    /// given a callback providing the actual color formatting
-    fn colored_with_format<W>(writer: &mut W, color: &Color, callback: fn(&mut W, &Color))
-    where
-        W: std::io::Write,
-    {
-        use std::io::IsTerminal;
-        let colorize = std::io::stdout().is_terminal();
+    /// the color of the channels, as rgba for 4 channels and rgb otherwise
+    pub fn color(self) -> Color {
+        Color {
+            color_type: match N {
+                4 => ColorType::Rgba8,
+                _ => ColorType::Rgb8,
+            },
+            data: self.0.to_vec(),
+        }
+    }
 
-        if !colorize {
-            // just print formatted color, no ansi codes
-            return callback(writer, color);
+    /// squared euclidean distance, in integer arithmetic
+    /// (4 channels of at most 255 sum to at most `4 * 255^2`, which fits a u32)
+    pub fn squared_distance(self, other: Self) -> u32 {
+        let mut sum = 0;
+        for i in 0..N {
+            sum += (self.0[i] as i32 - other.0[i] as i32).unsigned_abs().pow(2);
         }
+        sum
+    }
+}
 
-        // ensure text has enough contrast to colored background
-        match brightness(color) {
-            ..128 => write!(writer, "\x1b[38;2;255;255;255m"), // dark  => white text
-            _ => write!(writer, "\x1b[38;2;0;0;0m"),           // light => black text
+/// channel sums are u64, see the implementation for [`Color`]
+impl<const N: usize> Kmeansable for Pixel<N> {
+    type Sum = [u64; N];
+
+    fn zero() -> Self::Sum {
+        [0; N]
+    }
+
+    fn distance(&self, other: &Self) -> f64 {
+        self.squared_distance(*other) as f64
+    }
+
+    fn add(sum: &Self::Sum, other: &Self) -> Self::Sum {
+        std::array::from_fn(|i| sum[i] + other.0[i] as u64)
+    }
+
+    fn add_weighted(sum: &Self::Sum, other: &Self, weight: u32) -> Self::Sum {
+        std::array::from_fn(|i| sum[i] + other.0[i] as u64 * weight as u64)
+    }
+
+    fn merge(sum: &Self::Sum, other: &Self::Sum) -> Self::Sum {
+        std::array::from_fn(|i| sum[i] + other[i])
+    }
+
+    fn div(sum: &Self::Sum, count: usize, rounding: Rounding) -> Self {
+        Pixel(std::array::from_fn(|i| {
+            rounding.div(sum[i], count as u64) as u8
+        }))
+    }
+}
+
+impl Color {
+    /// red, green and blue channels (missing channels of malformed colors are 0)
+    pub fn rgb(&self) -> [u8; 3] {
+        let channel = |i: usize| self.data.get(i).copied().unwrap_or(0);
+        [channel(0), channel(1), channel(2)]
+    }
+
+    /// alpha channel of rgba colors (opaque if missing from malformed colors)
+    pub fn alpha(&self) -> Option<u8> {
+        match self.color_type {
+            ColorType::Rgba8 => Some(self.data.get(3).copied().unwrap_or(u8::MAX)),
+            _ => None,
         }
-        .expect("failed to write output");
+    }
 
-        let &[r, g, b, ..] = &color.data[..] else {
-            unreachable!("invalid color type. only rgb or rgba colors should ever be used here.");
+    /// the color as rgba (keeping or adding opaque alpha) or rgb (dropping alpha)
+    pub fn with_alpha(&self, alpha: bool) -> Color {
+        let data = self.rgb().into_iter();
+        match alpha {
+            true => Color {
+                data: data.chain([self.alpha().unwrap_or(u8::MAX)]).collect(),
+                color_type: ColorType::Rgba8,
+            },
+            false => Color {
+                data: data.collect(),
+                color_type: ColorType::Rgb8,
+            },
+        }
+    }
+}
+
+/// parse a color code
+/// - hex: `#rrggbb` or `#rrggbbaa` (leading `#` optional)
+/// - rgb: `rgb(r, g, b)` or `rgba(r, g, b, a)`, with alpha as in [`parse_alpha`]
+/// - css color level 4: `rgb(r g b)`, `hsl(h s% l%)`, `hwb(h w% b%)` or
+///   `oklch(l% c h)`, each with optional alpha after a slash (eg. `/ 50%`)
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || messages::text("invalid-color", &[("color", &s)]);
+
+        let Some((function, args)) = s.strip_suffix(')').and_then(|s| s.split_once('(')) else {
+            let hex = s.trim_start_matches('#');
+            let data = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or_default(), 16).ok())
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(invalid)?;
+
+            let color_type = match data.len() {
+                3 => ColorType::Rgb8,
+                4 => ColorType::Rgba8,
+                _ => return Err(invalid()),
+            };
+            return Ok(Color { color_type, data });
+        };
+
+        let (channels, alpha) = match args.split_once('/') {
+            Some((channels, alpha)) => (channels, Some(alpha.trim())),
+            None => (args, None),
+        };
+        let mut values = (channels.split([',', ' ']))
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>();
+
+        // legacy `rgba(r, g, b, a)` and `hsla(h, s, l, a)` give alpha as fourth value
+        let alpha = match alpha {
+            None if values.len() == 4 && function.ends_with('a') => values.pop(),
+            alpha => alpha,
+        };
+        if values.len() != 3 {
+            return Err(invalid());
+        }
+
+        let numbers = (values.iter())
+            .map(|v| {
+                v.trim_end_matches(['%'])
+                    .trim_end_matches("deg")
+                    .parse::<f64>()
+                    .ok()
+            })
+            .collect::<Option<Vec<f64>>>()
+            .ok_or_else(invalid)?;
+        let numbers = [numbers[0], numbers[1], numbers[2]];
+
+        let mut color = match function {
+            "rgb" | "rgba" => Color {
+                color_type: ColorType::Rgb8,
+                data: (values.iter())
+                    .map(|v| v.parse::<u8>().ok())
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(invalid)?,
+            },
+            "hsl" | "hsla" => from_hsl(numbers),
+            "hwb" => from_hwb(numbers),
+            "oklch" => {
+                let [l, c, h] = numbers;
+                match values[0].ends_with('%') {
+                    true => from_oklch([l / 100.0, c, h]),
+                    false => from_oklch([l, c, h]),
+                }
+            }
+            _ => return Err(invalid()),
         };
 
-        // print ansi codes for colored background
-        write!(writer, "\x1b[48;2;{r};{g};{b}m").expect("failed to write output");
+        if let Some(alpha) = alpha {
+            color.data.push(parse_alpha(alpha).ok_or_else(invalid)?);
+            color.color_type = ColorType::Rgba8;
+        }
+        Ok(color)
+    }
+}
+
+/// squared euclidean distance of two colors, in integer arithmetic
+/// - rgb colors (the most common case) take a branch without iterating over channels
+/// - 4 channels of at most 255 sum to at most `4 * 255^2`, which fits a u32
+pub fn squared_distance(a: &Color, b: &Color) -> u32 {
+    let diff = |x: u8, y: u8| (x as i32 - y as i32).unsigned_abs().pow(2);
+    match (a.data.as_slice(), b.data.as_slice()) {
+        (&[r1, g1, b1], &[r2, g2, b2]) => diff(r1, r2) + diff(g1, g2) + diff(b1, b2),
+        (a, b) => a.iter().zip(b).map(|(&x, &y)| diff(x, y)).sum(),
+    }
+}
+
+/// assign each color to its nearest palette color (in parallel), see [`kmeans::assign`]
+/// - rgb and rgba colors are compared as [`Pixel`]s, in integer arithmetic
+pub fn assign(colors: &[Color], palette: &[Color]) -> Vec<usize> {
+    match colors.first().map(|c| c.data.len()) {
+        _ if palette.is_empty() => vec![0; colors.len()],
+        Some(3) => assign_pixels::<3>(colors, palette),
+        Some(4) => assign_pixels::<4>(colors, palette),
+        _ => kmeans::assign(colors, palette),
+    }
+}
+
+/// [`assign`] for colors with `N` channels, skipping distance calculations like
+/// [`kmeans::assign`]
+fn assign_pixels<const N: usize>(colors: &[Color], palette: &[Color]) -> Vec<usize> {
+    let palette = palette.iter().map(Pixel::<N>::new).collect::<Vec<_>>();
+    let k = palette.len();
+    let mut palette_distances = vec![0u32; k * k];
+    for i in 0..k {
+        for j in 0..k {
+            palette_distances[i * k + j] = palette[i].squared_distance(palette[j]);
+        }
+    }
+
+    (colors.par_iter())
+        .map(|color| {
+            let pixel = Pixel::<N>::new(color);
+            let mut closest_idx = 0;
+            let mut closest_dist = pixel.squared_distance(palette[0]);
+            for (j, &candidate) in palette.iter().enumerate().skip(1) {
+                // (squared) triangle inequality, see `kmeans::nearest`
+                if palette_distances[closest_idx * k + j] >= 4 * closest_dist {
+                    continue;
+                }
+                let dist = pixel.squared_distance(candidate);
+                if dist < closest_dist {
+                    closest_dist = dist;
+                    closest_idx = j;
+                }
+            }
+            closest_idx
+        })
+        .collect()
+}
+
+/// relative luminance of a color in `[0, 1]`, ignoring alpha
+/// - computed in linear light (bt.709 / srgb primaries), unlike luma of gamma-encoded
+///   channels, so eg. saturated blues are not mistaken for mid tones
+pub fn luminance(color: &Color) -> f64 {
+    let [r, g, b] = color.rgb().map(linear);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// relative luminance of a color as a gamma-encoded level in `0..=255`, perceptually
+/// more even than [`luminance`] (eg. for binning colors by lightness)
+pub fn luminance_level(color: &Color) -> u8 {
+    gamma(luminance(color))
+}
+
+/// wcag contrast ratio of two colors, from 1 (same luminance) to 21 (black and white)
+pub fn contrast_ratio(a: &Color, b: &Color) -> f64 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// whether a color is light, ie. black text on it contrasts more than white text
+pub fn is_light(color: &Color) -> bool {
+    let gray = |v: u8| Color {
+        color_type: ColorType::Rgb8,
+        data: vec![v; 3],
+    };
+    contrast_ratio(color, &gray(0)) > contrast_ratio(color, &gray(u8::MAX))
+}
+
+/// weighted mean of two colors (equal weights if both weights are zero)
+pub fn mix(a: &Color, b: &Color, weight_a: f64, weight_b: f64) -> Color {
+    let (weight_a, weight_b) = match weight_a + weight_b {
+        0.0 => (1.0, 1.0),
+        _ => (weight_a, weight_b),
+    };
 
-        // call the actual color printing function
-        callback(writer, color);
+    let data = (a.data.iter().zip(&b.data))
+        .map(|(&x, &y)| {
+            ((x as f64 * weight_a + y as f64 * weight_b) / (weight_a + weight_b)).round() as u8
+        })
+        .collect();
 
-        // reset colors
-        write!(writer, "\x1b[0m").expect("failed to write output");
+    Color {
+        color_type: a.color_type,
+        data,
     }
+}
+
+/// convert a color to cie L*a*b* (d65 white point), ignoring alpha
+pub fn to_lab(color: &Color) -> [f64; 3] {
+    let [r, g, b] = color.rgb();
+
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+
+    // linear srgb -> xyz, normalized by the d65 white point
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f64| match t {
+        _ if t > 216.0 / 24389.0 => t.cbrt(),
+        _ => (24389.0 / 27.0 * t + 16.0) / 116.0,
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// convert cie L*a*b* (d65 white point) to an rgb color, clamping out of gamut values
+pub fn from_lab([l, a, b]: [f64; 3]) -> Color {
+    let f_inv = |t: f64| match t.powi(3) {
+        cube if cube > 216.0 / 24389.0 => cube,
+        _ => (116.0 * t - 16.0) * 27.0 / 24389.0,
+    };
+    let fy = (l + 16.0) / 116.0;
+    let (x, y, z) = (
+        f_inv(fy + a / 500.0) * 0.95047,
+        f_inv(fy),
+        f_inv(fy - b / 200.0) * 1.08883,
+    );
+
+    // xyz -> linear srgb
+    let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+    let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+    let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+
+    Color {
+        color_type: ColorType::Rgb8,
+        data: vec![gamma(r), gamma(g), gamma(b)],
+    }
+}
+
+/// convert a color to oklab, ignoring alpha
+pub fn to_oklab(color: &Color) -> [f64; 3] {
+    let [r, g, b] = color.rgb();
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+
+    // linear srgb -> lms cone responses, with cube root nonlinearity
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
 
-    /// print uncolored hex color code, with optional alpha
-    fn hex_color_code<W>(writer: &mut W, color: &Color)
-    where
-        W: std::io::Write,
-    {
-        let c = &color.data;
-        write!(writer, "#{:02x}{:02x}{:02x}", c[0], c[1], c[2]).expect("failed to write output");
+/// convert oklab to an rgb color, clamping out of gamut values
+pub fn from_oklab([l, a, b]: [f64; 3]) -> Color {
+    let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m_ = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s_ = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
 
-        if color.color_type == ColorType::Rgba8 {
-            write!(writer, "{:02x}", c[3]).expect("failed to write output");
+    let r = 4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_;
+    let g = -1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_;
+    let b = -0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_;
+
+    Color {
+        color_type: ColorType::Rgb8,
+        data: vec![gamma(r), gamma(g), gamma(b)],
+    }
+}
+
+/// convert a color to ycbcr (bt.601 luma and chroma, as in jpeg), ignoring alpha
+/// - luma ranges from 0 to 255, chroma from -127.5 to 127.5 (centered on gray)
+pub fn to_ycbcr(color: &Color) -> [f64; 3] {
+    let [r, g, b] = color.rgb().map(|v| v as f64);
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        -0.168736 * r - 0.331264 * g + 0.5 * b,
+        0.5 * r - 0.418688 * g - 0.081312 * b,
+    ]
+}
+
+/// convert ycbcr (see [`to_ycbcr`]) to an rgb color, clamping out of gamut values
+pub fn from_ycbcr([y, cb, cr]: [f64; 3]) -> Color {
+    let channel = |v: f64| v.round().clamp(0.0, 255.0) as u8;
+    Color {
+        color_type: ColorType::Rgb8,
+        data: vec![
+            channel(y + 1.402 * cr),
+            channel(y - 0.344136 * cb - 0.714136 * cr),
+            channel(y + 1.772 * cb),
+        ],
+    }
+}
+
+/// convert a color to oklch (lightness from 0 to 1, chroma, hue in degrees), ignoring alpha
+pub fn to_oklch(color: &Color) -> [f64; 3] {
+    let [l, a, b] = to_oklab(color);
+    [l, a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.0)]
+}
+
+/// convert oklch to an rgb color, clamping out of gamut values
+pub fn from_oklch([l, c, h]: [f64; 3]) -> Color {
+    let h = h.to_radians();
+    from_oklab([l, c * h.cos(), c * h.sin()])
+}
+
+/// convert a color to hsl (hue in degrees, saturation and lightness in percent),
+/// ignoring alpha
+pub fn to_hsl(color: &Color) -> [f64; 3] {
+    let [r, g, b] = color.rgb().map(|v| v as f64 / 255.0);
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+
+    let lightness = (max + min) / 2.0;
+    let saturation = match max - min {
+        0.0 => 0.0,
+        chroma => chroma / (1.0 - (2.0 * lightness - 1.0).abs()),
+    };
+    [hue([r, g, b]), saturation * 100.0, lightness * 100.0]
+}
+
+/// convert hsl to an rgb color
+pub fn from_hsl([h, s, l]: [f64; 3]) -> Color {
+    let (s, l) = ((s / 100.0).clamp(0.0, 1.0), (l / 100.0).clamp(0.0, 1.0));
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    from_hue(h, chroma, l - chroma / 2.0)
+}
+
+/// convert a color to hwb (hue in degrees, whiteness and blackness in percent),
+/// ignoring alpha
+pub fn to_hwb(color: &Color) -> [f64; 3] {
+    let [r, g, b] = color.rgb().map(|v| v as f64 / 255.0);
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    [hue([r, g, b]), min * 100.0, (1.0 - max) * 100.0]
+}
+
+/// convert hwb to an rgb color
+/// (whiteness and blackness adding up to more than 100% are scaled down to a gray)
+pub fn from_hwb([h, w, b]: [f64; 3]) -> Color {
+    let (w, b) = ((w / 100.0).clamp(0.0, 1.0), (b / 100.0).clamp(0.0, 1.0));
+    let (w, b) = match w + b {
+        sum if sum > 1.0 => (w / sum, b / sum),
+        _ => (w, b),
+    };
+    from_hue(h, 1.0 - w - b, w)
+}
+
+/// hue of rgb channels in `[0, 1]`, in degrees (0 for grays)
+fn hue([r, g, b]: [f64; 3]) -> f64 {
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let chroma = max - min;
+
+    let sector = match () {
+        _ if chroma == 0.0 => 0.0,
+        _ if max == r => ((g - b) / chroma).rem_euclid(6.0),
+        _ if max == g => (b - r) / chroma + 2.0,
+        _ => (r - g) / chroma + 4.0,
+    };
+    sector * 60.0
+}
+
+/// rgb color of a hue (in degrees) with a chroma, lifted by `min` (both in `[0, 1]`)
+fn from_hue(hue: f64, chroma: f64, min: f64) -> Color {
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+
+    Color {
+        color_type: ColorType::Rgb8,
+        data: [r, g, b]
+            .map(|v| ((v + min) * 255.0).round().clamp(0.0, 255.0) as u8)
+            .to_vec(),
+    }
+}
+
+/// srgb gamma expansion of a channel to linear light in `[0, 1]`
+pub(crate) fn linear(v: u8) -> f64 {
+    let v = v as f64 / 255.0;
+    match v {
+        ..=0.04045 => v / 12.92,
+        _ => ((v + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+/// srgb gamma compression of linear light to a channel, clamping out of gamut values
+pub(crate) fn gamma(v: f64) -> u8 {
+    let v = match v {
+        ..=0.0031308 => v * 12.92,
+        _ => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+    };
+    (v * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// cie76 color difference (euclidean distance in L*a*b*)
+pub fn delta_e(a: &Color, b: &Color) -> f64 {
+    let (a, b) = (to_lab(a), to_lab(b));
+    a.iter()
+        .zip(&b)
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// distinct colors in order of first occurrence,
+/// or `None` if there are more than `limit` of them
+pub fn distinct(colors: &[Color], limit: usize) -> Option<Vec<Color>> {
+    let mut seen = std::collections::HashSet::new();
+    let mut distinct = Vec::new();
+
+    for color in colors {
+        if seen.insert(&color.data[..]) {
+            if distinct.len() == limit {
+                return None;
+            }
+            distinct.push(color.clone());
+        }
+    }
+
+    Some(distinct)
+}
+
+/// number of distinct colors
+pub fn count_distinct(colors: &[Color]) -> usize {
+    colors
+        .iter()
+        .map(|c| &c.data[..])
+        .collect::<std::collections::HashSet<_>>()
+        .len()
+}
+
+/// parse the alpha of an rgb color code
+/// - integers are alpha from 0 to 255 (as written by `--format rgb`)
+/// - decimals are css alpha from 0 to 1, and percentages from 0% to 100%
+fn parse_alpha(s: &str) -> Option<u8> {
+    let fraction = match (s.strip_suffix('%'), s.contains('.')) {
+        (Some(percent), _) => percent.trim().parse::<f64>().ok()? / 100.0,
+        (None, true) => s.parse::<f64>().ok()?,
+        (None, false) => return s.parse::<u8>().ok(),
+    };
+    (0.0..=1.0)
+        .contains(&fraction)
+        .then(|| (fraction * 255.0).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// more white pixels than u32 channel sums can hold (`255 * 2^24 > u32::MAX`)
+    const HUGE: u32 = 20_000_000;
+
+    fn white() -> Color {
+        Color {
+            color_type: ColorType::Rgb8,
+            data: vec![u8::MAX; 3],
         }
     }
 
-    /// print uncolored rgb color code, with optional alpha
-    fn rgb_color_code<W>(writer: &mut W, color: &Color)
-    where
-        W: std::io::Write,
-    {
-        let c = &color.data;
-        match color.color_type {
-            ColorType::Rgba8 => write!(writer, "rgba({}, {}, {}, {})", c[0], c[1], c[2], c[3]),
-            ColorType::Rgb8 => write!(writer, "rgb({}, {}, {})", c[0], c[1], c[2]),
-            _ => unreachable!(
-                "invalid color type. only rgb or rgba colors should ever be used here."
-            ),
+    #[test]
+    fn huge_cluster_sums() {
+        let sum = Color::add_weighted(&Color::zero(), &white(), HUGE);
+        let sum = Color::merge(&sum, &Color::add(&Color::zero(), &white()));
+        let mean = Color::div(&sum, HUGE as usize + 1, Rounding::HalfEven);
+        assert_eq!(mean.rgb(), [u8::MAX; 3]);
+
+        let pixel = Pixel::<3>::new(&white());
+        let sum = Pixel::add_weighted(&Pixel::<3>::zero(), &pixel, HUGE);
+        let sum = Pixel::merge(&sum, &Pixel::add(&Pixel::<3>::zero(), &pixel));
+        let mean = Pixel::div(&sum, HUGE as usize + 1, Rounding::Truncate);
+        assert_eq!(mean, pixel);
+    }
+
+    #[test]
+    fn huge_cluster_k_means() {
+        let black = Color {
+            color_type: ColorType::Rgb8,
+            data: vec![0; 3],
+        };
+        let pixels = [white(), white(), black.clone()];
+        let weights = [HUGE, HUGE, 1];
+        for colorspace in [crate::ColorSpace::Srgb, crate::ColorSpace::Lab] {
+            let mut context = kmeans::Context::new(0).with_progress(false);
+            let (clusters, assignments) = colorspace
+                .k_means_weighted_with(&mut context, &pixels, &weights, 2, 5, |_, _| {})
+                .expect("clustering succeeds");
+            assert_eq!(clusters[assignments[0]].rgb(), [u8::MAX; 3]);
+            assert_eq!(clusters[assignments[2]].rgb(), [0; 3]);
         }
-        .expect("failed to write output")
     }
 }
@@ -0,0 +1,457 @@
+use rand::rngs::SmallRng;
+use rayon::prelude::*;
+
+use crate::Error;
+use crate::cam16;
+use crate::colors::{self, Color};
+use crate::kmeans::{self, Kmeansable, Rounding};
+use crate::messages;
+
+/// fixed-point scale of coordinate sums, keeping parallel sums deterministic
+const SUM_SCALE: f64 = (1 << 16) as f64;
+
+/// color space that clustering is performed in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorSpace {
+    /// Euclidean distance of rgb values (fastest)
+    #[default]
+    Srgb,
+    /// Euclidean distance in CIE L*a*b* (perceptual)
+    Lab,
+    /// Euclidean distance in Oklab (perceptual, more uniform hues)
+    Oklab,
+    /// Euclidean distance in CAM16-UCS (color appearance model, see `--viewing-conditions`)
+    Cam16,
+    /// Euclidean distance of luma and chroma, as in jpeg compression (see `--luma-weight`)
+    Ycbcr,
+}
+
+/// parameters of color spaces, see [`ColorSpace::assign`] and
+/// [`Context::with_parameters`](kmeans::Context::with_parameters)
+/// - weights apply to squared differences (which distances are compared by), so their
+///   coordinates are scaled by the root of the weight, as in [`assign_by_chroma`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Parameters {
+    /// weight of luma over chroma differences in ycbcr (eg. `2.0` for luma differences
+    /// counting twice as much as chroma differences)
+    pub luma_weight: f64,
+    /// viewing conditions of cam16
+    pub viewing_conditions: cam16::ViewingConditions,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            luma_weight: 1.0,
+            viewing_conditions: cam16::ViewingConditions::default(),
+        }
+    }
+}
+
+impl Parameters {
+    /// check that weights and viewing conditions are positive and finite
+    pub fn validate(&self) -> Result<(), Error> {
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+        if !positive(self.luma_weight) {
+            return Err(Error::InvalidParameter(messages::text(
+                "luma-weight-not-positive",
+                &[("value", &self.luma_weight)],
+            )));
+        }
+        let cam16::ViewingConditions {
+            luminance,
+            background,
+            ..
+        } = self.viewing_conditions;
+        if !positive(luminance) || !positive(background) {
+            return Err(Error::InvalidParameter(messages::text(
+                "viewing-conditions-not-positive",
+                &[("luminance", &luminance), ("background", &background)],
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// assign each pixel to its nearest cluster in oklab, with differences of hue and chroma
+/// weighted by `weight` over differences of lightness (and alpha)
+/// - above 1, saturated pixels stay with colorful clusters instead of similarly light grays
+pub fn assign_by_chroma(pixels: &[Color], clusters: &[Color], weight: f64) -> Vec<usize> {
+    match pixels.first().map(|c| c.data.len()) {
+        Some(4) => assign_by_chroma_points::<4>(pixels, clusters, weight),
+        _ => assign_by_chroma_points::<3>(pixels, clusters, weight),
+    }
+}
+
+/// [`assign_by_chroma`] of pixels converted to points with `N` coordinates
+fn assign_by_chroma_points<const N: usize>(
+    pixels: &[Color],
+    clusters: &[Color],
+    weight: f64,
+) -> Vec<usize> {
+    // distances are squared, so chroma coordinates are scaled by the root of the weight
+    let scale = weight.sqrt();
+    let conversion = Conversion::new(&Parameters::default());
+    let convert = |c: &Color| {
+        let Point(mut point) = ColorSpace::Oklab.convert::<N>(c, &conversion);
+        point[1] *= scale as f32;
+        point[2] *= scale as f32;
+        Point(point)
+    };
+    let points = pixels.par_iter().map(convert).collect::<Vec<_>>();
+    let clusters = clusters.iter().map(convert).collect::<Vec<_>>();
+    kmeans::assign(&points, &clusters)
+}
+
+/// initialization of the clusters of [`ColorSpace::cluster`]
+enum Start<'a> {
+    /// `k` clusters seeded by k-means++
+    Seeded(usize),
+    /// the given clusters
+    From(&'a [Color]),
+}
+
+/// values derived from [`Parameters`] once per conversion of many colors
+struct Conversion {
+    /// cam16 with [`Parameters::viewing_conditions`]
+    model: cam16::Model,
+    /// scale of ycbcr luma, the root of [`Parameters::luma_weight`]
+    luma_scale: f64,
+}
+
+impl Conversion {
+    fn new(parameters: &Parameters) -> Self {
+        Conversion {
+            model: cam16::Model::new(parameters.viewing_conditions),
+            luma_scale: parameters.luma_weight.sqrt(),
+        }
+    }
+}
+
+/// a color converted to a color space, with alpha scaled to the lightness range (so it
+/// weighs similar to lightness in distances)
+/// - `N` is 3 for colors without and 4 for colors with alpha
+/// - coordinates are floats, so cluster means keep their precision between iterations
+///   and are only rounded when converted back to colors
+#[derive(Clone, Copy, Debug)]
+pub struct Point<const N: usize>([f32; N]);
+
+impl<const N: usize> Kmeansable for Point<N> {
+    type Sum = [i64; N];
+
+    fn zero() -> Self::Sum {
+        [0; N]
+    }
+
+    /// squared euclidean distance
+    fn distance(&self, other: &Self) -> f64 {
+        let mut sum = 0.0;
+        for i in 0..N {
+            sum += (self.0[i] - other.0[i]).powi(2);
+        }
+        sum as f64
+    }
+
+    fn add(sum: &Self::Sum, other: &Self) -> Self::Sum {
+        Self::add_weighted(sum, other, 1)
+    }
+
+    fn add_weighted(sum: &Self::Sum, other: &Self, weight: u32) -> Self::Sum {
+        std::array::from_fn(|i| {
+            sum[i] + (other.0[i] as f64 * SUM_SCALE).round() as i64 * weight as i64
+        })
+    }
+
+    fn merge(sum: &Self::Sum, other: &Self::Sum) -> Self::Sum {
+        std::array::from_fn(|i| sum[i] + other[i])
+    }
+
+    /// points are not rounded, colors are rounded when converted back
+    fn div(sum: &Self::Sum, count: usize, _: Rounding) -> Self {
+        Point(std::array::from_fn(|i| {
+            (sum[i] as f64 / SUM_SCALE / count.max(1) as f64) as f32
+        }))
+    }
+}
+
+impl ColorSpace {
+    /// k-means clustering of colors in the color space, calling
+    /// `on_iteration(i, clusters)` after each iteration `i`
+    ///
+    /// clusters are converted back to rgb(a), see [`kmeans::Context::k_means`]
+    pub fn k_means_with<F>(
+        self,
+        context: &mut kmeans::Context<SmallRng>,
+        pixels: &[Color],
+        k: usize,
+        iterations: usize,
+        on_iteration: F,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error>
+    where
+        F: FnMut(usize, &[Color]),
+    {
+        self.cluster(
+            context,
+            pixels,
+            None,
+            Start::Seeded(k),
+            iterations,
+            on_iteration,
+        )
+    }
+
+    /// weighted k-means clustering of colors in the color space, where `pixels[i]`
+    /// counts `weights[i]` times, see [`ColorSpace::k_means_with`]
+    pub fn k_means_weighted_with<F>(
+        self,
+        context: &mut kmeans::Context<SmallRng>,
+        pixels: &[Color],
+        weights: &[u32],
+        k: usize,
+        iterations: usize,
+        on_iteration: F,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error>
+    where
+        F: FnMut(usize, &[Color]),
+    {
+        let start = Start::Seeded(k);
+        self.cluster(
+            context,
+            pixels,
+            Some(weights),
+            start,
+            iterations,
+            on_iteration,
+        )
+    }
+
+    /// weighted k-means clustering of colors in the color space, starting from `initial`
+    /// clusters, see [`kmeans::Context::k_means_weighted_from`]
+    pub fn k_means_weighted_from<F>(
+        self,
+        context: &mut kmeans::Context<SmallRng>,
+        pixels: &[Color],
+        weights: &[u32],
+        initial: &[Color],
+        iterations: usize,
+        on_iteration: F,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error>
+    where
+        F: FnMut(usize, &[Color]),
+    {
+        let start = Start::From(initial);
+        self.cluster(
+            context,
+            pixels,
+            Some(weights),
+            start,
+            iterations,
+            on_iteration,
+        )
+    }
+
+    /// assign each pixel to its nearest cluster in the color space, see [`kmeans::assign`]
+    /// (and [`colors::assign`] for srgb)
+    pub fn assign(
+        self,
+        pixels: &[Color],
+        clusters: &[Color],
+        parameters: &Parameters,
+    ) -> Vec<usize> {
+        let conversion = Conversion::new(parameters);
+        match pixels.first().map(|c| c.data.len()) {
+            _ if self == ColorSpace::Srgb => colors::assign(pixels, clusters),
+            Some(4) => self.assign_points::<4>(pixels, clusters, &conversion),
+            _ => self.assign_points::<3>(pixels, clusters, &conversion),
+        }
+    }
+
+    /// [`ColorSpace::assign`] of pixels converted to points with `N` coordinates
+    fn assign_points<const N: usize>(
+        self,
+        pixels: &[Color],
+        clusters: &[Color],
+        conversion: &Conversion,
+    ) -> Vec<usize> {
+        let points = pixels
+            .par_iter()
+            .map(|c| self.convert::<N>(c, conversion))
+            .collect::<Vec<_>>();
+        let clusters = (clusters.iter())
+            .map(|c| self.convert::<N>(c, conversion))
+            .collect::<Vec<_>>();
+        kmeans::assign(&points, &clusters)
+    }
+
+    /// k-means clustering with optional weights and initial clusters, see
+    /// [`ColorSpace::k_means_with`]
+    fn cluster<F>(
+        self,
+        context: &mut kmeans::Context<SmallRng>,
+        pixels: &[Color],
+        weights: Option<&[u32]>,
+        start: Start,
+        iterations: usize,
+        on_iteration: F,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error>
+    where
+        F: FnMut(usize, &[Color]),
+    {
+        match pixels.first().map(|c| c.data.len()) {
+            Some(4) => self.cluster_points::<4, F>(
+                context,
+                pixels,
+                weights,
+                start,
+                iterations,
+                on_iteration,
+            ),
+            _ => self.cluster_points::<3, F>(
+                context,
+                pixels,
+                weights,
+                start,
+                iterations,
+                on_iteration,
+            ),
+        }
+    }
+
+    /// [`ColorSpace::cluster`] of pixels converted to points with `N` coordinates
+    /// - clusters are rounded to colors only when reported or returned
+    fn cluster_points<const N: usize, F>(
+        self,
+        context: &mut kmeans::Context<SmallRng>,
+        pixels: &[Color],
+        weights: Option<&[u32]>,
+        start: Start,
+        iterations: usize,
+        mut on_iteration: F,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error>
+    where
+        F: FnMut(usize, &[Color]),
+    {
+        let conversion = Conversion::new(context.parameters());
+        let points = crate::timing::measure("preprocess", || {
+            pixels
+                .iter()
+                .map(|c| self.convert::<N>(c, &conversion))
+                .collect::<Vec<_>>()
+        });
+
+        let rounding = context.rounding();
+        let revert = |points: &[Point<N>]| {
+            (points.iter())
+                .map(|p| self.revert(p, rounding, &conversion))
+                .collect::<Vec<_>>()
+        };
+        let on_iteration = |i: usize, c: &[Point<N>]| on_iteration(i, &revert(c));
+        let start = match start {
+            Start::Seeded(k) => kmeans::Start::Seeded(k),
+            Start::From(initial) => kmeans::Start::From(
+                (initial.iter())
+                    .map(|c| self.convert::<N>(c, &conversion))
+                    .collect(),
+            ),
+        };
+
+        // clusters outside of the gamut are moved into it as colors, then converted back
+        let gamut = context.gamut().cloned();
+        let constrain = |point: &mut Point<N>| {
+            if let Some(gamut) = &gamut {
+                let color = self.revert(point, rounding, &conversion);
+                if !gamut.contains(&color) {
+                    *point = self.convert::<N>(&gamut.snap(&color), &conversion);
+                }
+            }
+        };
+        let (clusters, assignments) =
+            context.cluster(&points, weights, start, iterations, constrain, on_iteration)?;
+
+        // rounding to colors may leave the gamut slightly, see `Gamut::snap`
+        let clusters = revert(&clusters);
+        Ok(match &gamut {
+            Some(gamut) => (
+                clusters.iter().map(|c| gamut.snap(c)).collect(),
+                assignments,
+            ),
+            None => (clusters, assignments),
+        })
+    }
+
+    /// convert a color to a point in the color space, with alpha for `N = 4`
+    fn convert<const N: usize>(self, color: &Color, conversion: &Conversion) -> Point<N> {
+        let [x, y, z] = match self {
+            ColorSpace::Srgb => color.rgb().map(|v| v as f64),
+            ColorSpace::Lab => colors::to_lab(color),
+            ColorSpace::Oklab => colors::to_oklab(color),
+            ColorSpace::Cam16 => conversion.model.to_ucs(color),
+            ColorSpace::Ycbcr => {
+                let [y, cb, cr] = colors::to_ycbcr(color);
+                [y * conversion.luma_scale, cb, cr]
+            }
+        };
+
+        let alpha = color.alpha().unwrap_or(u8::MAX) as f64 / u8::MAX as f64 * self.lightness();
+        let coordinates = [x, y, z, alpha];
+        Point(std::array::from_fn(|i| coordinates[i] as f32))
+    }
+
+    /// convert a point in the color space back to a color, rounding channels as given
+    fn revert<const N: usize>(
+        self,
+        point: &Point<N>,
+        rounding: Rounding,
+        conversion: &Conversion,
+    ) -> Color {
+        let round = |v: f64| rounding.round(v).clamp(0.0, u8::MAX as f64) as u8;
+        let coordinates = [0, 1, 2].map(|i| point.0[i] as f64);
+        let mut color = match self {
+            ColorSpace::Srgb => Color {
+                color_type: image::ColorType::Rgb8,
+                data: coordinates.map(round).to_vec(),
+            },
+            ColorSpace::Lab => colors::from_lab(coordinates),
+            ColorSpace::Oklab => colors::from_oklab(coordinates),
+            ColorSpace::Cam16 => conversion.model.from_ucs(coordinates),
+            ColorSpace::Ycbcr => {
+                let [y, cb, cr] = coordinates;
+                colors::from_ycbcr([y / conversion.luma_scale, cb, cr])
+            }
+        };
+
+        if let Some(&alpha) = point.0.get(3) {
+            color
+                .data
+                .push(round(alpha as f64 / self.lightness() * u8::MAX as f64));
+            color.color_type = image::ColorType::Rgba8;
+        }
+        color
+    }
+
+    /// lightness of white in the color space
+    fn lightness(self) -> f64 {
+        match self {
+            ColorSpace::Srgb | ColorSpace::Ycbcr => u8::MAX as f64,
+            ColorSpace::Lab | ColorSpace::Cam16 => 100.0,
+            ColorSpace::Oklab => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_cluster_sums() {
+        // more white pixels than u32 sums of 8-bit channels can hold, at the largest
+        // coordinates of any color space (lightness 100)
+        let huge = 20_000_000;
+        let point = Point([100.0, -50.0, 0.5]);
+        let sum = Point::add_weighted(&Point::<3>::zero(), &point, huge);
+        let sum = Point::merge(&sum, &Point::add(&Point::<3>::zero(), &point));
+        let mean = Point::div(&sum, huge as usize + 1, Rounding::HalfEven);
+        assert_eq!(mean.0, point.0);
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use qtizer::colors::{self, Color};
+use qtizer::median_cut::MedianCut;
+use qtizer::quantizer::{Quantizer, Thumbnail};
+
+/// maximum number of pixels of the thumbnail previews are clustered from
+const PREVIEW_PIXELS: usize = 1 << 12;
+
+/// clustered input, as (input, preview, palette)
+type Clustered = (PathBuf, Vec<Color>, Vec<Color>);
+
+/// palettes of finished inputs of a batch, reused by inputs whose palettes would be
+/// nearly identical, eg. of near-duplicate screenshots (see `--dedupe-palettes`)
+/// - whether palettes are nearly identical is judged by their previews, palettes
+///   clustered from a small thumbnail (see [`preview`])
+pub struct Dedupe {
+    /// largest mean delta-e between previews of inputs that share a palette
+    threshold: f64,
+    /// clustered inputs
+    palettes: Mutex<Vec<Clustered>>,
+    /// inputs that reused a palette, as (input, input whose palette was reused)
+    reused: Mutex<Vec<(PathBuf, PathBuf)>>,
+}
+
+impl Dedupe {
+    pub fn new(threshold: f64) -> Self {
+        Dedupe {
+            threshold,
+            palettes: Mutex::new(Vec::new()),
+            reused: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// the palette of a finished input with a nearly identical preview (of as many colors
+    /// and channels), and that input
+    /// - the input is remembered as reusing it, see [`Dedupe::source`]
+    pub fn find(&self, input: &Path, preview: &[Color]) -> Option<(PathBuf, Vec<Color>)> {
+        let palettes = self.palettes.lock().unwrap_or_else(PoisonError::into_inner);
+        let channels = |colors: &[Color]| colors.first().map(|c| c.data.len());
+        let (source, _, palette) = (palettes.iter())
+            .filter(|(_, other, _)| other.len() == preview.len())
+            .filter(|(_, other, _)| channels(other) == channels(preview))
+            .map(|entry| (entry, distance(preview, &entry.1)))
+            .filter(|&(_, distance)| distance <= self.threshold)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))?
+            .0;
+
+        let mut reused = self.reused.lock().unwrap_or_else(PoisonError::into_inner);
+        reused.push((input.to_path_buf(), source.clone()));
+        Some((source.clone(), palette.clone()))
+    }
+
+    /// remember the palette clustered for an input, for later inputs to reuse
+    pub fn insert(&self, input: &Path, preview: Vec<Color>, palette: &[Color]) {
+        let mut palettes = self.palettes.lock().unwrap_or_else(PoisonError::into_inner);
+        palettes.push((input.to_path_buf(), preview, palette.to_vec()));
+    }
+
+    /// the input whose palette an input reused, if any
+    pub fn source(&self, input: &Path) -> Option<PathBuf> {
+        let reused = self.reused.lock().unwrap_or_else(PoisonError::into_inner);
+        (reused.iter())
+            .find(|(reusing, _)| reusing == input)
+            .map(|(_, source)| source.clone())
+    }
+}
+
+/// a palette of `k` colors cut quickly from a small thumbnail of the pixels of an image
+/// `width` pixels wide, whose colors are close to those of its full palette (`None` if
+/// there are no pixels)
+/// - previews are median cuts, which (unlike k-means) are the same for the same colors,
+///   so similar images get similar previews
+pub fn preview(pixels: &[Color], width: usize, k: usize) -> Option<Vec<Color>> {
+    let thumbnail = Thumbnail::pixels(pixels, width, PREVIEW_PIXELS);
+    let (preview, _) = MedianCut.quantize(&thumbnail, k).ok()?;
+    Some(preview)
+}
+
+/// mean delta-e of the colors of each palette to their nearest color of the other,
+/// whichever is larger
+fn distance(a: &[Color], b: &[Color]) -> f64 {
+    let mean = |from: &[Color], to: &[Color]| {
+        let nearest = |c: &Color| {
+            (to.iter())
+                .map(|other| colors::delta_e(c, other))
+                .fold(f64::INFINITY, f64::min)
+        };
+        from.iter().map(nearest).sum::<f64>() / from.len().max(1) as f64
+    };
+    mean(a, b).max(mean(b, a))
+}
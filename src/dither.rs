@@ -0,0 +1,132 @@
+use crate::colors::Color;
+
+/// side length of the ordered dithering threshold matrix
+const BAYER_SIZE: usize = 8;
+
+/// dithering method for quantized image output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Dither {
+    /// Replace each pixel with its nearest palette color
+    #[default]
+    None,
+    /// Diffuse the quantization error to neighboring pixels
+    FloydSteinberg,
+    /// Offset pixels by a repeating 8x8 bayer threshold pattern
+    Ordered,
+}
+
+/// assign pixels to palette colors with dithering, instead of plain nearest colors
+///
+/// returns assignments, such that for any given `x = assignments[i]`, `pixels[i]` is replaced by `palette[x]`
+pub fn dither(pixels: &[Color], width: u32, palette: &[Color], method: Dither) -> Vec<usize> {
+    match method {
+        Dither::None => crate::colors::assign(pixels, palette),
+        Dither::FloydSteinberg => floyd_steinberg(pixels, width as usize, palette),
+        Dither::Ordered => ordered(pixels, width as usize, palette),
+    }
+}
+
+/// error diffusion, distributing the error of each pixel as
+/// ```text
+///        *   7/16
+/// 3/16 5/16  1/16
+/// ```
+fn floyd_steinberg(pixels: &[Color], width: usize, palette: &[Color]) -> Vec<usize> {
+    let channels = pixels.first().map_or(0, |p| p.data.len());
+    let mut values = pixels
+        .iter()
+        .flat_map(|p| p.data.iter().map(|&v| v as f32))
+        .collect::<Vec<_>>();
+
+    let mut assignments = vec![0; pixels.len()];
+    for i in 0..pixels.len() {
+        let x = i % width;
+        let value = &values[i * channels..(i + 1) * channels];
+        let nearest = nearest(value, palette);
+        assignments[i] = nearest;
+
+        let error = (value.iter().zip(&palette[nearest].data))
+            .map(|(v, &p)| v - p as f32)
+            .collect::<Vec<_>>();
+
+        let neighbors = [
+            (x + 1 < width, i + 1, 7.0),
+            (x > 0, i + width - 1, 3.0),
+            (true, i + width, 5.0),
+            (x + 1 < width, i + width + 1, 1.0),
+        ];
+        for (valid, j, weight) in neighbors {
+            if !valid || j >= pixels.len() {
+                continue;
+            }
+            for (c, e) in error.iter().enumerate() {
+                values[j * channels + c] += e * weight / 16.0;
+            }
+        }
+    }
+
+    assignments
+}
+
+/// ordered dithering, offsetting pixels by a threshold matrix scaled to the palette spacing
+fn ordered(pixels: &[Color], width: usize, palette: &[Color]) -> Vec<usize> {
+    // approximate distance between palette colors, if evenly spread over the rgb cube
+    let spread = u8::MAX as f32 / (palette.len().max(1) as f32).cbrt();
+
+    pixels
+        .iter()
+        .enumerate()
+        .map(|(i, pixel)| {
+            let (x, y) = (i % width % BAYER_SIZE, i / width % BAYER_SIZE);
+            let threshold = (bayer(x, y) as f32 + 0.5) / (BAYER_SIZE * BAYER_SIZE) as f32 - 0.5;
+            let value = (pixel.data.iter())
+                .map(|&v| v as f32 + threshold * spread)
+                .collect::<Vec<_>>();
+            nearest(&value, palette)
+        })
+        .collect()
+}
+
+/// replace partial alpha by a pattern of fully opaque and fully transparent pixels
+/// (screen-door transparency), for formats with on/off transparency like gif
+/// - a pixel stays visible where its alpha exceeds the ordered threshold matrix, so soft
+///   edges keep their look as a share of visible pixels
+/// - transparent pixels become transparent black, so they cluster as a single color
+pub fn screen_door(pixels: &mut [Color], width: usize) {
+    let width = width.max(1);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let Some(alpha) = pixel.alpha() else {
+            continue;
+        };
+        let (x, y) = (i % width % BAYER_SIZE, i / width % BAYER_SIZE);
+        let threshold = (bayer(x, y) as f32 + 0.5) / (BAYER_SIZE * BAYER_SIZE) as f32;
+        match alpha as f32 > threshold * u8::MAX as f32 {
+            true => pixel.data[3] = u8::MAX,
+            false => pixel.data.fill(0),
+        }
+    }
+}
+
+/// entry of the bayer threshold matrix, in `0..BAYER_SIZE^2`
+/// (bit-interleaving of `x ^ y` and `y`, reversed)
+fn bayer(x: usize, y: usize) -> usize {
+    let bits = BAYER_SIZE.trailing_zeros();
+    (0..bits).fold(0, |value, bit| {
+        let (xy, y) = (((x ^ y) >> bit) & 1, (y >> bit) & 1);
+        value | (xy << (2 * (bits - 1 - bit) + 1)) | (y << (2 * (bits - 1 - bit)))
+    })
+}
+
+/// index of the palette color nearest to a (possibly out of range) color value
+fn nearest(value: &[f32], palette: &[Color]) -> usize {
+    palette
+        .iter()
+        .map(|color| {
+            (value.iter().zip(&color.data))
+                .map(|(v, &c)| (v - c as f32).powi(2))
+                .sum::<f32>()
+        })
+        .enumerate()
+        .min_by(|(_, x), (_, y)| x.total_cmp(y))
+        .map_or(0, |(i, _)| i)
+}
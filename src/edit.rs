@@ -0,0 +1,227 @@
+use std::io::{BufRead, Write};
+
+use crate::colors::{self, Color};
+use crate::logger;
+use crate::messages;
+use crate::palette::ColorCodeFormat;
+use crate::term::Capability;
+
+/// palette entry while editing
+struct Entry {
+    color: Color,
+    /// number of pixels assigned to the entry
+    count: usize,
+    locked: bool,
+}
+
+/// interactively edit a palette, reading commands from stdin
+/// - listings and feedback are written to stderr
+/// - returns the edited palette; pixels need to be reassigned afterwards
+pub fn edit(clusters: &[Color], assignments: &[usize], format: &ColorCodeFormat) -> Vec<Color> {
+    let mut counts = vec![0; clusters.len()];
+    for &i in assignments {
+        counts[i] += 1;
+    }
+
+    let mut entries = clusters
+        .iter()
+        .zip(counts)
+        .map(|(color, count)| Entry {
+            color: color.clone(),
+            count,
+            locked: false,
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| colors::luminance(&b.color).total_cmp(&colors::luminance(&a.color)));
+
+    let total = assignments.len().max(1);
+    print_entries(&entries, total, format);
+    eprintln!("{}", messages::text("edit-help", &[]));
+
+    let mut stdin = std::io::stdin().lock();
+    loop {
+        eprint!("edit> ");
+        std::io::stderr().flush().expect("failed to write output");
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line).expect("failed to read input") == 0 {
+            eprintln!();
+            break;
+        }
+
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let result = match words[..] {
+            [] => continue,
+            ["q"] => break,
+            ["h"] => Ok(messages::text("edit-help", &[])),
+            ["d", i] => delete(&mut entries, i),
+            ["m", i, j] => merge(&mut entries, i, j),
+            ["l", i] => toggle_lock(&mut entries, i),
+            ["t", i, ref changes @ ..] if !changes.is_empty() => tweak(&mut entries, i, changes),
+            _ => Err(messages::text("edit-unknown-command", &[])),
+        };
+
+        match result {
+            Ok(feedback) => {
+                print_entries(&entries, total, format);
+                eprintln!("{feedback}");
+            }
+            Err(err) => logger::error("edit-command", err),
+        }
+    }
+
+    entries.into_iter().map(|e| e.color).collect()
+}
+
+/// print the entries with their share of pixels and distance to the nearest other entry
+fn print_entries(entries: &[Entry], total: usize, format: &ColorCodeFormat) {
+    let mut stderr = std::io::stderr().lock();
+
+    for (i, entry) in entries.iter().enumerate() {
+        write!(stderr, "{:>4}  ", i + 1).expect("failed to write output");
+        let preview = Capability::stderr();
+        ColorCodeFormat::pretty_print_color_code(format, &mut stderr, &entry.color, preview)
+            .expect("failed to write output");
+
+        let share = 100.0 * entry.count as f64 / total as f64;
+        write!(stderr, "  {share:>6.2}%").expect("failed to write output");
+
+        let nearest = (entries.iter().enumerate())
+            .filter(|&(j, _)| j != i)
+            .map(|(j, other)| (j, colors::delta_e(&entry.color, &other.color)))
+            .min_by(|(_, x), (_, y)| x.total_cmp(y));
+        if let Some((j, delta_e)) = nearest {
+            write!(stderr, "  ΔE {delta_e:>6.2} to {}", j + 1).expect("failed to write output");
+        }
+
+        if entry.locked {
+            write!(stderr, "  [locked]").expect("failed to write output");
+        }
+        writeln!(stderr).expect("failed to write output");
+    }
+}
+
+/// parse a 1-based entry index
+fn index(entries: &[Entry], i: &str) -> Result<usize, String> {
+    match i.parse::<usize>() {
+        Ok(i @ 1..) if i <= entries.len() => Ok(i - 1),
+        _ => Err(messages::text(
+            "edit-invalid-index",
+            &[("index", &i), ("entries", &entries.len())],
+        )),
+    }
+}
+
+/// parse a 1-based entry index, refusing locked entries
+fn unlocked_index(entries: &[Entry], i: &str) -> Result<usize, String> {
+    let i = index(entries, i)?;
+    match entries[i].locked {
+        true => Err(messages::text("edit-locked", &[("entry", &(i + 1))])),
+        false => Ok(i),
+    }
+}
+
+/// delete an entry
+fn delete(entries: &mut Vec<Entry>, i: &str) -> Result<String, String> {
+    let i = unlocked_index(entries, i)?;
+    if entries.len() == 1 {
+        return Err(messages::text("edit-delete-last", &[]));
+    }
+
+    let entry = entries.remove(i);
+    let code = ColorCodeFormat::Hex.color_code(&entry.color);
+    Ok(messages::text("edit-deleted", &[("color", &code)]))
+}
+
+/// merge entry `j` into entry `i`, using the pixel-count weighted mean color
+fn merge(entries: &mut Vec<Entry>, i: &str, j: &str) -> Result<String, String> {
+    let (i, j) = (unlocked_index(entries, i)?, unlocked_index(entries, j)?);
+    if i == j {
+        return Err(messages::text("edit-merge-itself", &[]));
+    }
+
+    let (a, b) = (&entries[i], &entries[j]);
+    let merged = colors::mix(&a.color, &b.color, a.count as f64, b.count as f64);
+
+    let feedback = messages::text(
+        "edit-merged",
+        &[
+            ("color", &ColorCodeFormat::Hex.color_code(&merged)),
+            (
+                "first",
+                &format!("{:.2}", colors::delta_e(&merged, &a.color)),
+            ),
+            (
+                "second",
+                &format!("{:.2}", colors::delta_e(&merged, &b.color)),
+            ),
+        ],
+    );
+
+    entries[i].count += entries[j].count;
+    entries[i].color = merged;
+    entries.remove(j);
+
+    Ok(feedback)
+}
+
+/// lock or unlock an entry
+fn toggle_lock(entries: &mut [Entry], i: &str) -> Result<String, String> {
+    let i = index(entries, i)?;
+    entries[i].locked = !entries[i].locked;
+
+    Ok(match entries[i].locked {
+        true => messages::text("edit-lock", &[("entry", &(i + 1))]),
+        false => messages::text("edit-unlock", &[("entry", &(i + 1))]),
+    })
+}
+
+/// set an entry to a color, or adjust its channels by offsets
+fn tweak(entries: &mut [Entry], i: &str, changes: &[&str]) -> Result<String, String> {
+    let i = unlocked_index(entries, i)?;
+    let previous = entries[i].color.clone();
+
+    let mut color = match changes {
+        [code] if code.starts_with('#') => {
+            let mut color = code.parse::<Color>()?;
+            // keep the channel layout of the palette
+            color.data.resize(previous.data.len(), 255);
+            color.color_type = previous.color_type;
+            color
+        }
+        _ => previous.clone(),
+    };
+
+    for change in changes.iter().filter(|c| !c.starts_with('#')) {
+        let channel = match change.chars().next() {
+            Some('r') => 0,
+            Some('g') => 1,
+            Some('b') => 2,
+            Some('a') if color.data.len() == 4 => 3,
+            _ => {
+                return Err(messages::text(
+                    "edit-invalid-adjustment",
+                    &[("change", change)],
+                ));
+            }
+        };
+        let offset = (change[1..].parse::<i16>())
+            .map_err(|_| messages::text("edit-invalid-adjustment", &[("change", change)]))?;
+
+        color.data[channel] = (color.data[channel] as i16 + offset).clamp(0, 255) as u8;
+    }
+
+    let feedback = messages::text(
+        "edit-tweaked",
+        &[
+            (
+                "delta",
+                &format!("{:.2}", colors::delta_e(&color, &previous)),
+            ),
+            ("color", &ColorCodeFormat::Hex.color_code(&previous)),
+        ],
+    );
+    entries[i].color = color;
+
+    Ok(feedback)
+}
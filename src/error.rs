@@ -0,0 +1,55 @@
+/// errors returned by the library
+#[derive(Debug)]
+pub enum Error {
+    /// parameters that cannot be used (eg. 0 clusters)
+    InvalidParameter(String),
+    /// images that cannot be quantized (eg. without pixels)
+    InvalidImage(String),
+    /// malformed color codes or palette files
+    Parse(String),
+    /// failure decoding or encoding an image
+    Image(image::ImageError),
+    /// failure reading or writing a file
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidParameter(message) => write!(f, "{message}"),
+            Error::InvalidImage(message) => write!(f, "{message}"),
+            Error::Parse(message) => write!(f, "{message}"),
+            Error::Image(err) => write!(f, "{err}"),
+            Error::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Image(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Error::Image(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// the cli reports errors as messages
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.to_string()
+    }
+}
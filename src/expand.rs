@@ -0,0 +1,119 @@
+use crate::cli::ExpandArgs;
+use crate::colors::{self, Color};
+use crate::logger;
+use crate::messages;
+use crate::palette::{self, Palette};
+use crate::term::Capability;
+
+/// grow the entries of a palette file to k colors, interpolating new colors along ramps
+/// between similar colors
+pub fn expand(args: &ExpandArgs) -> Result<(), String> {
+    let palette = Palette::from_file(&args.palette)?;
+
+    let colors = match palette.colors.len() >= args.number {
+        true => {
+            logger::note(
+                "within-budget",
+                messages::text(
+                    "expand-within-budget",
+                    &[
+                        ("palette", &args.palette.display()),
+                        ("colors", &palette.colors.len()),
+                        ("k", &args.number),
+                    ],
+                ),
+            );
+            palette.colors
+        }
+        false => ramps(&palette.colors, args.number).ok_or_else(|| {
+            messages::text("expand-distinct", &[("palette", &args.palette.display())])
+        })?,
+    };
+
+    let format = args.format.unwrap_or_default();
+    match &args.output {
+        None => {
+            let options = palette::WriteOptions {
+                preview: Capability::stdout(),
+                ..Default::default()
+            };
+            palette::write(&colors, &mut std::io::stdout(), format, &options)
+        }
+        Some(output_file) => {
+            let mut file = std::fs::File::create(output_file)
+                .map_err(|err| messages::text("create-output", &[("error", &err)]))?;
+            palette::write(&colors, &mut file, format, &Default::default())
+        }
+    }
+    .map_err(|err| messages::text("write-palette", &[("error", &err)]))?;
+
+    Ok(())
+}
+
+/// the colors of a palette followed by `k - colors.len()` colors interpolated in oklab
+/// along the ramps between them, or `None` if all colors are the same
+/// - ramps connect each color to its most similar colors (a minimum spanning tree), so
+///   colors are not mixed with unrelated colors across the palette
+/// - each new color splits the ramp with the longest steps between its colors, evenly
+///   spacing the colors of each ramp
+fn ramps(colors: &[Color], k: usize) -> Option<Vec<Color>> {
+    let points = colors.iter().map(colors::to_oklab).collect::<Vec<_>>();
+    let distance = |i: usize, j: usize| {
+        let (a, b) = (points[i], points[j]);
+        (0..3).map(|c| (a[c] - b[c]).powi(2)).sum::<f64>().sqrt()
+    };
+
+    // prim's algorithm, starting from the first color
+    let mut edges = Vec::new();
+    let mut nearest = (0..colors.len())
+        .map(|j| (distance(0, j), 0))
+        .collect::<Vec<_>>();
+    let mut connected = vec![false; colors.len()];
+    connected[0] = true;
+    for _ in 1..colors.len() {
+        let next = (0..colors.len())
+            .filter(|&j| !connected[j])
+            .min_by(|&a, &b| nearest[a].0.total_cmp(&nearest[b].0))?;
+        let (length, from) = nearest[next];
+        connected[next] = true;
+        edges.push((from, next, length));
+        for j in (0..colors.len()).filter(|&j| !connected[j]) {
+            if distance(next, j) < nearest[j].0 {
+                nearest[j] = (distance(next, j), next);
+            }
+        }
+    }
+    if edges.iter().all(|&(_, _, length)| length == 0.0) {
+        return None;
+    }
+
+    // new colors go to the ramp whose steps are longest, one at a time
+    let mut splits = vec![0usize; edges.len()];
+    for _ in colors.len()..k {
+        let step = |e: usize| edges[e].2 / (splits[e] + 1) as f64;
+        let longest =
+            (0..edges.len()).max_by(|&a, &b| step(a).total_cmp(&step(b)).then(b.cmp(&a)))?;
+        splits[longest] += 1;
+    }
+
+    let mut expanded = colors.to_vec();
+    for (&(from, to, _), &count) in edges.iter().zip(&splits) {
+        for i in 1..=count {
+            let t = i as f64 / (count + 1) as f64;
+            expanded.push(mix(&colors[from], &colors[to], points[from], points[to], t));
+        }
+    }
+    Some(expanded)
+}
+
+/// the color `t` of the way from `a` to `b` in oklab (and in alpha, if either has alpha)
+fn mix(a: &Color, b: &Color, from: [f64; 3], to: [f64; 3], t: f64) -> Color {
+    let mut color = colors::from_oklab(std::array::from_fn(|c| from[c] + (to[c] - from[c]) * t));
+    if a.alpha().is_some() || b.alpha().is_some() {
+        let alpha = |c: &Color| c.alpha().unwrap_or(u8::MAX) as f64;
+        let mixed = alpha(a) + (alpha(b) - alpha(a)) * t;
+        color.data.push(mixed.round() as u8);
+        color.color_type = image::ColorType::Rgba8;
+    }
+    color
+}
@@ -0,0 +1,154 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::messages;
+use crate::{cli, warnings};
+
+/// first field of the status record written to stdout after each job
+const STATUS: &str = "qtizer-job";
+
+/// run jobs read from a named pipe, until interrupted (see `--listen-fifo`)
+/// - each line is a job, with the arguments of a run (without the binary name)
+/// - empty lines and lines starting with `#` are ignored
+/// - the pipe is reopened whenever its writers close it
+/// - failing jobs are reported in their status record, and do not stop the server
+pub fn listen(path: &Path) -> Result<(), String> {
+    check(path)?;
+    let name = path.display();
+
+    loop {
+        // blocks until a writer opens the pipe
+        let file = std::fs::File::open(path).map_err(|err| open_error(&name, err))?;
+
+        for line in BufReader::new(file).lines() {
+            let line = line
+                .map_err(|err| messages::text("fifo-read", &[("fifo", &name), ("error", &err)]))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let status = match job(line) {
+                Ok(true) => "ok".to_string(),
+                Ok(false) => format!("error {}", messages::text("fifo-inputs-failed", &[])),
+                Err(err) => format!("error {}", err.replace('\n', " ")),
+            };
+
+            let mut stdout = std::io::stdout().lock();
+            writeln!(stdout, "{STATUS} {status}")
+                .and_then(|_| stdout.flush())
+                .map_err(|err| messages::text("fifo-status", &[("error", &err)]))?;
+        }
+    }
+}
+
+/// parse, validate and run a single job, returning whether all its inputs succeeded
+fn job(line: &str) -> Result<bool, String> {
+    let words = split(line)?;
+    let args =
+        cli::parse_from(std::iter::once("qtizer".to_string()).chain(words)).map_err(message)?;
+    cli::validate(&args).map_err(message)?;
+
+    let unsupported = match () {
+        _ if args.command.is_some() => Some(messages::text("job-subcommands", &[])),
+        _ if args.version => Some("`--version`".to_string()),
+        _ if args.dry_run => Some("`--dry-run`".to_string()),
+        _ if args.listen_fifo.is_some() => Some("`--listen-fifo`".to_string()),
+        _ if args.edit => Some("`--edit`".to_string()),
+        _ if args.script.is_some() => Some("`--script`".to_string()),
+        _ if args.inputs().iter().any(|p| p == cli::STDIO) => {
+            Some(messages::text("job-stdin", &[]))
+        }
+        _ => None,
+    };
+    if let Some(unsupported) = unsupported {
+        return Err(messages::text(
+            "job-unsupported",
+            &[("unsupported", &unsupported)],
+        ));
+    }
+
+    warnings::check_args(&args);
+    crate::run(&args, args.seed.unwrap_or_else(crate::random_seed))
+        .map_err(|failure| failure.message)
+}
+
+/// error message of a clap error, without usage and help hints
+fn message(err: clap::Error) -> String {
+    let message = err.to_string();
+    let message = message.split("\n\n").next().unwrap_or_default();
+    let message = message.strip_prefix("error: ").unwrap_or(message);
+    message.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// split a job into arguments at whitespace, like a shell
+/// - single quotes keep everything up to the next single quote
+/// - double quotes and backslashes escape as in `sh` (without expansions)
+fn split(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let quoted = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => quoted.push(c),
+                        None => return Err(messages::text("job-single-quote", &[])),
+                    }
+                }
+            }
+            '"' => {
+                let quoted = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => quoted.push(c),
+                            Some(c) => quoted.extend(['\\', c]),
+                            None => return Err(messages::text("job-double-quote", &[])),
+                        },
+                        Some(c) => quoted.push(c),
+                        None => return Err(messages::text("job-double-quote", &[])),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped =
+                    (chars.next()).ok_or_else(|| messages::text("job-trailing-backslash", &[]))?;
+                word.get_or_insert_default().push(escaped);
+            }
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+/// check that a path is a named pipe, since regular files would be read in an endless loop
+fn check(path: &Path) -> Result<(), String> {
+    let name = path.display();
+    let metadata = std::fs::metadata(path).map_err(|err| open_error(&name, err))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if !metadata.file_type().is_fifo() {
+            return Err(messages::text("not-fifo", &[("fifo", &name)]));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+
+    Ok(())
+}
+
+/// error opening the named pipe
+fn open_error(name: &impl std::fmt::Display, err: std::io::Error) -> String {
+    messages::text("fifo-open", &[("fifo", name), ("error", &err)])
+}
@@ -0,0 +1,92 @@
+use image::*;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{FindArgs, FindMode};
+use crate::histogram::Histogram;
+use crate::io;
+use crate::messages;
+use crate::palette::Palette;
+
+/// max dimension of the thumbnails that images are scored on
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// rank the images in a directory by how strongly they contain the searched colors
+pub fn find(args: &FindArgs) {
+    let mut files = Vec::new();
+    collect_images(Path::new(&args.dir), args.recursive, &mut files);
+
+    let mut results = files
+        .into_iter()
+        .filter_map(|path| match io::decode(&path, None) {
+            Ok(decoded) => Some((score(&decoded.image, args), path)),
+            Err(err) => {
+                eprintln!(
+                    "{}",
+                    messages::text("skipping", &[("path", &path.display()), ("error", &err)])
+                );
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // best matches first
+    results.sort_by(|(x, _), (y, _)| y.total_cmp(x));
+
+    for (score, path) in results.iter().take(args.limit.unwrap_or(usize::MAX)) {
+        println!("{:>6.2}%  {}", score * 100.0, path.display());
+    }
+}
+
+/// score an image according to the ranking mode, in `[0, 1]`
+/// - `contain`: mean share of pixels close to each searched color
+/// - `match`: signature similarity of the image's colors to the searched palette
+fn score(img: &DynamicImage, args: &FindArgs) -> f64 {
+    let histogram = match img.width().max(img.height()) {
+        ..=THUMBNAIL_SIZE => Histogram::from_image(img),
+        _ => Histogram::from_image(&img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)),
+    };
+
+    match args.mode {
+        FindMode::Contain => {
+            args.colors
+                .iter()
+                .map(|color| histogram.coverage(color, args.tolerance))
+                .sum::<f64>()
+                / args.colors.len() as f64
+        }
+        FindMode::Match => {
+            let query = Palette::new(args.colors.clone()).signature();
+            let image = Palette::from_histogram(&histogram).signature();
+            1.0 - query.distance(&image)
+        }
+    }
+}
+
+/// collect paths with image file extensions in a directory
+fn collect_images(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!(
+                "{}",
+                messages::text("skipping", &[("path", &dir.display()), ("error", &err)])
+            );
+            return;
+        }
+    };
+
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            if recursive {
+                collect_images(&path, recursive, files);
+            }
+        } else if ImageFormat::from_path(&path).is_ok() {
+            files.push(path);
+        }
+    }
+}
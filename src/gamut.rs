@@ -0,0 +1,178 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::colors::{self, Color};
+use crate::messages;
+
+/// chroma below which colors count as gray, whose hue does not matter
+const ACHROMATIC: f64 = 4.0;
+
+/// hue angles around each color of a palette that [`Gamut::from_palette`] includes
+const HUE_SPREAD: f64 = 15.0;
+
+/// region of CIE L*C*h* colors that k-means clusters are kept within, see
+/// [`Context::with_gamut`](crate::kmeans::Context::with_gamut)
+/// - eg. pastel colors only (high lightness, low chroma), or only brand hues
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gamut {
+    /// range of lightness L*, within `[0, 100]`
+    pub lightness: (f64, f64),
+    /// range of chroma C*, from `0` (gray)
+    pub chroma: (f64, f64),
+    /// ranges of hue angles h in degrees, each from its first angle counterclockwise to
+    /// its second, wrapping through 0 (eg. `(330, 30)` for reds)
+    /// - empty for all hues
+    /// - grays (chroma below 4) are within any hue range
+    pub hues: Vec<(f64, f64)>,
+}
+
+impl Default for Gamut {
+    /// all colors
+    fn default() -> Self {
+        Gamut {
+            lightness: (0.0, 100.0),
+            chroma: (0.0, f64::INFINITY),
+            hues: Vec::new(),
+        }
+    }
+}
+
+impl Gamut {
+    /// the region spanned by the colors of a palette
+    /// - lightness between the darkest and lightest color
+    /// - chroma up to the most colorful color
+    /// - hues within 15 degrees of the hue of any color that is not gray
+    pub fn from_palette(palette: &[Color]) -> Self {
+        let lch = palette.iter().map(lch).collect::<Vec<_>>();
+        let (lightness, chroma) = lch.iter().fold(
+            ((f64::INFINITY, f64::NEG_INFINITY), 0.0f64),
+            |((min, max), chroma), &[l, c, _]| ((min.min(l), max.max(l)), chroma.max(c)),
+        );
+        let hues = (lch.iter())
+            .filter(|&&[_, c, _]| c >= ACHROMATIC)
+            .map(|&[_, _, h]| {
+                let angle = |h: f64| h.rem_euclid(360.0);
+                (angle(h - HUE_SPREAD), angle(h + HUE_SPREAD))
+            })
+            .collect();
+
+        match palette.is_empty() {
+            true => Gamut::default(),
+            false => Gamut {
+                lightness,
+                chroma: (0.0, chroma),
+                hues,
+            },
+        }
+    }
+
+    /// whether a color lies within the gamut (alpha is ignored)
+    pub fn contains(&self, color: &Color) -> bool {
+        let [l, c, h] = lch(color);
+        let within = |v: f64, (min, max): (f64, f64)| min <= v && v <= max;
+        within(l, self.lightness) && within(c, self.chroma) && self.contains_hue(h, c)
+    }
+
+    /// the color moved into the gamut, or the color itself if it already is within
+    /// - lightness and chroma are clamped to their ranges, and hues outside of all hue
+    ///   ranges move to the nearest end of one
+    /// - colors outside of rgb are clipped, so they may end up slightly outside of the
+    ///   gamut (eg. for colorful ranges at extreme lightness)
+    pub fn snap(&self, color: &Color) -> Color {
+        if self.contains(color) {
+            return color.clone();
+        }
+
+        let [l, c, h] = lch(color);
+        let l = l.clamp(self.lightness.0, self.lightness.1.max(self.lightness.0));
+        let c = c.clamp(self.chroma.0, self.chroma.1.max(self.chroma.0));
+        let h = match self.contains_hue(h, c) {
+            true => h,
+            false => self.nearest_hue(h),
+        };
+
+        let (sin, cos) = h.to_radians().sin_cos();
+        let mut snapped = colors::from_lab([l, c * cos, c * sin]);
+        if let Some(alpha) = color.alpha() {
+            snapped.data.push(alpha);
+            snapped.color_type = image::ColorType::Rgba8;
+        }
+        snapped
+    }
+
+    /// whether a hue lies within any hue range (grays always do)
+    fn contains_hue(&self, h: f64, c: f64) -> bool {
+        let within = |&(from, to): &(f64, f64)| match from <= to {
+            true => from <= h && h <= to,
+            false => h >= from || h <= to,
+        };
+        self.hues.is_empty() || c < ACHROMATIC || self.hues.iter().any(within)
+    }
+
+    /// the end of a hue range with the smallest angle to a hue
+    fn nearest_hue(&self, h: f64) -> f64 {
+        let angle = |a: f64| {
+            let d = (a - h).rem_euclid(360.0);
+            d.min(360.0 - d)
+        };
+        (self.hues.iter())
+            .flat_map(|&(from, to)| [from, to])
+            .min_by(|&a, &b| angle(a).total_cmp(&angle(b)))
+            .unwrap_or(h)
+    }
+}
+
+impl FromStr for Gamut {
+    type Err = String;
+
+    /// parse a mask of comma-separated ranges of lightness (`l`), chroma (`c`) and hue
+    /// (`h`, repeatable), eg. `l:70-100,c:0-40` or `h:330-30,h:200-240`
+    /// - channels without a range are not constrained
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || messages::text("invalid-gamut", &[("gamut", &s)]);
+        let mut gamut = Gamut::default();
+        for term in s.split(',') {
+            let (channel, range) = term.trim().split_once(':').ok_or_else(invalid)?;
+            let (min, max) = range.split_once('-').ok_or_else(invalid)?;
+            let parse = |v: &str| v.trim().parse::<f64>().ok().filter(|v| v.is_finite());
+            let (min, max) = (
+                parse(min).ok_or_else(invalid)?,
+                parse(max).ok_or_else(invalid)?,
+            );
+            match channel.trim() {
+                "h" if min > 360.0 || max > 360.0 => {
+                    return Err(messages::text("gamut-hue-range", &[("gamut", &s)]));
+                }
+                "h" => gamut.hues.push((min, max)),
+                _ if min > max => {
+                    return Err(messages::text(
+                        "empty-gamut-range",
+                        &[("range", &term.trim()), ("gamut", &s)],
+                    ));
+                }
+                "l" => gamut.lightness = (min, max),
+                "c" => gamut.chroma = (min, max),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(gamut)
+    }
+}
+
+impl fmt::Display for Gamut {
+    /// the ranges of the gamut, in the format parsed by [`Gamut::from_str`]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ranges = vec![format!("l:{:.0}-{:.0}", self.lightness.0, self.lightness.1)];
+        if self.chroma.1.is_finite() {
+            ranges.push(format!("c:{:.0}-{:.0}", self.chroma.0, self.chroma.1));
+        }
+        ranges.extend((self.hues.iter()).map(|(from, to)| format!("h:{from:.0}-{to:.0}")));
+        write!(f, "{}", ranges.join(","))
+    }
+}
+
+/// lightness, chroma and hue angle in degrees of a color in CIE L*C*h*
+fn lch(color: &Color) -> [f64; 3] {
+    let [l, a, b] = colors::to_lab(color);
+    [l, a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.0)]
+}
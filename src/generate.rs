@@ -0,0 +1,438 @@
+use clap::{Arg, Command, CommandFactory};
+use std::path::Path;
+
+use crate::cli::{self, Args, CompletionsArgs, ManpageArgs, Shell};
+use crate::messages;
+
+/// write shell completions to stdout, or into a directory using the shell's file naming
+pub fn completions(args: &CompletionsArgs) {
+    let cmd = command();
+    let (script, file_name) = match args.shell {
+        Shell::Bash => (bash(&cmd), cmd.get_name().to_string()),
+        Shell::Zsh => (zsh(&cmd), format!("_{}", cmd.get_name())),
+        Shell::Fish => (fish(&cmd), format!("{}.fish", cmd.get_name())),
+    };
+
+    write_output(args.dir.as_deref(), &[(file_name, script)]);
+}
+
+/// write the manpage to stdout, or the manpages of all commands into a directory
+pub fn manpage(args: &ManpageArgs) {
+    let cmd = command();
+
+    match &args.dir {
+        None => write_output(None, &[(String::new(), roff(&cmd, None))]),
+        Some(dir) => {
+            let mut pages = vec![(format!("{}.1", cmd.get_name()), roff(&cmd, None))];
+            for sub in visible_subcommands(&cmd) {
+                let name = format!("{}-{}.1", cmd.get_name(), sub.get_name());
+                pages.push((name, roff(sub, Some(&cmd))));
+            }
+            write_output(Some(dir), &pages);
+        }
+    }
+}
+
+/// fully built command definition (including generated `help`/`version` args)
+fn command() -> Command {
+    let mut cmd = Args::command();
+    cmd.build();
+    cmd
+}
+
+/// write (file name, content) pairs into a directory, or the content to stdout
+fn write_output(dir: Option<&Path>, files: &[(String, String)]) {
+    let Some(dir) = dir else {
+        for (_, content) in files {
+            print!("{content}");
+        }
+        return;
+    };
+
+    let result = std::fs::create_dir_all(dir).and_then(|_| {
+        files.iter().try_for_each(|(name, content)| {
+            let path = dir.join(name);
+            std::fs::write(&path, content)?;
+            eprintln!("{}", messages::text("wrote", &[("path", &path.display())]));
+            Ok(())
+        })
+    });
+
+    if let Err(err) = result {
+        cli::err_exit(
+            clap::error::ErrorKind::Io,
+            messages::text("write-generated", &[("error", &err)]),
+        );
+    }
+}
+
+/// non-hidden subcommands
+fn visible_subcommands(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands().filter(|sub| !sub.is_hide_set())
+}
+
+/// non-hidden, non-positional arguments
+fn options(cmd: &Command) -> impl Iterator<Item = &Arg> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+}
+
+/// whether an argument expects a value
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+/// names of the possible values of an argument
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+/// first line of the help text of an argument
+fn short_help(arg: &Arg) -> String {
+    let help = arg.get_help().map(|h| h.to_string()).unwrap_or_default();
+    help.lines().next().unwrap_or_default().to_string()
+}
+
+/// all flag spellings of an argument, eg. `-f`, `--format`
+fn flags(arg: &Arg) -> Vec<String> {
+    let short = arg.get_short().map(|s| format!("-{s}"));
+    let long = arg.get_long().map(|l| format!("--{l}"));
+    short.into_iter().chain(long).collect()
+}
+
+/// bash completion script
+fn bash(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let subcommands = visible_subcommands(cmd)
+        .map(|sub| sub.get_name())
+        .collect::<Vec<_>>();
+
+    let mut cases = bash_case(name, cmd, &subcommands);
+    for sub in visible_subcommands(cmd) {
+        cases += &bash_case(sub.get_name(), sub, &[]);
+    }
+
+    format!(
+        r#"_{name}() {{
+    local cur prev cmd
+    cur="${{COMP_WORDS[COMP_CWORD]}}"
+    prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+
+    cmd="{name}"
+    if [[ $COMP_CWORD -gt 1 ]]; then
+        case "${{COMP_WORDS[1]}}" in
+            {}) cmd="${{COMP_WORDS[1]}}" ;;
+        esac
+    fi
+
+    case "$cmd" in
+{cases}    esac
+}}
+
+complete -o filenames -o bashdefault -F _{name} {name}
+"#,
+        match subcommands.is_empty() {
+            true => "\"\"".to_string(),
+            false => subcommands.join("|"),
+        }
+    )
+}
+
+/// bash `case` branch completing a single command
+fn bash_case(name: &str, cmd: &Command, subcommands: &[&str]) -> String {
+    let mut value_cases = String::new();
+    for arg in options(cmd).filter(|arg| takes_value(arg)) {
+        let values = possible_values(arg);
+        let completion = match values.is_empty() {
+            true => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+            false => format!(
+                "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                values.join(" ")
+            ),
+        };
+        value_cases += &format!(
+            "                {})\n                    {completion}\n                    return\n                    ;;\n",
+            flags(arg).join("|")
+        );
+    }
+
+    let all_flags = options(cmd).flat_map(flags).collect::<Vec<_>>().join(" ");
+
+    format!(
+        r#"        {name})
+            case "$prev" in
+{value_cases}            esac
+
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=($(compgen -W "{all_flags}" -- "$cur"))
+            else
+                COMPREPLY=($(compgen -W "{}" -- "$cur") $(compgen -f -- "$cur"))
+            fi
+            ;;
+"#,
+        subcommands.join(" ")
+    )
+}
+
+/// zsh completion script
+fn zsh(cmd: &Command) -> String {
+    let name = cmd.get_name();
+
+    let subcommands = visible_subcommands(cmd)
+        .map(|sub| {
+            let about = sub.get_about().map(|a| a.to_string()).unwrap_or_default();
+            format!("'{}:{}'", sub.get_name(), zsh_escape(&about))
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut cases = String::new();
+    for sub in visible_subcommands(cmd) {
+        cases += &format!(
+            "        {})\n            words=(${{words[2,-1]}})\n            (( CURRENT-- ))\n            _arguments -s \\\n{}                '*:file:_files'\n            ;;\n",
+            sub.get_name(),
+            zsh_specs(sub)
+        );
+    }
+
+    format!(
+        r#"#compdef {name}
+
+_{name}() {{
+    local -a subcommands
+    subcommands=({subcommands})
+
+    case $words[2] in
+{cases}        *)
+            _arguments -s \
+{}                '1: :{{_describe command subcommands; _files}}' \
+                '*:file:_files'
+            ;;
+    esac
+}}
+
+_{name} "$@"
+"#,
+        zsh_specs(cmd)
+    )
+}
+
+/// zsh `_arguments` specs for the options of a command
+fn zsh_specs(cmd: &Command) -> String {
+    let mut specs = String::new();
+
+    for arg in options(cmd) {
+        let flags = flags(arg);
+        let help = zsh_escape(&short_help(arg));
+        let value = match (takes_value(arg), possible_values(arg)) {
+            (false, _) => String::new(),
+            (true, values) if values.is_empty() => format!(":{}:_files", value_name(arg)),
+            (true, values) => format!(":{}:({})", value_name(arg), values.join(" ")),
+        };
+
+        let spec = match &flags[..] {
+            [flag] => format!("'{flag}[{help}]{value}'"),
+            _ => format!(
+                "'({})'{{{}}}'[{help}]{value}'",
+                flags.join(" "),
+                flags.join(",")
+            ),
+        };
+        specs += &format!("                {spec} \\\n");
+    }
+
+    specs
+}
+
+/// escape text for use inside single-quoted zsh specs
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+/// fish completion script
+fn fish(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let subcommands = visible_subcommands(cmd)
+        .map(|sub| sub.get_name())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut script = String::new();
+    let root_condition = format!("not __fish_seen_subcommand_from {subcommands}");
+
+    for sub in visible_subcommands(cmd) {
+        let about = sub.get_about().map(|a| a.to_string()).unwrap_or_default();
+        script += &format!(
+            "complete -c {name} -n '__fish_use_subcommand' -f -a {} -d '{}'\n",
+            sub.get_name(),
+            fish_escape(&about)
+        );
+    }
+
+    script += &fish_options(name, cmd, &root_condition);
+    for sub in visible_subcommands(cmd) {
+        let condition = format!("__fish_seen_subcommand_from {}", sub.get_name());
+        script += &fish_options(name, sub, &condition);
+    }
+
+    script
+}
+
+/// fish `complete` lines for the options of a command
+fn fish_options(name: &str, cmd: &Command, condition: &str) -> String {
+    let mut lines = String::new();
+
+    for arg in options(cmd) {
+        let mut line = format!("complete -c {name} -n '{condition}'");
+        if let Some(short) = arg.get_short() {
+            line += &format!(" -s {short}");
+        }
+        if let Some(long) = arg.get_long() {
+            line += &format!(" -l {long}");
+        }
+
+        let values = possible_values(arg);
+        match (takes_value(arg), values.is_empty()) {
+            (false, _) => {}
+            (true, true) => line += " -r -F",
+            (true, false) => line += &format!(" -x -a '{}'", values.join(" ")),
+        }
+
+        line += &format!(" -d '{}'\n", fish_escape(&short_help(arg)));
+        lines += &line;
+    }
+
+    lines
+}
+
+/// escape text for use inside single-quoted fish strings
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+/// value name of an argument, eg. `count`
+fn value_name(arg: &Arg) -> String {
+    (arg.get_value_names())
+        .and_then(|names| names.first())
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| arg.get_id().to_string())
+}
+
+/// roff manpage of a command (`parent` is given for subcommands)
+fn roff(cmd: &Command, parent: Option<&Command>) -> String {
+    let root = parent.unwrap_or(cmd);
+    let title = match parent {
+        Some(parent) => format!("{}-{}", parent.get_name(), cmd.get_name()),
+        None => cmd.get_name().to_string(),
+    };
+    let version = root.get_version().unwrap_or_default();
+    let about = cmd.get_about().map(|a| a.to_string()).unwrap_or_default();
+
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{} {version}\"\n",
+        title.to_uppercase(),
+        root.get_name()
+    );
+    page += &format!(".SH NAME\n{} \\- {}\n", title, roff_escape(&about));
+
+    page += ".SH SYNOPSIS\n";
+    let usage = cmd.clone().render_usage().to_string();
+    let usage = usage.trim_start_matches("Usage:").trim();
+    for line in usage.lines() {
+        let line = line.trim();
+        let line = match parent {
+            Some(parent) if !line.starts_with(parent.get_name()) => {
+                format!("{} {line}", parent.get_name())
+            }
+            _ => line.to_string(),
+        };
+        page += &format!("{}\n.br\n", roff_escape(&line));
+    }
+
+    let positionals = cmd.get_positionals().filter(|arg| !arg.is_hide_set());
+    let mut positionals = positionals.peekable();
+    if positionals.peek().is_some() {
+        page += ".SH ARGUMENTS\n";
+        for arg in positionals {
+            page += &format!(".TP\n\\fI{}\\fR\n", roff_escape(&value_name(arg)));
+            page += &roff_help(arg);
+        }
+    }
+
+    page += ".SH OPTIONS\n";
+    for arg in options(cmd) {
+        let flags = flags(arg)
+            .iter()
+            .map(|flag| format!("\\fB{}\\fR", roff_escape(flag)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let value = match takes_value(arg) {
+            true => format!(" \\fI{}\\fR", roff_escape(&value_name(arg))),
+            false => String::new(),
+        };
+        page += &format!(".TP\n{flags}{value}\n");
+        page += &roff_help(arg);
+    }
+
+    let mut subcommands = visible_subcommands(cmd).peekable();
+    if subcommands.peek().is_some() {
+        page += ".SH COMMANDS\n";
+        for sub in subcommands {
+            let about = sub.get_about().map(|a| a.to_string()).unwrap_or_default();
+            page += &format!(
+                ".TP\n\\fB{}\\fR\n{}\n",
+                roff_escape(sub.get_name()),
+                roff_escape(&about)
+            );
+        }
+    }
+
+    if let Some(author) = root.get_author().filter(|a| !a.is_empty()) {
+        page += &format!(".SH AUTHORS\n{}\n", roff_escape(author));
+    }
+
+    page
+}
+
+/// roff paragraph with the full help text of an argument, including possible values
+fn roff_help(arg: &Arg) -> String {
+    let help = arg.get_help().map(|h| h.to_string()).unwrap_or_default();
+    let mut text = help
+        .lines()
+        .map(roff_escape)
+        .collect::<Vec<_>>()
+        .join("\n.br\n");
+
+    let values = possible_values(arg);
+    if !values.is_empty() {
+        text += &format!("\n.br\n[possible values: {}]", values.join(", "));
+    }
+    if let Some(default) = arg
+        .get_default_values()
+        .first()
+        .filter(|_| takes_value(arg))
+    {
+        text += &format!(
+            "\n.br\n[default: {}]",
+            roff_escape(&default.to_string_lossy())
+        );
+    }
+
+    text + "\n"
+}
+
+/// escape text for roff, including control characters at line starts
+fn roff_escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    match text.starts_with(['.', '\'']) {
+        true => format!("\\&{text}"),
+        false => text,
+    }
+}
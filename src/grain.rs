@@ -0,0 +1,55 @@
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+use crate::colors::Color;
+
+/// side length of the repeating noise tile
+const TILE_SIZE: usize = 64;
+
+/// add seeded luminance grain to pixels, masking banding in large flat regions
+/// - each pixel is brightened or darkened by up to `amount` rgb levels (alpha is kept)
+/// - the noise is blue-ish (high-pass filtered white noise), so it has no visible clumps
+///   at low amounts, and the same seed gives the same grain
+pub fn grain(pixels: &mut [Color], width: usize, amount: f64, seed: u64) {
+    let tile = noise_tile(seed);
+    let width = width.max(1);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let (x, y) = (i % width % TILE_SIZE, i / width % TILE_SIZE);
+        let offset = tile[y * TILE_SIZE + x] * 2.0 * amount;
+        for channel in pixel.data.iter_mut().take(3) {
+            *channel = (*channel as f64 + offset)
+                .round()
+                .clamp(0.0, u8::MAX as f64) as u8;
+        }
+    }
+}
+
+/// tile of noise values, uniformly distributed in `[-0.5, 0.5]`
+/// - white noise minus the mean of its (wrapping) 3x3 neighborhood, which removes low
+///   frequencies, then ranked so values are uniform again
+fn noise_tile(seed: u64) -> Vec<f64> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let white = (0..TILE_SIZE * TILE_SIZE)
+        .map(|_| rng.random::<f64>())
+        .collect::<Vec<_>>();
+
+    let wrap = |v: usize, d: isize| (v as isize + d).rem_euclid(TILE_SIZE as isize) as usize;
+    let filtered = (0..TILE_SIZE * TILE_SIZE)
+        .map(|i| {
+            let (x, y) = (i % TILE_SIZE, i / TILE_SIZE);
+            let mean = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                .map(|(dx, dy)| white[wrap(y, dy) * TILE_SIZE + wrap(x, dx)])
+                .sum::<f64>()
+                / 9.0;
+            white[i] - mean
+        })
+        .collect::<Vec<_>>();
+
+    let mut order = (0..filtered.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| filtered[a].total_cmp(&filtered[b]));
+    let mut tile = vec![0.0; filtered.len()];
+    for (rank, &i) in order.iter().enumerate() {
+        tile[i] = (rank as f64 + 0.5) / filtered.len() as f64 - 0.5;
+    }
+    tile
+}
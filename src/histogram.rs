@@ -0,0 +1,168 @@
+use image::*;
+use std::collections::HashMap;
+
+use crate::colors::Color;
+use crate::kmeans::Kmeansable;
+
+/// number of low bits dropped per channel when binning
+const SHIFT: u32 = 3;
+
+/// number of bins per channel
+const LEVELS: usize = 256 >> SHIFT;
+
+/// number of weighted points above which colors are merged into coarser buckets
+const MAX_POINTS: usize = 1 << 16;
+
+/// coarse rgb histogram of an image, used for fast color statistics
+pub struct Histogram {
+    bins: Vec<u32>,
+    total: u64,
+}
+
+impl Histogram {
+    /// build a histogram from the rgb values of all pixels of an image
+    pub fn from_image(img: &DynamicImage) -> Self {
+        let mut bins = vec![0; LEVELS * LEVELS * LEVELS];
+        let mut total = 0;
+
+        for Rgb([r, g, b]) in img.to_rgb8().pixels() {
+            bins[Self::index(*r, *g, *b)] += 1;
+            total += 1;
+        }
+
+        Histogram { bins, total }
+    }
+
+    /// iterate over non-empty bins as (bin center color, pixel count)
+    pub fn iter(&self) -> impl Iterator<Item = (Color, u32)> + '_ {
+        self.bins
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(i, &count)| (Self::center(i), count))
+    }
+
+    /// fraction of pixels whose bin lies within `radius` of `color`
+    pub fn coverage(&self, color: &Color, radius: f64) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+
+        // distances are squared (see `Color::distance`)
+        let matching = self
+            .iter()
+            .filter(|(c, _)| c.distance(color) <= radius * radius)
+            .map(|(_, count)| count as u64)
+            .sum::<u64>();
+
+        matching as f64 / self.total as f64
+    }
+
+    /// bin index of an rgb value
+    fn index(r: u8, g: u8, b: u8) -> usize {
+        let (r, g, b) = (r >> SHIFT, g >> SHIFT, b >> SHIFT);
+        (r as usize * LEVELS + g as usize) * LEVELS + b as usize
+    }
+
+    /// center color of the bin at an index
+    fn center(index: usize) -> Color {
+        let half = 1 << (SHIFT - 1);
+        let channel = |v: usize| ((v % LEVELS) << SHIFT) as u8 + half;
+
+        Color {
+            color_type: ColorType::Rgb8,
+            data: vec![
+                channel(index / (LEVELS * LEVELS)),
+                channel(index / LEVELS),
+                channel(index),
+            ],
+        }
+    }
+}
+
+/// collapse pixels into weighted points, so clustering visits each color once
+/// - identical colors become one point, weighted by their number of pixels
+/// - while there are more than `MAX_POINTS` points, low bits of every channel are
+///   dropped (up to `SHIFT` bits), merging each bucket into its weighted mean
+/// - at least `min` points are kept if there are that many distinct colors
+///
+/// returns (points, weights), sorted by color for deterministic clustering
+pub fn weighted(pixels: &[Color], min: usize) -> (Vec<Color>, Vec<u32>) {
+    collapse(pixels, |_| 1, min)
+}
+
+/// [`weighted`], with `pixels[i]` counting `weights[i]` times (at least once), eg. to
+/// emphasize pixels of fine detail (see [`quantizer::Detail`](crate::quantizer::Detail))
+pub fn weighted_by(pixels: &[Color], weights: &[u32], min: usize) -> (Vec<Color>, Vec<u32>) {
+    collapse(
+        pixels,
+        |i| weights.get(i).map_or(1, |&w| w.max(1) as u64),
+        min,
+    )
+}
+
+/// [`weighted`], with `pixels[i]` counting `weight(i)` times
+fn collapse<W>(pixels: &[Color], weight: W, min: usize) -> (Vec<Color>, Vec<u32>)
+where
+    W: Fn(usize) -> u64,
+{
+    let Some(first) = pixels.first() else {
+        return (Vec::new(), Vec::new());
+    };
+
+    // distinct colors with their (per channel sums, pixel count)
+    let mut buckets = HashMap::<[u8; 4], ([u64; 4], u64)>::new();
+    for (i, pixel) in pixels.iter().enumerate() {
+        let (key, weight) = (key(pixel), weight(i));
+        let (sums, count) = buckets.entry(key).or_default();
+        for (sum, value) in sums.iter_mut().zip(key) {
+            *sum += value as u64 * weight;
+        }
+        *count += weight;
+    }
+
+    for shift in 1..=SHIFT {
+        if buckets.len() <= MAX_POINTS {
+            break;
+        }
+
+        let mut coarser = HashMap::<[u8; 4], ([u64; 4], u64)>::new();
+        for (key, (sums, count)) in &buckets {
+            let (total, n) = coarser.entry(key.map(|v| v >> shift)).or_default();
+            for (total, sum) in total.iter_mut().zip(sums) {
+                *total += sum;
+            }
+            *n += count;
+        }
+
+        if coarser.len() < min {
+            break;
+        }
+        buckets = coarser;
+    }
+
+    let channels = first.data.len().min(4);
+    let mut points = (buckets.into_values())
+        .map(|(sums, count)| {
+            let mean = (sums[..channels].iter())
+                .map(|sum| ((sum + count / 2) / count) as u8)
+                .collect::<Vec<_>>();
+            (mean, count.min(u32::MAX as u64) as u32)
+        })
+        .collect::<Vec<_>>();
+    points.sort_unstable();
+
+    points
+        .into_iter()
+        .map(|(data, weight)| {
+            let color_type = first.color_type;
+            (Color { color_type, data }, weight)
+        })
+        .unzip()
+}
+
+/// channels of a color as a fixed size key (missing channels are 0)
+fn key(color: &Color) -> [u8; 4] {
+    let channel = |i: usize| color.data.get(i).copied().unwrap_or(0);
+    [channel(0), channel(1), channel(2), channel(3)]
+}
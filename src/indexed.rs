@@ -0,0 +1,179 @@
+use image::{ColorType, ImageFormat};
+use std::io::Write;
+
+use crate::colors::Color;
+use crate::io;
+use crate::messages;
+
+/// whether a quantized image with `colors` palette colors can be written as an
+/// indexed image (with a palette chunk and one index per pixel) in a format
+pub fn supported(format: ImageFormat, colors: usize) -> bool {
+    matches!(format, ImageFormat::Png | ImageFormat::Gif) && (1..=256).contains(&colors)
+}
+
+/// prepend `count` reserved slots to the palette of an indexed image, which no pixel is
+/// assigned to, see `--reserve`
+/// - slots have the `reserved` colors in order, and black after them
+/// - reserved colors with alpha (eg. a transparent slot) add alpha to the palette
+/// - assignments are shifted past the reserved slots
+pub fn reserve(
+    clusters: &[Color],
+    assignments: &[usize],
+    count: usize,
+    reserved: &[Color],
+) -> (Vec<Color>, Vec<usize>) {
+    let alpha = (clusters.iter().take(1).chain(reserved)).any(|c| c.alpha().is_some());
+    let black = Color {
+        color_type: ColorType::Rgb8,
+        data: vec![0; 3],
+    };
+    let slots = (reserved.iter().chain(std::iter::repeat(&black)))
+        .take(count)
+        .map(|c| c.with_alpha(alpha));
+    let palette = slots
+        .chain(clusters.iter().map(|c| c.with_alpha(alpha)))
+        .collect();
+    let assignments = assignments.iter().map(|&i| i + count).collect();
+    (palette, assignments)
+}
+
+/// encode an indexed png or gif image, where pixel `i` has color `clusters[assignments[i]]`
+/// - palette entries keep the order of `clusters`, and are never sorted or dropped, so
+///   images encoded with the same clusters share their indices
+/// - png images embed the icc color profile of the colors, if given
+/// - png indices are packed into 1, 2, 4 or 8 bits, depending on the number of colors
+/// - png alpha is kept per palette color (`tRNS` chunk)
+/// - gif only supports one fully transparent color: the most transparent palette color,
+///   if its alpha is below half
+#[cfg_attr(not(any(feature = "png", feature = "gif")), allow(unused_variables))]
+pub fn encode<W>(
+    writer: W,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    clusters: &[Color],
+    assignments: &[usize],
+    icc_profile: Option<&[u8]>,
+) -> Result<(), String>
+where
+    W: Write,
+{
+    if !supported(format, clusters.len()) {
+        return Err(io::Error::NotIndexable(format, clusters.len()).to_string());
+    }
+
+    let palette = clusters.iter().flat_map(Color::rgb).collect::<Vec<_>>();
+    let indices = assignments.iter().map(|&i| i as u8).collect::<Vec<_>>();
+
+    match format {
+        #[cfg(feature = "png")]
+        ImageFormat::Png => png(
+            writer,
+            (width, height),
+            clusters,
+            palette,
+            &indices,
+            icc_profile,
+        ),
+        #[cfg(feature = "gif")]
+        ImageFormat::Gif => gif(writer, width, height, clusters, &palette, indices),
+        format => Err(io::Error::MissingCodec(format).to_string()),
+    }
+}
+
+/// encode an indexed png with the smallest bit depth fitting the palette
+#[cfg(feature = "png")]
+fn png<W>(
+    writer: W,
+    (width, height): (u32, u32),
+    clusters: &[Color],
+    palette: Vec<u8>,
+    indices: &[u8],
+    icc_profile: Option<&[u8]>,
+) -> Result<(), String>
+where
+    W: Write,
+{
+    let bits = match clusters.len() {
+        0..=2 => png::BitDepth::One,
+        3..=4 => png::BitDepth::Two,
+        5..=16 => png::BitDepth::Four,
+        _ => png::BitDepth::Eight,
+    };
+
+    let error = |err: png::EncodingError| messages::text("encode-indexed-png", &[("error", &err)]);
+    let mut info = png::Info::with_size(width, height);
+    info.icc_profile = icc_profile.map(Into::into);
+    let mut encoder = png::Encoder::with_info(writer, info).map_err(error)?;
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(bits);
+    encoder.set_palette(palette);
+
+    // alpha of palette colors, up to the last translucent one
+    let alpha = clusters
+        .iter()
+        .map(|c| c.alpha().unwrap_or(u8::MAX))
+        .collect::<Vec<_>>();
+    if let Some(last) = alpha.iter().rposition(|&a| a < u8::MAX) {
+        encoder.set_trns(alpha[..=last].to_vec());
+    }
+
+    // rows start on byte boundaries, with indices packed from the most significant bit
+    let bits = bits as usize;
+    let row_bytes = (width as usize * bits).div_ceil(8);
+    let mut data = vec![0u8; row_bytes * height as usize];
+    if width > 0 {
+        for (row, indices) in data
+            .chunks_mut(row_bytes)
+            .zip(indices.chunks(width as usize))
+        {
+            for (x, &index) in indices.iter().enumerate() {
+                let offset = x * bits;
+                row[offset / 8] |= index << (8 - bits - offset % 8);
+            }
+        }
+    }
+
+    let mut writer = encoder.write_header().map_err(error)?;
+    writer.write_image_data(&data).map_err(error)?;
+    writer.finish().map_err(error)
+}
+
+/// encode a single-frame gif with a global palette
+#[cfg(feature = "gif")]
+fn gif<W>(
+    writer: W,
+    width: u32,
+    height: u32,
+    clusters: &[Color],
+    palette: &[u8],
+    indices: Vec<u8>,
+) -> Result<(), String>
+where
+    W: Write,
+{
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(messages::text("gif-size", &[("max", &u16::MAX)]));
+    };
+
+    let transparent = transparent_index(clusters);
+    let error = |err: gif::EncodingError| messages::text("encode-indexed-gif", &[("error", &err)]);
+    let mut encoder = gif::Encoder::new(writer, width, height, palette).map_err(error)?;
+    let frame = gif::Frame::from_indexed_pixels(width, height, indices, transparent);
+    encoder.write_frame(&frame).map_err(error)?;
+    encoder
+        .into_inner()
+        .map(drop)
+        .map_err(|err| messages::text("encode-indexed-gif", &[("error", &err)]))
+}
+
+/// index of the palette color that gif images make fully transparent: the most
+/// transparent color, if its alpha is below half (gif only supports one)
+#[cfg(feature = "gif")]
+pub fn transparent_index(clusters: &[Color]) -> Option<u8> {
+    (0..clusters.len())
+        .map(|i| (i, clusters[i].alpha().unwrap_or(u8::MAX)))
+        .min_by_key(|&(_, alpha)| alpha)
+        .filter(|&(_, alpha)| alpha < 128)
+        .map(|(i, _)| i as u8)
+}
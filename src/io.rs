@@ -0,0 +1,601 @@
+#[cfg(feature = "jpeg")]
+use image::codecs::jpeg::JpegEncoder;
+#[cfg(feature = "png")]
+use image::codecs::png::PngEncoder;
+#[cfg(feature = "webp")]
+use image::codecs::webp::WebPEncoder;
+use image::metadata::Orientation;
+use image::*;
+use std::io::{BufRead, Cursor, Read, Seek, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::cli;
+use crate::indexed;
+use crate::logger;
+use qtizer::colors::Color;
+use qtizer::{messages, palette, timing};
+
+/// side length of the squares of palette swatch images, in pixels
+const SWATCH_SIZE: u32 = 32;
+
+/// errors reading and decoding input images, or encoding and writing quantized images
+#[derive(Debug)]
+pub enum Error {
+    /// failure reading an input image
+    Read(std::io::Error),
+    /// malformed or unsupported input image
+    Decode(ImageError),
+    /// input image without pixels
+    Empty,
+    /// output path without a (writable) image extension
+    UnsupportedOutput(String),
+    /// image format whose codec this build was compiled without
+    MissingCodec(ImageFormat),
+    /// input image of a format whose codec only encodes (eg. avif, without a decoder)
+    EncodeOnly(ImageFormat),
+    /// colors with alpha written to a format without alpha channel
+    NoAlpha(ImageFormat),
+    /// palette that cannot be written as an indexed image, with its number of colors
+    NotIndexable(ImageFormat, usize),
+    /// quantized image that cannot be created (eg. from an empty palette), with the key
+    /// of its message, see [`messages::text`]
+    Quantize(&'static str),
+    /// failure encoding a quantized image
+    Encode(ImageError),
+    /// failure encoding an indexed image
+    EncodeIndexed(String),
+    /// failure writing an output image
+    Write(std::io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format_name = |format: &ImageFormat| format!("{format:?}");
+        let message = match self {
+            Error::Read(err) => messages::text("open-image", &[("error", err)]),
+            Error::Decode(err) => messages::text("decode-image", &[("error", err)]),
+            Error::Empty => messages::text("no-pixels", &[]),
+            Error::UnsupportedOutput(path) => {
+                messages::text("unsupported-extension", &[("path", path)])
+            }
+            Error::MissingCodec(format) => qtizer::missing_feature(
+                &messages::text(
+                    "image-format",
+                    &[("format", &format_name(format).to_lowercase())],
+                ),
+                codec_feature(*format),
+            ),
+            Error::EncodeOnly(format) => messages::text(
+                "encode-only",
+                &[("format", &format_name(format).to_lowercase())],
+            ),
+            Error::NoAlpha(format) => {
+                messages::text("no-alpha", &[("format", &format_name(format))])
+            }
+            Error::NotIndexable(format, colors) => messages::text(
+                "not-indexable",
+                &[("colors", colors), ("format", &format_name(format))],
+            ),
+            Error::Quantize(key) => messages::text(key, &[]),
+            // errors here are unexpected, since the alpha capability of output formats is
+            // validated before clustering
+            Error::Encode(err) => messages::text("encode-image", &[("error", err)]),
+            Error::EncodeIndexed(message) => message.clone(),
+            Error::Write(err) => messages::text("write-image", &[("error", err)]),
+        };
+        write!(f, "{message}")
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Read(err) | Error::Write(err) => Some(err),
+            Error::Decode(err) | Error::Encode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// the cli reports errors as messages
+impl Error {
+    /// code of the error in json diagnostics, see [`logger::Failure`]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Read(_) => "read",
+            Error::Decode(_) | Error::Empty | Error::EncodeOnly(_) => "decode",
+            Error::MissingCodec(_) => "missing-feature",
+            _ => "encode",
+        }
+    }
+}
+
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.to_string()
+    }
+}
+
+/// a decoded input image
+pub struct Decoded {
+    /// pixels, with the orientation of the input applied (eg. of photos taken upright)
+    pub image: DynamicImage,
+    /// format of the input, if detected or given
+    pub format: Option<ImageFormat>,
+    /// embedded icc color profile, which quantized colors of the image are still in
+    pub icc_profile: Option<Vec<u8>>,
+    /// (decoded rows, height) of an image whose decoding failed part of the way, of which
+    /// `image` holds only the rows decoded before (see [`decode_partial`])
+    pub truncated: Option<(u32, u32)>,
+}
+
+impl Decoded {
+    /// whether any pixel is not fully opaque
+    /// (a fully opaque alpha channel carries no information, so it is not clustered)
+    pub fn has_alpha(&self) -> bool {
+        !qtizer::is_opaque(&self.image)
+    }
+}
+
+/// open an image file, or stdin for `-`, for decoding
+/// - the format is detected from the contents, unless given (eg. by `--input-format`)
+pub fn reader(
+    input: impl AsRef<Path>,
+    format: Option<ImageFormat>,
+) -> Result<ImageReader<Cursor<Vec<u8>>>, Error> {
+    let bytes = match input.as_ref() {
+        path if path == Path::new(cli::STDIO) => {
+            let mut bytes = Vec::new();
+            std::io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+        }
+        path => std::fs::read(path),
+    };
+    let bytes = bytes.map_err(Error::Read)?;
+
+    match format {
+        Some(format) => Ok(ImageReader::with_format(Cursor::new(bytes), format)),
+        None => (ImageReader::new(Cursor::new(bytes)).with_guessed_format()).map_err(Error::Read),
+    }
+}
+
+/// decode an image file, or stdin for `-` (see [`reader`])
+pub fn decode(input: impl AsRef<Path>, format: Option<ImageFormat>) -> Result<Decoded, Error> {
+    decode_reader(reader(input, format)?)
+}
+
+/// decode an image file like [`decode`], but keep the rows decoded before an error, eg. of
+/// truncated or partially corrupt files (see `--allow-partial`)
+/// - fails like [`decode`] if no row could be decoded
+pub fn decode_partial(
+    input: impl AsRef<Path>,
+    format: Option<ImageFormat>,
+) -> Result<Decoded, Error> {
+    let reader = reader(input, format)?;
+    let Some(format) = reader.format() else {
+        return decode_reader(reader);
+    };
+
+    let bytes = reader.into_inner().into_inner();
+    match decode_reader(ImageReader::with_format(Cursor::new(&bytes), format)) {
+        Err(Error::Decode(err)) => {
+            timing::measure("decode", || partial(&bytes, format)).ok_or(Error::Decode(err))
+        }
+        decoded => decoded,
+    }
+}
+
+/// decode an input of the main command, see [`decode_partial`] for `--allow-partial`
+/// - truncated inputs are warned about, with the region that was decoded
+pub fn decode_input(args: &cli::Args, input: &Path) -> Result<Decoded, Error> {
+    if !args.allow_partial {
+        return decode(input, args.input_format);
+    }
+
+    let decoded = decode_partial(input, args.input_format)?;
+    if let Some((rows, height)) = decoded.truncated {
+        let region = format!("{}x{}", decoded.image.width(), decoded.image.height());
+        let message = messages::text(
+            "partial-image",
+            &[
+                ("input", &input.display()),
+                ("rows", &rows),
+                ("height", &height),
+                ("region", &region),
+            ],
+        );
+        logger::warn("partial-image", message);
+    }
+    Ok(decoded)
+}
+
+/// the leading rows of an image that a decoder wrote before failing
+/// - images are read twice into buffers of different bytes, rows that differ between
+///   both were never written
+fn partial(bytes: &[u8], format: ImageFormat) -> Option<Decoded> {
+    let read = |fill: u8| {
+        let mut decoder = (ImageReader::with_format(Cursor::new(bytes), format))
+            .into_decoder()
+            .ok()?;
+        let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+        let icc_profile = decoder.icc_profile().ok().flatten();
+        let ((width, height), color_type) = (decoder.dimensions(), decoder.color_type());
+        let mut buffer = vec![fill; usize::try_from(decoder.total_bytes()).ok()?];
+        // the error is expected, the rows before it are kept
+        let _ = decoder.read_image(&mut buffer);
+        Some((buffer, width, height, color_type, orientation, icc_profile))
+    };
+    let (mut buffer, width, height, color_type, orientation, icc_profile) = read(0)?;
+    let (written, ..) = read(u8::MAX)?;
+
+    let row = width as usize * color_type.bytes_per_pixel() as usize;
+    let rows = (buffer.chunks_exact(row.max(1)))
+        .zip(written.chunks_exact(row.max(1)))
+        .take_while(|(a, b)| a == b)
+        .count();
+    if rows == 0 || width == 0 {
+        return None;
+    }
+
+    buffer.truncate(rows * row);
+    // png decoders only swap 16-bit samples to native endianness after decoding succeeded
+    let big_endian = format == ImageFormat::Png;
+    let mut image = from_raw(width, rows as u32, color_type, buffer, big_endian)?;
+    image.apply_orientation(orientation);
+    Some(Decoded {
+        image,
+        format: Some(format),
+        icc_profile,
+        truncated: Some((rows as u32, height)),
+    })
+}
+
+/// an image of the bytes of its pixels, as written by decoders (in native endianness,
+/// unless `big_endian`)
+fn from_raw(
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    bytes: Vec<u8>,
+    big_endian: bool,
+) -> Option<DynamicImage> {
+    let words = || {
+        (bytes.chunks_exact(2))
+            .map(|b| match big_endian {
+                true => u16::from_be_bytes([b[0], b[1]]),
+                false => u16::from_ne_bytes([b[0], b[1]]),
+            })
+            .collect::<Vec<_>>()
+    };
+    let floats = || {
+        (bytes.chunks_exact(4))
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>()
+    };
+    match color_type {
+        ColorType::L8 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLuma8),
+        ColorType::La8 => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLumaA8)
+        }
+        ColorType::Rgb8 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8),
+        ColorType::Rgba8 => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8)
+        }
+        ColorType::L16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageLuma16)
+        }
+        ColorType::La16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageLumaA16)
+        }
+        ColorType::Rgb16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageRgb16)
+        }
+        ColorType::Rgba16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageRgba16)
+        }
+        ColorType::Rgb32F => {
+            ImageBuffer::from_raw(width, height, floats()).map(DynamicImage::ImageRgb32F)
+        }
+        ColorType::Rgba32F => {
+            ImageBuffer::from_raw(width, height, floats()).map(DynamicImage::ImageRgba32F)
+        }
+        _ => None,
+    }
+}
+
+/// decode an image in memory, detecting its format from the contents
+#[cfg(feature = "net")]
+pub fn decode_bytes(bytes: &[u8]) -> Result<Decoded, Error> {
+    let reader =
+        (ImageReader::new(Cursor::new(bytes)).with_guessed_format()).map_err(Error::Read)?;
+    decode_reader(reader)
+}
+
+/// decode an image with its metadata
+/// - unreadable orientations and color profiles are ignored, rather than failing the input
+fn decode_reader<R>(reader: ImageReader<R>) -> Result<Decoded, Error>
+where
+    R: BufRead + Seek,
+{
+    let format = reader.format();
+    if let Some(format) = format.filter(|&f| !compiled(f)) {
+        return Err(Error::MissingCodec(format));
+    }
+    if let Some(format) = format.filter(|&f| !decodable(f)) {
+        return Err(Error::EncodeOnly(format));
+    }
+    let decoded = timing::measure("decode", || {
+        let mut decoder = reader.into_decoder()?;
+        let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+        let icc_profile = decoder.icc_profile().ok().flatten();
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        Ok((image, icc_profile))
+    });
+    let (image, icc_profile) = decoded.map_err(Error::Decode)?;
+
+    if image.width() == 0 || image.height() == 0 {
+        return Err(Error::Empty);
+    }
+
+    Ok(Decoded {
+        image,
+        format,
+        icc_profile,
+        truncated: None,
+    })
+}
+
+/// image format written to an output path, or `None` if it receives a palette
+/// - a given `format` (eg. by `--output-format`) overrides the extension of the path
+/// - `-` writes an image to stdout, in the input format if it can be encoded (otherwise png)
+pub fn output_format(
+    output: &Path,
+    format: Option<ImageFormat>,
+    input_format: Option<ImageFormat>,
+) -> Option<ImageFormat> {
+    match output {
+        _ if format.is_some() => format,
+        output if cli::is_stdio(output) => Some(
+            input_format
+                .filter(|f| f.writing_enabled())
+                .unwrap_or(ImageFormat::Png),
+        ),
+        path => ImageFormat::from_path(path).ok(),
+    }
+}
+
+/// the cargo feature of the codec of an image format, see `Cargo.toml`
+pub fn codec_feature(format: ImageFormat) -> &'static str {
+    use ImageFormat::*;
+    match format {
+        Avif => "avif",
+        Bmp => "bmp",
+        Dds => "dds",
+        OpenExr => "exr",
+        Farbfeld => "ff",
+        Gif => "gif",
+        Hdr => "hdr",
+        Ico => "ico",
+        Jpeg => "jpeg",
+        Png => "png",
+        Pnm => "pnm",
+        Qoi => "qoi",
+        Tga => "tga",
+        Tiff => "tiff",
+        WebP => "webp",
+        _ => "full",
+    }
+}
+
+/// whether the codec of an image format is compiled into this build, see
+/// [`codec_feature`]
+/// - some codecs only decode (eg. dds) or encode (eg. avif)
+pub fn compiled(format: ImageFormat) -> bool {
+    format.reading_enabled() || format.writing_enabled()
+}
+
+/// whether images of a compiled format can be read
+/// - avif is only encoded, decoding needs the `avif-native` feature of `image` (and dav1d),
+///   even though [`ImageFormat::reading_enabled`] reports it as readable
+pub fn decodable(format: ImageFormat) -> bool {
+    format.reading_enabled() && format != ImageFormat::Avif
+}
+
+/// whether an image format can store an alpha channel
+pub fn supports_alpha(format: ImageFormat) -> bool {
+    use ImageFormat::*;
+    !matches!(format, Jpeg | Bmp | Pnm | Tiff)
+}
+
+/// whether an image format can embed an icc color profile (see [`EncodeOptions`])
+pub fn supports_icc(format: ImageFormat) -> bool {
+    use ImageFormat::*;
+    matches!(format, Png | Jpeg | WebP)
+}
+
+/// options of [`encode`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EncodeOptions<'a> {
+    /// format of the image, overriding the extension of the output
+    pub format: Option<ImageFormat>,
+    /// format of the input, which `-` writes to stdout (see [`output_format`])
+    pub input_format: Option<ImageFormat>,
+    /// whether png and gif images are written indexed, by default whenever the palette
+    /// fits (see [`indexed::supported`])
+    pub indexed: Option<bool>,
+    /// icc color profile of the colors, embedded in png, jpeg and webp images
+    pub icc_profile: Option<&'a [u8]>,
+}
+
+/// encode a quantized image, where pixel `i` has color `clusters[assignments[i]]`, and
+/// write it to a file (or stdout, see [`output_format`])
+pub fn encode(
+    output: &Path,
+    width: u32,
+    height: u32,
+    clusters: &[Color],
+    assignments: &[usize],
+    options: &EncodeOptions,
+) -> Result<(), Error> {
+    let format = output_format(output, options.format, options.input_format)
+        .ok_or_else(|| Error::UnsupportedOutput(output.display().to_string()))?;
+
+    let indexed = match options.indexed {
+        Some(true) if !indexed::supported(format, clusters.len()) => {
+            return Err(Error::NotIndexable(format, clusters.len()));
+        }
+        Some(indexed) => indexed,
+        None => indexed::supported(format, clusters.len()),
+    };
+
+    if indexed {
+        let start = Instant::now();
+        let mut bytes = Vec::new();
+        let icc_profile = options.icc_profile;
+        indexed::encode(
+            &mut bytes,
+            format,
+            width,
+            height,
+            clusters,
+            assignments,
+            icc_profile,
+        )
+        .map_err(Error::EncodeIndexed)?;
+        let status = write(output, &bytes);
+        timing::record("encode", start.elapsed());
+        return status;
+    }
+
+    // create new image by replacing each pixel with its cluster center
+    let quantized = timing::measure("remap", || {
+        assignments
+            .iter()
+            .flat_map(|&i| &clusters[i].data)
+            .copied()
+            .collect::<Vec<_>>()
+    });
+
+    let start = Instant::now();
+    let img = image(width, height, clusters.first(), quantized)?;
+    let status = encode_image(output, &img, format, options.icc_profile);
+    timing::record("encode", start.elapsed());
+    status
+}
+
+/// encode a full color image of pixels (eg. with `--grain`, which adds colors beyond the
+/// palette) and write it to a file (or stdout, see [`output_format`])
+/// - `options.indexed` does not apply
+pub fn encode_pixels(
+    output: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+    options: &EncodeOptions,
+) -> Result<(), Error> {
+    let format = output_format(output, options.format, options.input_format)
+        .ok_or_else(|| Error::UnsupportedOutput(output.display().to_string()))?;
+
+    let start = Instant::now();
+    let data = pixels.iter().flat_map(|c| &c.data).copied().collect();
+    let img = image(width, height, pixels.first(), data)?;
+    let status = encode_image(output, &img, format, options.icc_profile);
+    timing::record("encode", start.elapsed());
+    status
+}
+
+/// image of raw pixel data, with the color type of its first color
+fn image(
+    width: u32,
+    height: u32,
+    first: Option<&Color>,
+    data: Vec<u8>,
+) -> Result<DynamicImage, Error> {
+    match first {
+        Some(c) if c.color_type == ColorType::Rgba8 => {
+            let img = ImageBuffer::from_vec(width, height, data);
+            Ok(DynamicImage::ImageRgba8(
+                img.ok_or(Error::Quantize("create-quantized-image"))?,
+            ))
+        }
+        Some(c) if c.color_type == ColorType::Rgb8 => {
+            let img = ImageBuffer::from_vec(width, height, data);
+            Ok(DynamicImage::ImageRgb8(
+                img.ok_or(Error::Quantize("create-quantized-image"))?,
+            ))
+        }
+        _ => Err(Error::Quantize("empty-palette-image")),
+    }
+}
+
+/// encode a full color image in a format, and write it to a file (or stdout for `-`)
+/// - the icc color profile is embedded in png, jpeg and webp images
+pub fn encode_image(
+    output: &Path,
+    img: &DynamicImage,
+    format: ImageFormat,
+    icc_profile: Option<&[u8]>,
+) -> Result<(), Error> {
+    if !compiled(format) {
+        return Err(Error::MissingCodec(format));
+    }
+
+    // encoders may need to seek, so images are encoded to memory first
+    let mut buffer = Cursor::new(Vec::new());
+    let encoded = match (format, icc_profile) {
+        #[cfg(feature = "png")]
+        (ImageFormat::Png, Some(icc)) => {
+            img.write_with_encoder(with_icc(PngEncoder::new(&mut buffer), icc))
+        }
+        #[cfg(feature = "jpeg")]
+        (ImageFormat::Jpeg, Some(icc)) => {
+            img.write_with_encoder(with_icc(JpegEncoder::new(&mut buffer), icc))
+        }
+        #[cfg(feature = "webp")]
+        (ImageFormat::WebP, Some(icc)) => {
+            img.write_with_encoder(with_icc(WebPEncoder::new_lossless(&mut buffer), icc))
+        }
+        _ => img.write_to(&mut buffer, format),
+    };
+    encoded.map_err(Error::Encode)?;
+
+    write(output, buffer.get_ref())
+}
+
+/// encode a palette as a swatch image: a row of squares of `SWATCH_SIZE` pixels, sorted by
+/// brightness, see [`encode`]
+pub fn encode_swatch(
+    output: &Path,
+    colors: &[Color],
+    options: &EncodeOptions,
+) -> Result<(), Error> {
+    let mut sorted = colors.to_vec();
+    palette::sort_by_luminance(&mut sorted);
+
+    let (width, height) = (SWATCH_SIZE * sorted.len() as u32, SWATCH_SIZE);
+    let assignments = (0..width * height)
+        .map(|i| (i % width / SWATCH_SIZE) as usize)
+        .collect::<Vec<_>>();
+    encode(output, width, height, &sorted, &assignments, options)
+}
+
+/// embed an icc color profile in the images of an encoder
+#[cfg(any(feature = "jpeg", feature = "png", feature = "webp"))]
+fn with_icc<E: ImageEncoder>(mut encoder: E, icc_profile: &[u8]) -> E {
+    // only called for encoders supporting icc profiles
+    let _ = encoder.set_icc_profile(icc_profile.to_vec());
+    encoder
+}
+
+/// write an encoded image to a file, or stdout for `-`
+fn write(output: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let status = match output {
+        output if cli::is_stdio(output) => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(bytes).and_then(|_| stdout.flush())
+        }
+        path => std::fs::write(path, bytes),
+    };
+    status.map_err(Error::Write)
+}
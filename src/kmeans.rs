@@ -1,9 +1,18 @@
-use rand::{SeedableRng, rngs::SmallRng, seq::IndexedRandom};
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+use rayon::prelude::*;
+use std::time::Instant;
+
+use crate::Error;
+use crate::colorspace::Parameters;
+use crate::gamut::Gamut;
+use crate::messages;
+use crate::term::{self, Capability};
+use crate::timing;
 
 /// trait for types that can be clustered using k-means
 pub trait Kmeansable {
     /// output type for summation during mean calculation
-    type Sum: Clone + std::fmt::Debug;
+    type Sum: Clone + std::fmt::Debug + Send;
 
     /// initial value for sum
     fn zero() -> Self::Sum;
@@ -14,13 +23,141 @@ pub trait Kmeansable {
     /// summation for mean calculation
     fn add(sum: &Self::Sum, other: &Self) -> Self::Sum;
 
-    /// division for mean calculation
-    fn div(sum: &Self::Sum, count: usize) -> Self;
+    /// summation for weighted mean calculation, adding `other` `weight` times
+    fn add_weighted(sum: &Self::Sum, other: &Self, weight: u32) -> Self::Sum;
+
+    /// combination of partial sums, for parallel mean calculation
+    fn merge(sum: &Self::Sum, other: &Self::Sum) -> Self::Sum;
+
+    /// division for mean calculation, with integer results rounded as given
+    fn div(sum: &Self::Sum, count: usize, rounding: Rounding) -> Self;
+}
+
+/// rounding of cluster means to integer values (eg. rgb channels)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Rounding {
+    /// round to the nearest value, ties to even (unbiased)
+    #[default]
+    HalfEven,
+    /// round towards zero (biased towards darker colors)
+    Truncate,
 }
 
-// TODO: look for speedups before parallelizing
+impl Rounding {
+    /// round a value to an integer
+    pub fn round(self, value: f64) -> f64 {
+        match self {
+            Rounding::HalfEven => value.round_ties_even(),
+            Rounding::Truncate => value.trunc(),
+        }
+    }
+
+    /// divide an integer sum by a count (of at least 1)
+    pub fn div(self, sum: u64, count: u64) -> u64 {
+        let count = count.max(1);
+        let (quotient, remainder) = (sum / count, sum % count);
+        match self {
+            Rounding::Truncate => quotient,
+            Rounding::HalfEven => match (2 * remainder).cmp(&count) {
+                std::cmp::Ordering::Less => quotient,
+                std::cmp::Ordering::Equal => quotient + (quotient & 1),
+                std::cmp::Ordering::Greater => quotient + 1,
+            },
+        }
+    }
+}
+
+/// assign each point to its nearest cluster (in parallel)
+///
+/// returns assignments, such that for any given `x = assignments[i]`, `data[i]` is closest to `clusters[x]`
+pub fn assign<T>(data: &[T], clusters: &[T]) -> Vec<usize>
+where
+    T: Kmeansable + Sync,
+{
+    if clusters.is_empty() {
+        return vec![0; data.len()];
+    }
+
+    let mut distances = Vec::new();
+    cluster_distances(clusters, &mut distances);
+    data.par_iter()
+        .map(|point| nearest(point, clusters, &distances))
+        .collect()
+}
+
+/// iteration budget for clustering `points` points with `unique` distinct values into `k` clusters
+/// - more iterations the more distinct values each cluster has to settle between
+/// - a few more iterations for large inputs, where clusters move in smaller steps
+/// - clustering stops early once converged, so generous budgets are cheap
+pub fn auto_iterations(points: usize, unique: usize, k: usize) -> usize {
+    let per_cluster = (unique as f64 / k.max(1) as f64).max(1.0);
+    let size = (points as f64 / (1 << 16) as f64).max(1.0);
+    (4.0 + 2.0 * per_cluster.log2() + size.log2()).ceil() as usize
+}
+
+/// number of points assigned between progress updates
+const PROGRESS_INTERVAL: usize = 1 << 16;
+
+/// number of partial sums per iteration, per worker thread
+/// - more parts than threads balance uneven chunks
+const PARTS_PER_THREAD: usize = 4;
+
+/// distances between clusters, to skip some distance calculations in [`nearest`]
+/// - `distances[i * k + j]` is only set for `i < j` -- note: `dist[i][j] == dist[j][i]`
+/// - the buffer is reused, so repeated calls do not allocate
+fn cluster_distances<T>(clusters: &[T], distances: &mut Vec<f64>)
+where
+    T: Kmeansable,
+{
+    let k = clusters.len();
+    distances.clear();
+    distances.resize(k * k, 0.0);
+    for i in 0..k {
+        for j in (i + 1)..k {
+            distances[i * k + j] = clusters[i].distance(&clusters[j]);
+        }
+    }
+}
+
+/// nearest cluster of a point, using precomputed distances between clusters
+/// (see [`cluster_distances`]) to skip distance calculations
+fn nearest<T>(point: &T, clusters: &[T], cluster_distances: &[f64]) -> usize
+where
+    T: Kmeansable,
+{
+    let mut closest_idx = 0;
+    let mut closest_dist = clusters[0].distance(point);
+
+    for (j, cluster) in clusters.iter().enumerate().skip(1) {
+        // skip distance calculation if the cluster is too far away
+        // (distances are squared, so the factor of 2 is squared too)
+        let (a, b) = (closest_idx.min(j), closest_idx.max(j));
+        if cluster_distances[a * clusters.len() + b] >= 4.0 * closest_dist {
+            // d(c_j, c_min) >= 2 * d(p, c_min)
+            // d(p,   c_j  ) >=     d(p, c_min)
+            continue;
+        }
+
+        let dist = cluster.distance(point);
+        if dist < closest_dist {
+            closest_dist = dist;
+            closest_idx = j;
+        }
+    }
+
+    closest_idx
+}
+
+// TODO: look for further speedups
 //       - k-d tree for nearest neighbor search?
-//       - triangle inequality to skip distance calculations?
+
+/// initialization of the clusters of [`Context::cluster`]
+pub(crate) enum Start<T> {
+    /// `k` clusters seeded by k-means++
+    Seeded(usize),
+    /// the given clusters
+    From(Vec<T>),
+}
 
 /// context for k-means clustering, containing an rng to initialize clusters
 pub struct Context<R = SmallRng>
@@ -28,113 +165,428 @@ where
     R: rand::Rng,
 {
     rng: R,
+    /// whether to print progress to stderr
+    progress: bool,
+    /// cluster movement below which iterations stop early
+    tolerance: f64,
+    /// rounding of cluster means
+    rounding: Rounding,
+    /// number of iterations performed by the last clustering
+    iterations: usize,
+    /// region that clusters of colors are kept within
+    gamut: Option<Gamut>,
+    /// parameters of the color space that colors are clustered in
+    parameters: Parameters,
 }
 
 impl Context<SmallRng> {
     /// k-means clustering for pixel data
     ///
     /// returns (clusters, assignments), such that for any given `x = assignments[i]`, `data[i]` belongs to `clusters[x]`
-    pub fn k_means<T>(&mut self, data: &[T], k: usize, iterations: usize) -> (Vec<T>, Vec<usize>)
+    ///
+    /// fails for `k = 0`, `iterations = 0`, empty data and `k` larger than the number of points
+    pub fn k_means<T>(
+        &mut self,
+        data: &[T],
+        k: usize,
+        iterations: usize,
+    ) -> Result<(Vec<T>, Vec<usize>), Error>
+    where
+        T: Kmeansable + Clone + Sync,
+    {
+        self.k_means_with(data, k, iterations, |_, _| {})
+    }
+
+    /// k-means clustering, calling `on_iteration(i, clusters)` after each iteration `i`
+    ///
+    /// see [`Context::k_means`]
+    pub fn k_means_with<T, F>(
+        &mut self,
+        data: &[T],
+        k: usize,
+        iterations: usize,
+        on_iteration: F,
+    ) -> Result<(Vec<T>, Vec<usize>), Error>
+    where
+        T: Kmeansable + Clone + Sync,
+        F: FnMut(usize, &[T]),
+    {
+        let start = Start::Seeded(k);
+        self.cluster(data, None, start, iterations, |_| {}, on_iteration)
+    }
+
+    /// k-means clustering of weighted points, where `data[i]` counts `weights[i]` times
+    /// towards the mean of its cluster
+    ///
+    /// see [`Context::k_means`]
+    pub fn k_means_weighted<T>(
+        &mut self,
+        data: &[T],
+        weights: &[u32],
+        k: usize,
+        iterations: usize,
+    ) -> Result<(Vec<T>, Vec<usize>), Error>
+    where
+        T: Kmeansable + Clone + Sync,
+    {
+        self.k_means_weighted_with(data, weights, k, iterations, |_, _| {})
+    }
+
+    /// weighted k-means clustering, calling `on_iteration(i, clusters)` after each iteration `i`
+    ///
+    /// see [`Context::k_means_weighted`]
+    pub fn k_means_weighted_with<T, F>(
+        &mut self,
+        data: &[T],
+        weights: &[u32],
+        k: usize,
+        iterations: usize,
+        on_iteration: F,
+    ) -> Result<(Vec<T>, Vec<usize>), Error>
+    where
+        T: Kmeansable + Clone + Sync,
+        F: FnMut(usize, &[T]),
+    {
+        let start = Start::Seeded(k);
+        self.cluster(data, Some(weights), start, iterations, |_| {}, on_iteration)
+    }
+
+    /// weighted k-means clustering starting from `initial` clusters instead of k-means++
+    /// seeding, eg. from the clusters of a similar image (a warm start)
+    ///
+    /// see [`Context::k_means_weighted_with`], with `k` the number of initial clusters
+    pub fn k_means_weighted_from<T, F>(
+        &mut self,
+        data: &[T],
+        weights: &[u32],
+        initial: Vec<T>,
+        iterations: usize,
+        on_iteration: F,
+    ) -> Result<(Vec<T>, Vec<usize>), Error>
+    where
+        T: Kmeansable + Clone + Sync,
+        F: FnMut(usize, &[T]),
+    {
+        let start = Start::From(initial);
+        self.cluster(data, Some(weights), start, iterations, |_| {}, on_iteration)
+    }
+
+    /// k-means clustering with optional weights and initial clusters, see
+    /// [`Context::k_means_with`]
+    /// - `constrain` moves each cluster after it is updated, eg. into a [`Gamut`]
+    pub(crate) fn cluster<T, C, F>(
+        &mut self,
+        data: &[T],
+        weights: Option<&[u32]>,
+        start: Start<T>,
+        iterations: usize,
+        constrain: C,
+        mut on_iteration: F,
+    ) -> Result<(Vec<T>, Vec<usize>), Error>
     where
-        T: Kmeansable + Clone,
+        T: Kmeansable + Clone + Sync,
+        C: Fn(&mut T),
+        F: FnMut(usize, &[T]),
     {
+        let k = match &start {
+            Start::Seeded(k) => *k,
+            Start::From(initial) => initial.len(),
+        };
+        match () {
+            _ if k == 0 => {
+                return Err(Error::InvalidParameter(messages::text(
+                    "zero-clusters",
+                    &[],
+                )));
+            }
+            _ if iterations == 0 => {
+                return Err(Error::InvalidParameter(messages::text(
+                    "zero-iterations",
+                    &[],
+                )));
+            }
+            _ if data.is_empty() => {
+                return Err(Error::InvalidParameter(messages::text("no-points", &[])));
+            }
+            _ if k > data.len() => {
+                return Err(Error::InvalidParameter(messages::text(
+                    "too-few-points",
+                    &[("points", &data.len()), ("k", &k)],
+                )));
+            }
+            _ if weights.is_some_and(|w| w.len() != data.len()) => {
+                return Err(Error::InvalidParameter(messages::text(
+                    "point-weights-mismatch",
+                    &[],
+                )));
+            }
+            _ => {}
+        }
+
         let mut assignments: Vec<usize> = vec![0; data.len()];
-        let mut clusters = data
-            .choose_multiple(&mut self.rng, k)
-            .cloned()
+        let mut clusters = match start {
+            Start::From(initial) => initial,
+            Start::Seeded(_) => timing::measure("k-means: initialization", || {
+                self.k_means_pp(data, weights, k)
+            }),
+        };
+        self.iterations = 0;
+
+        // buffers are allocated once and reused by every iteration
+        // points are split into a fixed number of parts, each summed into its own buffer
+        let parts = rayon::current_num_threads() * PARTS_PER_THREAD;
+        let part_len = data.len().div_ceil(parts).max(1);
+        let mut distances = Vec::with_capacity(k * k);
+        let mut partials = (data.chunks(part_len))
+            .map(|_| (vec![0usize; k], vec![T::zero(); k]))
             .collect::<Vec<_>>();
+        let (mut counts, mut sums) = (vec![0usize; k], vec![T::zero(); k]);
+
+        // status lines are only rewritten on terminals
+        let rewrite = self.progress && Capability::stderr().is_terminal();
 
         // make cursor invisible
-        eprint!("\x1b[?25l");
+        if rewrite {
+            eprint!("{}", term::HIDE_CURSOR);
+        }
+
+        // labels of status lines, right-aligned to the longer one
+        let (iteration_label, point_label) = (
+            messages::text("progress-iteration", &[]),
+            messages::text("progress-point", &[]),
+        );
+        let width = (iteration_label.chars().count()).max(point_label.chars().count());
 
         for i in 0..iterations {
             // TODO: implement static logger functionality for progress
             //       once implemented, replace other eprint(ln)! calls too
-            eprintln!(
-                "processing k-means iteration: [ {:>9} / {:>9} ]...",
-                i + 1,
-                iterations
-            );
-
-            // precompute cluster distances to skip some distance calculations later
-            // only set for i < j -- note: dist[i][j] == dist[j][i]
-            let mut cluster_distances = vec![vec![0.0; k]; k];
-            for i in 0..k {
-                for j in (i + 1)..k {
-                    let dist = clusters[i].distance(&clusters[j]);
-                    cluster_distances[i][j] = dist; // i < j case only
-                }
+            if self.progress {
+                eprintln!(
+                    "{iteration_label:>width$}: [ {:>9} / {:>9} ]...",
+                    i + 1,
+                    iterations
+                );
             }
 
-            // assign each point to the nearest cluster
-            for (i, point) in data.iter().enumerate() {
-                let mut closest_idx = 0;
-                let mut closest_dist = clusters[0].distance(point);
+            let start = Instant::now();
+
+            // precompute cluster distances to skip some distance calculations later
+            cluster_distances(&clusters, &mut distances);
 
-                // print progress every 500 points
-                if i % 500 == 0 {
-                    if i > 0 {
+            // assign each point to the nearest cluster, in parallel
+            // progress is printed between chunks, since points finish out of order
+            for (chunk, (points, assignments)) in data
+                .chunks(PROGRESS_INTERVAL)
+                .zip(assignments.chunks_mut(PROGRESS_INTERVAL))
+                .enumerate()
+            {
+                if rewrite {
+                    if chunk > 0 {
                         // restore cursor position (write over previous status)
-                        eprint!("\x1b[1F");
+                        eprint!("{}", term::rewind(1));
                     }
-                    let label_len = "processing k-means iteration".len();
                     eprintln!(
-                        "{:>label_len$}: [ {:>9} / {:>9} ]...",
-                        "assigning point",
-                        i,
+                        "{point_label:>width$}: [ {:>9} / {:>9} ]...",
+                        chunk * PROGRESS_INTERVAL,
                         data.len()
                     );
                 }
 
-                for j in 1..k {
-                    // skip distance calculation if the cluster is too far away
-                    let (a, b) = (closest_idx.min(j), closest_idx.max(j));
-                    if cluster_distances[a][b] >= 2.0 * closest_dist {
-                        // d(c_j, c_min) >= 2 * d(p, c_min)
-                        // d(p,   c_j  ) >=     d(p, c_min)
-                        continue;
-                    }
-
-                    let dist = clusters[j].distance(point);
-                    if dist < closest_dist {
-                        closest_dist = dist;
-                        closest_idx = j;
-                    }
-                }
-
-                assignments[i] = closest_idx;
+                assignments
+                    .par_iter_mut()
+                    .zip(points)
+                    .for_each(|(assignment, point)| {
+                        *assignment = nearest(point, &clusters, &distances);
+                    });
             }
 
             // restore cursor position (write over previous status)
-            eprint!("\x1b[2F");
+            if rewrite {
+                eprint!("{}", term::rewind(2));
+            }
+
+            timing::record("k-means: assignment", start.elapsed());
+            let start = Instant::now();
 
             // move cluster to mean of its assigned points
-            let mut counts: Vec<usize> = vec![0; k];
-            let mut sums = vec![T::zero(); k];
+            // sums are integers, so the result does not depend on how points are split up
+            partials
+                .par_iter_mut()
+                .zip(data.par_chunks(part_len))
+                .zip(assignments.par_chunks(part_len))
+                .enumerate()
+                .for_each(|(part, (((counts, sums), points), assignments))| {
+                    counts.fill(0);
+                    sums.fill(T::zero());
+                    for (i, (point, &cluster_idx)) in points.iter().zip(assignments).enumerate() {
+                        match weights {
+                            None => {
+                                counts[cluster_idx] += 1;
+                                sums[cluster_idx] = T::add(&sums[cluster_idx], point);
+                            }
+                            Some(weights) => {
+                                let weight = weights[part * part_len + i];
+                                counts[cluster_idx] += weight as usize;
+                                sums[cluster_idx] =
+                                    T::add_weighted(&sums[cluster_idx], point, weight);
+                            }
+                        }
+                    }
+                });
 
-            for (i, point) in data.iter().enumerate() {
-                let cluster_idx = assignments[i];
-                counts[cluster_idx] += 1;
-                sums[cluster_idx] = T::add(&sums[cluster_idx], point);
+            counts.fill(0);
+            sums.fill(T::zero());
+            for (part_counts, part_sums) in &partials {
+                for i in 0..k {
+                    counts[i] += part_counts[i];
+                    sums[i] = T::merge(&sums[i], &part_sums[i]);
+                }
             }
 
+            // distances are squared, so the tolerance is too
+            let mut moved = false;
             for i in 0..k {
                 if counts[i] != 0 {
-                    clusters[i] = T::div(&sums[i].clone(), counts[i]);
+                    let mut mean = T::div(&sums[i], counts[i], self.rounding);
+                    constrain(&mut mean);
+                    moved |= mean.distance(&clusters[i]) > self.tolerance.powi(2);
+                    clusters[i] = mean;
                 }
             }
+
+            timing::record("k-means: update", start.elapsed());
+
+            self.iterations = i + 1;
+            on_iteration(i, &clusters);
+
+            // converged -- further iterations would (almost) not change anything
+            if !moved {
+                break;
+            }
         }
 
         // make cursor visible again
-        eprint!("\x1b[?25h");
+        if rewrite {
+            eprint!("{}", term::SHOW_CURSOR);
+        }
+
+        Ok((clusters, assignments))
+    }
+
+    /// k-means++ seeding: the first cluster is a random point, every further cluster is
+    /// a point picked with probability proportional to its (weighted, squared) distance
+    /// to the nearest cluster so far, spreading initial clusters over the data
+    fn k_means_pp<T>(&mut self, data: &[T], weights: Option<&[u32]>, k: usize) -> Vec<T>
+    where
+        T: Kmeansable + Clone + Sync,
+    {
+        let weight = |i: usize| weights.map_or(1.0, |w| w[i] as f64);
+
+        let first = self.sample((0..data.len()).map(weight));
+        let mut clusters = vec![data[first].clone()];
+        let mut nearest = data
+            .par_iter()
+            .map(|point| point.distance(&clusters[0]))
+            .collect::<Vec<_>>();
+
+        while clusters.len() < k {
+            let next = self.sample(nearest.iter().enumerate().map(|(i, d)| d * weight(i)));
+            let cluster = data[next].clone();
+            nearest
+                .par_iter_mut()
+                .zip(data)
+                .for_each(|(nearest, point)| *nearest = nearest.min(point.distance(&cluster)));
+            clusters.push(cluster);
+        }
+
+        clusters
+    }
+
+    /// pick an index with probability proportional to its score
+    /// (uniformly, if all scores are zero -- eg. when all points are duplicates of clusters)
+    fn sample(&mut self, scores: impl Iterator<Item = f64> + Clone) -> usize {
+        let total = scores.clone().sum::<f64>();
+        let len = scores.clone().count();
+        if total <= 0.0 {
+            return self.rng.random_range(0..len);
+        }
+
+        let mut target = self.rng.random::<f64>() * total;
+        for (i, score) in scores.enumerate() {
+            target -= score;
+            if target < 0.0 {
+                return i;
+            }
+        }
+        len - 1
+    }
 
-        (clusters, assignments)
+    /// rounding of cluster means, see [`Context::with_rounding`]
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+
+    /// number of iterations performed by the last clustering
+    /// (fewer than requested, if it converged early)
+    pub fn iterations(&self) -> usize {
+        self.iterations
     }
 
     /// create a new context with a seed
     pub fn new(seed: u64) -> Self {
         Self {
             rng: SmallRng::seed_from_u64(seed),
+            progress: true,
+            tolerance: 0.0,
+            rounding: Rounding::default(),
+            iterations: 0,
+            gamut: None,
+            parameters: Parameters::default(),
         }
     }
+
+    /// stop iterating once no cluster moves further than `tolerance`
+    /// (in units of the clustered data, eg. rgb channel values)
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// round cluster means to integer values as given (for float points, such as
+    /// [`ColorSpace`](crate::ColorSpace) clustering, only when converted back to colors)
+    pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// keep clusters of colors within a gamut, moving them into it after each iteration
+    /// (see [`Gamut::snap`])
+    /// - applies to clustering in a [`ColorSpace`](crate::ColorSpace), other points are
+    ///   not constrained
+    pub fn with_gamut(mut self, gamut: Option<Gamut>) -> Self {
+        self.gamut = gamut;
+        self
+    }
+
+    /// gamut that clusters of colors are kept within, see [`Context::with_gamut`]
+    pub fn gamut(&self) -> Option<&Gamut> {
+        self.gamut.as_ref()
+    }
+
+    /// cluster colors in color spaces with the given parameters (eg. a luma weight)
+    pub fn with_parameters(mut self, parameters: Parameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// parameters of color spaces, see [`Context::with_parameters`]
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    /// enable or disable progress output to stderr
+    pub fn with_progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
 }
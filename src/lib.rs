@@ -0,0 +1,432 @@
+//! palette generation and color quantization using k-means clustering
+//!
+//! ```no_run
+//! let img = image::open("wallpaper.png").unwrap();
+//! let palette = qtizer::quantize(&img, &qtizer::Options::default()).unwrap();
+//! for (color, weight) in palette.colors.iter().zip(&palette.weights) {
+//!     println!("{} {:.1}%", qtizer::palette::ColorCodeFormat::Hex.color_code(color), weight * 100.0);
+//! }
+//! ```
+
+use image::*;
+
+pub mod cam16;
+pub mod colors;
+pub mod colorspace;
+pub mod dither;
+mod error;
+pub mod gamut;
+pub mod grain;
+pub mod histogram;
+pub mod kmeans;
+pub mod median_cut;
+pub mod messages;
+pub mod octree;
+pub mod palette;
+pub mod quantizer;
+pub mod term;
+pub mod timing;
+pub mod tonemap;
+
+pub use crate::colors::Color;
+pub use crate::colorspace::ColorSpace;
+pub use crate::dither::Dither;
+pub use crate::error::Error;
+pub use crate::kmeans::{Context, Kmeansable, Rounding};
+pub use crate::palette::{ColorCodeFormat, Palette};
+pub use crate::quantizer::{Algorithm, Quantizer, Sample};
+pub use crate::tonemap::Tonemap;
+
+/// parameters for [`quantize`]
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// number of colors in the palette
+    pub k: usize,
+    /// algorithm computing the palette
+    pub algorithm: Algorithm,
+    /// maximum number of k-means iterations
+    pub iterations: usize,
+    /// cluster with the alpha channel (ignored for fully opaque images)
+    pub alpha: bool,
+    /// color space to cluster in (k-means only)
+    pub colorspace: ColorSpace,
+    /// parameters of the color space, eg. the luma weight of ycbcr
+    pub parameters: colorspace::Parameters,
+    /// stop k-means once no cluster moves further than this
+    pub tolerance: f64,
+    /// rounding of k-means cluster means to rgb(a) values
+    pub rounding: Rounding,
+    /// compute the palette from a random subset of pixels (replaces the thumbnail)
+    pub sample: Option<Sample>,
+    /// compute the palette from all pixels, instead of a thumbnail of large images
+    /// (see [`quantizer::Thumbnail`])
+    pub exact: bool,
+    /// seed for the initial clusters
+    pub seed: u64,
+    /// tone mapping of high dynamic range images (eg. exr), see [`tonemap::tonemap`]
+    pub tonemap: Tonemap,
+    /// region that palette colors are kept within (k-means only), see
+    /// [`Context::with_gamut`]
+    pub gamut: Option<gamut::Gamut>,
+    /// weight pixels of edges up to `1 + strength` times (k-means only), see
+    /// [`quantizer::Detail`]
+    pub protect_detail: Option<f64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            k: 8,
+            algorithm: Algorithm::default(),
+            iterations: 5,
+            alpha: false,
+            colorspace: ColorSpace::default(),
+            parameters: colorspace::Parameters::default(),
+            tolerance: 0.0,
+            rounding: Rounding::default(),
+            sample: None,
+            exact: false,
+            seed: 0,
+            tonemap: Tonemap::default(),
+            gamut: None,
+            protect_detail: None,
+        }
+    }
+}
+
+/// compute a palette of (at most) `options.k` colors representing an image
+/// - colors are weighted by the share of pixels they represent
+/// - images already within the color budget are not clustered
+pub fn quantize(img: &DynamicImage, options: &Options) -> Result<Palette, Error> {
+    if img.width() == 0 || img.height() == 0 {
+        return Err(Error::InvalidImage(messages::text("no-pixels", &[])));
+    }
+
+    if let Some(strength) = options.protect_detail
+        && !(strength > 0.0 && strength <= quantizer::Detail::MAX_STRENGTH)
+    {
+        return Err(Error::InvalidParameter(messages::text(
+            "invalid-detail-strength",
+            &[
+                ("value", &strength),
+                ("max", &quantizer::Detail::MAX_STRENGTH),
+            ],
+        )));
+    }
+
+    options.parameters.validate()?;
+
+    let mapped = tonemap::tonemap(img, options.tonemap);
+    let img = mapped.as_ref().unwrap_or(img);
+
+    let alpha = options.alpha && !is_opaque(img);
+    let pixels = pixels(img, alpha);
+
+    // colors of images within the budget may lie outside of the gamut
+    let within_budget = (options.gamut.is_none())
+        .then(|| colors::distinct(&pixels, options.k))
+        .flatten();
+    let (clusters, assignments) = match within_budget {
+        Some(clusters) => {
+            let assignments = colors::assign(&pixels, &clusters);
+            (clusters, assignments)
+        }
+        None => {
+            let mut context = Context::new(options.seed)
+                .with_progress(false)
+                .with_tolerance(options.tolerance)
+                .with_rounding(options.rounding)
+                .with_gamut(options.gamut.clone())
+                .with_parameters(options.parameters);
+            let quantizer = options.algorithm.quantizer(
+                &mut context,
+                options.colorspace,
+                options.iterations,
+                |_, _| {},
+            );
+            let mut quantizer: Box<dyn Quantizer> = match (options.sample, options.exact) {
+                (Some(sample), _) => Box::new(quantizer::Sampled {
+                    quantizer,
+                    sample,
+                    seed: options.seed,
+                }),
+                (None, false) => Box::new(quantizer::Thumbnail {
+                    quantizer,
+                    width: img.width() as usize,
+                    limit: quantizer::THUMBNAIL_PIXELS,
+                }),
+                (None, true) => quantizer,
+            };
+            if let Some(strength) = options.protect_detail {
+                quantizer = Box::new(quantizer::Detail {
+                    quantizer,
+                    width: img.width() as usize,
+                    strength,
+                });
+            }
+            quantizer.quantize(&pixels, options.k)?
+        }
+    };
+
+    let mut counts = vec![0.0; clusters.len()];
+    for &i in &assignments {
+        counts[i] += 1.0;
+    }
+
+    Ok(Palette::with_weights(clusters, counts))
+}
+
+/// handling of the alpha channel of images by [`remap`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaPolicy {
+    /// remap rgb only, to an opaque image
+    #[default]
+    Ignore,
+    /// remap rgba, to the palette colors with alpha (colors without alpha are opaque)
+    Remap,
+    /// remap rgb only, copying the alpha channel of the image unchanged
+    Preserve,
+    /// dither partial alpha into a pattern of opaque and transparent pixels, then remap
+    /// rgba (see [`dither::screen_door`])
+    ScreenDoor,
+}
+
+/// parameters for [`remap`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RemapOptions {
+    /// dithering of the remapped pixels
+    pub dither: Dither,
+    /// color space to find nearest palette colors in (without dithering, which is srgb)
+    pub colorspace: ColorSpace,
+    /// parameters of the color space, see [`Options::parameters`]
+    pub parameters: colorspace::Parameters,
+    /// handling of the alpha channel of the image
+    pub alpha_policy: AlphaPolicy,
+}
+
+/// remap an image to the colors of a fixed palette, without clustering
+/// - images with alpha are remapped to rgba images (unless it is ignored), others to rgb
+/// - high dynamic range images are clipped, see [`tonemap::tonemap`] to map them first
+pub fn remap(
+    img: &DynamicImage,
+    palette: &[Color],
+    options: &RemapOptions,
+) -> Result<DynamicImage, Error> {
+    if img.width() == 0 || img.height() == 0 {
+        return Err(Error::InvalidImage(messages::text("no-pixels", &[])));
+    }
+    if palette.is_empty() {
+        return Err(Error::InvalidParameter(messages::text(
+            "remap-empty-palette",
+            &[],
+        )));
+    }
+
+    options.parameters.validate()?;
+
+    let opaque = is_opaque(img);
+    let alpha = !opaque
+        && matches!(
+            options.alpha_policy,
+            AlphaPolicy::Remap | AlphaPolicy::ScreenDoor
+        );
+    let mut pixels = pixels(img, alpha);
+    if alpha && options.alpha_policy == AlphaPolicy::ScreenDoor {
+        dither::screen_door(&mut pixels, img.width() as usize);
+    }
+    let palette = palette
+        .iter()
+        .map(|c| c.with_alpha(alpha))
+        .collect::<Vec<_>>();
+
+    let assignments = match options.dither {
+        Dither::None => (options.colorspace).assign(&pixels, &palette, &options.parameters),
+        method => dither::dither(&pixels, img.width(), &palette, method),
+    };
+
+    let (width, height) = img.dimensions();
+    let remapped = match options.alpha_policy {
+        AlphaPolicy::Preserve if !opaque => {
+            let data = (assignments.iter().zip(img.to_rgba8().pixels()))
+                .flat_map(|(&i, p)| palette[i].rgb().into_iter().chain([p.0[3]]))
+                .collect();
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        _ => {
+            let data = (assignments.iter())
+                .flat_map(|&i| palette[i].data.iter().copied())
+                .collect();
+            match alpha {
+                true => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+                false => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+            }
+        }
+    };
+    Ok(remapped.expect("remapped pixels fill the image"))
+}
+
+/// whether an image has no alpha channel, or only fully opaque pixels
+pub fn is_opaque(img: &DynamicImage) -> bool {
+    !img.color().has_alpha() || img.to_rgba8().pixels().all(|p| p.0[3] == u8::MAX)
+}
+
+/// collect the pixels of an image as rgb or rgba colors
+pub fn pixels(img: &DynamicImage, alpha: bool) -> Vec<Color> {
+    match alpha {
+        true => img
+            .to_rgba8()
+            .pixels()
+            .map(|p| Color {
+                data: p.0.to_vec(),
+                color_type: ColorType::Rgba8,
+            })
+            .collect::<Vec<_>>(),
+        false => img
+            .to_rgb8()
+            .pixels()
+            .map(|p| Color {
+                data: p.0.to_vec(),
+                color_type: ColorType::Rgb8,
+            })
+            .collect::<Vec<_>>(),
+    }
+}
+
+/// error message for functionality of a cargo feature that this build was compiled
+/// without, eg. `missing_feature("`--edit`", "edit")`
+pub fn missing_feature(what: &str, feature: &str) -> String {
+    messages::text("missing-feature", &[("what", &what), ("feature", &feature)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    /// pick a random variant of an enum
+    fn pick<T: ValueEnum + Clone>(rng: &mut SmallRng) -> T {
+        let variants = T::value_variants();
+        variants[rng.random_range(0..variants.len())].clone()
+    }
+
+    /// a random number, including edge cases like 0, negatives, nan and infinity
+    fn number(rng: &mut SmallRng, max: f64) -> f64 {
+        match rng.random_range(0..8) {
+            0 => 0.0,
+            1 => -rng.random_range(0.0..max),
+            2 => f64::NAN,
+            3 => f64::INFINITY,
+            4 => max * 1e9,
+            _ => rng.random_range(0.0..max),
+        }
+    }
+
+    /// a random image of up to 24 by 24 pixels (possibly empty), of a random color type
+    fn image(rng: &mut SmallRng) -> DynamicImage {
+        let (width, height) = (rng.random_range(0..24), rng.random_range(0..24));
+        let (colors, kind) = (rng.random_range(1..64), rng.random_range(0..5));
+        let mut bytes = |channels: usize| {
+            let palette = (0..colors * channels)
+                .map(|_| rng.random())
+                .collect::<Vec<u8>>();
+            (0..width * height)
+                .flat_map(|_| {
+                    let i = rng.random_range(0..colors);
+                    palette[i * channels..(i + 1) * channels].to_vec()
+                })
+                .collect::<Vec<u8>>()
+        };
+        let image = match kind {
+            0 => ImageBuffer::from_raw(width, height, bytes(1)).map(DynamicImage::ImageLuma8),
+            1 => ImageBuffer::from_raw(width, height, bytes(2)).map(DynamicImage::ImageLumaA8),
+            2 => ImageBuffer::from_raw(width, height, bytes(3)).map(DynamicImage::ImageRgb8),
+            3 => ImageBuffer::from_raw(width, height, bytes(4)).map(DynamicImage::ImageRgba8),
+            _ => {
+                let values = bytes(3).into_iter().map(|v| v as f32 / 32.0).collect();
+                ImageBuffer::from_raw(width, height, values).map(DynamicImage::ImageRgb32F)
+            }
+        };
+        image.expect("pixels fill the image")
+    }
+
+    /// random, possibly malformed colors (eg. with missing or extra channels)
+    fn colors(rng: &mut SmallRng) -> Vec<Color> {
+        (0..rng.random_range(0..6))
+            .map(|_| {
+                let channels = rng.random_range(0..6);
+                Color {
+                    color_type: match rng.random() {
+                        true => ColorType::Rgba8,
+                        false => ColorType::Rgb8,
+                    },
+                    data: (0..channels).map(|_| rng.random()).collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// random options, including invalid ones
+    fn options(rng: &mut SmallRng) -> Options {
+        Options {
+            k: rng.random_range(0..12),
+            algorithm: pick(rng),
+            iterations: rng.random_range(0..4),
+            alpha: rng.random(),
+            colorspace: pick(rng),
+            tolerance: number(rng, 10.0),
+            rounding: pick(rng),
+            sample: match rng.random_range(0..3) {
+                0 => Some(Sample::Fraction(number(rng, 1.0))),
+                1 => Some(Sample::Count(rng.random_range(0..64))),
+                _ => None,
+            },
+            exact: rng.random(),
+            seed: rng.random(),
+            tonemap: pick(rng),
+            gamut: None,
+            protect_detail: rng.random::<bool>().then(|| number(rng, 100.0)),
+            parameters: colorspace::Parameters {
+                luma_weight: number(rng, 4.0),
+                viewing_conditions: cam16::ViewingConditions {
+                    luminance: number(rng, 1000.0),
+                    background: number(rng, 100.0),
+                    surround: Default::default(),
+                },
+            },
+        }
+    }
+
+    /// arbitrary images and parameters return errors instead of panicking
+    #[test]
+    fn arbitrary_inputs() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..500 {
+            let img = image(&mut rng);
+            let options = options(&mut rng);
+            if let Ok(palette) = quantize(&img, &options) {
+                assert!(palette.colors.len() <= options.k);
+            }
+
+            let palette = colors(&mut rng);
+            let options = RemapOptions {
+                dither: pick(&mut rng),
+                colorspace: pick(&mut rng),
+                alpha_policy: [
+                    AlphaPolicy::Ignore,
+                    AlphaPolicy::Remap,
+                    AlphaPolicy::Preserve,
+                    AlphaPolicy::ScreenDoor,
+                ][rng.random_range(0..4)],
+                parameters: colorspace::Parameters {
+                    luma_weight: number(&mut rng, 4.0),
+                    ..Default::default()
+                },
+            };
+            if let Ok(remapped) = remap(&img, &palette, &options) {
+                assert_eq!(remapped.dimensions(), img.dimensions());
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::io::{BufRead, BufReader, Read};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use image::DynamicImage;
+use image::codecs::pnm::PnmDecoder;
+use qtizer::colors::Color;
+use qtizer::quantizer::{self, Algorithm, Quantizer, Thumbnail, WarmKMeans};
+use qtizer::{is_opaque, kmeans, pixels, timing};
+
+#[cfg(feature = "capture")]
+use crate::capture::Screen;
+use crate::cli::{self, Args};
+use crate::messages;
+use crate::stream::Stream;
+use crate::{logger, zones};
+
+/// frames quantized per second without `--fps`
+pub const DEFAULT_FPS: f64 = 10.0;
+
+/// the most recent frame of a source, shared by the thread reading the source
+#[derive(Default)]
+struct Latest {
+    /// frame not yet quantized, replaced by newer frames
+    frame: Option<DynamicImage>,
+    /// number of frames read so far
+    received: usize,
+    /// whether the source has ended, and why if it failed
+    ended: Option<Result<(), String>>,
+}
+
+/// quantize the frames of a live source, printing the palette (or `--zones`) of each,
+/// until the source ends (see `--live`)
+/// - frames are read in the background, and only the most recent one is quantized
+/// - clusters start from the palette of the previous frame (a warm start), unless the
+///   number of colors or channels changed
+/// - frames are quantized at most `--fps` times per second
+pub fn run(args: &Args, seed: u64) -> Result<(), String> {
+    let fps = args.fps.unwrap_or(DEFAULT_FPS);
+    let interval = Duration::from_secs_f64(1.0 / fps);
+
+    let latest = Arc::new((Mutex::new(Latest::default()), Condvar::new()));
+    let reader = Arc::clone(&latest);
+    match args.capture {
+        // `--capture` is rejected without the `capture` feature, see `cli::validate`
+        #[cfg(feature = "capture")]
+        Some(capture) => {
+            let screen = Screen::open(capture)?;
+            std::thread::spawn(move || grab(&screen, interval, &reader));
+        }
+        _ => {
+            let input = &args.inputs()[0];
+            let source: Box<dyn Read + Send> = match cli::is_stdio(input) {
+                true => Box::new(std::io::stdin()),
+                false => Box::new(std::fs::File::open(input).map_err(|err| {
+                    messages::text(
+                        "open-input",
+                        &[("input", &input.display()), ("error", &err)],
+                    )
+                })?),
+            };
+            std::thread::spawn(move || read(BufReader::new(source), &reader));
+        }
+    }
+
+    let mut context = kmeans::Context::new(seed)
+        .with_progress(false)
+        .with_tolerance(args.tolerance)
+        .with_rounding(args.rounding)
+        .with_parameters(args.parameters());
+    let mut stream = Stream::new(args.color_codes()).with_label("frame");
+    let mut previous: Option<Vec<Color>> = None;
+    let (mut quantized, mut overrun) = (0, false);
+
+    let (lock, ready) = &*latest;
+    loop {
+        let start = Instant::now();
+        let frame = {
+            let mut latest = lock.lock().unwrap_or_else(|err| err.into_inner());
+            while latest.frame.is_none() && latest.ended.is_none() {
+                latest = ready.wait(latest).unwrap_or_else(|err| err.into_inner());
+            }
+            match latest.frame.take() {
+                Some(frame) => frame,
+                None => break,
+            }
+        };
+
+        match args.zones {
+            Some(_) => zones::write(args, &zones::colors(args, &mut context, &frame)?, true)?,
+            None => {
+                let palette = quantize(args, &mut context, &frame, previous.as_deref())?;
+                stream.update(quantized, &palette);
+                previous = Some(palette);
+            }
+        }
+        quantized += 1;
+
+        // the next frame is taken once the interval of this one has passed
+        match interval.checked_sub(start.elapsed()) {
+            Some(remaining) => std::thread::sleep(remaining),
+            None if !overrun => {
+                overrun = true;
+                logger::warn(
+                    "live-overrun",
+                    messages::text("live-overrun", &[("fps", &fps)]),
+                );
+            }
+            None => {}
+        }
+    }
+
+    let latest = lock.lock().unwrap_or_else(|err| err.into_inner());
+    let skipped = latest.received - quantized;
+    if skipped > 0 {
+        logger::note(
+            "live-skipped",
+            messages::text(
+                "live-skipped",
+                &[
+                    ("skipped", &skipped),
+                    ("received", &latest.received),
+                    ("fps", &fps),
+                ],
+            ),
+        );
+    }
+    latest.ended.clone().unwrap_or(Ok(()))
+}
+
+/// read frames until the source ends, keeping only the most recent one (see [`run`])
+fn read<R>(mut source: R, latest: &(Mutex<Latest>, Condvar))
+where
+    R: BufRead,
+{
+    let result = loop {
+        match source.fill_buf() {
+            Ok([]) => break Ok(()),
+            Ok(_) => {}
+            Err(err) => break Err(messages::text("read-frame", &[("error", &err)])),
+        }
+        let frame = PnmDecoder::new(&mut source)
+            .and_then(DynamicImage::from_decoder)
+            .map_err(|err| messages::text("decode-frame", &[("error", &err)]));
+        match frame {
+            Ok(frame) => publish(latest, frame),
+            Err(err) => break Err(err),
+        }
+    };
+    end(latest, result);
+}
+
+/// capture a frame of the screen every `interval`, until capturing fails (see [`run`])
+#[cfg(feature = "capture")]
+fn grab(screen: &Screen, interval: Duration, latest: &(Mutex<Latest>, Condvar)) {
+    let result = loop {
+        let start = Instant::now();
+        match screen.frame() {
+            Ok(frame) => publish(latest, frame),
+            Err(err) => break Err(err),
+        }
+        if let Some(remaining) = interval.checked_sub(start.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+    };
+    end(latest, result);
+}
+
+/// replace the most recent frame of a source
+fn publish((lock, ready): &(Mutex<Latest>, Condvar), frame: DynamicImage) {
+    let mut latest = lock.lock().unwrap_or_else(|err| err.into_inner());
+    latest.frame = Some(frame);
+    latest.received += 1;
+    ready.notify_one();
+}
+
+/// mark a source as ended, see [`Latest::ended`]
+fn end((lock, ready): &(Mutex<Latest>, Condvar), result: Result<(), String>) {
+    let mut latest = lock.lock().unwrap_or_else(|err| err.into_inner());
+    latest.ended = Some(result);
+    ready.notify_one();
+}
+
+/// palette of a frame, clustered from the previous palette when given (see [`run`])
+fn quantize(
+    args: &Args,
+    context: &mut kmeans::Context,
+    frame: &DynamicImage,
+    previous: Option<&[Color]>,
+) -> Result<Vec<Color>, String> {
+    let alpha = args.alpha && !is_opaque(frame);
+    let pixels = timing::measure("preprocess", || {
+        let pixels = pixels(frame, alpha);
+        match args.exact {
+            true => pixels,
+            false => {
+                let width = frame.width() as usize;
+                Thumbnail::pixels(&pixels, width, quantizer::THUMBNAIL_PIXELS)
+            }
+        }
+    });
+
+    let k = args.number;
+    let channels = pixels.first().map_or(0, |c| c.data.len());
+    let warm = previous.filter(|p| p.len() == k && p.iter().all(|c| c.data.len() == channels));
+    let mut quantizer: Box<dyn Quantizer> = match warm {
+        Some(initial) if args.algorithm == Algorithm::Kmeans => Box::new(WarmKMeans {
+            context,
+            colorspace: args.colorspace,
+            iterations: args.iterations,
+            initial,
+            on_iteration: |_: usize, _: &[Color]| {},
+        }),
+        _ => (args.algorithm).quantizer(context, args.colorspace, args.iterations, |_, _| {}),
+    };
+    let (palette, _) = quantizer.quantize(&pixels, k)?;
+    Ok(palette)
+}
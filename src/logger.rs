@@ -0,0 +1,197 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::cli::{self, Args};
+use crate::messages;
+use crate::term::{self, Capability};
+
+/// whether warnings are printed
+static WARNINGS: AtomicBool = AtomicBool::new(true);
+
+/// whether diagnostics are printed as json records, see [`Diagnostics`]
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// format of warnings, notes and errors on stderr
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Diagnostics {
+    /// Human-readable messages
+    #[default]
+    Human,
+    /// One json object per line, with `level`, `code` and `message` (and `input` for
+    /// failed inputs of `--output-dir`)
+    Json,
+}
+
+/// a failed run, with the code of its json diagnostics record, see [`fail`]
+#[derive(Debug)]
+pub struct Failure {
+    /// kind of failure, eg. `decode` or `invalid-palette`
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(code: &'static str, message: impl std::fmt::Display) -> Self {
+        Failure {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// failures without a more specific kind are coded as `io`
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure::new("io", message)
+    }
+}
+
+impl From<crate::io::Error> for Failure {
+    fn from(err: crate::io::Error) -> Self {
+        Failure::new(err.code(), err)
+    }
+}
+
+/// configure the logger from parsed arguments
+pub fn init(args: &Args) {
+    WARNINGS.store(!args.no_warnings, Ordering::Relaxed);
+    set_diagnostics(args.diagnostics);
+}
+
+/// set the format of diagnostics (before arguments are fully parsed, see [`exit`])
+pub fn set_diagnostics(diagnostics: Diagnostics) {
+    JSON.store(diagnostics == Diagnostics::Json, Ordering::Relaxed);
+}
+
+/// print a warning to stderr (unless disabled with `--no-warnings`)
+/// - `code` identifies the kind of warning in json diagnostics, eg. `large-k`
+pub fn warn(code: &str, message: impl std::fmt::Display) {
+    if !WARNINGS.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if JSON.load(Ordering::Relaxed) {
+        return record("warning", code, &message, None);
+    }
+    print("prefix-warning", term::YELLOW, message);
+}
+
+/// print a note about automatic decisions to stderr
+/// - `code` identifies the kind of decision in json diagnostics, eg. `opaque-input`
+pub fn note(code: &str, message: impl std::fmt::Display) {
+    if JSON.load(Ordering::Relaxed) {
+        return record("note", code, &message, None);
+    }
+    print("prefix-note", term::CYAN, message);
+}
+
+/// print an error that does not end the run to stderr, eg. a rejected `--edit` command
+/// - `code` identifies the kind of error in json diagnostics, eg. `edit-command`
+pub fn error(code: &str, message: impl std::fmt::Display) {
+    if JSON.load(Ordering::Relaxed) {
+        return record("error", code, &message, None);
+    }
+    print("prefix-error", term::RED, message);
+}
+
+/// print the error of a failed input as a json record (human-readable errors of inputs
+/// are printed by their callers), see `--output-dir`
+pub fn input_error(input: &Path, message: impl std::fmt::Display) {
+    if JSON.load(Ordering::Relaxed) {
+        record(
+            "error",
+            "input-failed",
+            &message,
+            Some(&input.to_string_lossy()),
+        );
+    }
+}
+
+/// print an error and exit, like [`clap::Error::exit`]
+/// - json errors are coded by their kind, eg. `argument-conflict`
+/// - help and version output is never json
+pub fn exit(err: clap::Error) -> ! {
+    if !JSON.load(Ordering::Relaxed) || !err.use_stderr() {
+        err.exit()
+    }
+
+    // the message, without the `error:` prefix and the usage hints after it
+    let rendered = err.render().to_string();
+    let message = (rendered.split("\n\n").next().unwrap_or_default())
+        .trim()
+        .trim_start_matches("error:")
+        .trim();
+    record(
+        "error",
+        &kebab_case(&format!("{:?}", err.kind())),
+        &message,
+        None,
+    );
+    std::process::exit(err.exit_code())
+}
+
+/// print the error of a failed run and exit, like [`exit`]
+/// - json errors are coded by the failure, eg. `decode`
+pub fn fail(failure: Failure) -> ! {
+    if JSON.load(Ordering::Relaxed) {
+        record("error", failure.code, &failure.message, None);
+        std::process::exit(2)
+    }
+    exit(cli::error(clap::error::ErrorKind::Io, failure.message))
+}
+
+/// print a human-readable diagnostic to stderr, with its prefix (eg. `warning:`) colored
+/// on terminals
+fn print(prefix: &str, color: &str, message: impl std::fmt::Display) {
+    let prefix = messages::text(prefix, &[]);
+    match Capability::stderr().is_terminal() {
+        true => eprintln!("{color}{prefix}{} {message}", term::RESET),
+        false => eprintln!("{prefix} {message}"),
+    }
+}
+
+/// print a json diagnostics record to stderr
+fn record(level: &str, code: &str, message: &dyn std::fmt::Display, input: Option<&str>) {
+    let input = match input {
+        Some(input) => format!(",\"input\":\"{}\"", escape(input)),
+        None => String::new(),
+    };
+    eprintln!(
+        "{{\"level\":\"{level}\",\"code\":\"{code}\",\"message\":\"{}\"{input}}}",
+        escape(&message.to_string())
+    );
+}
+
+/// escape a string for a json string literal
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `CamelCase` as `kebab-case`, eg. for error kinds
+fn kebab_case(name: &str) -> String {
+    let mut kebab = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            kebab.push('-');
+        }
+        kebab.push(c.to_ascii_lowercase());
+    }
+    kebab
+}
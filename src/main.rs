@@ -1,148 +1,977 @@
-use clap::*;
 use image::*;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::cell::OnceCell;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
+mod anchor;
+#[cfg(feature = "gif")]
+mod animation;
+mod batch;
+mod cache;
+mod capture;
 mod cli;
-mod colors;
-mod kmeans;
+mod colorize;
+mod dedupe;
+#[cfg(feature = "edit")]
+mod edit;
+mod expand;
+mod fifo;
+mod find;
+mod generate;
+mod indexed;
+mod io;
+#[cfg(feature = "pnm")]
+mod live;
+mod logger;
+mod masks;
+#[cfg(feature = "net")]
+mod metrics;
+mod overlay;
+mod pipeline;
+mod plan;
+mod preflight;
+mod profile;
+#[cfg(feature = "net")]
+mod queue;
+mod reduce;
+mod score;
+mod sequence;
+#[cfg(feature = "net")]
+mod serve;
+mod session;
+mod sheet;
+mod stats;
+mod stream;
+mod swap;
+mod temporal;
+mod verify;
+mod version;
+mod warnings;
+mod zones;
 
-use crate::colors::*;
+use qtizer::colors::*;
+use qtizer::palette::{Palette, Porcelain};
+use qtizer::term::Capability;
+use qtizer::{
+    cam16, colors, colorspace, dither, gamut, grain, histogram, kmeans, messages, palette,
+    quantizer, term, timing, tonemap,
+};
+use qtizer::{is_opaque, pixels};
+
+use crate::dedupe::Dedupe;
+use crate::logger::Failure;
+use crate::temporal::Temporal;
+
+/// maximum width and height of thumbnails embedded in palettes, see `--embed-thumbnail`
+const THUMBNAIL_SIZE: u32 = 48;
 
 fn main() {
-    let args = cli::Args::parse();
+    let args = cli::parse();
+
+    if args.version {
+        version::print(args.verbose);
+        return;
+    }
+
+    if let Some(command) = &args.command {
+        match command {
+            cli::Command::Find(find_args) => find::find(find_args),
+            cli::Command::Reduce(reduce_args) => {
+                let seed = reduce_args.seed.unwrap_or_else(random_seed);
+                if let Err(err) = reduce::reduce(reduce_args, seed) {
+                    cli::err_exit(clap::error::ErrorKind::Io, err);
+                }
+            }
+            cli::Command::Expand(expand_args) => {
+                if let Err(err) = expand::expand(expand_args) {
+                    cli::err_exit(clap::error::ErrorKind::Io, err);
+                }
+            }
+            cli::Command::Colorize(colorize_args) => {
+                if let Err(err) = colorize::colorize(colorize_args) {
+                    cli::err_exit(clap::error::ErrorKind::Io, err);
+                }
+            }
+            cli::Command::ApplyLut(apply_lut_args) => {
+                if let Err(err) = colorize::apply_lut(apply_lut_args) {
+                    cli::err_exit(clap::error::ErrorKind::Io, err);
+                }
+            }
+            cli::Command::Swap(swap_args) => {
+                let seed = swap_args.seed.unwrap_or_else(random_seed);
+                if let Err(err) = swap::swap(swap_args, seed) {
+                    cli::err_exit(clap::error::ErrorKind::Io, err);
+                }
+            }
+            cli::Command::Score(score_args) => match score::score(score_args) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(err) => cli::err_exit(clap::error::ErrorKind::Io, err),
+            },
+            #[cfg(feature = "net")]
+            cli::Command::Serve(serve_args) => {
+                if let Err(err) = serve::serve(serve_args) {
+                    cli::err_exit(clap::error::ErrorKind::Io, err);
+                }
+            }
+            #[cfg(not(feature = "net"))]
+            cli::Command::Serve(_) => cli::err_exit(
+                clap::error::ErrorKind::InvalidSubcommand,
+                qtizer::missing_feature("`qtizer serve`", "net"),
+            ),
+            cli::Command::Completions(completions_args) => generate::completions(completions_args),
+            cli::Command::Manpage(manpage_args) => generate::manpage(manpage_args),
+        }
+        return;
+    }
+
     cli::semantically_validate(&args);
+    logger::init(&args);
+    warnings::check_args(&args);
 
-    let seed = args.seed.unwrap_or_else(|| {
-        let millis = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("you are a time traveler (system time < unix epoch)")
-            .as_millis();
+    let seed = args.seed.unwrap_or_else(random_seed);
 
-        // least significant 64 bits
-        (millis & u64::MAX as u128) as u64
-    });
+    if args.dry_run {
+        if let Err(err) = plan::dry_run(&args, seed) {
+            cli::err_exit(clap::error::ErrorKind::Io, err);
+        }
+        return;
+    }
 
-    let mut context = kmeans::Context::new(seed);
+    // phases are also recorded for the session log
+    if args.timing || args.session_log.is_some() {
+        timing::enable();
+    }
 
-    // open file and parse image
-    let img = image::open(args.file_path).expect("failed to open image");
-
-    let pixels = match args.alpha {
-        true => img
-            .to_rgba8()
-            .pixels()
-            .map(|p| Color {
-                data: p.0.to_vec(),
-                color_type: ColorType::Rgba8,
-            })
-            .collect::<Vec<_>>(),
-        false => img
-            .to_rgb8()
-            .pixels()
-            .map(|p| Color {
-                data: p.0.to_vec(),
-                color_type: ColorType::Rgb8,
-            })
-            .collect::<Vec<_>>(),
-    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs())
+        .build_global()
+        .expect("failed to create worker pool");
+
+    if let Some(fifo) = &args.listen_fifo {
+        if let Err(err) = fifo::listen(fifo) {
+            cli::err_exit(clap::error::ErrorKind::Io, err);
+        }
+        return;
+    }
 
-    // run kmeans
-    let (clusters, assignments) = context.k_means(&pixels, args.number, args.iterations);
+    let start = Instant::now();
+    let result = run(&args, seed);
+    if args.timing {
+        timing::report(start.elapsed());
+    }
+    session::append(&args, seed, &result, start.elapsed());
+    let success = result.unwrap_or_else(|failure| logger::fail(failure));
 
-    // handle output
-    match args.output.or(args.output_positional) {
-        None => palette_handler(
-            &clusters,
-            &mut std::io::stdout(),
-            &args.format.unwrap_or_default(),
-        ),
-
-        Some(output_file) if ImageFormat::from_path(&output_file).is_ok() => {
-            let (width, height) = img.dimensions();
-            image_file_handler(width, height, &clusters, &assignments, output_file);
+    if !success {
+        std::process::exit(1);
+    }
+}
+
+/// seed derived from the current time, for when no seed is given
+fn random_seed() -> u64 {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("you are a time traveler (system time < unix epoch)")
+        .as_millis();
+
+    // least significant 64 bits
+    (millis & u64::MAX as u128) as u64
+}
+
+/// run batch, pipeline, live or single-file processing, returning whether all inputs succeeded
+/// - errors of batch inputs are reported per input, other errors are returned
+fn run(args: &cli::Args, seed: u64) -> Result<bool, Failure> {
+    // a typo in an output path fails before clustering, not after it
+    timing::measure("preflight", || preflight::check(args))?;
+
+    if let Some(output_dir) = &args.output_dir {
+        return Ok(batch::run(args, output_dir, seed));
+    }
+
+    if let Some(pipeline_file) = &args.pipeline {
+        pipeline::run(args, seed, pipeline_file)?;
+        return Ok(true);
+    }
+
+    #[cfg(feature = "pnm")]
+    if args.live {
+        live::run(args, seed)?;
+        return Ok(true);
+    }
+
+    if args.zones.is_some() {
+        zones::run(args, seed)?;
+        return Ok(true);
+    }
+
+    let output = args.output_path();
+    let input = &args.inputs()[0];
+    let palettes = quantize_file(args, seed, input, output.as_deref(), true, None, None)?;
+
+    // porcelain output only consists of palettes
+    let saved = (output.filter(|o| !cli::is_stdio(o) && args.output_image_format().is_some()))
+        .filter(|_| args.porcelain.is_none());
+    if let Some(output_file) = saved {
+        for k in args.quantize_levels() {
+            let output_file = cli::with_level(&output_file, *k);
+            match args.as_palette {
+                true => println!(
+                    "{}",
+                    messages::text("saved-swatch", &[("path", &output_file.display())])
+                ),
+                false => println!(
+                    "{}",
+                    messages::text("saved-image", &[("path", &output_file.display())])
+                ),
+            }
+        }
+    }
+
+    if let Some(path) = &args.animate_iterations {
+        for k in args.quantize_levels() {
+            let path = cli::with_level(path, *k);
+            let saved = match args.sequence_format {
+                Some(_) => "saved-frames",
+                None => "saved-animation",
+            };
+            println!("{}", messages::text(saved, &[("path", &path.display())]));
         }
+    }
 
-        Some(output_file) => {
-            let mut file =
-                std::fs::File::create(output_file).expect("failed to create output file");
-            palette_handler(&clusters, &mut file, &args.format.unwrap_or_default());
+    if let Some(path) = &args.sheet {
+        println!(
+            "{}",
+            messages::text("saved-sheet", &[("path", &path.display())])
+        );
+    }
+
+    if let Some(dir) = &args.cluster_masks {
+        for k in args.quantize_levels() {
+            let dir = cli::with_level(dir, *k);
+            println!(
+                "{}",
+                messages::text("saved-masks", &[("path", &dir.display())])
+            );
+        }
+    }
+
+    if let Some(path) = &args.overlay {
+        for k in args.quantize_levels() {
+            let path = cli::with_level(path, *k);
+            println!(
+                "{}",
+                messages::text("saved-overlay", &[("path", &path.display())])
+            );
         }
     }
+
+    if let (Some(expected), [palette]) = (&args.verify, palettes.as_slice()) {
+        return verify::check(args, input, expected, palette)
+            .map_err(|err| Failure::new("invalid-palette", err));
+    }
+
+    Ok(true)
 }
 
-/// handle palette output to terminal or file
-fn palette_handler<W>(clusters: &[Color], writer: &mut W, format: &ColorCodeFormat)
-where
-    W: std::io::Write,
-{
-    // sort colors by alpha and brightness
-    let mut clusters = clusters.to_vec();
-    clusters.sort_by(|x, y| {
-        let (&[r_x, g_x, b_x], &[r_y, g_y, b_y]) = (&x.data[..3], &y.data[..3]) else {
-            unreachable!("invalid color type. only rgb or rgba colors should ever be used here.");
-        };
-        u32::cmp(&colors::brightness(y), &colors::brightness(x)) // descending brightness
-            .then_with(|| {
-                u32::from_be_bytes([r_x, g_x, b_x, 0]).cmp(&u32::from_be_bytes([r_y, g_y, b_y, 0]))
-            })
+/// cached clusters and assignments of an image, see `--cache-dir`
+fn cached(
+    args: &cli::Args,
+    key: &Option<String>,
+    pixels: usize,
+) -> Option<(Vec<Color>, Vec<usize>)> {
+    let (dir, key) = (args.cache_dir.as_ref()?, key.as_ref()?);
+    timing::measure("cache", || cache::load(Path::new(dir), key, pixels))
+}
+
+/// an input image, decoded and preprocessed once for all `-k` levels
+struct Input<'a> {
+    name: &'a Path,
+    decoded: io::Decoded,
+    alpha: bool,
+    pixels: Vec<Color>,
+    /// colors of `--palette`, which replace clustering
+    palette: Option<Palette>,
+    /// number of distinct colors, counted once for `--auto-iterations`
+    unique: OnceCell<usize>,
+    /// color profile of `--output-profile` (or `--output-gamut`), which quantized images
+    /// are converted to
+    output_profile: Option<profile::Profile>,
+    /// alpha of each pixel, copied to quantized images with `--preserve-alpha`
+    alpha_channel: Option<Vec<u8>>,
+}
+
+/// quantize a single input file and write its palettes or quantized images
+/// - without an output file, the palettes are written to stdout
+/// - output files with image extensions receive the quantized images
+/// - each `-k` level is written to the output path with `{k}` replaced by its count
+/// - with `temporal`, the palette continues from the previous input of a sequence
+/// - with `dedupe`, palettes of earlier inputs are reused if nearly identical
+/// - returns the palette written for each `-k` level
+pub fn quantize_file(
+    args: &cli::Args,
+    seed: u64,
+    path: &Path,
+    output: Option<&Path>,
+    progress: bool,
+    mut temporal: Option<&mut Temporal>,
+    dedupe: Option<&Dedupe>,
+) -> Result<Vec<Vec<Color>>, Failure> {
+    // open file and parse image
+    let mut decoded = io::decode_input(args, path)?;
+    let input = path.display();
+
+    warnings::check_image(args, path, &decoded.image);
+
+    let operator = args.tonemap.unwrap_or_default();
+    match timing::measure("tonemap", || tonemap::tonemap(&decoded.image, operator)) {
+        Some(mapped) => decoded.image = mapped,
+        None if args.tonemap.is_some() => {
+            logger::note("not-hdr", messages::text("not-hdr", &[("input", &input)]))
+        }
+        None => {}
+    }
+
+    let alpha = args.alpha && decoded.has_alpha();
+    if args.alpha && !alpha {
+        logger::note(
+            "opaque-input",
+            messages::text("opaque-input", &[("input", &input)]),
+        );
+    }
+
+    let alpha_channel = match args.preserve_alpha && decoded.has_alpha() {
+        true => Some(timing::measure("preprocess", || {
+            (decoded.image.to_rgba8().pixels())
+                .map(|p| p.0[3])
+                .collect::<Vec<_>>()
+        })),
+        false => None,
+    };
+    if args.preserve_alpha && alpha_channel.is_none() {
+        logger::note(
+            "opaque-input",
+            messages::text("opaque-preserve-alpha", &[("input", &input)]),
+        );
+    }
+
+    let output_format = (output.filter(|_| !args.palette_file_output()))
+        .and_then(|path| io::output_format(path, args.output_format, decoded.format));
+    let with_alpha = alpha || alpha_channel.is_some();
+    if let Some(format) = output_format.filter(|&f| with_alpha && !io::supports_alpha(f)) {
+        return Err(io::Error::NoAlpha(format).into());
+    }
+
+    let pixels = timing::measure("preprocess", || {
+        let mut pixels = pixels(&decoded.image, alpha);
+        if args.alpha_dither {
+            dither::screen_door(&mut pixels, decoded.image.width() as usize);
+        }
+        pixels
     });
 
-    // output palette as hex #rrggbbaa
-    // output with ansi escape codes for color preview in terminal
-    for color in &clusters {
-        ColorCodeFormat::pretty_print_color_code(format, writer, color);
-        writeln!(writer).expect("failed to write color to output");
+    // a given palette replaces clustering
+    let palette = args
+        .palette
+        .as_deref()
+        .map(Palette::from_file)
+        .transpose()
+        .map_err(|err| Failure::new("invalid-palette", err))?;
+
+    let output_profile = match (&args.output_profile, args.output_gamut) {
+        (Some(name), _) => Some(profile::Profile::load(name)?),
+        (None, gamut) => gamut.map(profile::Gamut::profile),
+    };
+
+    let source = Input {
+        name: path,
+        decoded,
+        alpha,
+        pixels,
+        palette,
+        unique: OnceCell::new(),
+        output_profile,
+        alpha_channel,
+    };
+
+    let mut sheet = args.sheet.as_ref().map(|_| sheet::Sheet::default());
+
+    let mut palettes = Vec::new();
+    for (i, &k) in args.quantize_levels().iter().enumerate() {
+        // palettes of all levels are written to stdout, separated by empty lines
+        if i > 0 && output.is_none() && !args.print0 {
+            println!();
+        }
+
+        let args = cli::Args {
+            number: k,
+            ..args.clone()
+        };
+        let output = output.map(|path| cli::with_level(path, k));
+        let (clusters, assignments) = quantize_level(
+            &args,
+            seed,
+            &source,
+            output.as_deref(),
+            progress,
+            temporal.as_deref_mut(),
+            dedupe,
+        )?;
+
+        if let Some(sheet) = sheet.as_mut() {
+            let width = source.decoded.image.width() as usize;
+            timing::measure("sheet", || {
+                let pixels = &source.pixels;
+                sheet.add(pixels, width, &clusters, &assignments, &args)
+            });
+        }
+
+        palettes.push(palette_colors(&args, &source, &clusters)?);
+    }
+
+    if let (Some(sheet), Some(path)) = (sheet, &args.sheet) {
+        timing::measure("sheet", || sheet.write(path))?;
     }
+
+    Ok(palettes)
 }
 
-/// handle image output to file
-fn image_file_handler(
-    width: u32,
-    height: u32,
-    clusters: &[Color],
-    assignments: &[usize],
-    output_file: String,
-) {
-    // create new image by replacing each pixel with its cluster center
-    let quantized = assignments
-        .iter()
-        .flat_map(|&i| &clusters[i].data)
-        .copied()
-        .collect::<Vec<_>>();
+/// quantize a decoded input to `args.number` colors and write its palette or quantized
+/// image, see [`quantize_file`]
+/// - returns the palette and the (undithered) assignments of the pixels to it
+fn quantize_level(
+    args: &cli::Args,
+    seed: u64,
+    source: &Input,
+    output: Option<&Path>,
+    progress: bool,
+    temporal: Option<&mut Temporal>,
+    dedupe: Option<&Dedupe>,
+) -> Result<(Vec<Color>, Vec<usize>), String> {
+    // streamed palettes replace the progress output
+    let mut context = kmeans::Context::new(seed)
+        .with_progress(progress && !args.stream)
+        .with_tolerance(args.tolerance)
+        .with_rounding(args.rounding)
+        .with_gamut(args.gamut.clone())
+        .with_parameters(args.parameters());
+
+    let Input {
+        name,
+        decoded,
+        alpha,
+        pixels,
+        palette,
+        ..
+    } = source;
+    let (img, alpha) = (&decoded.image, *alpha);
+    let input = name.display();
+    let output_format = (output.filter(|_| !args.palette_file_output()))
+        .and_then(|path| io::output_format(path, args.output_format, decoded.format));
 
-    let status = match clusters.first() {
-        Some(c) if c.color_type == ColorType::Rgba8 => {
-            let img = ImageBuffer::from_vec(width, height, quantized);
-            let img: ImageBuffer<Rgba<u8>, _> = img.expect("failed to create quantized image");
-            img.save(&output_file)
-        }
-        Some(c) if c.color_type == ColorType::Rgb8 => {
-            let img = ImageBuffer::from_vec(width, height, quantized);
-            let img: ImageBuffer<Rgb<u8>, _> = img.expect("failed to create quantized image");
-            img.save(&output_file)
-        }
-        _ => {
-            cli::err_exit(
-                clap::error::ErrorKind::InvalidValue,
-                "unexpected error - this is a bug".to_string(),
+    // images already within the color budget (eg. indexed png or gif) need no clustering,
+    // unless their colors need to be moved into `--gamut`
+    let within_budget = match palette {
+        _ if args.gamut.is_some() => None,
+        Some(_) => None,
+        None => timing::measure("preprocess", || colors::distinct(pixels, args.number)),
+    };
+
+    let iterations = match args.auto_iterations && within_budget.is_none() {
+        true => {
+            let unique = *(source.unique)
+                .get_or_init(|| timing::measure("preprocess", || colors::count_distinct(pixels)));
+            let iterations = kmeans::auto_iterations(pixels.len(), unique, args.number);
+            logger::note(
+                "auto-iterations",
+                messages::text(
+                    "auto-iterations",
+                    &[
+                        ("input", &input),
+                        ("colors", &unique),
+                        ("iterations", &iterations),
+                    ],
+                ),
             );
-            unreachable!("should have exited");
+            iterations
         }
+        false => args.iterations,
     };
 
-    // TODO: better errors handling logger
-    // save image with inferred format
-    match status {
-        Ok(_) => println!("saved quantized image to {output_file}"),
-        Err(err) => {
-            // errors here are unexpected, since extension alpha-capability
-            // is validated in `cli::semantically_validate`
-            cli::err_exit(
-                clap::error::ErrorKind::InvalidValue,
-                "unexpectedly failed to save quantized image.\n".to_string()
-                    + "try checking the output file format. (does it support alpha?)\n"
-                    + &format!("    ({err})"),
+    // cached palettes would not continue from the previous input of a sequence
+    let uncached = palette.is_some() || within_budget.is_some() || temporal.is_some();
+    let cache_key = (args.cache_dir.is_some() && !uncached)
+        .then(|| timing::measure("cache", || cache::key(args, img, alpha, iterations)));
+
+    // inputs of a batch with nearly identical palettes reuse the earlier one
+    let preview =
+        (dedupe.filter(|_| palette.is_none() && within_budget.is_none())).and_then(|dedupe| {
+            let width = img.width() as usize;
+            let preview =
+                timing::measure("dedupe", || dedupe::preview(pixels, width, args.number))?;
+            Some((dedupe, preview))
+        });
+
+    // palettes of each iteration, for `--animate-iterations`
+    let mut frames = Vec::new();
+
+    // run the quantization algorithm
+    let clustered = palette.is_none() && within_budget.is_none();
+    let (clusters, assignments) = match (palette, within_budget, args.stream) {
+        (Some(palette), _, _) => {
+            let clusters = (palette.colors.iter())
+                .map(|c| c.with_alpha(alpha))
+                .collect::<Vec<_>>();
+            let assignments = timing::measure("remap", || {
+                args.colorspace
+                    .assign(pixels, &clusters, &args.parameters())
+            });
+            (clusters, assignments)
+        }
+        (None, Some(clusters), stream) => {
+            logger::note(
+                "within-budget",
+                messages::text(
+                    "within-budget",
+                    &[
+                        ("input", &input),
+                        ("colors", &clusters.len()),
+                        ("k", &args.number),
+                    ],
+                ),
+            );
+            if stream {
+                let mut stream = stream::Stream::new(args.color_codes());
+                stream.update(0, &clusters);
+                stream.finish();
+            }
+            let assignments = timing::measure("remap", || colors::assign(pixels, &clusters));
+            (clusters, assignments)
+        }
+        (None, None, stream) if let Some(cached) = cached(args, &cache_key, pixels.len()) => {
+            logger::note("cached", messages::text("cached", &[("input", &input)]));
+            if stream {
+                let mut stream = stream::Stream::new(args.color_codes());
+                stream.update(0, &cached.0);
+                stream.finish();
+            }
+            cached
+        }
+        (None, None, stream)
+            if let Some((earlier, clusters)) =
+                (preview.as_ref()).and_then(|(dedupe, preview)| dedupe.find(name, preview)) =>
+        {
+            let message = messages::text(
+                "deduplicated",
+                &[("input", &input), ("earlier", &earlier.display())],
             );
+            logger::note("deduplicated", message);
+            if stream {
+                let mut stream = stream::Stream::new(args.color_codes());
+                stream.update(0, &clusters);
+                stream.finish();
+            }
+            let assignments = timing::measure("remap", || {
+                args.colorspace
+                    .assign(pixels, &clusters, &args.parameters())
+            });
+            (clusters, assignments)
+        }
+        (None, None, streaming) => {
+            let mut stream = streaming.then(|| stream::Stream::new(args.color_codes()));
+            let on_iteration = |i: usize, c: &[Color]| {
+                if let Some(stream) = stream.as_mut() {
+                    stream.update(i, c);
+                }
+                if args.animate_iterations.is_some() {
+                    frames.push(c.to_vec());
+                }
+            };
+
+            let channels = pixels.first().map_or(0, |c| c.data.len());
+            let initial = (temporal.as_deref()).and_then(|t| t.previous(args.number, channels));
+            let quantizer: Box<dyn quantizer::Quantizer> = match initial {
+                Some(initial) => Box::new(quantizer::WarmKMeans {
+                    context: &mut context,
+                    colorspace: args.colorspace,
+                    iterations,
+                    initial,
+                    on_iteration,
+                }),
+                None => (args.algorithm).quantizer(
+                    &mut context,
+                    args.colorspace,
+                    iterations,
+                    on_iteration,
+                ),
+            };
+            let mut quantizer: Box<dyn quantizer::Quantizer> = match (args.sample, args.exact) {
+                (Some(sample), _) => Box::new(quantizer::Sampled {
+                    quantizer,
+                    sample,
+                    seed,
+                }),
+                (None, false) => Box::new(quantizer::Thumbnail {
+                    quantizer,
+                    width: img.width() as usize,
+                    limit: quantizer::THUMBNAIL_PIXELS,
+                }),
+                (None, true) => quantizer,
+            };
+            if let Some(strength) = args.protect_detail {
+                quantizer = Box::new(quantizer::Detail {
+                    quantizer,
+                    width: img.width() as usize,
+                    strength,
+                });
+            }
+            let result = quantizer.quantize(pixels, args.number);
+            drop(quantizer);
+
+            if let Some(stream) = stream {
+                stream.finish();
+            }
+
+            let (clusters, assignments) = result?;
+            if let Some((dedupe, preview)) = preview {
+                dedupe.insert(name, preview, &clusters);
+            }
+            if let (Some(dir), Some(key)) = (&args.cache_dir, &cache_key) {
+                let stored = timing::measure("cache", || {
+                    cache::store(Path::new(dir), key, &clusters, &assignments)
+                });
+                if let Err(err) = stored {
+                    logger::warn("cache-store", err);
+                }
+            }
+            (clusters, assignments)
+        }
+    };
+
+    // palettes of a sequence only move so far from the previous input
+    let (clusters, assignments) = match temporal {
+        Some(temporal) => {
+            let mut smoothed = temporal.smooth(clusters.clone());
+            if let Some(gamut) = &args.gamut {
+                smoothed = smoothed.iter().map(|c| gamut.snap(c)).collect();
+            }
+            match smoothed == clusters {
+                true => (clusters, assignments),
+                false => {
+                    let assignments = timing::measure("remap", || {
+                        args.colorspace
+                            .assign(pixels, &smoothed, &args.parameters())
+                    });
+                    (smoothed, assignments)
+                }
+            }
+        }
+        None => (clusters, assignments),
+    };
+
+    // the darkest and lightest entries are replaced with `--anchor-extremes`
+    let (clusters, assignments) = match &args.anchor_extremes {
+        Some(anchors) => {
+            let anchored = anchors.apply(&clusters);
+            let assignments = timing::measure("remap", || {
+                args.colorspace
+                    .assign(pixels, &anchored, &args.parameters())
+            });
+            (anchored, assignments)
+        }
+        None => (clusters, assignments),
+    };
+
+    // without clustering (eg. cached), the animation only shows the final palette
+    #[cfg(feature = "gif")]
+    if let Some(path) = &args.animate_iterations {
+        if frames.is_empty() {
+            frames.push(clusters.clone());
+        }
+        let path = cli::with_level(path, args.number);
+        let (width, swatches) = (img.width() as usize, args.as_palette);
+        timing::measure("animate", || {
+            let frames = animation::frames(pixels, width, &frames, args, swatches);
+            match &args.sequence_format {
+                Some(sequence) => {
+                    animation::write_sequence(&path, sequence, args.sequence_start, &frames)
+                }
+                None => animation::write(&path, &frames),
+            }
+        })?;
+    }
+
+    if (1..iterations).contains(&context.iterations()) {
+        logger::note(
+            "converged",
+            messages::text(
+                "converged",
+                &[
+                    ("input", &input),
+                    ("iterations", &context.iterations()),
+                    ("max", &iterations),
+                ],
+            ),
+        );
+    }
+
+    // let the user edit the palette, then remap pixels to the edited palette
+    #[cfg(feature = "edit")]
+    let (clusters, assignments) = match args.edit {
+        true => {
+            let clusters = edit::edit(&clusters, &assignments, &args.color_codes());
+            let assignments = timing::measure("remap", || {
+                args.colorspace
+                    .assign(pixels, &clusters, &args.parameters())
+            });
+            (clusters, assignments)
+        }
+        false => (clusters, assignments),
+    };
+
+    // pixels keep their chroma over their lightness with `--preserve-chroma`
+    let assignments = match args.preserve_chroma {
+        Some(weight) => timing::measure("remap", || {
+            colorspace::assign_by_chroma(pixels, &clusters, weight)
+        }),
+        None => assignments,
+    };
+
+    if let Some(dir) = &args.cluster_masks {
+        let dir = cli::with_level(dir, args.number);
+        let (width, height) = img.dimensions();
+        timing::measure("masks", || {
+            masks::write(&dir, width, height, &clusters, &assignments)
+        })?;
+    }
+
+    if let Some(path) = &args.overlay {
+        let path = cli::with_level(path, args.number);
+        timing::measure("overlay", || {
+            overlay::write(&path, img, &assignments, args.overlay_labels)
+        })?;
+    }
+
+    if args.stats {
+        let (width, height) = img.dimensions();
+        let origin = stats::origin(args, width as usize, height as usize, clustered);
+        let stats = timing::measure("stats", || {
+            stats::Stats::new(pixels, &clusters, &assignments)
+        });
+        stats.report(name, &origin);
+    }
+
+    // handle output
+    match output {
+        None if args.porcelain.is_some() => {
+            let colors = palette_colors(args, source, &clusters)?;
+            let version = args.porcelain.unwrap_or_default();
+            let mut stdout = std::io::stdout();
+            porcelain_handler(&colors, &assignments, &mut stdout, version, args.print0)
+                .map_err(|err| messages::text("write-palette", &[("error", &err)]))?;
         }
+
+        None => {
+            let colors = palette_colors(args, source, &clusters)?;
+            let preview = Capability::stdout();
+            if let Some(width) = args.blocks.filter(|_| preview.is_terminal()) {
+                let mut sorted = colors.clone();
+                palette::sort_by_luminance(&mut sorted);
+                let rgb = sorted.iter().map(Color::rgb).collect::<Vec<_>>();
+                println!("{}", term::swatches(preview, &rgb, width as usize));
+            }
+
+            let source = embedded_source(args, img, &colors, &assignments)?;
+            let options = palette::WriteOptions {
+                preview,
+                css: args.css_compat,
+                layout: args.layout(),
+                source: source.as_ref(),
+            };
+            let format = args.format.unwrap_or_default();
+            palette::write(&colors, &mut std::io::stdout(), format, &options)
+                .map_err(|err| messages::text("write-palette", &[("error", &err)]))?;
+        }
+
+        Some(output_file) if output_format.is_some() && args.as_palette => {
+            let (colors, icc_profile) = output_colors(args, source, &clusters)?;
+            let options = io::EncodeOptions {
+                format: args.output_format,
+                input_format: decoded.format,
+                indexed: args.indexed,
+                icc_profile: icc_profile.as_deref(),
+            };
+            io::encode_swatch(output_file, &colors, &options)?;
+        }
+
+        Some(output_file) if output_format.is_some() => {
+            let (width, height) = img.dimensions();
+            let dithered = match args.dither {
+                dither::Dither::None => None,
+                method => Some(timing::measure("dither", || {
+                    dither::dither(pixels, width, &clusters, method)
+                })),
+            };
+            let (colors, icc_profile) = output_colors(args, source, &clusters)?;
+            let options = io::EncodeOptions {
+                format: args.output_format,
+                input_format: decoded.format,
+                indexed: args.indexed,
+                icc_profile: icc_profile.as_deref(),
+            };
+            let assignments = dithered.as_ref().unwrap_or(&assignments);
+            match (args.grain, &source.alpha_channel) {
+                (None, None) if let Some(count) = args.reserve => {
+                    let reserved = &args.reserve_colors;
+                    let (colors, assignments) =
+                        indexed::reserve(&colors, assignments, count as usize, reserved);
+                    let options = io::EncodeOptions {
+                        indexed: Some(true),
+                        ..options
+                    };
+                    io::encode(output_file, width, height, &colors, &assignments, &options)?
+                }
+                (None, None) => {
+                    io::encode(output_file, width, height, &colors, assignments, &options)?
+                }
+                (grain, alpha_channel) => {
+                    let mut quantized = (assignments.iter())
+                        .map(|&i| colors[i].clone())
+                        .collect::<Vec<_>>();
+                    if let Some(alpha_channel) = alpha_channel {
+                        for (color, &alpha) in quantized.iter_mut().zip(alpha_channel) {
+                            *color = Color {
+                                data: color.rgb().into_iter().chain([alpha]).collect(),
+                                color_type: ColorType::Rgba8,
+                            };
+                        }
+                    }
+                    if let Some(amount) = grain {
+                        timing::measure("grain", || {
+                            grain::grain(&mut quantized, width as usize, amount, seed)
+                        });
+                    }
+                    io::encode_pixels(output_file, width, height, &quantized, &options)?;
+                }
+            }
+        }
+
+        Some(output_file) => {
+            let colors = palette_colors(args, source, &clusters)?;
+            let mut file = std::fs::File::create(output_file)
+                .map_err(|err| messages::text("create-output", &[("error", &err)]))?;
+            match args.porcelain {
+                Some(version) => {
+                    porcelain_handler(&colors, &assignments, &mut file, version, args.print0)
+                        .map_err(|err| messages::text("write-palette", &[("error", &err)]))?;
+                }
+                None => {
+                    let source = embedded_source(args, img, &colors, &assignments)?;
+                    let options = palette::WriteOptions {
+                        css: args.css_compat,
+                        layout: args.layout(),
+                        source: source.as_ref(),
+                        ..Default::default()
+                    };
+                    let format = args.format.unwrap_or_default();
+                    palette::write(&colors, &mut file, format, &options)
+                        .map_err(|err| messages::text("write-palette", &[("error", &err)]))?;
+                }
+            }
+        }
+    }
+
+    Ok((clusters, assignments))
+}
+
+/// colors of a quantized image in its output profile, and the icc profile to embed with
+/// them, see [`profile::tag`]
+fn output_colors(
+    args: &cli::Args,
+    source: &Input,
+    clusters: &[Color],
+) -> Result<(Vec<Color>, Option<Vec<u8>>), String> {
+    // colors of a palette file are not in the color profile of the image
+    let input = (source.decoded.icc_profile.as_deref()).filter(|_| args.palette.is_none());
+    let target = source.output_profile.as_ref();
+    timing::measure("profile", || {
+        profile::tag(clusters, input, target, args.colorspace)
+    })
+}
+
+/// colors of palette outputs, mapped into `--output-gamut` (see [`output_colors`])
+fn palette_colors(
+    args: &cli::Args,
+    source: &Input,
+    clusters: &[Color],
+) -> Result<Vec<Color>, String> {
+    match args.output_gamut {
+        Some(_) => output_colors(args, source, clusters).map(|(colors, _)| colors),
+        None => Ok(clusters.to_vec()),
     }
 }
+
+/// where a palette came from, to embed in json palettes with `--embed-thumbnail`: a
+/// tiny png thumbnail of the image and the share of pixels of each color
+fn embedded_source(
+    args: &cli::Args,
+    img: &DynamicImage,
+    colors: &[Color],
+    assignments: &[usize],
+) -> Result<Option<palette::Source>, String> {
+    if !args.embed_thumbnail {
+        return Ok(None);
+    }
+
+    let mut thumbnail = std::io::Cursor::new(Vec::new());
+    let rgb = DynamicImage::ImageRgb8(img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8());
+    (rgb.write_to(&mut thumbnail, ImageFormat::Png))
+        .map_err(|err| messages::text("encode-thumbnail", &[("error", &err)]))?;
+
+    let mut counts = vec![0usize; colors.len()];
+    for &i in assignments {
+        counts[i] += 1;
+    }
+    let total = assignments.len().max(1) as f64;
+    Ok(Some(palette::Source {
+        thumbnail: thumbnail.into_inner(),
+        shares: counts.iter().map(|&count| count as f64 / total).collect(),
+    }))
+}
+
+/// handle porcelain output to terminal or file, with the share of pixels of each color
+/// - records are terminated by newlines, or null bytes with `--print0`
+fn porcelain_handler<W>(
+    clusters: &[Color],
+    assignments: &[usize],
+    writer: &mut W,
+    version: Porcelain,
+    print0: bool,
+) -> std::io::Result<()>
+where
+    W: std::io::Write,
+{
+    let mut counts = vec![0usize; clusters.len()];
+    for &i in assignments {
+        counts[i] += 1;
+    }
+
+    // sort colors by luminance, keeping their counts (duplicate colors take turns)
+    let mut sorted = clusters.to_vec();
+    palette::sort_by_luminance(&mut sorted);
+    let total = assignments.len().max(1) as f64;
+    let mut taken = vec![false; clusters.len()];
+    let records = sorted
+        .into_iter()
+        .map(|color| {
+            let i = (0..clusters.len())
+                .find(|&i| !taken[i] && clusters[i] == color)
+                .unwrap_or(0);
+            taken[i] = true;
+            (color, counts[i] as f64 / total)
+        })
+        .collect::<Vec<_>>();
+
+    let terminator = if print0 { b'\0' } else { b'\n' };
+    version.write(writer, &records, terminator)
+}
@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use image::{DynamicImage, GrayImage, ImageFormat};
+
+use crate::colors::Color;
+use crate::io;
+use crate::messages;
+
+/// value of pixels belonging to a cluster in its mask (others are 0)
+const COVERED: u8 = u8::MAX;
+
+/// write one binary mask per cluster to a directory (created if missing), showing which
+/// pixels of the image are assigned to it, see `--cluster-masks`
+/// - masks are grayscale png images named by cluster index and color, eg. `0-1a2b3c.png`
+/// - pixels of `clusters[i]` are white in mask `i`, all other pixels black
+pub fn write(
+    dir: &Path,
+    width: u32,
+    height: u32,
+    clusters: &[Color],
+    assignments: &[usize],
+) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|err| {
+        messages::text(
+            "create-mask-dir",
+            &[("directory", &dir.display()), ("error", &err)],
+        )
+    })?;
+
+    for (i, cluster) in clusters.iter().enumerate() {
+        let data = (assignments.iter())
+            .map(|&assigned| if assigned == i { COVERED } else { 0 })
+            .collect();
+        let mask = GrayImage::from_vec(width, height, data)
+            .ok_or_else(|| messages::text("mask-size", &[]))?;
+
+        let [r, g, b] = cluster.rgb();
+        let path = dir.join(format!("{i}-{r:02x}{g:02x}{b:02x}.png"));
+        io::encode_image(
+            &path,
+            &DynamicImage::ImageLuma8(mask),
+            ImageFormat::Png,
+            None,
+        )?;
+    }
+    Ok(())
+}
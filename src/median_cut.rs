@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::Error;
+use crate::colors::Color;
+use crate::quantizer::{self, Quantizer};
+
+/// median cut quantization: starting with a box around all colors, the box with the
+/// widest channel range is split at the median of that channel, until there are `k` boxes
+/// - boxes hold distinct colors, weighted by their number of pixels
+pub struct MedianCut;
+
+/// distinct color (missing channels are 0) and its number of pixels
+type Entry = ([u8; 4], u64);
+
+/// range of entries (indices into the sorted entries) with their widest channel
+struct Bounds {
+    entries: Range<usize>,
+    channel: usize,
+    width: u8,
+}
+
+impl Quantizer for MedianCut {
+    fn quantize(&mut self, pixels: &[Color], k: usize) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        quantizer::validate(pixels, k)?;
+        let channels = pixels[0].data.len().min(4);
+
+        let mut counts = HashMap::new();
+        for pixel in pixels {
+            *counts.entry(channels_of(pixel)).or_insert(0) += 1;
+        }
+        let mut entries = counts.into_iter().collect::<Vec<Entry>>();
+        let mut boxes = vec![bounds(&entries, channels, 0..entries.len())];
+
+        while boxes.len() < k {
+            let widest = (boxes.iter().enumerate())
+                .filter(|(_, b)| b.width > 0)
+                .max_by_key(|(_, b)| b.width)
+                .map(|(i, _)| i);
+            let Some(i) = widest else {
+                break; // every box holds a single color
+            };
+
+            let Bounds {
+                entries: range,
+                channel,
+                ..
+            } = boxes.swap_remove(i);
+            let slice = &mut entries[range.clone()];
+            slice.sort_unstable_by_key(|(color, _)| color[channel]);
+
+            // first entry past half of the pixels
+            let total = slice.iter().map(|(_, n)| n).sum::<u64>();
+            let mut seen = 0;
+            let middle = (slice.iter())
+                .position(|(_, n)| {
+                    seen += n;
+                    2 * seen > total
+                })
+                .unwrap_or(0);
+
+            // split next to the median, between two different values
+            let median = slice[middle].0[channel];
+            let lower = slice.partition_point(|(color, _)| color[channel] < median);
+            let upper = slice.partition_point(|(color, _)| color[channel] <= median);
+            let split = match (lower, upper) {
+                (0, upper) => upper,
+                (lower, upper) if upper == slice.len() => lower,
+                (lower, upper) if middle - lower < upper - middle => lower,
+                (_, upper) => upper,
+            };
+
+            let split = range.start + split;
+            boxes.push(bounds(&entries, channels, range.start..split));
+            boxes.push(bounds(&entries, channels, split..range.end));
+        }
+
+        let color_type = pixels[0].color_type;
+        let mut index = HashMap::with_capacity(entries.len());
+        let palette = (boxes.iter().enumerate())
+            .map(|(i, b)| {
+                let entries = &entries[b.entries.clone()];
+                index.extend(entries.iter().map(|&(color, _)| (color, i)));
+
+                // rounded mean, weighted by the number of pixels
+                let count = entries.iter().map(|(_, n)| n).sum::<u64>().max(1);
+                let data = (0..channels)
+                    .map(|c| {
+                        let sum = entries.iter().map(|(color, n)| color[c] as u64 * n);
+                        ((sum.sum::<u64>() + count / 2) / count) as u8
+                    })
+                    .collect();
+                Color { color_type, data }
+            })
+            .collect();
+
+        let assignments = pixels.iter().map(|p| index[&channels_of(p)]).collect();
+        Ok((palette, assignments))
+    }
+}
+
+/// channels of a color as a fixed size key
+fn channels_of(color: &Color) -> [u8; 4] {
+    let channel = |i: usize| color.data.get(i).copied().unwrap_or(0);
+    [channel(0), channel(1), channel(2), channel(3)]
+}
+
+/// bounds of a range of entries, with the channel of the largest range of values
+fn bounds(entries: &[Entry], channels: usize, range: Range<usize>) -> Bounds {
+    let (channel, width) = (0..channels)
+        .map(|channel| {
+            let values = entries[range.clone()]
+                .iter()
+                .map(|(color, _)| color[channel]);
+            let (min, max) = values.fold((u8::MAX, 0), |(min, max), v| (min.min(v), max.max(v)));
+            (channel, max.saturating_sub(min))
+        })
+        .max_by_key(|&(_, width)| width)
+        .unwrap_or((0, 0));
+
+    Bounds {
+        entries: range,
+        channel,
+        width,
+    }
+}
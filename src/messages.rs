@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// the english catalog, compiled into every build (and a template for translations)
+const ENGLISH: &str = include_str!("../locale/en.txt");
+
+/// directory of translated catalogs without `QTIZER_LOCALEDIR`, set when building (eg. by
+/// distributions shipping translations) or `/usr/share/qtizer/locale`
+const LOCALEDIR: Option<&str> = option_env!("QTIZER_LOCALEDIR");
+
+/// the english catalog, see [`ENGLISH`]
+static BUILTIN: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// the catalog of the language of the user, if translated (see [`language`])
+static LOCALIZED: OnceLock<Option<HashMap<String, String>>> = OnceLock::new();
+
+/// a user-facing message of the catalog, with its `{name}` placeholders replaced by the
+/// arguments of the same name
+/// - messages come from the catalog of the user's language, or the english one for
+///   messages it does not translate
+/// - keys without any message are returned as they are
+///
+/// eg. `text("large-k", &[("k", &4097)])`
+pub fn text(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let localized = LOCALIZED.get_or_init(|| language().and_then(|lang| load(&lang)));
+    let builtin = BUILTIN.get_or_init(|| parse(ENGLISH));
+    let template = (localized.as_ref().and_then(|catalog| catalog.get(key)))
+        .or_else(|| builtin.get(key))
+        .map_or(key, String::as_str);
+    fill(template, args)
+}
+
+/// the language of messages, from `QTIZER_LANG` or the locale (`LC_ALL`, `LC_MESSAGES`
+/// or `LANG`), eg. `de_DE` for `de_DE.UTF-8`
+/// - `None` for english, and the `C` and `POSIX` locales
+pub fn language() -> Option<String> {
+    let locale = ["QTIZER_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())?;
+    let lang = locale.split(['.', '@']).next().unwrap_or_default();
+    match lang {
+        "C" | "POSIX" => None,
+        lang if lang == "en" || lang.starts_with("en_") => None,
+        lang => Some(lang.to_string()),
+    }
+}
+
+/// the catalog of a language, eg. `de_DE.txt` or else `de.txt` of the locale directory
+/// (`QTIZER_LOCALEDIR`, see [`LOCALEDIR`])
+fn load(lang: &str) -> Option<HashMap<String, String>> {
+    let dir = std::env::var_os("QTIZER_LOCALEDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(LOCALEDIR.unwrap_or("/usr/share/qtizer/locale")));
+    let base = lang.split('_').next().unwrap_or(lang);
+    [lang, base]
+        .iter()
+        .find_map(|name| std::fs::read_to_string(dir.join(format!("{name}.txt"))).ok())
+        .map(|source| parse(&source))
+}
+
+/// parse a catalog of `key = message` lines
+/// - blank lines and lines starting with `#` are ignored
+/// - `\n` in messages is a line break
+fn parse(source: &str) -> HashMap<String, String> {
+    (source.lines())
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_once(" = "))
+        .map(|(key, message)| (key.trim().to_string(), message.replace("\\n", "\n")))
+        .collect()
+}
+
+/// replace the `{name}` placeholders of a message by their arguments
+/// - `{{` and `}}` are literal braces, eg. `{{k}}` for `{k}`
+/// - placeholders without an argument are kept as they are
+fn fill(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        filled.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            filled.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        let placeholder = (rest.strip_prefix('{'))
+            .and_then(|r| r.split_once('}'))
+            .and_then(|(name, after)| Some((args.iter().find(|(n, _)| *n == name)?.1, after)));
+        match placeholder {
+            Some((value, after)) => {
+                filled.push_str(&value.to_string());
+                rest = after;
+            }
+            None => {
+                filled.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
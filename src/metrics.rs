@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::queue;
+
+/// upper bounds of the job duration histogram buckets, in seconds
+const BUCKETS: [f64; 11] = [0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// counters of a server, rendered in the prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// http responses by status code
+    responses: BTreeMap<u16, u64>,
+    succeeded: u64,
+    failed: u64,
+    /// job durations per bucket (not cumulative), with the last for `+Inf`
+    durations: [u64; BUCKETS.len() + 1],
+    duration_sum: f64,
+    pixels: u64,
+    cache_hits: u64,
+    /// duration of succeeded jobs, for throughput
+    pixel_seconds: f64,
+}
+
+impl Metrics {
+    /// count an http response
+    pub fn response(&self, status: u16) {
+        *self.lock().responses.entry(status).or_default() += 1;
+    }
+
+    /// count a job answered from the cache (without queueing)
+    pub fn cache_hit(&self) {
+        self.lock().cache_hits += 1;
+    }
+
+    /// record a finished job, with the number of pixels it quantized (`None` if it failed)
+    pub fn job(&self, duration: Duration, pixels: Option<u64>) {
+        let seconds = duration.as_secs_f64();
+        let mut inner = self.lock();
+
+        let bucket = BUCKETS.iter().position(|&le| seconds <= le);
+        inner.durations[bucket.unwrap_or(BUCKETS.len())] += 1;
+        inner.duration_sum += seconds;
+
+        match pixels {
+            Some(pixels) => {
+                inner.succeeded += 1;
+                inner.pixels += pixels;
+                inner.pixel_seconds += seconds;
+            }
+            None => inner.failed += 1,
+        }
+    }
+
+    /// render all metrics, including the load of the job queue
+    pub fn render(&self, queue: &queue::Status) -> String {
+        let inner = self.lock();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            out += &format!("# HELP qtizer_{name} {help}\n# TYPE qtizer_{name} {kind}\n");
+            for (labels, value) in samples {
+                out += &format!("qtizer_{name}{labels} {value}\n");
+            }
+        };
+        let sample = |labels: &str, value: &dyn ToString| (labels.to_string(), value.to_string());
+
+        metric(
+            "jobs_total",
+            "counter",
+            "Quantization jobs by outcome (timed out jobs may still finish).",
+            &[
+                sample("{outcome=\"succeeded\"}", &inner.succeeded),
+                sample("{outcome=\"failed\"}", &inner.failed),
+                sample("{outcome=\"rejected\"}", &queue.rejected),
+                sample("{outcome=\"timed_out\"}", &queue.timed_out),
+            ],
+        );
+        metric(
+            "jobs_running",
+            "gauge",
+            "Jobs currently quantizing.",
+            &[sample("", &queue.running)],
+        );
+        metric(
+            "jobs_waiting",
+            "gauge",
+            "Jobs waiting for a worker.",
+            &[sample("", &queue.waiting)],
+        );
+        metric(
+            "workers",
+            "gauge",
+            "Number of jobs quantizing at once.",
+            &[sample("", &queue.workers)],
+        );
+        metric(
+            "queue_capacity",
+            "gauge",
+            "Number of jobs that may wait for a worker.",
+            &[sample("", &queue.capacity)],
+        );
+
+        let mut cumulative = 0;
+        let mut buckets = Vec::new();
+        for (i, count) in inner.durations.iter().enumerate() {
+            cumulative += count;
+            let le = BUCKETS.get(i).map_or("+Inf".to_string(), f64::to_string);
+            buckets.push(sample(&format!("_bucket{{le=\"{le}\"}}"), &cumulative));
+        }
+        buckets.push(sample("_sum", &inner.duration_sum));
+        buckets.push(sample("_count", &cumulative));
+        metric(
+            "job_duration_seconds",
+            "histogram",
+            "Time spent quantizing per job, excluding the queue.",
+            &buckets,
+        );
+
+        metric(
+            "pixels_total",
+            "counter",
+            "Pixels quantized by succeeded jobs.",
+            &[sample("", &inner.pixels)],
+        );
+        let throughput = match inner.pixel_seconds {
+            0.0 => 0.0,
+            seconds => inner.pixels as f64 / seconds,
+        };
+        metric(
+            "pixels_per_second",
+            "gauge",
+            "Average throughput of succeeded jobs.",
+            &[sample("", &throughput)],
+        );
+
+        metric(
+            "cache_hits_total",
+            "counter",
+            "Jobs answered from the cache.",
+            &[sample("", &inner.cache_hits)],
+        );
+
+        let responses = (inner.responses.iter())
+            .map(|(code, count)| sample(&format!("{{code=\"{code}\"}}"), count))
+            .collect::<Vec<_>>();
+        metric(
+            "http_responses_total",
+            "counter",
+            "HTTP responses by status code.",
+            &responses,
+        );
+
+        out
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("metrics lock poisoned")
+    }
+}
@@ -0,0 +1,202 @@
+use crate::Error;
+use crate::colors::Color;
+use crate::quantizer::{self, Quantizer};
+
+/// depth of the tree, one level per bit of the rgb channels
+const DEPTH: usize = 8;
+
+/// octree quantization: colors are inserted into a tree branching on one bit of each
+/// rgb channel per level, then the sparsest of the deepest branches are merged into
+/// their parents until at most `k` leaves (colors) remain
+/// - alpha is averaged within leaves, but does not affect the tree structure
+pub struct Octree;
+
+#[derive(Clone, Default)]
+struct Node {
+    children: [Option<usize>; 8],
+    /// number of pixels in the subtree
+    count: u64,
+    /// channel sums of the pixels (of leaves)
+    sums: Vec<u64>,
+    leaf: bool,
+    /// palette index (of leaves)
+    index: usize,
+}
+
+impl Quantizer for Octree {
+    fn quantize(&mut self, pixels: &[Color], k: usize) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        quantizer::validate(pixels, k)?;
+
+        let mut nodes = vec![Node::default()];
+        // internal nodes by level, to merge the deepest ones first
+        let mut levels = vec![Vec::new(); DEPTH];
+        levels[0].push(0);
+        let mut leaves = 0;
+
+        for pixel in pixels {
+            let mut node = 0;
+            nodes[node].count += 1;
+
+            for level in 0..DEPTH {
+                let branch = branch(pixel, level);
+                node = match nodes[node].children[branch] {
+                    Some(child) => child,
+                    None => {
+                        let child = nodes.len();
+                        nodes.push(Node::default());
+                        nodes[node].children[branch] = Some(child);
+                        match level + 1 < DEPTH {
+                            true => levels[level + 1].push(child),
+                            false => leaves += 1,
+                        }
+                        child
+                    }
+                };
+                nodes[node].count += 1;
+            }
+
+            let leaf = &mut nodes[node];
+            leaf.leaf = true;
+            leaf.sums.resize(pixel.data.len().max(leaf.sums.len()), 0);
+            for (sum, &v) in leaf.sums.iter_mut().zip(&pixel.data) {
+                *sum += v as u64;
+            }
+        }
+
+        // merge the children (which are leaves) of the sparsest, deepest nodes
+        // (unless that leaves fewer than `k` colors, see below)
+        'reduce: for level in (0..DEPTH).rev() {
+            let mut candidates = std::mem::take(&mut levels[level]);
+            candidates.sort_by_key(|&node| nodes[node].count);
+
+            for node in candidates {
+                if leaves <= k {
+                    break 'reduce;
+                }
+
+                let children = (nodes[node].children.iter())
+                    .flatten()
+                    .copied()
+                    .collect::<Vec<_>>();
+                if leaves - (children.len() - 1) < k {
+                    break 'reduce;
+                }
+
+                let mut sums = Vec::new();
+                for &child in &children {
+                    add(&mut sums, &nodes[child].sums);
+                }
+
+                leaves -= children.len() - 1;
+                nodes[node] = Node {
+                    count: nodes[node].count,
+                    sums,
+                    leaf: true,
+                    ..Node::default()
+                };
+            }
+        }
+
+        // leaves in tree order
+        let mut entries = Vec::with_capacity(leaves);
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            match nodes[node].leaf {
+                true => {
+                    nodes[node].index = entries.len();
+                    entries.push((nodes[node].sums.clone(), nodes[node].count));
+                }
+                false => stack.extend(nodes[node].children.iter().rev().flatten()),
+            }
+        }
+
+        // remaining excess leaves are merged pairwise, closest (by the increase in
+        // squared error) first
+        let mut merged_into = (0..entries.len()).collect::<Vec<_>>();
+        let mut alive = (0..entries.len()).collect::<Vec<_>>();
+        while alive.len() > k {
+            let (a, b) = (0..alive.len())
+                .flat_map(|a| ((a + 1)..alive.len()).map(move |b| (a, b)))
+                .min_by(|&(a, b), &(c, d)| {
+                    let cost = |x: usize, y: usize| cost(&entries[alive[x]], &entries[alive[y]]);
+                    cost(a, b).total_cmp(&cost(c, d))
+                })
+                .unwrap_or((0, 1));
+
+            let (target, source) = (alive[a], alive.remove(b));
+            let (sums, count) = entries[source].clone();
+            add(&mut entries[target].0, &sums);
+            entries[target].1 += count;
+            merged_into[source] = target;
+        }
+
+        let color_type = pixels[0].color_type;
+        let palette = alive
+            .iter()
+            .map(|&i| Color {
+                color_type,
+                data: mean(&entries[i]),
+            })
+            .collect();
+
+        // palette index of each leaf
+        let index = (0..entries.len())
+            .map(|mut i| {
+                while merged_into[i] != i {
+                    i = merged_into[i];
+                }
+                alive.iter().position(|&j| j == i).unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+
+        let assignments = pixels
+            .iter()
+            .map(|pixel| {
+                let mut node = 0;
+                for level in 0..DEPTH {
+                    if nodes[node].leaf {
+                        break;
+                    }
+                    match nodes[node].children[branch(pixel, level)] {
+                        Some(child) => node = child,
+                        None => break,
+                    }
+                }
+                index[nodes[node].index]
+            })
+            .collect();
+
+        Ok((palette, assignments))
+    }
+}
+
+/// add channel sums
+fn add(sums: &mut Vec<u64>, other: &[u64]) {
+    sums.resize(sums.len().max(other.len()), 0);
+    for (sum, v) in sums.iter_mut().zip(other) {
+        *sum += v;
+    }
+}
+
+/// rounded mean of channel sums
+fn mean((sums, count): &(Vec<u64>, u64)) -> Vec<u8> {
+    sums.iter()
+        .map(|&sum| ((sum + count / 2) / (*count).max(1)) as u8)
+        .collect()
+}
+
+/// increase in squared error when merging two groups of pixels (ward's criterion)
+fn cost(a: &(Vec<u64>, u64), b: &(Vec<u64>, u64)) -> f64 {
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let distance = (mean_a.iter().zip(&mean_b))
+        .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+        .sum::<f64>();
+    distance * (a.1 * b.1) as f64 / (a.1 + b.1) as f64
+}
+
+/// child of a node at a level that a color belongs to, given by one bit of each channel
+fn branch(color: &Color, level: usize) -> usize {
+    let bit = DEPTH - 1 - level;
+    let [r, g, b] = color.rgb().map(|v| ((v >> bit) & 1) as usize);
+    (r << 2) | (g << 1) | b
+}
@@ -0,0 +1,132 @@
+use std::path::Path;
+
+use image::{ColorType, DynamicImage, ImageFormat, Rgb};
+
+use crate::colors::{self, Color};
+use crate::io;
+use crate::sheet::{self, BACKGROUND, GLYPH, SCALE};
+
+/// space around labels, in pixels
+const PADDING: usize = 2;
+
+/// write the original image with the boundaries of cluster regions drawn over it, see
+/// `--overlay`
+/// - pixels whose right or lower neighbor belongs to another cluster are drawn black on
+///   light pixels and white on dark pixels
+/// - with `labels`, regions large enough to fit a label show their cluster index, larger
+///   regions first, skipping labels that would overlap others
+/// - the image is written in the format of the extension of `path`, without alpha
+pub fn write(
+    path: &Path,
+    img: &DynamicImage,
+    assignments: &[usize],
+    labels: bool,
+) -> Result<(), String> {
+    let format = ImageFormat::from_path(path)
+        .map_err(|_| io::Error::UnsupportedOutput(path.display().to_string()))?;
+
+    let mut overlay = img.to_rgb8();
+    let (width, height) = (overlay.width() as usize, overlay.height() as usize);
+    for (i, &assigned) in assignments.iter().enumerate() {
+        let (x, y) = (i % width, i / width);
+        let right = x + 1 < width && assignments[i + 1] != assigned;
+        let below = y + 1 < height && assignments[i + width] != assigned;
+        if right || below {
+            let pixel = overlay.get_pixel_mut(x as u32, y as u32);
+            let color = Color {
+                color_type: ColorType::Rgb8,
+                data: pixel.0.to_vec(),
+            };
+            *pixel = Rgb(match colors::is_light(&color) {
+                true => [0; 3],
+                false => [u8::MAX; 3],
+            });
+        }
+    }
+
+    if labels {
+        let mut regions = regions(assignments, width);
+        regions.sort_by_key(|&(_, _, area)| std::cmp::Reverse(area));
+        let mut drawn: Vec<(usize, usize, usize, usize)> = Vec::new();
+        for (cluster, anchor, area) in regions {
+            let label = cluster.to_string();
+            let label_width = sheet::text_width(&label) + 2 * PADDING;
+            let label_height = GLYPH.1 * SCALE + 2 * PADDING;
+            if area < 2 * label_width * label_height {
+                continue;
+            }
+
+            // labels are centered on their anchor, within the image
+            let (x, y) = (anchor % width, anchor / width);
+            let left = (x.saturating_sub(label_width / 2)).min(width.saturating_sub(label_width));
+            let top = (y.saturating_sub(label_height / 2)).min(height.saturating_sub(label_height));
+            let overlaps = drawn.iter().any(|&(l, t, w, h)| {
+                left < l + w && l < left + label_width && top < t + h && t < top + label_height
+            });
+            if overlaps {
+                continue;
+            }
+            drawn.push((left, top, label_width, label_height));
+
+            for (dx, dy) in
+                (0..label_width).flat_map(|dx| (0..label_height).map(move |dy| (dx, dy)))
+            {
+                let (px, py) = ((left + dx) as u32, (top + dy) as u32);
+                if px < overlay.width() && py < overlay.height() {
+                    overlay.put_pixel(px, py, Rgb(BACKGROUND));
+                }
+            }
+            sheet::draw_text(&mut overlay, left + PADDING, top + PADDING, &label);
+        }
+    }
+
+    io::encode_image(path, &DynamicImage::ImageRgb8(overlay), format, None)?;
+    Ok(())
+}
+
+/// connected regions of pixels assigned to the same cluster (horizontally or vertically
+/// adjacent), as their cluster, the index of the pixel of the region nearest to its
+/// centroid, and their number of pixels
+fn regions(assignments: &[usize], width: usize) -> Vec<(usize, usize, usize)> {
+    let mut visited = vec![false; assignments.len()];
+    let mut regions = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..assignments.len() {
+        if visited[start] {
+            continue;
+        }
+        let cluster = assignments[start];
+        visited[start] = true;
+        stack.push(start);
+
+        let mut pixels = Vec::new();
+        while let Some(i) = stack.pop() {
+            pixels.push(i);
+            let (x, y) = (i % width, i / width);
+            let neighbors = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                Some(i + width).filter(|&j| j < assignments.len()),
+            ];
+            for j in neighbors.into_iter().flatten() {
+                if !visited[j] && assignments[j] == cluster {
+                    visited[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+
+        let count = pixels.len() as f64;
+        let cx = pixels.iter().map(|&i| (i % width) as f64).sum::<f64>() / count;
+        let cy = pixels.iter().map(|&i| (i / width) as f64).sum::<f64>() / count;
+        let distance =
+            |i: usize| ((i % width) as f64 - cx).powi(2) + ((i / width) as f64 - cy).powi(2);
+        let anchor = (pixels.iter().copied())
+            .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+            .unwrap_or(start);
+        regions.push((cluster, anchor, pixels.len()));
+    }
+    regions
+}
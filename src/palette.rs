@@ -0,0 +1,1267 @@
+use image::ColorType;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::Error;
+use crate::colors::{self, Color};
+use crate::histogram::Histogram;
+use crate::messages;
+use crate::term::{self, Capability};
+
+/// number of lightness levels in a signature
+const LIGHTNESS_LEVELS: usize = 4;
+
+/// number of hue sectors in a signature (plus one achromatic bin per lightness level)
+const HUE_SECTORS: usize = 8;
+
+/// chroma (max - min channel) below which colors count as achromatic
+const ACHROMATIC_CHROMA: u8 = 24;
+
+/// names of hues, by sectors of 30 degrees centered on red, orange, ...
+const HUE_NAMES: [&str; 12] = [
+    "red", "orange", "yellow", "lime", "green", "mint", "cyan", "azure", "blue", "violet",
+    "magenta", "pink",
+];
+
+/// contrast ratio of color codes to their swatch below which they are underlined
+/// (wcag aaa for normal text; black or white text always reaches aa, 4.5)
+const MIN_TEXT_CONTRAST: f64 = 7.0;
+
+/// number of bins in a palette signature
+pub const SIGNATURE_LEN: usize = LIGHTNESS_LEVELS * (HUE_SECTORS + 1);
+
+/// a set of colors, each weighted by the share of the image it represents
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: Vec<Color>,
+    /// relative weights of `colors`, summing to 1
+    pub weights: Vec<f64>,
+}
+
+/// fixed-size perceptual descriptor of a palette
+///
+/// colors are binned by lightness and hue (with a separate achromatic bin per
+/// lightness level), weighted by their share of the palette. hue is assigned
+/// softly to the two nearest sectors, so similar colors produce similar signatures.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature(pub [f32; SIGNATURE_LEN]);
+
+impl Palette {
+    /// create a palette with equally weighted colors
+    pub fn new(colors: Vec<Color>) -> Self {
+        let weights = vec![1.0; colors.len()];
+        Self::with_weights(colors, weights)
+    }
+
+    /// create a palette with weighted colors (weights are normalized)
+    pub fn with_weights(colors: Vec<Color>, weights: Vec<f64>) -> Self {
+        let total = weights.iter().sum::<f64>();
+        let weights = match total {
+            0.0 => weights,
+            _ => weights.into_iter().map(|w| w / total).collect(),
+        };
+        Palette { colors, weights }
+    }
+
+    /// create a palette from the bins of a histogram, weighted by pixel count
+    pub fn from_histogram(histogram: &Histogram) -> Self {
+        let (colors, weights) = histogram.iter().map(|(c, n)| (c, n as f64)).unzip();
+        Self::with_weights(colors, weights)
+    }
+
+    /// read a palette file, in any format written by `--format` or `--porcelain`
+    /// - see [`Palette::from_bytes`]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let bytes = std::fs::read(path).map_err(|err| {
+            std::io::Error::new(
+                err.kind(),
+                messages::text("read-palette", &[("error", &err)]),
+            )
+        })?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// parse a palette, detecting its format
+    /// - lines are color codes (hex, rgb, hsl, hwb or oklch), optionally followed by a
+    ///   usage count, or several color codes separated by any delimiter (`--inline`)
+    /// - lines may also be terminated by null bytes (`--print0`)
+    /// - gimp palettes (`.gpl`) are supported, with names that are integers used as counts
+    /// - json palettes are arrays of color codes or of objects with a `hex` (or `rgb`,
+    ///   `rgba`) field, or objects with such a `colors` or `palette` array (eg. as
+    ///   printed by `--stream`)
+    /// - css palettes are custom properties of a `:root` rule
+    /// - adobe swatch exchange palettes (`.ase`) may hold rgb, gray, cmyk or lab colors
+    /// - porcelain records are weighted by their share of pixels
+    /// - colors without counts are weighted equally
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (mut colors, weights) = match bytes.starts_with(b"ASEF") {
+            true => parse_ase(bytes)?,
+            false => {
+                let source = std::str::from_utf8(bytes)
+                    .map_err(|_| Error::Parse(messages::text("binary-palette", &[])))?;
+                let start = source.trim_start();
+                match () {
+                    _ if start.starts_with(['[', '{']) => parse_json(source)?,
+                    _ if start.starts_with(":root") => parse_css(source)?,
+                    _ if start.starts_with("qtizer-porcelain") => parse_porcelain(source)?,
+                    _ => parse_lines(source)?,
+                }
+            }
+        };
+
+        if colors.is_empty() {
+            return Err(Error::Parse(messages::text("empty-palette-file", &[])));
+        }
+
+        // mixed entries are all treated as rgba, with opaque rgb entries
+        if colors.iter().any(|c| c.color_type == ColorType::Rgba8) {
+            for color in colors
+                .iter_mut()
+                .filter(|c| c.color_type == ColorType::Rgb8)
+            {
+                color.color_type = ColorType::Rgba8;
+                color.data.push(u8::MAX);
+            }
+        }
+
+        Ok(Self::with_weights(colors, weights))
+    }
+
+    /// compute the perceptual signature of the palette
+    pub fn signature(&self) -> Signature {
+        let mut bins = [0.0; SIGNATURE_LEN];
+
+        for (color, &weight) in self.colors.iter().zip(&self.weights) {
+            let [r, g, b] = color.rgb();
+
+            let level = (colors::luminance_level(color) as usize * LIGHTNESS_LEVELS / 256)
+                .min(LIGHTNESS_LEVELS - 1);
+            let row = level * (HUE_SECTORS + 1);
+
+            let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+            if max - min < ACHROMATIC_CHROMA {
+                bins[row + HUE_SECTORS] += weight as f32;
+                continue;
+            }
+
+            // split weight between the two nearest hue sectors
+            let sector = hue(r, g, b) / 360.0 * HUE_SECTORS as f64;
+            let (lower, fraction) = (sector.floor() as usize % HUE_SECTORS, sector.fract());
+            let upper = (lower + 1) % HUE_SECTORS;
+            bins[row + lower] += (weight * (1.0 - fraction)) as f32;
+            bins[row + upper] += (weight * fraction) as f32;
+        }
+
+        Signature(bins)
+    }
+}
+
+impl Signature {
+    /// distance between two signatures in `[0, 1]`
+    /// (0 for identical, 1 for completely disjoint signatures)
+    pub fn distance(&self, other: &Signature) -> f64 {
+        let l1 = self
+            .0
+            .iter()
+            .zip(&other.0)
+            .map(|(a, b)| (a - b).abs() as f64)
+            .sum::<f64>();
+        (l1 / 2.0).min(1.0)
+    }
+}
+
+/// descriptive names for colors, eg. `dark blue` or `light gray`
+/// - repeated names are numbered (`dark blue 2`), so names are unique
+pub fn names(colors: &[Color]) -> Vec<String> {
+    let mut counts = HashMap::new();
+    colors
+        .iter()
+        .map(|color| {
+            let name = name(color);
+            let count = counts.entry(name.clone()).or_insert(0);
+            *count += 1;
+            match *count {
+                1 => name,
+                n => format!("{name} {n}"),
+            }
+        })
+        .collect()
+}
+
+/// descriptive name of a color by its hue and lightness
+fn name(color: &Color) -> String {
+    let [r, g, b] = color.rgb();
+    let level = colors::luminance_level(color);
+
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    if max - min < ACHROMATIC_CHROMA {
+        let gray = match level {
+            ..32 => "black",
+            32..96 => "dark gray",
+            96..160 => "gray",
+            160..224 => "light gray",
+            _ => "white",
+        };
+        return gray.to_string();
+    }
+
+    let sector = ((hue(r, g, b) + 15.0) / 30.0) as usize % HUE_NAMES.len();
+    match level {
+        ..80 => format!("dark {}", HUE_NAMES[sector]),
+        80..176 => HUE_NAMES[sector].to_string(),
+        _ => format!("light {}", HUE_NAMES[sector]),
+    }
+}
+
+/// parse the colors and weights of a line-based (or gimp) palette
+fn parse_lines(source: &str) -> Result<(Vec<Color>, Vec<f64>), Error> {
+    let mut lines = source
+        .split(['\n', '\0'])
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()));
+    let gimp = source.starts_with("GIMP Palette");
+    if gimp {
+        lines.next();
+    }
+
+    let (mut colors, mut weights) = (Vec::new(), Vec::new());
+    for (line_number, line) in lines {
+        let entries = match gimp {
+            true if line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("Name:")
+                || line.starts_with("Columns:") =>
+            {
+                continue;
+            }
+            true => parse_gimp_entry(line).map(|entry| vec![entry]),
+            false if line.is_empty() => continue,
+            false => (parse_entry(line).map(|entry| vec![entry]))
+                .or_else(|| parse_inline(line).map(|c| c.into_iter().map(|c| (c, None)).collect())),
+        };
+        let entries = entries.ok_or_else(|| {
+            Error::Parse(messages::text(
+                "invalid-palette-entry",
+                &[("line", &line_number), ("entry", &line)],
+            ))
+        })?;
+        for (color, count) in entries {
+            colors.push(color);
+            weights.push(count.unwrap_or(1) as f64);
+        }
+    }
+
+    Ok((colors, weights))
+}
+
+/// parse the colors of a json palette (an array of color codes or color objects,
+/// possibly as the `colors` or `palette` field of an object)
+fn parse_json(source: &str) -> Result<(Vec<Color>, Vec<f64>), Error> {
+    let invalid = || Error::Parse(messages::text("invalid-json-palette", &[]));
+
+    let source = source.trim();
+    let array = match source.starts_with('{') {
+        true => ["\"colors\"", "\"palette\""]
+            .iter()
+            .find_map(|key| source.find(key).map(|i| &source[i + key.len()..]))
+            .and_then(|rest| rest.trim_start().strip_prefix(':'))
+            .ok_or_else(invalid)?,
+        false => source,
+    };
+    let mut rest = (array.trim_start().strip_prefix('['))
+        .ok_or_else(invalid)?
+        .trim_start();
+
+    let mut colors = Vec::new();
+    while !rest.starts_with(']') {
+        let tail = match rest.chars().next() {
+            Some('"') => {
+                let (code, tail) = rest[1..].split_once('"').ok_or_else(invalid)?;
+                colors.push(code.parse().map_err(Error::Parse)?);
+                tail
+            }
+            Some('{') => {
+                let (object, tail) = rest[1..].split_once('}').ok_or_else(invalid)?;
+                let color = parse_json_object(object).ok_or_else(|| {
+                    Error::Parse(messages::text(
+                        "invalid-json-entry",
+                        &[("entry", &format!("{{{object}}}"))],
+                    ))
+                })?;
+                colors.push(color);
+                tail
+            }
+            _ => return Err(invalid()),
+        };
+
+        let tail = tail.trim_start();
+        rest = tail.strip_prefix(',').unwrap_or(tail).trim_start();
+    }
+
+    let weights = vec![1.0; colors.len()];
+    Ok((colors, weights))
+}
+
+/// color of a json palette object (the body between braces), given by its `hex`
+/// color code, or its `rgb` or `rgba` channel array
+fn parse_json_object(object: &str) -> Option<Color> {
+    let field = |key: &str| {
+        let key = format!("\"{key}\"");
+        let value = &object[object.find(&key)? + key.len()..];
+        value.trim_start().strip_prefix(':').map(str::trim_start)
+    };
+
+    if let Some(hex) = field("hex") {
+        return hex.strip_prefix('"')?.split_once('"')?.0.parse().ok();
+    }
+
+    let (color_type, channels) = match field("rgba") {
+        Some(channels) => (ColorType::Rgba8, channels),
+        None => (ColorType::Rgb8, field("rgb")?),
+    };
+    let (channels, _) = channels.strip_prefix('[')?.split_once(']')?;
+    let data = (channels.split(','))
+        .map(|v| v.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    (data.len() == color_type.channel_count() as usize).then_some(Color { color_type, data })
+}
+
+/// parse the colors of css custom properties (`--name: <color code>;`)
+fn parse_css(source: &str) -> Result<(Vec<Color>, Vec<f64>), Error> {
+    let mut colors = Vec::new();
+    for declaration in source.split(';') {
+        let Some(start) = declaration.find("--") else {
+            continue;
+        };
+        let (name, value) = declaration[start..].split_once(':').ok_or_else(|| {
+            Error::Parse(messages::text(
+                "invalid-css-declaration",
+                &[("declaration", &declaration.trim())],
+            ))
+        })?;
+        let color = value.trim().parse().map_err(|err| {
+            Error::Parse(messages::text(
+                "invalid-css-property",
+                &[("property", &name.trim()), ("error", &err)],
+            ))
+        })?;
+        colors.push(color);
+    }
+
+    let weights = vec![1.0; colors.len()];
+    Ok((colors, weights))
+}
+
+/// parse the records of porcelain output, weighted by their share of pixels
+/// - records may be terminated by newlines or null bytes (`--print0`)
+fn parse_porcelain(source: &str) -> Result<(Vec<Color>, Vec<f64>), Error> {
+    let mut records = source.split(['\n', '\0']).map(str::trim);
+    match records.next() {
+        Some("qtizer-porcelain v1") => {}
+        header => {
+            return Err(Error::Parse(messages::text(
+                "porcelain-version",
+                &[("header", &header.unwrap_or_default())],
+            )));
+        }
+    }
+
+    let (mut colors, mut weights) = (Vec::new(), Vec::new());
+    for record in records.filter(|r| !r.is_empty()) {
+        let invalid = || Error::Parse(messages::text("invalid-porcelain", &[("record", &record)]));
+        let fields = record.split('\t').collect::<Vec<_>>();
+        let [_, r, g, b, a, share] = fields[..] else {
+            return Err(invalid());
+        };
+
+        let mut data = [r, g, b, a]
+            .iter()
+            .map(|v| v.parse::<u8>().ok())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        let color_type = match data[3] {
+            u8::MAX => {
+                data.pop();
+                ColorType::Rgb8
+            }
+            _ => ColorType::Rgba8,
+        };
+        colors.push(Color { color_type, data });
+        weights.push(share.parse::<f64>().map_err(|_| invalid())?);
+    }
+
+    Ok((colors, weights))
+}
+
+/// parse the colors of an adobe swatch exchange file (see `write_ase` in [`colors`])
+/// - groups are flattened, cmyk colors are converted without a color profile
+#[cfg(feature = "ase")]
+fn parse_ase(bytes: &[u8]) -> Result<(Vec<Color>, Vec<f64>), Error> {
+    let invalid = || Error::Parse(messages::text("malformed-ase", &[]));
+
+    // signature, version and block count
+    let mut rest = bytes.get(12..).ok_or_else(invalid)?;
+    let mut colors = Vec::new();
+    while !rest.is_empty() {
+        let kind = u16::from_be_bytes(take(&mut rest).ok_or_else(invalid)?);
+        let length = u32::from_be_bytes(take(&mut rest).ok_or_else(invalid)?) as usize;
+        let mut block = rest.get(..length).ok_or_else(invalid)?;
+        rest = &rest[length..];
+
+        // group start and end blocks carry no colors
+        if kind != 1 {
+            continue;
+        }
+
+        let name_length = u16::from_be_bytes(take(&mut block).ok_or_else(invalid)?) as usize;
+        block = block.get(2 * name_length..).ok_or_else(invalid)?;
+        let model = take::<4>(&mut block).ok_or_else(invalid)?;
+        let mut values = |n: usize| {
+            (0..n)
+                .map(|_| take(&mut block).map(|v| f32::from_be_bytes(v) as f64))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(invalid)
+        };
+        let channel = |v: f64| (v * u8::MAX as f64).round().clamp(0.0, u8::MAX as f64) as u8;
+
+        let color = match &model {
+            b"RGB " => {
+                let data = values(3)?.into_iter().map(channel).collect();
+                Color {
+                    color_type: ColorType::Rgb8,
+                    data,
+                }
+            }
+            b"Gray" => Color {
+                color_type: ColorType::Rgb8,
+                data: vec![channel(values(1)?[0]); 3],
+            },
+            b"CMYK" => {
+                let cmyk = values(4)?;
+                let data = cmyk[..3]
+                    .iter()
+                    .map(|v| channel((1.0 - v) * (1.0 - cmyk[3])))
+                    .collect();
+                Color {
+                    color_type: ColorType::Rgb8,
+                    data,
+                }
+            }
+            b"LAB " => {
+                let lab = values(3)?;
+                colors::from_lab([lab[0] * 100.0, lab[1], lab[2]])
+            }
+            model => {
+                return Err(Error::Parse(messages::text(
+                    "ase-color-model",
+                    &[("model", &String::from_utf8_lossy(model).trim())],
+                )));
+            }
+        };
+        colors.push(color);
+    }
+
+    let weights = vec![1.0; colors.len()];
+    Ok((colors, weights))
+}
+
+/// ase palettes, without the `ase` feature
+#[cfg(not(feature = "ase"))]
+fn parse_ase(_: &[u8]) -> Result<(Vec<Color>, Vec<f64>), Error> {
+    Err(Error::Parse(crate::missing_feature(
+        &messages::text("ase-format", &[]),
+        "ase",
+    )))
+}
+
+/// split `N` bytes off the front of a slice
+#[cfg(feature = "ase")]
+fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+    let (head, tail) = bytes.split_first_chunk::<N>()?;
+    *bytes = tail;
+    Some(*head)
+}
+
+/// parse color codes on a single line, separated by any delimiter (eg. as written by `--inline`)
+fn parse_inline(line: &str) -> Option<Vec<Color>> {
+    let mut colors = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c == '#' || c.is_ascii_alphabetic()) {
+        let code = &rest[start..];
+        let end = match code.strip_prefix('#') {
+            Some(digits) => {
+                1 + digits
+                    .find(|c: char| !c.is_ascii_hexdigit())
+                    .unwrap_or(digits.len())
+            }
+            None => code.find(')')? + 1,
+        };
+        colors.push(code[..end].parse().ok()?);
+        rest = &code[end..];
+    }
+
+    (!colors.is_empty()).then_some(colors)
+}
+
+/// parse a color code, optionally followed by whitespace and a usage count
+fn parse_entry(line: &str) -> Option<(Color, Option<u64>)> {
+    let weighted = line
+        .rsplit_once(char::is_whitespace)
+        .and_then(|(code, count)| Some((code.parse().ok()?, Some(count.parse().ok()?))));
+    weighted.or_else(|| line.parse().ok().map(|color| (color, None)))
+}
+
+/// parse a gimp palette entry (`r g b [name]`), using integer names as usage counts
+fn parse_gimp_entry(line: &str) -> Option<(Color, Option<u64>)> {
+    let mut fields = line.split_whitespace();
+    let data = (0..3)
+        .map(|_| fields.next()?.parse::<u8>().ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let name = fields.collect::<Vec<_>>().join(" ");
+
+    let color = Color {
+        color_type: ColorType::Rgb8,
+        data,
+    };
+    Some((color, name.parse().ok()))
+}
+
+/// hsv hue of an rgb color in degrees `[0, 360)`
+fn hue(r: u8, g: u8, b: u8) -> f64 {
+    let (r, g, b) = (r as f64, g as f64, b as f64);
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let delta = max - min;
+
+    let hue = match max {
+        _ if delta == 0.0 => 0.0,
+        m if m == r => 60.0 * ((g - b) / delta),
+        m if m == g => 60.0 * ((b - r) / delta + 2.0),
+        _ => 60.0 * ((r - g) / delta + 4.0),
+    };
+    hue.rem_euclid(360.0)
+}
+
+/// options of [`write()`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriteOptions<'a> {
+    /// capability of the terminal written to, for color previews (none for files)
+    pub preview: Capability,
+    /// css syntax of rgb color codes (see [`PaletteFormat::css_color_codes`])
+    pub css: Option<CssSyntax>,
+    /// arrangement of color codes (ignored by formats other than color codes)
+    pub layout: Layout<'a>,
+    /// where the palette came from, embedded in json palettes (ignored by other formats)
+    pub source: Option<&'a Source>,
+}
+
+/// where a palette came from, for palette browsers to show without the original image
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Source {
+    /// png image of a tiny thumbnail of the image
+    pub thumbnail: Vec<u8>,
+    /// share of the pixels of the image of each color, in the order of the colors
+    pub shares: Vec<f64>,
+}
+
+/// write palette colors in a format, sorted by luminance (see [`sort_by_luminance`])
+pub fn write<W>(
+    colors: &[Color],
+    sink: &mut W,
+    format: PaletteFormat,
+    options: &WriteOptions,
+) -> std::io::Result<()>
+where
+    W: std::io::Write,
+{
+    let mut order = (0..colors.len()).collect::<Vec<_>>();
+    order.sort_by(|&i, &j| by_luminance(&colors[i], &colors[j]));
+    let colors = order.iter().map(|&i| colors[i].clone()).collect::<Vec<_>>();
+
+    let WriteOptions { preview, css, .. } = *options;
+    match options.layout {
+        _ if let Some(source) = options.source
+            && format == PaletteFormat::Json =>
+        {
+            let shares = (order.iter())
+                .map(|&i| source.shares.get(i).copied().unwrap_or_default())
+                .collect::<Vec<_>>();
+            write_json_source(sink, &colors, &shares, &source.thumbnail)
+        }
+        Layout::Inline(delimiter) if format.is_color_codes() => {
+            format.write_inline(sink, &colors, preview, css, delimiter)
+        }
+        Layout::Null if format.is_color_codes() => format.write_null(sink, &colors, css),
+        _ => format.write(sink, &colors, preview, css),
+    }
+}
+
+/// sort colors by descending luminance (ties broken by rgb value)
+pub fn sort_by_luminance(colors: &mut [Color]) {
+    colors.sort_by(by_luminance);
+}
+
+/// order of colors by descending luminance (ties broken by rgb value)
+fn by_luminance(x: &Color, y: &Color) -> std::cmp::Ordering {
+    let ([r_x, g_x, b_x], [r_y, g_y, b_y]) = (x.rgb(), y.rgb());
+    f64::total_cmp(&colors::luminance(y), &colors::luminance(x)) // descending luminance
+        .then_with(|| {
+            u32::from_be_bytes([r_x, g_x, b_x, 0]).cmp(&u32::from_be_bytes([r_y, g_y, b_y, 0]))
+        })
+}
+
+/// write a json palette object with where it came from, see [`Source`]
+/// - `colors` holds the colors as by [`PaletteFormat::Json`], each with its share of pixels
+/// - `order` holds the indices of the colors by descending share (dominant colors first)
+/// - `thumbnail` holds the thumbnail as a png data url
+fn write_json_source<W>(
+    writer: &mut W,
+    colors: &[Color],
+    shares: &[f64],
+    thumbnail: &[u8],
+) -> std::io::Result<()>
+where
+    W: std::io::Write,
+{
+    let names = names(colors);
+    writeln!(writer, "{{\n  \"colors\": [")?;
+    for (i, ((color, name), share)) in colors.iter().zip(&names).zip(shares).enumerate() {
+        let [r, g, b] = color.rgb();
+        let (key, alpha) = match color.alpha() {
+            Some(a) => ("rgba", format!(", {a}")),
+            None => ("rgb", String::new()),
+        };
+        let separator = if i + 1 < colors.len() { "," } else { "" };
+        writeln!(
+            writer,
+            "    {{\"name\": \"{name}\", \"hex\": \"{}\", \"{key}\": [{r}, {g}, {b}{alpha}], \"share\": {share:.6}}}{separator}",
+            ColorCodeFormat::Hex.color_code(color)
+        )?;
+    }
+    writeln!(writer, "  ],")?;
+
+    let mut order = (0..colors.len()).collect::<Vec<_>>();
+    order.sort_by(|&i, &j| shares[j].total_cmp(&shares[i]));
+    let order = order.iter().map(usize::to_string).collect::<Vec<_>>();
+    writeln!(writer, "  \"order\": [{}],", order.join(", "))?;
+
+    writeln!(
+        writer,
+        "  \"thumbnail\": \"data:image/png;base64,{}\"\n}}",
+        base64(thumbnail)
+    )
+}
+
+/// standard base64 encoding of bytes, with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| chunk.get(i).copied().unwrap_or(0) as usize);
+        let sextets = [
+            a >> 2,
+            (a & 0x03) << 4 | b >> 4,
+            (b & 0x0f) << 2 | c >> 6,
+            c & 0x3f,
+        ];
+        for (i, sextet) in sextets.into_iter().enumerate() {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[sextet] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+/// color code output format
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum ColorCodeFormat {
+    /// `#rrggbb` or `#rrggbbaa`
+    #[default]
+    Hex,
+    /// `rgb(r, g, b)` or `rgba(r, g, b, a)`
+    Rgb,
+    /// `rgb(r, g, b)` or `rgba(r, g, b, 0.67)`, with css alpha from 0 to 1
+    RgbCss,
+    /// `rgb(r g b)` or `rgb(r g b / 67%)` (css color level 4)
+    RgbModern,
+    /// `hsl(h s% l%)` or `hsl(h s% l% / 67%)`
+    Hsl,
+    /// `hwb(h w% b%)` or `hwb(h w% b% / 67%)`
+    Hwb,
+    /// `oklch(l% c h)` or `oklch(l% c h / 67%)`
+    Oklch,
+}
+
+/// css syntax of rgb color codes with alpha (`--css-compat`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CssSyntax {
+    /// `rgba(r, g, b, 0.67)`
+    #[default]
+    Legacy,
+    /// `rgb(r g b / 67%)`
+    Modern,
+}
+
+/// palette output format
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PaletteFormat {
+    /// `#rrggbb` or `#rrggbbaa`, one color per line
+    #[default]
+    Hex,
+    /// `rgb(r, g, b)` or `rgba(r, g, b, a)`, one color per line
+    Rgb,
+    /// `hsl(h s% l%)`, one color per line
+    Hsl,
+    /// `hwb(h w% b%)`, one color per line
+    Hwb,
+    /// `oklch(l% c h)`, one color per line
+    Oklch,
+    /// GIMP palette (`.gpl`, also read by Krita and Inkscape)
+    Gpl,
+    /// Adobe swatch exchange (`.ase`, binary)
+    Ase,
+    /// JSON array of named colors
+    Json,
+    /// CSS custom properties
+    Css,
+}
+
+impl PaletteFormat {
+    /// format for single color codes (eg. when streaming or editing palettes)
+    pub fn color_codes(self) -> ColorCodeFormat {
+        match self {
+            PaletteFormat::Rgb => ColorCodeFormat::Rgb,
+            PaletteFormat::Hsl => ColorCodeFormat::Hsl,
+            PaletteFormat::Hwb => ColorCodeFormat::Hwb,
+            PaletteFormat::Oklch => ColorCodeFormat::Oklch,
+            _ => ColorCodeFormat::Hex,
+        }
+    }
+
+    /// whether the format is plain color codes, one per line
+    pub fn is_color_codes(self) -> bool {
+        use PaletteFormat::*;
+        matches!(self, Hex | Rgb | Hsl | Hwb | Oklch)
+    }
+
+    /// format for single color codes, in valid css syntax if given
+    /// (hex codes already are valid css)
+    pub fn css_color_codes(self, css: Option<CssSyntax>) -> ColorCodeFormat {
+        match (self.color_codes(), css) {
+            (ColorCodeFormat::Rgb, Some(CssSyntax::Legacy)) => ColorCodeFormat::RgbCss,
+            (ColorCodeFormat::Rgb, Some(CssSyntax::Modern)) => ColorCodeFormat::RgbModern,
+            (codes, _) => codes,
+        }
+    }
+
+    /// file extension of palette files in the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            PaletteFormat::Hex
+            | PaletteFormat::Rgb
+            | PaletteFormat::Hsl
+            | PaletteFormat::Hwb
+            | PaletteFormat::Oklch => "txt",
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::Ase => "ase",
+            PaletteFormat::Json => "json",
+            PaletteFormat::Css => "css",
+        }
+    }
+
+    /// write a palette in the format
+    /// - color codes are previewed in color, given the capability of a terminal
+    /// - rgb codes are written in `css` syntax, if given
+    /// - colors are named by their hue and lightness, see [`names`]
+    pub fn write<W>(
+        self,
+        writer: &mut W,
+        colors: &[Color],
+        preview: Capability,
+        css: Option<CssSyntax>,
+    ) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let names = names(colors);
+        let hex = |color| ColorCodeFormat::Hex.color_code(color);
+
+        match self {
+            PaletteFormat::Hex
+            | PaletteFormat::Rgb
+            | PaletteFormat::Hsl
+            | PaletteFormat::Hwb
+            | PaletteFormat::Oklch => {
+                for color in colors {
+                    ColorCodeFormat::pretty_print_color_code(
+                        &self.css_color_codes(css),
+                        writer,
+                        color,
+                        preview,
+                    )?;
+                    writeln!(writer)?;
+                }
+            }
+
+            // alpha is not supported by gimp palettes
+            PaletteFormat::Gpl => {
+                writeln!(writer, "GIMP Palette\nName: qtizer\n#")?;
+                for (color, name) in colors.iter().zip(&names) {
+                    let [r, g, b] = color.rgb();
+                    writeln!(writer, "{r:>3} {g:>3} {b:>3}\t{name}")?;
+                }
+            }
+
+            PaletteFormat::Ase => write_ase(writer, colors, &names)?,
+
+            PaletteFormat::Json => {
+                writeln!(writer, "[")?;
+                for (i, (color, name)) in colors.iter().zip(&names).enumerate() {
+                    let [r, g, b] = color.rgb();
+                    let (key, alpha) = match color.alpha() {
+                        Some(a) => ("rgba", format!(", {a}")),
+                        None => ("rgb", String::new()),
+                    };
+                    let separator = if i + 1 < colors.len() { "," } else { "" };
+                    writeln!(
+                        writer,
+                        "  {{\"name\": \"{name}\", \"hex\": \"{}\", \"{key}\": [{r}, {g}, {b}{alpha}]}}{separator}",
+                        hex(color)
+                    )?;
+                }
+                writeln!(writer, "]")?;
+            }
+
+            PaletteFormat::Css => {
+                writeln!(writer, ":root {{")?;
+                for (color, name) in colors.iter().zip(&names) {
+                    writeln!(writer, "  --{}: {};", name.replace(' ', "-"), hex(color))?;
+                }
+                writeln!(writer, "}}")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// write a palette as color codes, each followed by a null byte (no previews)
+    pub fn write_null<W>(
+        self,
+        writer: &mut W,
+        colors: &[Color],
+        css: Option<CssSyntax>,
+    ) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        for color in colors {
+            write!(writer, "{}\0", self.css_color_codes(css).color_code(color))?;
+        }
+        Ok(())
+    }
+
+    /// write a palette as color codes on a single line, separated by `delimiter`
+    /// (see [`PaletteFormat::css_color_codes`])
+    pub fn write_inline<W>(
+        self,
+        writer: &mut W,
+        colors: &[Color],
+        preview: Capability,
+        css: Option<CssSyntax>,
+        delimiter: &str,
+    ) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let codes = self.css_color_codes(css);
+        for (i, color) in colors.iter().enumerate() {
+            if i > 0 {
+                write!(writer, "{delimiter}")?;
+            }
+            ColorCodeFormat::pretty_print_color_code(&codes, writer, color, preview)?;
+        }
+        writeln!(writer)
+    }
+}
+
+/// arrangement of color codes (see [`PaletteFormat::is_color_codes`])
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Layout<'a> {
+    /// one color per line
+    #[default]
+    Lines,
+    /// on a single line, separated by a delimiter
+    Inline(&'a str),
+    /// each color terminated by a null byte, without previews (`--print0`)
+    Null,
+}
+
+/// stable, versioned palette output for scripts (`--porcelain`)
+/// - never contains escape sequences, regardless of the terminal
+/// - fields and their order never change within a version
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Porcelain {
+    /// `qtizer-porcelain v1` header record, then one record per color:
+    /// `#rrggbb`, red, green, blue, alpha (255 when opaque) and share of pixels
+    /// (0 to 1, 6 decimals), separated by tabs
+    #[default]
+    V1,
+}
+
+impl Porcelain {
+    /// write records (colors with their share of pixels), each followed by `terminator`
+    pub fn write<W>(
+        self,
+        writer: &mut W,
+        colors: &[(Color, f64)],
+        terminator: u8,
+    ) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        match self {
+            Porcelain::V1 => {
+                write!(writer, "qtizer-porcelain v1")?;
+                writer.write_all(&[terminator])?;
+                for (color, share) in colors {
+                    let [r, g, b] = color.rgb();
+                    let a = color.alpha().unwrap_or(u8::MAX);
+                    write!(
+                        writer,
+                        "#{r:02x}{g:02x}{b:02x}\t{r}\t{g}\t{b}\t{a}\t{share:.6}"
+                    )?;
+                    writer.write_all(&[terminator])?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// write an adobe swatch exchange file (version 1.0, big endian)
+/// - each color is a block with its name (utf-16, null-terminated) and rgb as floats
+/// - alpha is not supported by the format
+#[cfg(feature = "ase")]
+fn write_ase<W>(writer: &mut W, colors: &[Color], names: &[String]) -> std::io::Result<()>
+where
+    W: std::io::Write,
+{
+    writer.write_all(b"ASEF")?;
+    writer.write_all(&1u16.to_be_bytes())?; // major version
+    writer.write_all(&0u16.to_be_bytes())?; // minor version
+    writer.write_all(&(colors.len() as u32).to_be_bytes())?;
+
+    for (color, name) in colors.iter().zip(names) {
+        let name = name.encode_utf16().chain([0]).collect::<Vec<_>>();
+        let length = 2 + 2 * name.len() + 4 + 3 * 4 + 2;
+
+        writer.write_all(&1u16.to_be_bytes())?; // color entry
+        writer.write_all(&(length as u32).to_be_bytes())?;
+        writer.write_all(&(name.len() as u16).to_be_bytes())?;
+        for unit in name {
+            writer.write_all(&unit.to_be_bytes())?;
+        }
+        writer.write_all(b"RGB ")?;
+        for v in color.rgb() {
+            writer.write_all(&(v as f32 / u8::MAX as f32).to_be_bytes())?;
+        }
+        writer.write_all(&2u16.to_be_bytes())?; // normal (not global or spot) color
+    }
+
+    Ok(())
+}
+
+/// ase palettes, without the `ase` feature
+#[cfg(not(feature = "ase"))]
+fn write_ase<W>(_: &mut W, _: &[Color], _: &[String]) -> std::io::Result<()> {
+    Err(std::io::Error::other(crate::missing_feature(
+        &messages::text("ase-format", &[]),
+        "ase",
+    )))
+}
+
+impl ColorCodeFormat {
+    /// pretty print a color code in the format
+    /// when writing to terminals, uses ansi escape codes for color preview
+    pub fn pretty_print_color_code<W>(
+        format: &ColorCodeFormat,
+        writer: &mut W,
+        color: &Color,
+        preview: Capability,
+    ) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        match format {
+            ColorCodeFormat::Hex => {
+                Self::colored_with_format(writer, color, preview, Self::hex_color_code)
+            }
+            ColorCodeFormat::Rgb => {
+                Self::colored_with_format(writer, color, preview, Self::rgb_color_code)
+            }
+            ColorCodeFormat::RgbCss => {
+                Self::colored_with_format(writer, color, preview, Self::rgb_css_color_code)
+            }
+            ColorCodeFormat::RgbModern => {
+                Self::colored_with_format(writer, color, preview, Self::rgb_modern_color_code)
+            }
+            ColorCodeFormat::Hsl => {
+                Self::colored_with_format(writer, color, preview, Self::hsl_color_code)
+            }
+            ColorCodeFormat::Hwb => {
+                Self::colored_with_format(writer, color, preview, Self::hwb_color_code)
+            }
+            ColorCodeFormat::Oklch => {
+                Self::colored_with_format(writer, color, preview, Self::oklch_color_code)
+            }
+        }
+    }
+
+    /// uncolored color code in the format
+    pub fn color_code(&self, color: &Color) -> String {
+        let mut buffer = Vec::new();
+        match self {
+            ColorCodeFormat::Hex => Self::hex_color_code(&mut buffer, color),
+            ColorCodeFormat::Rgb => Self::rgb_color_code(&mut buffer, color),
+            ColorCodeFormat::RgbCss => Self::rgb_css_color_code(&mut buffer, color),
+            ColorCodeFormat::RgbModern => Self::rgb_modern_color_code(&mut buffer, color),
+            ColorCodeFormat::Hsl => Self::hsl_color_code(&mut buffer, color),
+            ColorCodeFormat::Hwb => Self::hwb_color_code(&mut buffer, color),
+            ColorCodeFormat::Oklch => Self::oklch_color_code(&mut buffer, color),
+        }
+        .expect("writing to a buffer cannot fail");
+        String::from_utf8(buffer).expect("color codes are valid utf-8")
+    }
+
+    /// pretty print wrapper that colors output
+    /// given a callback providing the actual color formatting
+    fn colored_with_format<W>(
+        writer: &mut W,
+        color: &Color,
+        capability: Capability,
+        callback: fn(&mut W, &Color) -> std::io::Result<()>,
+    ) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        if !capability.is_terminal() {
+            // just print formatted color, no ansi codes
+            return callback(writer, color);
+        }
+
+        // ensure text has enough contrast to the background actually displayed
+        // (approximated on terminals without truecolor support)
+        let background = Color {
+            color_type: ColorType::Rgb8,
+            data: term::displayed(capability, color.rgb()).to_vec(),
+        };
+        let text = match colors::is_light(&background) {
+            false => [u8::MAX; 3], // dark  => white text
+            true => [0; 3],        // light => black text
+        };
+        let text_color = Color {
+            color_type: ColorType::Rgb8,
+            data: text.to_vec(),
+        };
+
+        // print ansi codes for colored background
+        let (fg, bg) = (
+            term::fg(capability, text),
+            term::bg(capability, color.rgb()),
+        );
+        write!(writer, "{fg}{bg}")?;
+
+        // underline text too low in contrast to be read comfortably
+        if colors::contrast_ratio(&text_color, &background) < MIN_TEXT_CONTRAST {
+            write!(writer, "{}", term::UNDERLINE)?;
+        }
+
+        // call the actual color printing function
+        callback(writer, color)?;
+
+        // reset colors
+        write!(writer, "{}", term::RESET)
+    }
+
+    /// print uncolored hex color code, with optional alpha
+    fn hex_color_code<W>(writer: &mut W, color: &Color) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let [r, g, b] = color.rgb();
+        write!(writer, "#{r:02x}{g:02x}{b:02x}")?;
+
+        match color.alpha() {
+            Some(a) => write!(writer, "{a:02x}"),
+            None => Ok(()),
+        }
+    }
+
+    /// print uncolored rgb color code, with optional alpha
+    fn rgb_color_code<W>(writer: &mut W, color: &Color) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let [r, g, b] = color.rgb();
+        match color.alpha() {
+            Some(a) => write!(writer, "rgba({r}, {g}, {b}, {a})"),
+            None => write!(writer, "rgb({r}, {g}, {b})"),
+        }
+    }
+
+    /// print uncolored rgb color code, with optional alpha from 0 to 1
+    fn rgb_css_color_code<W>(writer: &mut W, color: &Color) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let [r, g, b] = color.rgb();
+        match color.alpha() {
+            Some(a) => write!(writer, "rgba({r}, {g}, {b}, {})", css_alpha(a, 1.0, 1)),
+            None => write!(writer, "rgb({r}, {g}, {b})"),
+        }
+    }
+
+    /// print uncolored space-separated rgb color code, with optional alpha percentage
+    fn rgb_modern_color_code<W>(writer: &mut W, color: &Color) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let [r, g, b] = color.rgb();
+        match color.alpha() {
+            Some(a) => write!(writer, "rgb({r} {g} {b} / {}%)", css_alpha(a, 100.0, 0)),
+            None => write!(writer, "rgb({r} {g} {b})"),
+        }
+    }
+
+    /// print uncolored hsl color code, with optional alpha percentage
+    fn hsl_color_code<W>(writer: &mut W, color: &Color) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let [h, s, l] = colors::to_hsl(color);
+        let (h, s, l) = (hue_decimal(h, 1), decimal(s, 1), decimal(l, 1));
+        write!(writer, "hsl({h} {s}% {l}%{})", alpha_suffix(color))
+    }
+
+    /// print uncolored hwb color code, with optional alpha percentage
+    fn hwb_color_code<W>(writer: &mut W, color: &Color) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let [h, w, b] = colors::to_hwb(color);
+        let (h, w, b) = (hue_decimal(h, 1), decimal(w, 1), decimal(b, 1));
+        write!(writer, "hwb({h} {w}% {b}%{})", alpha_suffix(color))
+    }
+
+    /// print uncolored oklch color code, with optional alpha percentage
+    /// (the hue of grays is 0)
+    fn oklch_color_code<W>(writer: &mut W, color: &Color) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let [l, c, h] = colors::to_oklch(color);
+        let h = if c < 0.000005 { 0.0 } else { h };
+        let (l, c, h) = (decimal(l * 100.0, 2), decimal(c, 5), hue_decimal(h, 2));
+        write!(writer, "oklch({l}% {c} {h}{})", alpha_suffix(color))
+    }
+}
+
+/// css alpha percentage after a slash (eg. ` / 67%`), or nothing without alpha
+fn alpha_suffix(color: &Color) -> String {
+    match color.alpha() {
+        Some(a) => format!(" / {}%", css_alpha(a, 100.0, 0)),
+        None => String::new(),
+    }
+}
+
+/// number with at most `decimals` decimals, without trailing zeros
+fn decimal(value: f64, decimals: usize) -> String {
+    let s = format!("{value:.decimals$}");
+    let s = match s.contains('.') {
+        true => s.trim_end_matches('0').trim_end_matches('.'),
+        false => &s,
+    };
+    match s {
+        "-0" => "0".to_string(),
+        s => s.to_string(),
+    }
+}
+
+/// hue in degrees with at most `decimals` decimals, wrapping 360 to 0
+fn hue_decimal(hue: f64, decimals: usize) -> String {
+    match decimal(hue, decimals).as_str() {
+        "360" => "0".to_string(),
+        s => s.to_string(),
+    }
+}
+
+/// alpha scaled from 0 to `scale`, with the fewest decimals (at least `min_decimals`)
+/// that still parse back to the same alpha (when parsing colors, see [`Color`])
+fn css_alpha(alpha: u8, scale: f64, min_decimals: usize) -> String {
+    let value = alpha as f64 / 255.0 * scale;
+    (min_decimals..=3)
+        .map(|decimals| format!("{value:.decimals$}"))
+        .find(|s| {
+            s.parse::<f64>()
+                .is_ok_and(|v| (v / scale * 255.0).round() == alpha as f64)
+        })
+        .unwrap_or_else(|| format!("{value:.3}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// colors spread over the rgb cube, with alpha if given
+    fn colors(alpha: bool) -> Vec<Color> {
+        let levels = [0, 1, 37, 128, 200, 254, u8::MAX];
+        let mut colors = (levels.iter().enumerate())
+            .flat_map(|(i, &r)| {
+                (levels.iter().enumerate()).map(move |(j, &g)| {
+                    let b = levels[(i + 2 * j) % levels.len()];
+                    let a = levels[(i + j) % levels.len()];
+                    Color {
+                        color_type: ColorType::Rgba8,
+                        data: vec![r, g, b, a],
+                    }
+                    .with_alpha(alpha)
+                })
+            })
+            .collect::<Vec<_>>();
+        colors.dedup();
+        colors
+    }
+
+    #[test]
+    fn round_trip() {
+        use clap::ValueEnum;
+
+        for format in PaletteFormat::value_variants() {
+            if *format == PaletteFormat::Ase && !cfg!(feature = "ase") {
+                continue;
+            }
+            // gimp and ase palettes have no alpha
+            let has_alpha = !matches!(format, PaletteFormat::Gpl | PaletteFormat::Ase);
+
+            for alpha in [false, true] {
+                let written = colors(alpha);
+                let mut buffer = Vec::new();
+                write(&written, &mut buffer, *format, &WriteOptions::default())
+                    .expect("writing to a buffer cannot fail");
+                let read = Palette::from_bytes(&buffer)
+                    .unwrap_or_else(|err| panic!("{format:?} (alpha {alpha}): {err}"));
+
+                let mut expected = (written.iter())
+                    .map(|c| c.with_alpha(alpha && has_alpha))
+                    .collect::<Vec<_>>();
+                sort_by_luminance(&mut expected);
+                let mut read = read.colors;
+                sort_by_luminance(&mut read);
+                assert_eq!(read, expected, "{format:?} (alpha {alpha})");
+            }
+        }
+    }
+}
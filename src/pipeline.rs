@@ -0,0 +1,544 @@
+use image::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::cli::Args;
+use crate::colors::{self, Color};
+use crate::colorspace::ColorSpace;
+use crate::dither::{self, Dither};
+use crate::messages;
+use crate::palette::{self, PaletteFormat};
+use crate::quantizer::Algorithm;
+use crate::term::Capability;
+use crate::tonemap;
+use crate::{io, kmeans};
+
+/// supported operations, with the keys each of them accepts
+const OPERATIONS: &[(&str, &[&str])] = &[
+    ("resize", &["width", "height"]),
+    (
+        "cluster",
+        &[
+            "k",
+            "iterations",
+            "seed",
+            "alpha",
+            "colorspace",
+            "tolerance",
+            "algorithm",
+        ],
+    ),
+    ("merge-similar", &["threshold"]),
+    ("harmonize", &["scheme", "strength"]),
+    ("undo", &[]),
+    ("export", &["path", "format", "dither"]),
+];
+
+/// a value in a pipeline file
+#[derive(Clone, Debug)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+/// a single `[[step]]` of a pipeline file
+#[derive(Debug)]
+struct Step {
+    op: String,
+    /// line of the `[[step]]` header, for error messages
+    line: usize,
+    values: HashMap<String, Value>,
+}
+
+/// state threaded through the steps of a pipeline
+struct State {
+    img: DynamicImage,
+    palette: Option<Vec<Color>>,
+    /// color profile of the input, for exported images
+    icc_profile: Option<Vec<u8>>,
+    /// image and palette before each step that changed them, for `undo` steps
+    history: Vec<(DynamicImage, Option<Vec<Color>>)>,
+}
+
+/// hue offsets (in degrees) from the dominant hue of each harmony scheme
+const SCHEMES: &[(&str, &[f64])] = &[
+    ("analogous", &[-30.0, 0.0, 30.0]),
+    ("complementary", &[0.0, 180.0]),
+    ("split-complementary", &[0.0, 150.0, 210.0]),
+    ("triadic", &[0.0, 120.0, 240.0]),
+    ("tetradic", &[0.0, 90.0, 180.0, 270.0]),
+];
+
+/// palette entries with an oklch chroma below this are treated as neutrals by `harmonize`
+const NEUTRAL_CHROMA: f64 = 0.02;
+
+/// run the pipeline described in a file on an input image
+///
+/// pipeline files are a toml subset: a list of `[[step]]` tables, each with an `op`
+/// key and `key = value` pairs (strings, integers, floats, booleans), eg.
+/// ```toml
+/// [[step]]
+/// op = "cluster"
+/// k = 16
+///
+/// [[step]]
+/// op = "export"
+/// path = "quantized.png"
+/// ```
+/// cluster steps default to the `-k`, `-n`, `-a`, `-s`, `--colorspace`, `--tolerance` and
+/// `--algorithm` arguments, and `undo` steps revert the last step that changed the image
+/// or palette
+pub fn run(args: &Args, seed: u64, pipeline_file: &Path) -> Result<(), String> {
+    let steps = load(pipeline_file)?;
+
+    let decoded = io::decode_input(args, &args.inputs()[0])?;
+    let operator = args.tonemap.unwrap_or_default();
+    let mut state = State {
+        img: tonemap::tonemap(&decoded.image, operator).unwrap_or(decoded.image),
+        palette: None,
+        icc_profile: decoded.icc_profile,
+        history: Vec::new(),
+    };
+
+    for step in &steps {
+        execute(args, seed, step, &mut state).map_err(|err| {
+            messages::text(
+                "pipeline-step",
+                &[("op", &step.op), ("line", &step.line), ("error", &err)],
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// operations of the steps in a pipeline file, after validating it
+pub fn operations(pipeline_file: &Path) -> Result<Vec<String>, String> {
+    Ok(load(pipeline_file)?
+        .into_iter()
+        .map(|step| step.op)
+        .collect())
+}
+
+/// read, parse and validate a pipeline file
+fn load(pipeline_file: &Path) -> Result<Vec<Step>, String> {
+    let source = std::fs::read_to_string(pipeline_file)
+        .map_err(|err| messages::text("read-pipeline", &[("error", &err)]))?;
+    let steps = parse(&source)?;
+    validate(&steps)?;
+    Ok(steps)
+}
+
+/// parse the toml subset of pipeline files into steps
+fn parse(source: &str) -> Result<Vec<Step>, String> {
+    let mut steps: Vec<Step> = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let line = strip_comment(line).trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line == "[[step]]" {
+            steps.push(Step {
+                op: String::new(),
+                line: line_number,
+                values: HashMap::new(),
+            });
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(messages::text("pipeline-syntax", &[("line", &line_number)]));
+        };
+        let (key, value) = (key.trim(), parse_value(value.trim()));
+        let value = value.map_err(|err| {
+            messages::text("pipeline-line", &[("line", &line_number), ("error", &err)])
+        })?;
+
+        let Some(step) = steps.last_mut() else {
+            return Err(messages::text(
+                "pipeline-outside-step",
+                &[("line", &line_number), ("key", &key)],
+            ));
+        };
+
+        match (key, value) {
+            ("op", Value::String(op)) => step.op = op,
+            ("op", _) => return Err(messages::text("pipeline-op", &[("line", &line_number)])),
+            (key, value) => {
+                if step.values.insert(key.to_string(), value).is_some() {
+                    return Err(messages::text(
+                        "pipeline-duplicate-key",
+                        &[("line", &line_number), ("key", &key)],
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(steps)
+}
+
+/// remove a trailing `#` comment outside of strings
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// parse a string, integer, float or boolean value
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        let mut string = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            match (c, c == '\\') {
+                (_, false) => string.push(c),
+                (_, true) => match chars.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some(e @ ('"' | '\\')) => string.push(e),
+                    _ => return Err(messages::text("pipeline-escape", &[("value", &value)])),
+                },
+            }
+        }
+        return Ok(Value::String(string));
+    }
+
+    match value {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+
+    let number = value.replace('_', "");
+    if let Ok(integer) = number.parse::<i64>() {
+        return Ok(Value::Integer(integer));
+    }
+    if let Ok(float) = number.parse::<f64>() {
+        return Ok(Value::Float(float));
+    }
+
+    Err(messages::text("pipeline-value", &[("value", &value)]))
+}
+
+/// check operations and keys of all steps before running anything
+fn validate(steps: &[Step]) -> Result<(), String> {
+    if steps.is_empty() {
+        return Err(messages::text("pipeline-empty", &[]));
+    }
+
+    for step in steps {
+        let Some((_, keys)) = OPERATIONS.iter().find(|(op, _)| *op == step.op) else {
+            let supported = OPERATIONS.iter().map(|(op, _)| *op).collect::<Vec<_>>();
+            return Err(messages::text(
+                "pipeline-operation",
+                &[
+                    ("line", &step.line),
+                    ("op", &step.op),
+                    ("supported", &supported.join(", ")),
+                ],
+            ));
+        };
+
+        if let Some(key) = step.values.keys().find(|k| !keys.contains(&k.as_str())) {
+            return Err(messages::text(
+                "pipeline-key",
+                &[("line", &step.line), ("key", &key), ("op", &step.op)],
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// run a single step
+fn execute(args: &Args, seed: u64, step: &Step, state: &mut State) -> Result<(), String> {
+    if !matches!(step.op.as_str(), "export" | "undo") {
+        (state.history).push((state.img.clone(), state.palette.clone()));
+    }
+
+    match step.op.as_str() {
+        "resize" => {
+            let width = step.integer("width")?.map(|w| w as u32);
+            let height = step.integer("height")?.map(|h| h as u32);
+            if width.is_none() && height.is_none() {
+                return Err(messages::text("pipeline-resize", &[]));
+            }
+
+            // fit into the given bounds, preserving aspect ratio
+            state.img = state.img.resize(
+                width.unwrap_or(u32::MAX),
+                height.unwrap_or(u32::MAX),
+                imageops::FilterType::Lanczos3,
+            );
+        }
+
+        "cluster" => {
+            let k = step.integer("k")?.map_or(args.number, |k| k as usize);
+            let iterations = (step.integer("iterations")?).map_or(args.iterations, |n| n as usize);
+            let seed = step.integer("seed")?.map_or(seed, |s| s as u64);
+            let alpha = step.boolean("alpha")?.unwrap_or(args.alpha);
+            let alpha = alpha && !crate::is_opaque(&state.img);
+            let space: ColorSpace = match step.string("colorspace")? {
+                None => args.colorspace,
+                Some(space) => clap::ValueEnum::from_str(&space, true).map_err(|_| {
+                    messages::text("pipeline-colorspace", &[("colorspace", &space)])
+                })?,
+            };
+
+            let algorithm: Algorithm = match step.string("algorithm")? {
+                None => args.algorithm,
+                Some(algorithm) => clap::ValueEnum::from_str(&algorithm, true).map_err(|_| {
+                    messages::text("pipeline-algorithm", &[("algorithm", &algorithm)])
+                })?,
+            };
+
+            let pixels = crate::pixels(&state.img, alpha);
+            let tolerance = step.float("tolerance")?.unwrap_or(args.tolerance);
+            let mut context = (kmeans::Context::new(seed))
+                .with_tolerance(tolerance)
+                .with_rounding(args.rounding)
+                .with_parameters(args.parameters());
+            let mut quantizer = algorithm.quantizer(&mut context, space, iterations, |_, _| {});
+            let (palette, _) = quantizer.quantize(&pixels, k)?;
+            state.palette = Some(palette);
+        }
+
+        "merge-similar" => {
+            let threshold = step.float("threshold")?.unwrap_or(5.0);
+            let palette = state
+                .palette
+                .take()
+                .ok_or_else(|| messages::text("pipeline-no-palette", &[]))?;
+            let pixels = crate::pixels(
+                &state.img,
+                palette.first().is_some_and(|c| c.data.len() == 4),
+            );
+            state.palette = Some(merge_similar(palette, &pixels, threshold));
+        }
+
+        "harmonize" => {
+            let scheme = step.string("scheme")?;
+            let scheme = scheme.as_deref().unwrap_or("analogous");
+            let Some((_, offsets)) = SCHEMES.iter().find(|(name, _)| *name == scheme) else {
+                let supported = SCHEMES.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+                return Err(messages::text(
+                    "pipeline-scheme",
+                    &[("scheme", &scheme), ("supported", &supported.join(", "))],
+                ));
+            };
+            let strength = step.float("strength")?.unwrap_or(0.5);
+            if !(0.0..=1.0).contains(&strength) {
+                return Err(messages::text("pipeline-strength", &[]));
+            }
+
+            let palette = state
+                .palette
+                .take()
+                .ok_or_else(|| messages::text("pipeline-no-palette", &[]))?;
+            let pixels = crate::pixels(
+                &state.img,
+                palette.first().is_some_and(|c| c.data.len() == 4),
+            );
+            state.palette = Some(harmonize(palette, &pixels, offsets, strength));
+        }
+
+        "undo" => {
+            let (img, palette) =
+                (state.history.pop()).ok_or_else(|| messages::text("pipeline-undo", &[]))?;
+            state.img = img;
+            state.palette = palette;
+        }
+
+        "export" => {
+            let path =
+                (step.string("path")?).ok_or_else(|| messages::text("pipeline-path", &[]))?;
+            let palette = state
+                .palette
+                .as_ref()
+                .ok_or_else(|| messages::text("pipeline-no-palette", &[]))?;
+
+            let format = match step.string("format")? {
+                None => PaletteFormat::default(),
+                Some(format) => clap::ValueEnum::from_str(&format, true)
+                    .map_err(|_| messages::text("pipeline-format", &[("format", &format)]))?,
+            };
+
+            match path.as_str() {
+                "-" => {
+                    let options = palette::WriteOptions {
+                        preview: Capability::stdout(),
+                        css: args.css_compat,
+                        ..Default::default()
+                    };
+                    palette::write(palette, &mut std::io::stdout(), format, &options)
+                        .map_err(|err| messages::text("write-palette", &[("error", &err)]))?;
+                }
+                path if ImageFormat::from_path(path).is_ok() => {
+                    let pixels = crate::pixels(
+                        &state.img,
+                        palette.first().is_some_and(|c| c.data.len() == 4),
+                    );
+                    let (width, height) = state.img.dimensions();
+                    let method = match step.string("dither")? {
+                        None => Dither::None,
+                        Some(method) => clap::ValueEnum::from_str(&method, true).map_err(|_| {
+                            messages::text("pipeline-dither", &[("method", &method)])
+                        })?,
+                    };
+                    let assignments = dither::dither(&pixels, width, palette, method);
+                    let options = io::EncodeOptions {
+                        icc_profile: state.icc_profile.as_deref(),
+                        ..Default::default()
+                    };
+                    io::encode(
+                        Path::new(path),
+                        width,
+                        height,
+                        palette,
+                        &assignments,
+                        &options,
+                    )?;
+                    println!("{}", messages::text("saved-image", &[("path", &path)]));
+                }
+                path => {
+                    let mut file = std::fs::File::create(path)
+                        .map_err(|err| messages::text("create-output", &[("error", &err)]))?;
+                    let options = palette::WriteOptions {
+                        css: args.css_compat,
+                        ..Default::default()
+                    };
+                    palette::write(palette, &mut file, format, &options)
+                        .map_err(|err| messages::text("write-palette", &[("error", &err)]))?;
+                }
+            }
+        }
+
+        op => unreachable!("operation `{op}` should have been rejected by `validate`"),
+    }
+
+    Ok(())
+}
+
+/// repeatedly merge the two closest palette entries while their ΔE is below a threshold
+/// (merged colors are weighted by the number of pixels assigned to them)
+fn merge_similar(mut palette: Vec<Color>, pixels: &[Color], threshold: f64) -> Vec<Color> {
+    let mut counts = vec![0usize; palette.len()];
+    for i in colors::assign(pixels, &palette) {
+        counts[i] += 1;
+    }
+
+    while palette.len() > 1 {
+        let closest = (0..palette.len())
+            .flat_map(|i| ((i + 1)..palette.len()).map(move |j| (i, j)))
+            .map(|(i, j)| (i, j, colors::delta_e(&palette[i], &palette[j])))
+            .min_by(|(_, _, x), (_, _, y)| x.total_cmp(y));
+
+        let Some((i, j, _)) = closest.filter(|&(_, _, delta_e)| delta_e < threshold) else {
+            break;
+        };
+
+        palette[i] = colors::mix(&palette[i], &palette[j], counts[i] as f64, counts[j] as f64);
+        counts[i] += counts[j];
+        palette.remove(j);
+        counts.remove(j);
+    }
+
+    palette
+}
+
+/// rotate the hues of a palette towards the nearest hue of a harmony scheme, anchored at
+/// the hue of the chromatic entry with the most assigned pixels
+/// (neutrals are left untouched, as are lightness, chroma and alpha)
+fn harmonize(palette: Vec<Color>, pixels: &[Color], offsets: &[f64], strength: f64) -> Vec<Color> {
+    let mut counts = vec![0usize; palette.len()];
+    for i in colors::assign(pixels, &palette) {
+        counts[i] += 1;
+    }
+
+    let lch = palette.iter().map(colors::to_oklch).collect::<Vec<_>>();
+    let dominant = (0..palette.len())
+        .filter(|&i| lch[i][1] >= NEUTRAL_CHROMA)
+        .max_by_key(|&i| counts[i]);
+    let Some(dominant) = dominant else {
+        return palette;
+    };
+    let base = lch[dominant][2];
+
+    // signed difference between two hues, in (-180, 180]
+    let difference = |from: f64, to: f64| 180.0 - (from - to + 180.0).rem_euclid(360.0);
+
+    palette
+        .into_iter()
+        .zip(lch)
+        .map(|(color, [l, c, h])| {
+            if c < NEUTRAL_CHROMA {
+                return color;
+            }
+            let shift = (offsets.iter())
+                .map(|offset| difference(h, base + offset))
+                .min_by(|x, y| x.abs().total_cmp(&y.abs()))
+                .unwrap_or(0.0);
+
+            let mut harmonized = colors::from_oklch([l, c, h + strength * shift]);
+            harmonized.data.extend(color.alpha());
+            harmonized.color_type = color.color_type;
+            harmonized
+        })
+        .collect()
+}
+
+impl Step {
+    /// get an optional non-negative integer value
+    fn integer(&self, key: &str) -> Result<Option<i64>, String> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(i)) if *i >= 0 => Ok(Some(*i)),
+            Some(_) => Err(messages::text("pipeline-integer", &[("key", &key)])),
+        }
+    }
+
+    /// get an optional float value (integers are accepted too)
+    fn float(&self, key: &str) -> Result<Option<f64>, String> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::Float(f)) => Ok(Some(*f)),
+            Some(Value::Integer(i)) => Ok(Some(*i as f64)),
+            Some(_) => Err(messages::text("pipeline-number", &[("key", &key)])),
+        }
+    }
+
+    /// get an optional boolean value
+    fn boolean(&self, key: &str) -> Result<Option<bool>, String> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::Boolean(b)) => Ok(Some(*b)),
+            Some(_) => Err(messages::text("pipeline-boolean", &[("key", &key)])),
+        }
+    }
+
+    /// get an optional string value
+    fn string(&self, key: &str) -> Result<Option<String>, String> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(messages::text("pipeline-string", &[("key", &key)])),
+        }
+    }
+}
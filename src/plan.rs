@@ -0,0 +1,407 @@
+use image::*;
+use std::path::Path;
+
+use crate::cam16::ViewingConditions;
+use crate::cli::{self, Args};
+use crate::colors::Color;
+use crate::dither::Dither;
+use crate::palette::Palette;
+use crate::quantizer::{Algorithm, Sample, THUMBNAIL_PIXELS, Thumbnail};
+use crate::{batch, io, messages, pipeline};
+
+/// rough throughput of point-to-cluster distance calculations (release build)
+const DISTANCES_PER_SECOND: f64 = 1e8;
+
+/// validate inputs and print the resolved configuration, without decoding any image
+/// - image dimensions are read from file headers only
+/// - memory and time are rough estimates for planning long runs
+pub fn dry_run(args: &Args, seed: u64) -> Result<(), String> {
+    let inputs = args.inputs();
+
+    let mut total_pixels = 0u64;
+    let mut sampled_pixels = 0u64;
+    let mut largest = 0u64;
+    for (i, input) in inputs.iter().enumerate() {
+        let (width, height, format) = header(input, args.input_format)?;
+        let pixels = width as u64 * height as u64;
+        total_pixels += pixels;
+        sampled_pixels += match (args.sample, args.exact) {
+            (Some(sample), _) => sample.of(pixels as usize) as u64,
+            (None, false) => {
+                let (width, height) = (width as usize, height as usize);
+                let (width, height) = Thumbnail::dimensions(width, height, THUMBNAIL_PIXELS);
+                (width * height) as u64
+            }
+            (None, true) => pixels,
+        };
+        largest = largest.max(pixels);
+
+        print(
+            "dry-run-input",
+            &[
+                ("input", &input.display()),
+                ("width", &width),
+                ("height", &height),
+                ("format", &format!("{format:?}")),
+                ("pixels", &pixels),
+            ],
+        );
+        print(
+            "dry-run-output",
+            &[("output", &describe_output(args, i, input))],
+        );
+    }
+
+    if let Some(count) = args.reserve {
+        print("dry-run-reserved", &[("last", &(count - 1))]);
+    }
+    if let Some(path) = &args.sheet {
+        print("dry-run-sheet", &[("path", &path.display())]);
+    }
+    if let Some(path) = &args.animate_iterations {
+        match &args.sequence_format {
+            Some(sequence) => print(
+                "dry-run-sequence",
+                &[
+                    ("path", &path.display()),
+                    ("first", &sequence.name(args.sequence_start)),
+                ],
+            ),
+            None => print("dry-run-animation", &[("path", &path.display())]),
+        }
+    }
+    if let Some(dir) = &args.cluster_masks {
+        print("dry-run-masks", &[("dir", &dir.display())]);
+    }
+    if let Some(path) = &args.overlay {
+        let key = match args.overlay_labels {
+            true => "dry-run-overlay-labeled",
+            false => "dry-run-overlay",
+        };
+        print(key, &[("path", &path.display())]);
+    }
+
+    if let Some(distance) = args.temporal_smoothing {
+        print("dry-run-smoothing", &[("distance", &distance)]);
+    }
+    if let Some(threshold) = args.dedupe_palettes {
+        print("dry-run-dedupe", &[("threshold", &threshold)]);
+    }
+    if let Some(expected) = &args.verify {
+        match args.deterministic {
+            true => print("dry-run-verify-equal", &[("expected", &expected.display())]),
+            false => print(
+                "dry-run-verify",
+                &[
+                    ("tolerance", &args.verify_tolerance),
+                    ("expected", &expected.display()),
+                ],
+            ),
+        }
+    }
+    if let Some(weight) = args.preserve_chroma {
+        print("dry-run-remap", &[("weight", &weight)]);
+    }
+    if let Some(anchors) = &args.anchor_extremes {
+        let codes = args.color_codes();
+        print(
+            "dry-run-anchors",
+            &[
+                ("dark", &codes.color_code(&anchors.dark)),
+                ("light", &codes.color_code(&anchors.light)),
+            ],
+        );
+    }
+    if let Some(zones) = args.zones {
+        print(
+            "dry-run-zones",
+            &[
+                ("count", &zones.cells().len()),
+                ("rows", &zones.rows),
+                ("columns", &zones.columns),
+                ("format", &value_name(args.zones_format)),
+            ],
+        );
+        if let Some(address) = &args.zones_udp {
+            print("dry-run-zones-udp", &[("address", address)]);
+        }
+    }
+
+    if let Some(pipeline_file) = &args.pipeline {
+        let operations = pipeline::operations(pipeline_file)?;
+        print(
+            "dry-run-pipeline",
+            &[
+                ("path", &pipeline_file.display()),
+                ("operations", &operations.join(" -> ")),
+            ],
+        );
+    }
+
+    let palette = args
+        .palette
+        .as_deref()
+        .map(Palette::from_file)
+        .transpose()?;
+    let channels = if args.alpha { 4 } else { 3 };
+
+    // remapping to a palette is a single assignment pass
+    let limited = || {
+        (args.levels.iter())
+            .map(|&k| k.min(largest as usize))
+            .collect()
+    };
+    let (levels, iterations): (Vec<_>, _) = match &palette {
+        Some(palette) => (vec![palette.colors.len()], 1),
+        None if args.algorithm != Algorithm::Kmeans => (limited(), 1),
+        None => (limited(), args.iterations),
+    };
+
+    match (&args.palette, &palette) {
+        (Some(palette_file), Some(palette)) => {
+            print(
+                "dry-run-palette",
+                &[
+                    ("path", &palette_file.display()),
+                    ("colors", &palette.colors.len()),
+                ],
+            );
+            print("dry-run-alpha", &[("alpha", &describe_alpha(args))]);
+            print("dry-run-remap-palette", &[]);
+        }
+        _ => {
+            let list = |levels: &[usize]| {
+                let levels = levels.iter().map(ToString::to_string).collect::<Vec<_>>();
+                levels.join(", ")
+            };
+            let (k, requested) = (list(&levels), list(&args.levels));
+            let key = match k != requested {
+                true => "dry-run-k-limited",
+                false => "dry-run-k",
+            };
+            print(key, &[("k", &k), ("requested", &requested)]);
+            let kmeans = args.algorithm == Algorithm::Kmeans;
+            match args.auto_iterations {
+                _ if !kmeans => {}
+                true => print("dry-run-auto-iterations", &[]),
+                false => print("dry-run-iterations", &[("iterations", &iterations)]),
+            }
+            if kmeans && args.tolerance > 0.0 {
+                print("dry-run-tolerance", &[("tolerance", &args.tolerance)]);
+            }
+            if kmeans {
+                print(
+                    "dry-run-rounding",
+                    &[("rounding", &value_name(args.rounding))],
+                );
+            }
+            match args.sample {
+                Some(Sample::Fraction(fraction)) => print(
+                    "dry-run-sample-fraction",
+                    &[("percent", &(fraction * 100.0))],
+                ),
+                Some(Sample::Count(count)) => print("dry-run-sample-count", &[("count", &count)]),
+                None if args.exact => print("dry-run-no-thumbnail", &[]),
+                None => print("dry-run-thumbnail", &[("pixels", &THUMBNAIL_PIXELS)]),
+            }
+            print("dry-run-alpha", &[("alpha", &describe_alpha(args))]);
+            if kmeans {
+                print("dry-run-seed", &[("seed", &seed)]);
+                print(
+                    "dry-run-colorspace",
+                    &[(
+                        "colorspace",
+                        &format!("{:?}", args.colorspace).to_lowercase(),
+                    )],
+                );
+                if let Some(conditions) = args.viewing_conditions {
+                    let ViewingConditions {
+                        luminance,
+                        background,
+                        surround,
+                    } = conditions;
+                    print(
+                        "dry-run-viewing",
+                        &[
+                            ("luminance", &format!("{luminance:.1}")),
+                            ("background", &background),
+                            ("surround", &format!("{surround:?}").to_lowercase()),
+                        ],
+                    );
+                }
+                if let Some(weight) = args.luma_weight {
+                    print("dry-run-luma-weight", &[("weight", &weight)]);
+                }
+                if let Some(strength) = args.protect_detail {
+                    print("dry-run-detail", &[("weight", &(1.0 + strength))]);
+                }
+                if let Some(gamut) = &args.gamut {
+                    print("dry-run-within", &[("gamut", gamut)]);
+                }
+            }
+            match args.algorithm {
+                Algorithm::Kmeans => print("dry-run-kmeans", &[]),
+                algorithm => print(
+                    "dry-run-algorithm",
+                    &[("algorithm", &value_name(algorithm))],
+                ),
+            }
+        }
+    }
+    if args.dither != Dither::None {
+        print("dry-run-dither", &[("dither", &value_name(args.dither))]);
+    }
+    if let Some(operator) = args.tonemap {
+        print("dry-run-tonemap", &[("operator", &value_name(operator))]);
+    }
+    if let Some(gamut) = args.output_gamut {
+        print("dry-run-gamut", &[("gamut", &value_name(gamut))]);
+    }
+    if let Some(profile) = &args.output_profile {
+        print("dry-run-profile", &[("profile", profile)]);
+    }
+    if let Some(amount) = args.grain {
+        print("dry-run-grain", &[("amount", &amount)]);
+    }
+    print("dry-run-jobs", &[("jobs", &args.jobs())]);
+
+    // decoded rgba image + converted copy + pixel colors (with heap data) + assignments
+    let per_pixel = 4 + channels + size_of::<Color>() + channels.max(8) + size_of::<usize>();
+    let concurrent = match args.output_dir {
+        Some(_) => args.jobs().min(inputs.len()) as u64,
+        None => 1,
+    };
+    let memory = per_pixel as u64 * largest * concurrent;
+    print("dry-run-memory", &[("memory", &human_bytes(memory))]);
+
+    // clustering visits sampled (or thumbnail) pixels each iteration, all pixels are
+    // assigned once (per level)
+    let passes = sampled_pixels * iterations as u64 + total_pixels;
+    let distances = passes as f64 * levels.iter().sum::<usize>() as f64;
+    let seconds = distances / DISTANCES_PER_SECOND / args.jobs() as f64;
+    print("dry-run-time", &[("seconds", &format!("{seconds:.1}"))]);
+
+    Ok(())
+}
+
+/// print a line of the plan, see [`messages::text`]
+fn print(key: &str, args: &[(&str, &dyn std::fmt::Display)]) {
+    println!("{}", messages::text(key, args));
+}
+
+/// name of a value as given on the command line
+fn value_name(value: impl clap::ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |v| v.get_name().to_string())
+}
+
+/// read image dimensions and format from the file header
+/// (stdin is read completely, since it cannot be reopened)
+fn header(input: &Path, format: Option<ImageFormat>) -> Result<(u32, u32, ImageFormat), String> {
+    if cli::is_stdio(input) {
+        return dimensions(io::reader(input, format)?, input);
+    }
+
+    let error = |err: std::io::Error| {
+        messages::text(
+            "dry-run-open",
+            &[("input", &input.display()), ("error", &err)],
+        )
+    };
+    let mut reader = ImageReader::open(input).map_err(error)?;
+    match format {
+        Some(format) => reader.set_format(format),
+        None => reader = reader.with_guessed_format().map_err(error)?,
+    }
+
+    dimensions(reader, input)
+}
+
+/// read image dimensions and format of a reader
+fn dimensions<R>(reader: ImageReader<R>, input: &Path) -> Result<(u32, u32, ImageFormat), String>
+where
+    R: std::io::BufRead + std::io::Seek,
+{
+    let input = input.display();
+    let format = reader
+        .format()
+        .ok_or_else(|| messages::text("dry-run-format", &[("input", &input)]))?;
+    let (width, height) = reader
+        .into_dimensions()
+        .map_err(|err| messages::text("dry-run-header", &[("input", &input), ("error", &err)]))?;
+
+    Ok((width, height, format))
+}
+
+/// describe where and in which form the output for an input ends up
+fn describe_output(args: &Args, i: usize, input: &Path) -> String {
+    if args.pipeline.is_some() {
+        return messages::text("describe-output-pipeline", &[]);
+    }
+    if args.zones.is_some() {
+        return messages::text("describe-output-zones", &[]);
+    }
+
+    let output = match &args.output_dir {
+        Some(output_dir) => Some(batch::output_path(args, output_dir, i, input)),
+        None => args.output_path(),
+    };
+    let format = match args.porcelain {
+        Some(version) => messages::text(
+            "describe-output-porcelain",
+            &[("version", &value_name(version))],
+        ),
+        None => value_name(args.format.unwrap_or_default()),
+    };
+
+    let input_format = ImageFormat::from_path(input).ok().or(args.input_format);
+    match output {
+        None => messages::text("describe-output-stdout", &[("format", &format)]),
+        Some(path) => {
+            let key = match args.as_palette {
+                true => "describe-output-swatch",
+                false => "describe-output-image",
+            };
+            let image_format = (Some(&path).filter(|_| !args.palette_file_output()))
+                .and_then(|path| io::output_format(path, args.output_format, input_format));
+            let path = match cli::is_stdio(&path) {
+                true => messages::text("describe-output-stdout-path", &[]),
+                false => path.display().to_string(),
+            };
+            match image_format {
+                Some(image_format) => messages::text(
+                    key,
+                    &[("path", &path), ("format", &format!("{image_format:?}"))],
+                ),
+                None => messages::text(
+                    "describe-output-palette",
+                    &[("path", &path), ("format", &format)],
+                ),
+            }
+        }
+    }
+}
+
+/// format a byte count with binary units
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", units[unit])
+}
+
+/// whether pixels are quantized with alpha, see `--with-alpha` and `--preserve-alpha`
+fn describe_alpha(args: &Args) -> String {
+    let key = match (args.alpha, args.preserve_alpha) {
+        (_, true) => "describe-alpha-preserved",
+        (true, false) if args.alpha_dither => "describe-alpha-dithered",
+        (true, false) => "describe-alpha-yes",
+        (false, false) => "describe-alpha-no",
+    };
+    messages::text(key, &[])
+}
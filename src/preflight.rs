@@ -0,0 +1,90 @@
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+use image::ImageFormat;
+
+use crate::batch;
+use crate::cli::{self, Args};
+use crate::io;
+use crate::messages;
+
+/// check that all outputs of a run can be written, before any decoding or clustering
+/// - output directories (`--output-dir`, `--cluster-masks`) are created
+/// - a file that exists must be a writable file, otherwise its directory must exist and
+///   accept new files (probed with an empty file, removed again)
+/// - image outputs need the codec of their format, see [`io::compiled`]
+/// - outputs of `--pipeline` steps are not known in advance, and not checked
+pub fn check(args: &Args) -> Result<(), String> {
+    let levels = args.quantize_levels();
+    let per_level = |path: &PathBuf| {
+        (levels.iter())
+            .map(|&k| cli::with_level(path, k))
+            .collect::<Vec<_>>()
+    };
+
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    match &args.output_dir {
+        Some(dir) => {
+            dirs.push(dir.clone());
+            let inputs = args.inputs().iter().enumerate();
+            files.extend(inputs.map(|(i, input)| batch::output_path(args, dir, i, input)));
+        }
+        None => {
+            let output = args.output_path().filter(|o| !cli::is_stdio(o));
+            files.extend(output.iter().flat_map(per_level));
+        }
+    }
+    files.extend(args.sheet.iter().cloned());
+    match args.sequence_format {
+        Some(_) => dirs.extend(args.animate_iterations.iter().flat_map(per_level)),
+        None => files.extend(args.animate_iterations.iter().flat_map(per_level)),
+    }
+    files.extend(args.overlay.iter().flat_map(per_level));
+    dirs.extend(args.cluster_masks.iter().flat_map(per_level));
+
+    for dir in &dirs {
+        std::fs::create_dir_all(dir).map_err(|err| {
+            messages::text("preflight-dir", &[("dir", &dir.display()), ("error", &err)])
+        })?;
+    }
+    for file in &files {
+        if let Ok(format) = ImageFormat::from_path(file)
+            && !io::compiled(format)
+        {
+            return Err(io::Error::MissingCodec(format).to_string());
+        }
+        writable(file).map_err(|err| {
+            messages::text(
+                "preflight-file",
+                &[("file", &file.display()), ("error", &err)],
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// check that a file can be written, without changing it (see [`check`])
+fn writable(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        return Err(std::io::Error::other(messages::text("is-directory", &[])));
+    }
+    if path.exists() {
+        return OpenOptions::new().write(true).open(path).map(drop);
+    }
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        let missing = messages::text("missing-directory", &[("dir", &dir.display())]);
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, missing));
+    }
+    let probe = dir.join(format!(".qtizer-preflight-{}", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    std::fs::remove_file(probe)
+}
@@ -0,0 +1,134 @@
+use moxcms::{ColorProfile, Layout, TransformOptions};
+
+use crate::colors::Color;
+use crate::colorspace::ColorSpace;
+use crate::messages;
+
+/// gamut that palettes and images are written in, see `--output-gamut`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Gamut {
+    /// Standard gamut of most displays and the web
+    Srgb,
+    /// Wide gamut of recent displays (eg. of phones and laptops)
+    DisplayP3,
+}
+
+impl Gamut {
+    /// the built-in profile of the gamut
+    pub fn profile(self) -> Profile {
+        let name = match self {
+            Gamut::Srgb => "srgb",
+            Gamut::DisplayP3 => "display-p3",
+        };
+        Profile::load(name).expect("built-in profiles are encodable")
+    }
+}
+
+/// an icc color profile that quantized images are written in, see `--output-profile`
+#[derive(Clone, Debug)]
+pub struct Profile {
+    profile: ColorProfile,
+    /// the encoded profile, embedded in output images
+    icc: Vec<u8>,
+}
+
+impl Profile {
+    /// a built-in profile by name (`srgb`, `display-p3` or `adobe-rgb`), or an icc profile file
+    pub fn load(name: &str) -> Result<Self, String> {
+        let profile = match name {
+            "srgb" => ColorProfile::new_srgb(),
+            "display-p3" => ColorProfile::new_display_p3(),
+            "adobe-rgb" => ColorProfile::new_adobe_rgb(),
+            path => {
+                let icc = std::fs::read(path).map_err(|err| {
+                    messages::text("read-profile", &[("profile", &path), ("error", &err)])
+                })?;
+                return Self::from_icc(icc).map_err(|err| {
+                    messages::text("invalid-profile", &[("profile", &path), ("error", &err)])
+                });
+            }
+        };
+        let icc = (profile.encode()).map_err(|err| {
+            messages::text("encode-profile", &[("profile", &name), ("error", &err)])
+        })?;
+        Ok(Profile { profile, icc })
+    }
+
+    /// the srgb profile, which colors without an embedded profile are assumed to be in
+    pub fn srgb() -> Self {
+        Gamut::Srgb.profile()
+    }
+
+    /// a profile decoded from icc data, eg. embedded in an input image
+    pub fn from_icc(icc: Vec<u8>) -> Result<Self, String> {
+        let profile = ColorProfile::new_from_slice(&icc).map_err(|err| err.to_string())?;
+        Ok(Profile { profile, icc })
+    }
+
+    /// the encoded profile
+    pub fn icc(&self) -> &[u8] {
+        &self.icc
+    }
+
+    /// convert colors in this profile to `target` (alpha is kept)
+    pub fn convert(&self, colors: &[Color], target: &Profile) -> Result<Vec<Color>, String> {
+        let Some(first) = colors.first() else {
+            return Ok(Vec::new());
+        };
+        let layout = match first.alpha() {
+            Some(_) => Layout::Rgba,
+            None => Layout::Rgb,
+        };
+        let transform = (self.profile)
+            .create_transform_8bit(layout, &target.profile, layout, TransformOptions::default())
+            .map_err(|err| messages::text("convert-profile", &[("error", &err)]))?;
+
+        let source = colors
+            .iter()
+            .flat_map(|c| &c.data)
+            .copied()
+            .collect::<Vec<_>>();
+        let mut converted = vec![0; source.len()];
+        (transform.transform(&source, &mut converted))
+            .map_err(|err| messages::text("convert-profile", &[("error", &err)]))?;
+
+        let channels = first.data.len();
+        let colors = (converted.chunks_exact(channels))
+            .map(|data| Color {
+                color_type: first.color_type,
+                data: data.to_vec(),
+            })
+            .collect();
+        Ok(colors)
+    }
+}
+
+/// colors of a quantized image, and the icc profile to embed with them
+/// - with a `target` profile, colors are converted to it from the profile of the input
+///   (`input`, or srgb without one)
+/// - otherwise, colors stay in the profile of the input, which is embedded again
+/// - without either, colors clustered in a perceptual color space are tagged as srgb, which
+///   they were converted back to
+pub fn tag(
+    colors: &[Color],
+    input: Option<&[u8]>,
+    target: Option<&Profile>,
+    colorspace: ColorSpace,
+) -> Result<(Vec<Color>, Option<Vec<u8>>), String> {
+    match (target, input) {
+        (Some(target), input) => {
+            let source = match input {
+                Some(icc) => Profile::from_icc(icc.to_vec())
+                    .map_err(|err| messages::text("invalid-input-profile", &[("error", &err)]))?,
+                None => Profile::srgb(),
+            };
+            let converted = source.convert(colors, target)?;
+            Ok((converted, Some(target.icc().to_vec())))
+        }
+        (None, Some(icc)) => Ok((colors.to_vec(), Some(icc.to_vec()))),
+        (None, None) if colorspace != ColorSpace::Srgb => {
+            Ok((colors.to_vec(), Some(Profile::srgb().icc().to_vec())))
+        }
+        (None, None) => Ok((colors.to_vec(), None)),
+    }
+}
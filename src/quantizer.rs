@@ -0,0 +1,537 @@
+use rand::{SeedableRng, rngs::SmallRng};
+use rayon::prelude::*;
+use std::str::FromStr;
+
+use crate::Error;
+use crate::colors::{self, Color};
+use crate::colorspace::ColorSpace;
+use crate::kmeans::Context;
+use crate::median_cut::MedianCut;
+use crate::messages;
+use crate::octree::Octree;
+use crate::{histogram, timing};
+
+/// algorithm computing the palette
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Algorithm {
+    /// k-means clustering (slowest, best palettes)
+    #[default]
+    Kmeans,
+    /// recursive splitting of the color range at the median
+    MedianCut,
+    /// merging of the sparsest branches of a color octree (fastest)
+    Octree,
+}
+
+/// computes a palette of (at most) `k` colors for pixels
+pub trait Quantizer {
+    /// returns (palette, assignments), such that `pixels[i]` is represented by `palette[assignments[i]]`
+    fn quantize(&mut self, pixels: &[Color], k: usize) -> Result<(Vec<Color>, Vec<usize>), Error>;
+
+    /// [`Quantizer::quantize`], with `pixels[i]` counting `weights[i]` times towards the
+    /// palette (see [`Detail`])
+    /// - quantizers that cannot weight pixels ignore the weights
+    fn quantize_weighted(
+        &mut self,
+        pixels: &[Color],
+        weights: &[u32],
+        k: usize,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        let _ = weights;
+        self.quantize(pixels, k)
+    }
+
+    /// assign each pixel to its nearest palette color, see [`colors::assign`]
+    fn assign(&self, pixels: &[Color], palette: &[Color]) -> Vec<usize> {
+        colors::assign(pixels, palette)
+    }
+}
+
+/// k-means clustering in a color space, see [`ColorSpace::k_means_with`]
+/// - identical (or, for many colors, similar) pixels are clustered as one weighted point,
+///   see [`histogram::weighted`]
+/// - all pixels are assigned to the final clusters in a single pass
+pub struct KMeans<'a, F> {
+    pub context: &'a mut Context<SmallRng>,
+    pub colorspace: ColorSpace,
+    pub iterations: usize,
+    /// called with the provisional palette after each iteration
+    pub on_iteration: F,
+}
+
+impl<F> Quantizer for KMeans<'_, F>
+where
+    F: FnMut(usize, &[Color]),
+{
+    fn quantize(&mut self, pixels: &[Color], k: usize) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        self.quantize_weighted(pixels, &vec![1; pixels.len()], k)
+    }
+
+    fn quantize_weighted(
+        &mut self,
+        pixels: &[Color],
+        weights: &[u32],
+        k: usize,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        validate_weighted(pixels, weights, k)?;
+        let (points, weights) =
+            timing::measure("preprocess", || histogram::weighted_by(pixels, weights, k));
+
+        // fewer distinct colors than clusters are a palette of their own
+        let palette = match points.len() <= k {
+            true => snap(self.context, points),
+            false => {
+                let on_iteration = &mut self.on_iteration;
+                let (clusters, _) = (self.colorspace).k_means_weighted_with(
+                    self.context,
+                    &points,
+                    &weights,
+                    k,
+                    self.iterations,
+                    on_iteration,
+                )?;
+                clusters
+            }
+        };
+
+        let assignments = timing::measure("assign", || self.assign(pixels, &palette));
+        Ok((palette, assignments))
+    }
+
+    fn assign(&self, pixels: &[Color], palette: &[Color]) -> Vec<usize> {
+        (self.colorspace).assign(pixels, palette, self.context.parameters())
+    }
+}
+
+/// k-means clustering in a color space starting from given clusters, see
+/// [`ColorSpace::k_means_weighted_from`]
+/// - eg. from the palette of the previous frame of a video, so palette entries keep their
+///   index and only move as far as the image changes
+/// - quantizing to a different number of colors than `initial` fails
+pub struct WarmKMeans<'a, F> {
+    pub context: &'a mut Context<SmallRng>,
+    pub colorspace: ColorSpace,
+    pub iterations: usize,
+    pub initial: &'a [Color],
+    /// called with the provisional palette after each iteration
+    pub on_iteration: F,
+}
+
+impl<F> Quantizer for WarmKMeans<'_, F>
+where
+    F: FnMut(usize, &[Color]),
+{
+    fn quantize(&mut self, pixels: &[Color], k: usize) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        self.quantize_weighted(pixels, &vec![1; pixels.len()], k)
+    }
+
+    fn quantize_weighted(
+        &mut self,
+        pixels: &[Color],
+        weights: &[u32],
+        k: usize,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        validate_weighted(pixels, weights, k)?;
+        if k != self.initial.len() {
+            return Err(Error::InvalidParameter(messages::text(
+                "initial-clusters-mismatch",
+                &[("k", &k), ("initial", &self.initial.len())],
+            )));
+        }
+        let (points, weights) =
+            timing::measure("preprocess", || histogram::weighted_by(pixels, weights, k));
+
+        let palette = match points.len() <= k {
+            true => snap(self.context, points),
+            false => {
+                let on_iteration = &mut self.on_iteration;
+                let (clusters, _) = (self.colorspace).k_means_weighted_from(
+                    self.context,
+                    &points,
+                    &weights,
+                    self.initial,
+                    self.iterations,
+                    on_iteration,
+                )?;
+                clusters
+            }
+        };
+
+        let assignments = timing::measure("assign", || self.assign(pixels, &palette));
+        Ok((palette, assignments))
+    }
+
+    fn assign(&self, pixels: &[Color], palette: &[Color]) -> Vec<usize> {
+        (self.colorspace).assign(pixels, palette, self.context.parameters())
+    }
+}
+
+/// size of the random subset of pixels a palette is computed from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sample {
+    /// fraction of all pixels, in `(0, 1]`
+    Fraction(f64),
+    /// number of pixels
+    Count(usize),
+}
+
+impl Sample {
+    /// number of pixels sampled from `pixels` pixels
+    pub fn of(self, pixels: usize) -> usize {
+        match self {
+            Sample::Fraction(fraction) => (pixels as f64 * fraction).ceil() as usize,
+            Sample::Count(count) => count,
+        }
+        .min(pixels)
+    }
+}
+
+impl FromStr for Sample {
+    type Err = String;
+
+    /// parse a fraction (`0.1`), a percentage (`10%`) or a pixel count (`50000`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || messages::text("invalid-sample", &[("sample", &s)]);
+        let s = s.trim();
+
+        let fraction = match s.strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>().map_err(|_| invalid())? / 100.0,
+            None if !s.contains(['.', 'e', 'E']) => {
+                return match s.parse::<usize>() {
+                    Ok(0) => Err(messages::text("empty-sample", &[("sample", &s)])),
+                    Ok(count) => Ok(Sample::Count(count)),
+                    Err(_) => Err(invalid()),
+                };
+            }
+            None => s.parse::<f64>().map_err(|_| invalid())?,
+        };
+
+        match fraction > 0.0 && fraction <= 1.0 {
+            true => Ok(Sample::Fraction(fraction)),
+            false => Err(messages::text("sample-out-of-range", &[("sample", &s)])),
+        }
+    }
+}
+
+/// computes the palette from a random subset of pixels, then assigns all pixels to it
+pub struct Sampled<'a> {
+    pub quantizer: Box<dyn Quantizer + 'a>,
+    pub sample: Sample,
+    /// seed of the rng choosing the subset
+    pub seed: u64,
+}
+
+impl Sampled<'_> {
+    /// indices of the random subset of pixels, or `None` for all pixels
+    fn subset(&self, pixels: usize, k: usize) -> Option<Vec<usize>> {
+        let count = self.sample.of(pixels).max(k).min(pixels);
+        (count < pixels).then(|| {
+            let mut rng = SmallRng::seed_from_u64(self.seed);
+            let mut indices = rand::seq::index::sample(&mut rng, pixels, count).into_vec();
+            indices.sort_unstable();
+            indices
+        })
+    }
+}
+
+impl Quantizer for Sampled<'_> {
+    fn quantize(&mut self, pixels: &[Color], k: usize) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        validate(pixels, k)?;
+        let Some(indices) = timing::measure("sample", || self.subset(pixels.len(), k)) else {
+            return self.quantizer.quantize(pixels, k);
+        };
+
+        let subset = indices
+            .iter()
+            .map(|&i| pixels[i].clone())
+            .collect::<Vec<_>>();
+        let (palette, _) = self.quantizer.quantize(&subset, k)?;
+        let assignments = timing::measure("assign", || self.quantizer.assign(pixels, &palette));
+        Ok((palette, assignments))
+    }
+
+    fn quantize_weighted(
+        &mut self,
+        pixels: &[Color],
+        weights: &[u32],
+        k: usize,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        validate_weighted(pixels, weights, k)?;
+        let Some(indices) = timing::measure("sample", || self.subset(pixels.len(), k)) else {
+            return self.quantizer.quantize_weighted(pixels, weights, k);
+        };
+
+        let subset = indices
+            .iter()
+            .map(|&i| pixels[i].clone())
+            .collect::<Vec<_>>();
+        let weights = indices.iter().map(|&i| weights[i]).collect::<Vec<_>>();
+        let (palette, _) = self.quantizer.quantize_weighted(&subset, &weights, k)?;
+        let assignments = timing::measure("assign", || self.quantizer.assign(pixels, &palette));
+        Ok((palette, assignments))
+    }
+
+    fn assign(&self, pixels: &[Color], palette: &[Color]) -> Vec<usize> {
+        self.quantizer.assign(pixels, palette)
+    }
+}
+
+/// default maximum number of pixels of the thumbnail a palette is computed from
+pub const THUMBNAIL_PIXELS: usize = 1 << 18;
+
+/// computes the palette from a downscaled thumbnail of the image, then assigns all
+/// pixels to it
+/// - thumbnail pixels are the center pixels of square blocks (nearest-neighbor scaling),
+///   since averaging would blend the colors of noisy or detailed images
+/// - images with at most `limit` pixels are quantized at full resolution
+pub struct Thumbnail<'a> {
+    pub quantizer: Box<dyn Quantizer + 'a>,
+    /// width of the image, whose pixels are given in row-major order
+    pub width: usize,
+    /// maximum number of thumbnail pixels
+    pub limit: usize,
+}
+
+impl Thumbnail<'_> {
+    /// side length of the blocks of pixels averaged into a thumbnail pixel
+    /// - 1 for images with at most `limit` pixels
+    pub fn factor(width: usize, height: usize, limit: usize) -> usize {
+        let pixels = width * height;
+        let mut factor = (pixels as f64 / limit.max(1) as f64).sqrt().ceil().max(1.0) as usize;
+        while width.div_ceil(factor) * height.div_ceil(factor) > limit.max(1) {
+            factor += 1;
+        }
+        factor
+    }
+
+    /// (width, height) of the thumbnail of an image
+    pub fn dimensions(width: usize, height: usize, limit: usize) -> (usize, usize) {
+        let factor = Self::factor(width, height, limit);
+        (width.div_ceil(factor), height.div_ceil(factor))
+    }
+
+    /// thumbnail pixels of an image `width` pixels wide, see [`Thumbnail::dimensions`]
+    pub fn pixels(pixels: &[Color], width: usize, limit: usize) -> Vec<Color> {
+        let width = width.max(1);
+        match Self::factor(width, pixels.len() / width, limit) {
+            1 => pixels.to_vec(),
+            factor => downscale(pixels, width, factor),
+        }
+    }
+}
+
+impl Quantizer for Thumbnail<'_> {
+    fn quantize(&mut self, pixels: &[Color], k: usize) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        validate(pixels, k)?;
+        let width = self.width.max(1);
+        let height = pixels.len() / width;
+        let factor = Self::factor(width, height, self.limit.max(k));
+        if factor == 1 || width * height != pixels.len() {
+            return self.quantizer.quantize(pixels, k);
+        }
+
+        let thumbnail = timing::measure("thumbnail", || downscale(pixels, width, factor));
+        let (palette, _) = self.quantizer.quantize(&thumbnail, k)?;
+        let assignments = timing::measure("assign", || self.quantizer.assign(pixels, &palette));
+        Ok((palette, assignments))
+    }
+
+    /// thumbnail pixels keep the weights of the pixels they are picked from
+    fn quantize_weighted(
+        &mut self,
+        pixels: &[Color],
+        weights: &[u32],
+        k: usize,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        validate_weighted(pixels, weights, k)?;
+        let width = self.width.max(1);
+        let height = pixels.len() / width;
+        let factor = Self::factor(width, height, self.limit.max(k));
+        if factor == 1 || width * height != pixels.len() {
+            return self.quantizer.quantize_weighted(pixels, weights, k);
+        }
+
+        let (thumbnail, weights) = timing::measure("thumbnail", || {
+            let indices = block_centers(width, height, factor);
+            let thumbnail = (indices.par_iter())
+                .map(|&i| pixels[i].clone())
+                .collect::<Vec<_>>();
+            let weights = indices.iter().map(|&i| weights[i]).collect::<Vec<_>>();
+            (thumbnail, weights)
+        });
+        let (palette, _) = self.quantizer.quantize_weighted(&thumbnail, &weights, k)?;
+        let assignments = timing::measure("assign", || self.quantizer.assign(pixels, &palette));
+        Ok((palette, assignments))
+    }
+
+    fn assign(&self, pixels: &[Color], palette: &[Color]) -> Vec<usize> {
+        self.quantizer.assign(pixels, palette)
+    }
+}
+
+/// pick the center pixel of each square block of `factor` by `factor` pixels (or fewer,
+/// at the edges)
+fn downscale(pixels: &[Color], width: usize, factor: usize) -> Vec<Color> {
+    let indices = block_centers(width, pixels.len() / width, factor);
+    indices.into_par_iter().map(|i| pixels[i].clone()).collect()
+}
+
+/// indices of the center pixels of the blocks of [`downscale`], in row-major order
+fn block_centers(width: usize, height: usize, factor: usize) -> Vec<usize> {
+    let center = |block: usize, size: usize| (block * factor + factor / 2).min(size - 1);
+    (0..height.div_ceil(factor))
+        .flat_map(|block_y| {
+            let y = center(block_y, height);
+            (0..width.div_ceil(factor)).map(move |block_x| y * width + center(block_x, width))
+        })
+        .collect()
+}
+
+/// weights pixels by the detail around them for the palette (see [`detail`]), then
+/// assigns all pixels to it
+/// - small but important elements, eg. text or logos, keep accurate colors even at low
+///   `k`, instead of merging into the colors of large flat areas
+/// - only weighted quantizers (k-means) are affected, see [`Quantizer::quantize_weighted`]
+pub struct Detail<'a> {
+    pub quantizer: Box<dyn Quantizer + 'a>,
+    /// width of the image, whose pixels are given in row-major order
+    pub width: usize,
+    /// weight of pixels of the sharpest edges, over 1 for pixels of flat areas
+    /// (at most [`Detail::MAX_STRENGTH`])
+    pub strength: f64,
+}
+
+impl Detail<'_> {
+    /// largest strength, keeping weighted channel sums of huge images within `u64`
+    pub const MAX_STRENGTH: f64 = 1000.0;
+
+    /// weight of each pixel, from 1 in flat areas to `1 + strength` at the sharpest edges
+    fn weights(&self, pixels: &[Color]) -> Vec<u32> {
+        let strength = self.strength.clamp(0.0, Self::MAX_STRENGTH);
+        (detail(pixels, self.width).into_iter())
+            .map(|edge| 1u32.saturating_add((strength * edge).round().min(u32::MAX as f64) as u32))
+            .collect()
+    }
+}
+
+impl Quantizer for Detail<'_> {
+    fn quantize(&mut self, pixels: &[Color], k: usize) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        validate(pixels, k)?;
+        let weights = timing::measure("detail", || self.weights(pixels));
+        self.quantizer.quantize_weighted(pixels, &weights, k)
+    }
+
+    fn quantize_weighted(
+        &mut self,
+        pixels: &[Color],
+        weights: &[u32],
+        k: usize,
+    ) -> Result<(Vec<Color>, Vec<usize>), Error> {
+        validate_weighted(pixels, weights, k)?;
+        let detail = timing::measure("detail", || self.weights(pixels));
+        let weights = (weights.iter().zip(detail))
+            .map(|(&w, d)| w.saturating_mul(d))
+            .collect::<Vec<_>>();
+        self.quantizer.quantize_weighted(pixels, &weights, k)
+    }
+
+    fn assign(&self, pixels: &[Color], palette: &[Color]) -> Vec<usize> {
+        self.quantizer.assign(pixels, palette)
+    }
+}
+
+/// edge map of an image `width` pixels wide: the sobel gradient around each pixel, of the
+/// rgb channel changing the most, from 0 in flat areas to 1 at (and beyond) an edge from
+/// black to white
+/// - edges between colors of similar brightness count as well, eg. of red text on gray
+/// - pixels at the border of the image repeat their neighbors
+/// - images whose pixels do not fill whole rows have no detail
+pub fn detail(pixels: &[Color], width: usize) -> Vec<f64> {
+    let width = width.max(1);
+    let height = pixels.len() / width;
+    if width * height != pixels.len() {
+        return vec![0.0; pixels.len()];
+    }
+
+    let at = |x: isize, y: isize, channel: usize| {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        pixels[y * width + x].rgb()[channel] as f64
+    };
+
+    // a step from 0 to 255 has a gradient of 4 * 255
+    (0..pixels.len())
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            let gradient = |c: usize| {
+                let gx = (at(x + 1, y - 1, c) + 2.0 * at(x + 1, y, c) + at(x + 1, y + 1, c))
+                    - (at(x - 1, y - 1, c) + 2.0 * at(x - 1, y, c) + at(x - 1, y + 1, c));
+                let gy = (at(x - 1, y + 1, c) + 2.0 * at(x, y + 1, c) + at(x + 1, y + 1, c))
+                    - (at(x - 1, y - 1, c) + 2.0 * at(x, y - 1, c) + at(x + 1, y - 1, c));
+                gx.hypot(gy)
+            };
+            let strongest = (0..3).map(gradient).fold(0.0, f64::max);
+            (strongest / (4.0 * u8::MAX as f64)).min(1.0)
+        })
+        .collect()
+}
+
+impl Algorithm {
+    /// quantizer of the algorithm
+    /// - the k-means `context`, `colorspace`, `iterations` and `on_iteration` are
+    ///   ignored by the other algorithms
+    pub fn quantizer<'a, F>(
+        self,
+        context: &'a mut Context<SmallRng>,
+        colorspace: ColorSpace,
+        iterations: usize,
+        on_iteration: F,
+    ) -> Box<dyn Quantizer + 'a>
+    where
+        F: FnMut(usize, &[Color]) + 'a,
+    {
+        match self {
+            Algorithm::Kmeans => Box::new(KMeans {
+                context,
+                colorspace,
+                iterations,
+                on_iteration,
+            }),
+            Algorithm::MedianCut => Box::new(MedianCut),
+            Algorithm::Octree => Box::new(Octree),
+        }
+    }
+}
+
+/// colors moved into the gamut of a context, if any, see [`Context::with_gamut`]
+fn snap(context: &Context<SmallRng>, colors: Vec<Color>) -> Vec<Color> {
+    match context.gamut() {
+        Some(gamut) => colors.iter().map(|c| gamut.snap(c)).collect(),
+        None => colors,
+    }
+}
+
+/// check parameters shared by all quantizers, and that there is a weight per pixel
+fn validate_weighted(pixels: &[Color], weights: &[u32], k: usize) -> Result<(), Error> {
+    validate(pixels, k)?;
+    match weights.len() == pixels.len() {
+        true => Ok(()),
+        false => Err(Error::InvalidParameter(messages::text(
+            "pixel-weights-mismatch",
+            &[],
+        ))),
+    }
+}
+
+/// check parameters shared by all quantizers
+pub(crate) fn validate(pixels: &[Color], k: usize) -> Result<(), Error> {
+    match () {
+        _ if k == 0 => Err(Error::InvalidParameter(messages::text("zero-colors", &[]))),
+        _ if pixels.is_empty() => Err(Error::InvalidParameter(messages::text(
+            "no-pixels-to-quantize",
+            &[],
+        ))),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,135 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// bounded queue of jobs, run by a fixed number of workers in order of priority
+/// - higher priorities run first, jobs of equal priority in order of arrival
+/// - jobs beyond the capacity are rejected instead of waiting
+pub struct Queue {
+    workers: usize,
+    capacity: usize,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+/// position of a waiting job: priority, then arrival (earlier first)
+type Key = (i32, Reverse<u64>);
+
+#[derive(Default)]
+struct State {
+    waiting: BinaryHeap<Key>,
+    arrivals: u64,
+    running: usize,
+    finished: u64,
+    rejected: u64,
+    timed_out: u64,
+}
+
+/// snapshot of a queue, see [`Queue::status`]
+pub struct Status {
+    pub workers: usize,
+    pub capacity: usize,
+    pub running: usize,
+    pub waiting: usize,
+    /// jobs that ran to completion (even if their client timed out)
+    pub finished: u64,
+    /// jobs rejected because the queue was full
+    pub rejected: u64,
+    /// jobs that timed out, while waiting or running
+    pub timed_out: u64,
+}
+
+/// reason a job did not get a worker
+pub enum Rejection {
+    /// all workers are busy and the queue is full
+    Full,
+    /// the deadline passed while waiting
+    TimedOut,
+}
+
+/// worker of a running job, released when dropped
+pub struct Slot {
+    queue: Arc<Queue>,
+}
+
+impl Queue {
+    pub fn new(workers: usize, capacity: usize) -> Arc<Self> {
+        Arc::new(Queue {
+            workers,
+            capacity,
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        })
+    }
+
+    /// wait for a worker, until `deadline`
+    pub fn acquire(self: &Arc<Self>, priority: i32, deadline: Instant) -> Result<Slot, Rejection> {
+        let mut state = self.lock();
+        if state.running + state.waiting.len() >= self.workers + self.capacity {
+            state.rejected += 1;
+            return Err(Rejection::Full);
+        }
+
+        let key = (priority, Reverse(state.arrivals));
+        state.arrivals += 1;
+        state.waiting.push(key);
+
+        loop {
+            if state.running < self.workers && state.waiting.peek() == Some(&key) {
+                state.waiting.pop();
+                state.running += 1;
+                return Ok(Slot {
+                    queue: Arc::clone(self),
+                });
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                state.waiting.retain(|k| *k != key);
+                state.timed_out += 1;
+                drop(state);
+                // the next job may have been waiting behind this one
+                self.changed.notify_all();
+                return Err(Rejection::TimedOut);
+            }
+
+            state = (self.changed.wait_timeout(state, deadline - now))
+                .expect("queue lock poisoned")
+                .0;
+        }
+    }
+
+    /// count a job whose deadline passed while running
+    pub fn time_out(&self) {
+        self.lock().timed_out += 1;
+    }
+
+    /// current load and counts of past jobs
+    pub fn status(&self) -> Status {
+        let state = self.lock();
+        Status {
+            workers: self.workers,
+            capacity: self.capacity,
+            running: state.running,
+            waiting: state.waiting.len(),
+            finished: state.finished,
+            rejected: state.rejected,
+            timed_out: state.timed_out,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("queue lock poisoned")
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = self.queue.lock();
+        state.running -= 1;
+        state.finished += 1;
+        drop(state);
+        self.queue.changed.notify_all();
+    }
+}
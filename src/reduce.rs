@@ -0,0 +1,68 @@
+use crate::cli::ReduceArgs;
+use crate::kmeans;
+use crate::logger;
+use crate::messages;
+use crate::palette::{self, Palette};
+use crate::term::Capability;
+
+/// scale of the integer weights that normalized palette weights are converted to
+/// (a precision of 23 bits, far within the `u64` weighted sums of 8-bit channels)
+const WEIGHT_SCALE: f64 = (1 << 23) as f64;
+
+/// cluster the entries of a palette file down to k representative colors,
+/// weighted by their usage counts
+pub fn reduce(args: &ReduceArgs, seed: u64) -> Result<(), String> {
+    let palette = Palette::from_file(&args.palette)?;
+
+    let colors = match palette.colors.len() <= args.number {
+        true => {
+            logger::note(
+                "within-budget",
+                messages::text(
+                    "reduce-within-budget",
+                    &[
+                        ("palette", &args.palette.display()),
+                        ("colors", &palette.colors.len()),
+                        ("k", &args.number),
+                    ],
+                ),
+            );
+            palette.colors
+        }
+        false => {
+            let weights = palette
+                .weights
+                .iter()
+                .map(|w| (w * WEIGHT_SCALE).round().max(1.0) as u32)
+                .collect::<Vec<_>>();
+
+            let mut context = kmeans::Context::new(seed).with_progress(false);
+            let (clusters, _) = context.k_means_weighted(
+                &palette.colors,
+                &weights,
+                args.number,
+                args.iterations,
+            )?;
+            clusters
+        }
+    };
+
+    let format = args.format.unwrap_or_default();
+    match &args.output {
+        None => {
+            let options = palette::WriteOptions {
+                preview: Capability::stdout(),
+                ..Default::default()
+            };
+            palette::write(&colors, &mut std::io::stdout(), format, &options)
+        }
+        Some(output_file) => {
+            let mut file = std::fs::File::create(output_file)
+                .map_err(|err| messages::text("create-output", &[("error", &err)]))?;
+            palette::write(&colors, &mut file, format, &Default::default())
+        }
+    }
+    .map_err(|err| messages::text("write-palette", &[("error", &err)]))?;
+
+    Ok(())
+}
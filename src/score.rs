@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use crate::cli::ScoreArgs;
+use crate::colors::{self, Color};
+use crate::io;
+use crate::messages;
+use crate::palette::Palette;
+
+/// how well an image conforms to a reference palette, see `qtizer score`
+pub struct Score {
+    /// mean delta-e of pixels to their nearest reference color
+    pub mean: f64,
+    /// largest delta-e of a pixel to its nearest reference color
+    pub max: f64,
+    /// share of pixels further than `--tolerance` from every reference color, in `[0, 1]`
+    pub outside: f64,
+    /// share of pixels nearest to each reference color, in `[0, 1]`
+    pub shares: Vec<f64>,
+}
+
+/// score an image against a reference palette and print the report to stdout
+/// - returns whether the image passes `--max-outside`
+pub fn score(args: &ScoreArgs) -> Result<bool, String> {
+    let img = (io::decode(&args.image, None).map(|d| d.image))
+        .map_err(|err| format!("{}: {err}", args.image.display()))?;
+    let palette = Palette::from_file(&args.against)
+        .map_err(|err| format!("{}: {err}", args.against.display()))?;
+    if palette.colors.is_empty() {
+        return Err(messages::text(
+            "score-empty-palette",
+            &[("palette", &args.against.display())],
+        ));
+    }
+
+    let score = Score::new(&crate::pixels(&img, true), &palette.colors, args.tolerance)
+        .ok_or_else(|| messages::text("score-transparent", &[("image", &args.image.display())]))?;
+    score.report(args, &palette.colors);
+
+    Ok(args
+        .max_outside
+        .is_none_or(|max| score.outside * 100.0 <= max))
+}
+
+impl Score {
+    /// measure the distances of pixels to their nearest color of `reference`
+    /// - fully transparent pixels are ignored, returns `None` if there are no others
+    pub fn new(pixels: &[Color], reference: &[Color], tolerance: f64) -> Option<Self> {
+        // distances are computed once per distinct color
+        let mut counts = HashMap::<[u8; 3], (&Color, u64)>::new();
+        for pixel in pixels.iter().filter(|p| p.alpha() != Some(0)) {
+            counts.entry(pixel.rgb()).or_insert((pixel, 0)).1 += 1;
+        }
+        let total = counts.values().map(|&(_, count)| count).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+
+        let reference = reference.iter().map(colors::to_lab).collect::<Vec<_>>();
+        let (mut sum, mut max, mut outside) = (0.0, 0.0f64, 0);
+        let mut nearest_counts = vec![0u64; reference.len()];
+        for (color, count) in counts.into_values() {
+            let lab = colors::to_lab(color);
+            let (nearest, distance) = (reference.iter())
+                .map(|r| (0..3).map(|i| (lab[i] - r[i]).powi(2)).sum::<f64>().sqrt())
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .expect("reference palette is not empty");
+            sum += distance * count as f64;
+            max = max.max(distance);
+            if distance > tolerance {
+                outside += count;
+            }
+            nearest_counts[nearest] += count;
+        }
+
+        Some(Score {
+            mean: sum / total as f64,
+            max,
+            outside: outside as f64 / total as f64,
+            shares: (nearest_counts.iter())
+                .map(|&n| n as f64 / total as f64)
+                .collect(),
+        })
+    }
+
+    /// print the score to stdout, with the share of each reference color
+    pub fn report(&self, args: &ScoreArgs, reference: &[Color]) {
+        let text = |key, args: &[(&str, &dyn std::fmt::Display)]| messages::text(key, args);
+        println!("{}", text("score", &[("image", &args.image.display())]));
+        println!(
+            "  {}",
+            text("score-against", &[("palette", &args.against.display())])
+        );
+        println!(
+            "  {}",
+            text("score-mean", &[("mean", &format!("{:.2}", self.mean))])
+        );
+        println!(
+            "  {}",
+            text("score-max", &[("max", &format!("{:.2}", self.max))])
+        );
+        println!(
+            "  {}",
+            text(
+                "score-outside",
+                &[
+                    ("percent", &format!("{:.2}", self.outside * 100.0)),
+                    ("tolerance", &args.tolerance),
+                ],
+            )
+        );
+        println!("  {}", text("score-nearest", &[]));
+        for (color, share) in reference.iter().zip(&self.shares) {
+            let [r, g, b] = color.rgb();
+            println!("    #{r:02x}{g:02x}{b:02x}  {:>6.2}%", share * 100.0);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::str::FromStr;
+
+use crate::messages;
+
+/// file names of numbered outputs, see `--sequence-format`
+/// - a printf-style pattern with a single number, eg. `frame_%04d.png`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequenceFormat {
+    /// text before the number
+    prefix: String,
+    /// minimum number of digits, padded with zeros
+    digits: usize,
+    /// text after the number
+    suffix: String,
+}
+
+impl FromStr for SequenceFormat {
+    type Err = String;
+
+    /// parse a pattern of `%d` (or `%0<digits>d`) for the number and `%%` for `%`,
+    /// eg. `frame_%04d.png`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || messages::text("invalid-sequence-format", &[("format", &s)]);
+        let (mut prefix, mut suffix) = (String::new(), String::new());
+        let mut digits = None;
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            let text = match digits {
+                None => &mut prefix,
+                Some(_) => &mut suffix,
+            };
+            match c {
+                '%' if chars.peek() == Some(&'%') => {
+                    chars.next();
+                    text.push('%');
+                }
+                '%' if digits.is_none() => {
+                    let mut width = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        width.push(digit);
+                    }
+                    // numbers are padded with zeros only, not with spaces as by `%4d`
+                    if chars.next() != Some('d') || !(width.is_empty() || width.starts_with('0')) {
+                        return Err(invalid());
+                    }
+                    digits = Some(width.parse().unwrap_or(0));
+                }
+                '%' => return Err(invalid()),
+                c => text.push(c),
+            }
+        }
+
+        match digits {
+            Some(digits) if !prefix.contains('/') && !suffix.contains('/') => Ok(SequenceFormat {
+                prefix,
+                digits,
+                suffix,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl SequenceFormat {
+    /// the file name of the output numbered `n`, eg. `frame_0007.png` for 7
+    pub fn name(&self, n: usize) -> String {
+        let SequenceFormat {
+            prefix,
+            digits,
+            suffix,
+        } = self;
+        format!("{prefix}{n:0digits$}{suffix}")
+    }
+
+    /// whether names of the pattern have an extension, eg. `.png`
+    pub fn has_extension(&self) -> bool {
+        self.suffix.contains('.')
+    }
+}
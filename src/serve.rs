@@ -0,0 +1,606 @@
+use image::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
+
+use qtizer::palette::PaletteFormat;
+use qtizer::term::Capability;
+use qtizer::{ColorSpace, Options, tonemap};
+
+use crate::cache::{self, Hasher};
+use crate::cli::ServeArgs;
+use crate::indexed;
+use crate::io;
+use crate::logger;
+use crate::messages;
+use crate::metrics::Metrics;
+use crate::queue::{Queue, Rejection};
+
+/// bundled web ui, served at `/` with `--static` (unless the directory has an `index.html`)
+const INDEX: &str = include_str!("serve.html");
+
+/// maximum size of an uploaded image
+const MAX_BODY: usize = 64 << 20;
+
+/// maximum number of request headers
+const MAX_HEADERS: usize = 100;
+
+/// maximum length of the request line and of each header, in bytes
+const MAX_LINE: usize = 8 << 10;
+
+/// longest wait for a client to send or receive data, before its connection is closed
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// largest `k` of jobs, so jobs that time out keep their worker for a bounded time
+const MAX_K: usize = 1024;
+
+/// largest `iterations` of jobs, see [`MAX_K`]
+const MAX_ITERATIONS: usize = 100;
+
+/// http response, always sent with `Connection: close`
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Response {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    /// plain text error message
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", format!("{message}\n"))
+    }
+
+    /// cache entry of a successful response: its content type on the first line, then its body
+    fn to_cache(&self) -> Vec<u8> {
+        [self.content_type.as_bytes(), b"\n", &self.body].concat()
+    }
+
+    /// successful response of a cache entry, see [`Response::to_cache`]
+    fn from_cache(entry: Vec<u8>) -> Option<Self> {
+        let newline = entry.iter().position(|&b| b == b'\n')?;
+        let content_type = std::str::from_utf8(&entry[..newline]).ok()?;
+
+        // content types are static, so they are matched against the known ones
+        let content_type = [
+            "application/json",
+            "application/octet-stream",
+            "image/png",
+            "text/css; charset=utf-8",
+            "text/plain; charset=utf-8",
+        ]
+        .into_iter()
+        .find(|&known| known == content_type)?;
+        Some(Self::new(200, content_type, &entry[newline + 1..]))
+    }
+}
+
+/// shared state of connections
+struct Server {
+    static_dir: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    queue: Arc<Queue>,
+    metrics: Arc<Metrics>,
+    /// maximum duration of jobs, including their time in the queue
+    timeout: Duration,
+    /// number of open connections
+    connections: AtomicUsize,
+    /// maximum number of open connections, beyond which connections are rejected
+    max_connections: usize,
+}
+
+/// parsed http request (the body is only read for `POST`)
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+/// serve the http api (and static files) until interrupted
+/// - `POST /api/quantize` quantizes the image in the request body, see [`job`]
+/// - `GET /status` reports the load of the job queue, see [`status`]
+/// - `GET /metrics` reports counters in the prometheus text format, see [`Metrics`]
+/// - with `--static`, other `GET` requests are served from a directory
+/// - each connection is handled on its own thread, and closed after one response
+/// - connections beyond `--connections` are answered with 503 right away, and clients
+///   that stall for [`IO_TIMEOUT`] are disconnected
+pub fn serve(args: &ServeArgs) -> Result<(), String> {
+    if !(args.timeout.is_finite() && args.timeout > 0.0) {
+        return Err(messages::text(
+            "invalid-timeout",
+            &[("timeout", &args.timeout)],
+        ));
+    }
+
+    let static_dir = match &args.static_dir {
+        Some(dir) => Some(std::fs::canonicalize(dir).map_err(|err| {
+            messages::text("open-input", &[("input", &dir.display()), ("error", &err)])
+        })?),
+        None => None,
+    };
+
+    let listener = TcpListener::bind(&args.listen)
+        .map_err(|err| messages::text("listen", &[("address", &args.listen), ("error", &err)]))?;
+    let address = listener.local_addr().map_err(|err| err.to_string())?;
+    eprintln!("{}", messages::text("listening", &[("address", &address)]));
+
+    let server = Server {
+        static_dir,
+        cache_dir: args.cache_dir.as_ref().map(PathBuf::from),
+        queue: Queue::new(args.workers as usize, args.queue),
+        metrics: Arc::default(),
+        timeout: Duration::from_secs_f64(args.timeout),
+        connections: AtomicUsize::new(0),
+        max_connections: args.connections as usize,
+    };
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let timeouts = (stream.set_read_timeout(Some(IO_TIMEOUT)))
+                .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)));
+            if timeouts.is_err() {
+                continue;
+            }
+
+            let server = &server;
+            if server.connections.fetch_add(1, Ordering::AcqRel) >= server.max_connections {
+                server.connections.fetch_sub(1, Ordering::AcqRel);
+                let response = Response::error(503, messages::text("http-connections", &[]));
+                server.metrics.response(response.status);
+                respond(&mut stream, &response);
+                continue;
+            }
+            scope.spawn(move || {
+                handle(stream, server);
+                server.connections.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// read a request, route it and write the response
+fn handle(mut stream: TcpStream, server: &Server) {
+    let response = match read_request(&mut stream) {
+        Ok(request) => {
+            let (method, path) = (request.method.clone(), request.path.clone());
+            let response = route(request, server);
+            eprintln!("{method} {path} {}", response.status);
+            response
+        }
+        Err(response) => response,
+    };
+    server.metrics.response(response.status);
+    respond(&mut stream, &response);
+}
+
+/// write a response
+fn respond(stream: &mut TcpStream, response: &Response) {
+    let reason = match response.status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let header = format!(
+        "HTTP/1.1 {} {reason}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+         Access-Control-Allow-Headers: Content-Type\r\n\
+         Connection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+
+    // clients may disconnect at any time
+    let _ = (stream.write_all(header.as_bytes()))
+        .and_then(|_| stream.write_all(&response.body))
+        .and_then(|_| stream.flush());
+}
+
+/// read the request line, headers and (for `POST`) the body of a request
+fn read_request(stream: &mut TcpStream) -> Result<Request, Response> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    let bad_request = || Response::error(400, messages::text("http-malformed", &[]));
+
+    // lines are read up to their limit, so clients cannot grow them without bound
+    let mut read_line = |line: &mut String, too_long: Response| {
+        line.clear();
+        match (&mut reader).take(MAX_LINE as u64 + 1).read_line(line) {
+            Ok(_) if line.len() > MAX_LINE => Err(too_long),
+            Ok(_) => Ok(()),
+            Err(_) => Err(bad_request()),
+        }
+    };
+
+    read_line(
+        &mut line,
+        Response::error(414, messages::text("http-line-too-long", &[])),
+    )?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(Response::error(
+            400,
+            messages::text("http-malformed-line", &[]),
+        ));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        body: Vec::new(),
+    };
+
+    let mut length = None;
+    for i in 0..=MAX_HEADERS {
+        read_line(
+            &mut line,
+            Response::error(431, messages::text("http-header-too-long", &[])),
+        )?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if i == MAX_HEADERS {
+            return Err(Response::error(431, messages::text("http-headers", &[])));
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = Some(value.trim().parse::<usize>().map_err(|_| bad_request())?);
+        }
+    }
+
+    if request.method == "POST" {
+        let length =
+            length.ok_or_else(|| Response::error(411, messages::text("http-length", &[])))?;
+        if length > MAX_BODY {
+            return Err(Response::error(
+                413,
+                messages::text("http-body-too-large", &[("max", &(MAX_BODY >> 20))]),
+            ));
+        }
+        request.body = vec![0; length];
+        reader
+            .read_exact(&mut request.body)
+            .map_err(|_| bad_request())?;
+    }
+
+    Ok(request)
+}
+
+/// respond to a request
+fn route(request: Request, server: &Server) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("OPTIONS", _) => Response::new(204, "text/plain", ""),
+        ("POST", "/api/quantize") => job(request.query, request.body, server),
+        (_, "/api/quantize") => Response::error(405, messages::text("http-use-post", &[])),
+        ("GET", "/status") => status(&server.queue),
+        ("GET", "/metrics") => Response::new(
+            200,
+            "text/plain; version=0.0.4; charset=utf-8",
+            server.metrics.render(&server.queue.status()),
+        ),
+        ("GET", path) => match server.static_dir.as_deref() {
+            Some(dir) => static_file(dir, path),
+            None => Response::error(404, messages::text("http-no-static", &[])),
+        },
+        _ => Response::error(405, messages::text("http-method", &[])),
+    }
+}
+
+/// run a quantization job once a worker is free, see [`Queue`]
+/// - query parameters: `priority` (an integer, higher first, default 0) and `timeout`
+///   (in seconds, at most `--timeout`), besides those of [`quantize`]
+/// - jobs that time out while running keep their worker until they finish, since
+///   clustering cannot be interrupted (for a bounded time, see [`MAX_K`])
+/// - with `--cache-dir`, cached responses are returned without queueing
+fn job(query: String, body: Vec<u8>, server: &Server) -> Response {
+    let (priority, timeout) = match job_parameters(&query, server.timeout) {
+        Ok(parameters) => parameters,
+        Err(message) => return Response::error(400, message),
+    };
+
+    let cache = (server.cache_dir.clone()).map(|dir| (dir, cache_key(&query, &body)));
+    if let Some((dir, key)) = &cache
+        && let Some(response) = cache::read(dir, key).and_then(Response::from_cache)
+    {
+        server.metrics.cache_hit();
+        return response;
+    }
+
+    let deadline = Instant::now() + timeout;
+    let slot = match server.queue.acquire(priority, deadline) {
+        Ok(slot) => slot,
+        Err(Rejection::Full) => {
+            return Response::error(503, messages::text("http-queue-full", &[]));
+        }
+        Err(Rejection::TimedOut) => {
+            return Response::error(503, messages::text("http-queue-timeout", &[]));
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let metrics = Arc::clone(&server.metrics);
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        let result = quantize(&query, &body);
+        metrics.job(
+            start.elapsed(),
+            result.as_ref().ok().map(|(_, pixels)| *pixels),
+        );
+        drop(slot);
+
+        let response = match result {
+            Ok((response, _)) => {
+                if let Some((dir, key)) = cache
+                    && let Err(err) = cache::write(&dir, &key, &response.to_cache())
+                {
+                    logger::warn("cache-store", err);
+                }
+                response
+            }
+            Err(message) => Response::error(400, message),
+        };
+        let _ = sender.send(response);
+    });
+
+    match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(response) => response,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            server.queue.time_out();
+            Response::error(503, messages::text("http-job-timeout", &[]))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            Response::error(500, messages::text("http-job-failed", &[]))
+        }
+    }
+}
+
+/// cache key of a job: hashes of the image and of its parameters (in any order, without
+/// `priority` and `timeout`, which do not affect the response)
+fn cache_key(query: &str, body: &[u8]) -> String {
+    let mut image = Hasher::new();
+    image.write(body);
+
+    let mut parameters = (query.split('&'))
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| !pair.starts_with("priority=") && !pair.starts_with("timeout="))
+        .collect::<Vec<_>>();
+    parameters.sort_unstable();
+    let mut options = Hasher::new();
+    options.write(env!("CARGO_PKG_VERSION").as_bytes());
+    options.write(parameters.join("&").as_bytes());
+
+    format!("{:016x}-{:016x}", image.finish(), options.finish())
+}
+
+/// priority and timeout of a job, given by its query parameters
+fn job_parameters(query: &str, max_timeout: Duration) -> Result<(i32, Duration), String> {
+    let (mut priority, mut timeout) = (0, max_timeout);
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        let value = decode(value, true);
+        let invalid = || messages::text("http-invalid", &[("value", &value), ("key", &key)]);
+        match key {
+            "priority" => priority = value.parse().map_err(|_| invalid())?,
+            "timeout" => {
+                let seconds = value.parse::<f64>().map_err(|_| invalid())?;
+                if !(seconds.is_finite() && seconds > 0.0) {
+                    return Err(invalid());
+                }
+                timeout = timeout.min(Duration::from_secs_f64(seconds));
+            }
+            _ => {}
+        }
+    }
+    Ok((priority, timeout))
+}
+
+/// load of the job queue as json
+fn status(queue: &Queue) -> Response {
+    let status = queue.status();
+    let json = format!(
+        "{{\"workers\": {}, \"running\": {}, \"capacity\": {}, \"waiting\": {}, \
+         \"finished\": {}, \"rejected\": {}, \"timed_out\": {}}}\n",
+        status.workers,
+        status.running,
+        status.capacity,
+        status.waiting,
+        status.finished,
+        status.rejected,
+        status.timed_out
+    );
+    Response::new(200, "application/json", json)
+}
+
+/// quantize the image in a request body
+/// - query parameters: `k` (at most [`MAX_K`]), `iterations` (at most
+///   [`MAX_ITERATIONS`]), `algorithm`, `colorspace`, `tonemap`, `alpha`, `exact`, `seed`
+///   (as the flags of the same name) and `format` (of the palette)
+/// - `output=image` responds with the quantized image as png instead of the palette
+///
+/// returns the response and the number of quantized pixels
+fn quantize(query: &str, body: &[u8]) -> Result<(Response, u64), String> {
+    let mut options = Options::default();
+    let mut format = PaletteFormat::default();
+    let mut image = false;
+
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        let value = decode(value, true);
+        let invalid = || messages::text("http-invalid", &[("value", &value), ("key", &key)]);
+        match key {
+            "k" => options.k = value.parse().map_err(|_| invalid())?,
+            "iterations" => options.iterations = value.parse().map_err(|_| invalid())?,
+            "seed" => options.seed = value.parse().map_err(|_| invalid())?,
+            "alpha" => options.alpha = value.parse().map_err(|_| invalid())?,
+            "exact" => options.exact = value.parse().map_err(|_| invalid())?,
+            "algorithm" => options.algorithm = value_enum(&value).ok_or_else(invalid)?,
+            "colorspace" => {
+                options.colorspace = value_enum::<ColorSpace>(&value).ok_or_else(invalid)?
+            }
+            "tonemap" => options.tonemap = value_enum(&value).ok_or_else(invalid)?,
+            "format" => format = value_enum(&value).ok_or_else(invalid)?,
+            "output" => {
+                image = match value.as_str() {
+                    "palette" => false,
+                    "image" => true,
+                    _ => return Err(invalid()),
+                }
+            }
+            "priority" | "timeout" => {} // see `job`
+            _ => return Err(messages::text("http-parameter", &[("key", &key)])),
+        }
+    }
+    if image && options.k > 256 {
+        return Err(messages::text("http-image-colors", &[("max", &256)]));
+    }
+    if options.k > MAX_K {
+        return Err(messages::text("http-k", &[("max", &MAX_K)]));
+    }
+    if options.iterations > MAX_ITERATIONS {
+        return Err(messages::text(
+            "http-iterations",
+            &[("max", &MAX_ITERATIONS)],
+        ));
+    }
+
+    let img = io::decode_bytes(body)?.image;
+    let palette = qtizer::quantize(&img, &options).map_err(|err| err.to_string())?;
+    let pixel_count = img.width() as u64 * img.height() as u64;
+
+    if !image {
+        let mut buffer = Vec::new();
+        (format.write(&mut buffer, &palette.colors, Capability::None, None))
+            .map_err(|err| err.to_string())?;
+        let content_type = match format {
+            PaletteFormat::Json => "application/json",
+            PaletteFormat::Css => "text/css; charset=utf-8",
+            PaletteFormat::Ase => "application/octet-stream",
+            _ => "text/plain; charset=utf-8",
+        };
+        return Ok((Response::new(200, content_type, buffer), pixel_count));
+    }
+
+    // the library tonemaps only to cluster, so remap the same tonemapped pixels
+    let img = tonemap::tonemap(&img, options.tonemap).unwrap_or(img);
+    // palette colors have an alpha channel exactly if the pixels were clustered with it
+    let alpha = (palette.colors.first()).is_some_and(|c| c.color_type == ColorType::Rgba8);
+    let pixels = qtizer::pixels(&img, alpha);
+    let assignments = (options.colorspace).assign(&pixels, &palette.colors, &options.parameters);
+
+    let mut buffer = Vec::new();
+    let (width, height) = img.dimensions();
+    indexed::encode(
+        &mut buffer,
+        ImageFormat::Png,
+        width,
+        height,
+        &palette.colors,
+        &assignments,
+        None,
+    )?;
+    Ok((Response::new(200, "image/png", buffer), pixel_count))
+}
+
+/// serve a file of the static directory, or the bundled web ui for `/`
+fn static_file(dir: &Path, path: &str) -> Response {
+    let relative = decode(path.trim_start_matches('/'), false);
+    let relative = match relative.as_str() {
+        "" => "index.html",
+        relative => relative,
+    };
+
+    // only plain path components, no `..` or absolute paths
+    let relative = Path::new(relative);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Response::error(400, messages::text("http-path", &[]));
+    }
+
+    let file: PathBuf = dir.join(relative);
+    match std::fs::read(&file) {
+        Ok(contents) => Response::new(200, content_type(&file), contents),
+        Err(_) if relative == Path::new("index.html") => {
+            Response::new(200, "text/html; charset=utf-8", INDEX)
+        }
+        Err(_) => Response::error(404, messages::text("http-not-found", &[])),
+    }
+}
+
+/// content type of a static file, by extension
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "txt" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// parse a value as on the command line (eg. `median-cut`)
+fn value_enum<T: clap::ValueEnum>(value: &str) -> Option<T> {
+    T::from_str(value, true).ok()
+}
+
+/// decode percent-encoded query values (with `+` for space) and paths
+fn decode(value: &str, plus_as_space: bool) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        let hex = (byte == b'%')
+            .then(|| rest.get(..2))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, hex) {
+            (_, Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &rest[2..];
+            }
+            (b'+', None) if plus_as_space => bytes.push(b' '),
+            (byte, None) => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
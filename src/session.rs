@@ -0,0 +1,70 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cli::Args;
+use crate::logger::{self, Failure};
+use crate::messages;
+use crate::timing;
+
+/// append a json record of a run to the `--session-log` file, warning if that fails
+/// - records hold the time, version, arguments, seed, outcome, wall-clock time and the
+///   time of each processing phase (see `--timing`), and are never sent anywhere
+pub fn append(args: &Args, seed: u64, outcome: &Result<bool, Failure>, wall_clock: Duration) {
+    let Some(path) = &args.session_log else {
+        return;
+    };
+
+    if let Err(err) = write(path, &record(args, seed, outcome, wall_clock)) {
+        logger::warn(
+            "session-log",
+            messages::text(
+                "write-session-log",
+                &[("path", &path.display()), ("error", &err)],
+            ),
+        );
+    }
+}
+
+/// append a line to a file, creating it if needed
+fn write(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = (std::fs::OpenOptions::new())
+        .create(true)
+        .append(true)
+        .open(path)?;
+    // a single write, so records of concurrent runs do not interleave
+    file.write_all(format!("{line}\n").as_bytes())
+}
+
+/// the json record of a run, on a single line
+fn record(args: &Args, seed: u64, outcome: &Result<bool, Failure>, wall_clock: Duration) -> String {
+    let time = (SystemTime::now().duration_since(UNIX_EPOCH))
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let arguments = (std::env::args_os().skip(1))
+        .map(|arg| format!("\"{}\"", logger::escape(&arg.to_string_lossy())))
+        .collect::<Vec<_>>();
+    let outcome = match outcome {
+        Ok(true) => "ok",
+        Ok(false) => "failed",
+        Err(_) => "error",
+    };
+    let phases = (timing::phases().iter())
+        .map(|(name, total, _)| format!("\"{name}\":{:.3}", millis(*total)))
+        .collect::<Vec<_>>();
+
+    format!(
+        "{{\"time\":{time},\"version\":\"{}\",\"args\":[{}],\"seed\":{seed},\"inputs\":{},\
+         \"outcome\":\"{outcome}\",\"ms\":{:.3},\"phases\":{{{}}}}}",
+        env!("CARGO_PKG_VERSION"),
+        arguments.join(","),
+        args.inputs().len(),
+        millis(wall_clock),
+        phases.join(",")
+    )
+}
+
+/// a duration in milliseconds
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,176 @@
+use std::path::Path;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+
+use crate::cli::Args;
+use crate::colors::Color;
+use crate::dither::{self, Dither};
+use crate::io;
+use crate::quantizer::Thumbnail;
+use crate::stats::Stats;
+
+/// maximum number of pixels of the image in each cell, which is scaled down to fit
+/// (see [`Thumbnail`])
+const CELL_PIXELS: usize = 256 * 256;
+
+/// space around and between cells, in pixels
+const MARGIN: usize = 16;
+
+/// size of the pixels of label glyphs, in pixels
+pub const SCALE: usize = 2;
+
+/// width and height of label glyphs, in font pixels (with one pixel between glyphs)
+pub const GLYPH: (usize, usize) = (5, 7);
+
+pub const BACKGROUND: [u8; 3] = [0xee, 0xee, 0xee];
+pub const TEXT: [u8; 3] = [0x22, 0x22, 0x22];
+
+/// glyphs of the characters of labels, as rows of 5 bits (most significant on the left)
+const FONT: [(char, [u8; 7]); 17] = [
+    ('0', [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e]),
+    ('1', [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e]),
+    ('2', [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f]),
+    ('3', [0x1e, 0x01, 0x01, 0x0e, 0x01, 0x01, 0x1e]),
+    ('4', [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02]),
+    ('5', [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e]),
+    ('6', [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e]),
+    ('7', [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e]),
+    ('9', [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c]),
+    ('=', [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00]),
+    ('k', [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12]),
+    ('e', [0x00, 0x00, 0x0e, 0x11, 0x1f, 0x10, 0x0e]),
+    ('r', [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10]),
+    ('o', [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e]),
+    (' ', [0x00; 7]),
+];
+
+/// a quantized image in a contact sheet, with its number of colors and mean error
+struct Cell {
+    k: usize,
+    error: f64,
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Cell {
+    /// label below the image, eg. `k=8  error 12.34`
+    fn label(&self) -> String {
+        format!("k={}  error {:.2}", self.k, self.error)
+    }
+}
+
+/// contact sheet of an image quantized to several numbers of colors, see `--sheet`
+#[derive(Default)]
+pub struct Sheet {
+    cells: Vec<Cell>,
+}
+
+impl Sheet {
+    /// add an image quantized to `clusters`, where `pixels[i]` is represented by
+    /// `clusters[assignments[i]]`
+    /// - the cell shows a thumbnail of the image remapped (and dithered) to the clusters
+    /// - its error is the mean error of `--stats`, at full resolution
+    pub fn add(
+        &mut self,
+        pixels: &[Color],
+        width: usize,
+        clusters: &[Color],
+        assignments: &[usize],
+        args: &Args,
+    ) {
+        let error = Stats::new(pixels, clusters, assignments).error.sqrt();
+
+        let height = pixels.len() / width.max(1);
+        let thumbnail = Thumbnail::pixels(pixels, width, CELL_PIXELS);
+        let (width, height) = Thumbnail::dimensions(width, height, CELL_PIXELS);
+        let assignments = match args.dither {
+            Dither::None => (args.colorspace).assign(&thumbnail, clusters, &args.parameters()),
+            method => dither::dither(&thumbnail, width as u32, clusters, method),
+        };
+
+        self.cells.push(Cell {
+            k: clusters.len(),
+            error,
+            width,
+            height,
+            pixels: assignments.iter().map(|&i| clusters[i].clone()).collect(),
+        });
+    }
+
+    /// write the sheet as an image, in the format of the extension of `path`
+    /// - cells are laid out in a grid, in the order they were added
+    /// - colors with alpha are blended onto the background
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let format = ImageFormat::from_path(path)
+            .map_err(|_| io::Error::UnsupportedOutput(path.display().to_string()))?;
+
+        let label_width = |cell: &Cell| text_width(&cell.label());
+        let label_height = GLYPH.1 * SCALE + MARGIN / 2;
+        let cell_width = (self.cells.iter())
+            .map(|cell| cell.width.max(label_width(cell)))
+            .max()
+            .unwrap_or(0);
+        let cell_height = self.cells.iter().map(|cell| cell.height).max().unwrap_or(0);
+
+        let columns = (self.cells.len() as f64).sqrt().ceil().max(1.0) as usize;
+        let rows = self.cells.len().div_ceil(columns);
+        let width = MARGIN + columns * (cell_width + MARGIN);
+        let height = MARGIN + rows * (cell_height + label_height + MARGIN);
+
+        let mut img = RgbImage::from_pixel(width as u32, height as u32, Rgb(BACKGROUND));
+        for (i, cell) in self.cells.iter().enumerate() {
+            let x = MARGIN + i % columns * (cell_width + MARGIN);
+            let y = MARGIN + i / columns * (cell_height + label_height + MARGIN);
+
+            // images are centered in their cells, labels below them
+            let offset = (cell_width - cell.width) / 2;
+            for (j, color) in cell.pixels.iter().enumerate() {
+                let (px, py) = (x + offset + j % cell.width, y + j / cell.width);
+                img.put_pixel(px as u32, py as u32, Rgb(blend(color)));
+            }
+            draw_text(&mut img, x, y + cell_height + MARGIN / 2, &cell.label());
+        }
+
+        io::encode_image(path, &DynamicImage::ImageRgb8(img), format, None)?;
+        Ok(())
+    }
+}
+
+/// rgb of a color, with alpha blended onto the background
+fn blend(color: &Color) -> [u8; 3] {
+    let alpha = color.alpha().unwrap_or(u8::MAX) as u32;
+    let rgb = color.rgb();
+    std::array::from_fn(|i| {
+        let value = rgb[i] as u32 * alpha + BACKGROUND[i] as u32 * (u8::MAX as u32 - alpha);
+        (value / u8::MAX as u32) as u8
+    })
+}
+
+/// width of a text drawn by [`draw_text`], in pixels
+pub fn text_width(text: &str) -> usize {
+    text.chars().count() * (GLYPH.0 + 1) * SCALE
+}
+
+/// draw a text with its top left corner at (x, y)
+/// - characters without glyphs in [`FONT`] are drawn as spaces
+pub fn draw_text(img: &mut RgbImage, x: usize, y: usize, text: &str) {
+    for (i, c) in text.chars().enumerate() {
+        let Some((_, rows)) = FONT.iter().find(|(glyph, _)| *glyph == c) else {
+            continue;
+        };
+        let left = x + i * (GLYPH.0 + 1) * SCALE;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in (0..GLYPH.0).filter(|column| bits >> (GLYPH.0 - 1 - column) & 1 == 1) {
+                for (dx, dy) in (0..SCALE).flat_map(|dx| (0..SCALE).map(move |dy| (dx, dy))) {
+                    let (px, py) = (left + column * SCALE + dx, y + row * SCALE + dy);
+                    if px < img.width() as usize && py < img.height() as usize {
+                        img.put_pixel(px as u32, py as u32, Rgb(TEXT));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use crate::cli::Args;
+use crate::colors::Color;
+use crate::messages;
+use crate::quantizer::{THUMBNAIL_PIXELS, Thumbnail};
+
+/// quality of a palette for the pixels assigned to it, see `--stats`
+pub struct Stats {
+    /// mean squared distance of pixels to their colors
+    pub error: f64,
+    /// mean squared distance after moving each color to the mean of its pixels
+    /// (one k-means iteration over all pixels, without reassigning them)
+    pub refined: f64,
+}
+
+impl Stats {
+    /// measure the quality of a palette, where `pixels[i]` is represented by
+    /// `palette[assignments[i]]`
+    pub fn new(pixels: &[Color], palette: &[Color], assignments: &[usize]) -> Self {
+        let mut counts = vec![0u64; palette.len()];
+        let mut sums = vec![[0u64; 4]; palette.len()];
+        let mut squared = 0u64;
+        for (pixel, &i) in pixels.iter().zip(assignments) {
+            counts[i] += 1;
+            for ((sum, &p), &c) in sums[i].iter_mut().zip(&pixel.data).zip(&palette[i].data) {
+                *sum += p as u64;
+                squared += (p as i64 - c as i64).pow(2) as u64;
+            }
+        }
+
+        // the mean of a cluster lowers its squared error by `count * |color - mean|^2`
+        let gain = (palette.iter().zip(&counts).zip(&sums))
+            .filter(|((_, count), _)| **count > 0)
+            .map(|((color, &count), sum)| {
+                let distance = (color.data.iter().zip(sum))
+                    .map(|(&c, &sum)| (c as f64 - sum as f64 / count as f64).powi(2))
+                    .sum::<f64>();
+                count as f64 * distance
+            })
+            .sum::<f64>();
+
+        let pixels = pixels.len().max(1) as f64;
+        let error = squared as f64 / pixels;
+        Stats {
+            error,
+            refined: (error - gain / pixels).max(0.0),
+        }
+    }
+
+    /// relative error reduction of refining the colors at full resolution, in percent
+    pub fn refinement_gain(&self) -> f64 {
+        match self.error > 0.0 {
+            true => (self.error - self.refined) / self.error * 100.0,
+            false => 0.0,
+        }
+    }
+
+    /// print the stats of an input to stderr
+    pub fn report(&self, input: &Path, origin: &str) {
+        let error = format!("{:.2}", self.error.sqrt());
+        let gain = format!("{:.2}", self.refinement_gain());
+        eprintln!(
+            "{}",
+            messages::text("stats", &[("input", &input.display())])
+        );
+        eprintln!(
+            "  {}",
+            messages::text("stats-origin", &[("origin", &origin)])
+        );
+        eprintln!("  {}", messages::text("stats-error", &[("error", &error)]));
+        eprintln!("  {}", messages::text("stats-gain", &[("gain", &gain)]));
+    }
+}
+
+/// description of the pixels a palette was computed from
+/// - `clustered` is false for given palettes and images within the color budget
+pub fn origin(args: &Args, width: usize, height: usize, clustered: bool) -> String {
+    let pixels = width * height;
+    match (args.sample, args.exact) {
+        _ if args.palette.is_some() => messages::text("origin-palette", &[]),
+        _ if !clustered => messages::text("origin-distinct", &[]),
+        (Some(sample), _) => {
+            let count = sample.of(pixels).max(args.number).min(pixels);
+            messages::text("origin-sample", &[("count", &count), ("pixels", &pixels)])
+        }
+        (None, false) if Thumbnail::factor(width, height, THUMBNAIL_PIXELS) > 1 => {
+            let (w, h) = Thumbnail::dimensions(width, height, THUMBNAIL_PIXELS);
+            messages::text(
+                "origin-thumbnail",
+                &[
+                    ("thumbnail", &format!("{w}x{h}")),
+                    ("size", &format!("{width}x{height}")),
+                ],
+            )
+        }
+        _ => messages::text("origin-all", &[("size", &format!("{width}x{height}"))]),
+    }
+}
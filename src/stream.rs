@@ -0,0 +1,97 @@
+use std::io::Write;
+
+use crate::colors::Color;
+use crate::palette::{self, ColorCodeFormat};
+use crate::term::{self, Capability};
+
+/// prints provisional palettes to stdout while k-means iterations are running
+/// - on terminals, the previous palette is overwritten in place
+/// - otherwise, one json object per iteration is appended (json lines)
+pub struct Stream {
+    format: ColorCodeFormat,
+    capability: Capability,
+    /// number of lines of the previous palette still on the terminal
+    printed: usize,
+    /// key of the palette's number in json lines, eg. `iteration`
+    label: &'static str,
+}
+
+impl Stream {
+    /// create a stream printing color codes in a format
+    pub fn new(format: ColorCodeFormat) -> Self {
+        Stream {
+            format,
+            capability: Capability::stdout(),
+            printed: 0,
+            label: "iteration",
+        }
+    }
+
+    /// number palettes by another key in json lines, eg. `frame` for `--live`
+    #[cfg(feature = "pnm")]
+    pub fn with_label(mut self, label: &'static str) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// print the provisional palette after iteration (or frame) `i`
+    pub fn update(&mut self, i: usize, clusters: &[Color]) {
+        let mut palette = clusters.to_vec();
+        palette::sort_by_luminance(&mut palette);
+
+        let mut stdout = std::io::stdout().lock();
+
+        if self.capability.is_terminal() {
+            self.clear(&mut stdout);
+            for color in &palette {
+                let capability = self.capability;
+                ColorCodeFormat::pretty_print_color_code(
+                    &self.format,
+                    &mut stdout,
+                    color,
+                    capability,
+                )
+                .expect("failed to write output");
+                writeln!(stdout).expect("failed to write output");
+            }
+            self.printed = palette.len();
+        } else {
+            let codes = palette
+                .iter()
+                .map(|c| format!("\"{}\"", self.format.color_code(c)))
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(
+                stdout,
+                "{{\"{}\":{},\"palette\":[{codes}]}}",
+                self.label,
+                i + 1
+            )
+            .expect("failed to write output");
+        }
+
+        stdout.flush().expect("failed to write output");
+    }
+
+    /// remove the provisional palette from terminals, making room for the final output
+    pub fn finish(mut self) {
+        self.clear(&mut std::io::stdout().lock());
+    }
+
+    /// move the cursor back over the previous palette and erase it
+    fn clear<W>(&mut self, writer: &mut W)
+    where
+        W: Write,
+    {
+        if self.printed > 0 {
+            write!(
+                writer,
+                "{}{}",
+                term::rewind(self.printed),
+                term::CLEAR_BELOW
+            )
+            .expect("failed to write output");
+        }
+        self.printed = 0;
+    }
+}
@@ -0,0 +1,152 @@
+use image::*;
+
+use crate::cli::{SwapArgs, SwapMode, Transfer};
+use crate::colors::{self, Color};
+use crate::io;
+use crate::kmeans;
+use crate::messages;
+
+/// remap the source image onto the palette extracted from the target image
+pub fn swap(args: &SwapArgs, seed: u64) -> Result<(), String> {
+    let source = (io::decode(&args.source, None).map(|d| d.image))
+        .map_err(|err| format!("{}: {err}", args.source.display()))?;
+    let target = (io::decode(&args.target, None).map(|d| d.image))
+        .map_err(|err| format!("{}: {err}", args.target.display()))?;
+
+    if let Some(transfer) = args.transfer {
+        let pixels = crate::pixels(&source, false);
+        let target = crate::pixels(&target, false);
+        let transferred = match transfer {
+            Transfer::Reinhard => reinhard(&pixels, &target),
+            Transfer::Histogram => histogram_match(&pixels, &target),
+        };
+
+        let data = transferred.into_iter().flat_map(|c| c.data).collect();
+        let (width, height) = source.dimensions();
+        let img: RgbImage =
+            ImageBuffer::from_vec(width, height, data).expect("failed to create transferred image");
+        img.save(&args.output)
+            .map_err(|err| messages::text("save-transferred", &[("error", &err)]))?;
+        println!(
+            "{}",
+            messages::text("saved-transferred", &[("path", &args.output.display())])
+        );
+        return Ok(());
+    }
+
+    let mut context = kmeans::Context::new(seed);
+    let (palette, _) =
+        context.k_means(&crate::pixels(&target, false), args.number, args.iterations)?;
+
+    let pixels = crate::pixels(&source, false);
+    let assignments = match args.mode {
+        SwapMode::Nearest => colors::assign(&pixels, &palette),
+        SwapMode::Luminance => {
+            let (source_palette, assignments) =
+                context.k_means(&pixels, args.number, args.iterations)?;
+
+            // pair up source and target palette colors by luminance rank
+            let by_luminance = |palette: &[Color]| {
+                let mut order = (0..palette.len()).collect::<Vec<_>>();
+                order.sort_by(|&i, &j| {
+                    colors::luminance(&palette[i]).total_cmp(&colors::luminance(&palette[j]))
+                });
+                order
+            };
+            let (source_order, target_order) =
+                (by_luminance(&source_palette), by_luminance(&palette));
+
+            let mut mapping = vec![0; source_palette.len()];
+            for (rank, &i) in source_order.iter().enumerate() {
+                // scale ranks, in case the palettes differ in size
+                let target_rank = rank * target_order.len() / source_order.len();
+                mapping[i] = target_order[target_rank];
+            }
+
+            assignments.into_iter().map(|i| mapping[i]).collect()
+        }
+    };
+
+    let (width, height) = source.dimensions();
+    let options = io::EncodeOptions::default();
+    io::encode(
+        &args.output,
+        width,
+        height,
+        &palette,
+        &assignments,
+        &options,
+    )?;
+    println!(
+        "{}",
+        messages::text("saved-swapped", &[("path", &args.output.display())])
+    );
+
+    Ok(())
+}
+
+/// shift and scale each L*a*b* channel of the source to the mean and standard
+/// deviation of the target (reinhard et al., "color transfer between images")
+fn reinhard(source: &[Color], target: &[Color]) -> Vec<Color> {
+    let source = source.iter().map(colors::to_lab).collect::<Vec<_>>();
+    let target = target.iter().map(colors::to_lab).collect::<Vec<_>>();
+    let (source_stats, target_stats) = (statistics(&source), statistics(&target));
+
+    source
+        .iter()
+        .map(|lab| {
+            let mut transferred = [0.0; 3];
+            for c in 0..3 {
+                let ((mean_s, dev_s), (mean_t, dev_t)) = (source_stats[c], target_stats[c]);
+                let scale = match dev_s {
+                    0.0 => 1.0,
+                    _ => dev_t / dev_s,
+                };
+                transferred[c] = (lab[c] - mean_s) * scale + mean_t;
+            }
+            colors::from_lab(transferred)
+        })
+        .collect()
+}
+
+/// mean and standard deviation of each channel
+fn statistics(labs: &[[f64; 3]]) -> [(f64, f64); 3] {
+    let n = labs.len().max(1) as f64;
+    let mut stats = [(0.0, 0.0); 3];
+    for (c, (mean, dev)) in stats.iter_mut().enumerate() {
+        *mean = labs.iter().map(|lab| lab[c]).sum::<f64>() / n;
+        *dev = (labs.iter().map(|lab| (lab[c] - *mean).powi(2)).sum::<f64>() / n).sqrt();
+    }
+    stats
+}
+
+/// map each rgb channel of the source through the inverse cumulative histogram
+/// of the target, so both have the same distribution of values
+fn histogram_match(source: &[Color], target: &[Color]) -> Vec<Color> {
+    let cdf = |pixels: &[Color], c: usize| {
+        let mut counts = [0u64; 256];
+        for pixel in pixels {
+            counts[pixel.data[c] as usize] += 1;
+        }
+        let total = pixels.len().max(1) as f64;
+        let mut cumulative = 0;
+        counts.map(|count| {
+            cumulative += count;
+            cumulative as f64 / total
+        })
+    };
+
+    // lookup table per channel: smallest target value whose cdf reaches the source cdf
+    let luts = [0, 1, 2].map(|c| {
+        let (source_cdf, target_cdf) = (cdf(source, c), cdf(target, c));
+        source_cdf.map(|p| target_cdf.iter().position(|&q| q >= p).unwrap_or(255) as u8)
+    });
+
+    source
+        .iter()
+        .map(|pixel| Color {
+            color_type: ColorType::Rgb8,
+            data: (0..3).map(|c| luts[c][pixel.data[c] as usize]).collect(),
+        })
+        .collect()
+}
@@ -0,0 +1,59 @@
+use qtizer::colors::Color;
+
+/// palettes carried from input to input of a sequence, eg. frames of a video (see
+/// `--temporal-smoothing`)
+pub struct Temporal {
+    /// largest rgb distance a palette entry moves from one input to the next
+    max_movement: f64,
+    /// palette of the previous input
+    previous: Option<Vec<Color>>,
+}
+
+impl Temporal {
+    pub fn new(max_movement: f64) -> Self {
+        Temporal {
+            max_movement,
+            previous: None,
+        }
+    }
+
+    /// palette of the previous input, to start clustering `k` colors of `channels`
+    /// channels from (none for the first input, or when `k` or the channels differ)
+    pub fn previous(&self, k: usize, channels: usize) -> Option<&[Color]> {
+        (self.previous.as_deref())
+            .filter(|previous| previous.len() == k)
+            .filter(|previous| previous.iter().all(|c| c.data.len() == channels))
+    }
+
+    /// move each entry of a palette at most `max_movement` away from the entry with the
+    /// same index in the previous palette, and keep the result for the next input
+    /// - palettes of a different size or channels replace the previous one as they are
+    pub fn smooth(&mut self, clusters: Vec<Color>) -> Vec<Color> {
+        let channels = clusters.first().map_or(0, |c| c.data.len());
+        let smoothed = match self.previous(clusters.len(), channels) {
+            None => clusters,
+            Some(previous) => (previous.iter().zip(clusters))
+                .map(|(from, to)| self.limit(from, to))
+                .collect(),
+        };
+        self.previous = Some(smoothed.clone());
+        smoothed
+    }
+
+    /// `to`, moved back towards `from` until at most `max_movement` away from it
+    fn limit(&self, from: &Color, to: Color) -> Color {
+        let delta = (from.data.iter().zip(&to.data))
+            .map(|(&a, &b)| b as f64 - a as f64)
+            .collect::<Vec<_>>();
+        let distance = delta.iter().map(|d| d * d).sum::<f64>().sqrt();
+        if distance <= self.max_movement {
+            return to;
+        }
+
+        let scale = self.max_movement / distance;
+        let data = (from.data.iter().zip(delta))
+            .map(|(&a, d)| (a as f64 + d * scale).round().clamp(0.0, 255.0) as u8)
+            .collect();
+        Color { data, ..to }
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::IsTerminal;
+
+/// hide the cursor (eg. while progress is redrawn)
+pub const HIDE_CURSOR: &str = "\x1b[?25l";
+
+/// show the cursor again
+pub const SHOW_CURSOR: &str = "\x1b[?25h";
+
+/// reset all colors and text attributes
+pub const RESET: &str = "\x1b[0m";
+
+/// underline text (eg. where neither black nor white text contrasts enough)
+pub const UNDERLINE: &str = "\x1b[4m";
+
+/// erase everything from the cursor to the end of the screen
+pub const CLEAR_BELOW: &str = "\x1b[0J";
+
+/// basic (16 color) foreground colors, supported by any color terminal
+pub const RED: &str = "\x1b[31m";
+pub const YELLOW: &str = "\x1b[33m";
+pub const CYAN: &str = "\x1b[36m";
+
+/// what a terminal is able to display
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Capability {
+    /// not a terminal (or a dumb one): no escape sequences at all
+    #[default]
+    None,
+    /// 256 colors (xterm palette) and cursor movement
+    Ansi256,
+    /// 24-bit colors and cursor movement
+    TrueColor,
+}
+
+impl Capability {
+    /// detect the capability of a stream
+    /// - streams that are not terminals, or `TERM=dumb`, get none
+    /// - `COLORTERM=truecolor` (or `24bit`) indicates truecolor
+    /// - any other terminal is assumed to handle 256 colors
+    pub fn detect(stream: &impl IsTerminal) -> Self {
+        if !stream.is_terminal() {
+            return Capability::None;
+        }
+
+        let term = std::env::var("TERM").unwrap_or_default();
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        match (term.as_str(), colorterm.as_str()) {
+            ("dumb", _) => Capability::None,
+            (_, "truecolor" | "24bit") => Capability::TrueColor,
+            _ => Capability::Ansi256,
+        }
+    }
+
+    /// capability of stdout (palette previews)
+    pub fn stdout() -> Self {
+        Self::detect(&std::io::stdout())
+    }
+
+    /// capability of stderr (progress, warnings and notes)
+    pub fn stderr() -> Self {
+        Self::detect(&std::io::stderr())
+    }
+
+    /// whether escape sequences can be used at all
+    pub fn is_terminal(self) -> bool {
+        self != Capability::None
+    }
+}
+
+/// move the cursor to the start of the line `lines` lines up
+pub fn rewind(lines: usize) -> String {
+    format!("\x1b[{lines}F")
+}
+
+/// a row of colored blocks, `width` characters per color
+/// (empty without color capability)
+pub fn swatches(capability: Capability, colors: &[[u8; 3]], width: usize) -> String {
+    if !capability.is_terminal() {
+        return String::new();
+    }
+
+    let blocks = colors
+        .iter()
+        .map(|&rgb| fg(capability, rgb) + &"█".repeat(width))
+        .collect::<String>();
+    blocks + RESET
+}
+
+/// levels of the channels in the xterm 256 color cube
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// sgr parameters selecting a color, approximated for 256 color terminals
+fn color(capability: Capability, [r, g, b]: [u8; 3]) -> Option<String> {
+    match capability {
+        Capability::None => None,
+        Capability::Ansi256 => Some(format!("5;{}", ansi256([r, g, b]))),
+        Capability::TrueColor => Some(format!("2;{r};{g};{b}")),
+    }
+}
+
+/// set the foreground color (nothing without color capability)
+pub fn fg(capability: Capability, rgb: [u8; 3]) -> String {
+    color(capability, rgb).map_or_else(String::new, |c| format!("\x1b[38;{c}m"))
+}
+
+/// set the background color (nothing without color capability)
+pub fn bg(capability: Capability, rgb: [u8; 3]) -> String {
+    color(capability, rgb).map_or_else(String::new, |c| format!("\x1b[48;{c}m"))
+}
+
+/// the color a terminal displays for a requested color, eg. its closest color of the
+/// xterm 256 color palette (unchanged without color capability)
+pub fn displayed(capability: Capability, rgb: [u8; 3]) -> [u8; 3] {
+    match capability {
+        Capability::Ansi256 => ansi256_rgb(ansi256(rgb)),
+        _ => rgb,
+    }
+}
+
+/// the color of an index of the xterm 256 color palette from [`ansi256`]
+fn ansi256_rgb(index: u8) -> [u8; 3] {
+    match index {
+        232.. => [8 + 10 * (index - 232); 3],
+        _ => {
+            let cube = index.saturating_sub(16) as usize;
+            [cube / 36, cube / 6 % 6, cube % 6].map(|i| CUBE_LEVELS[i])
+        }
+    }
+}
+
+/// closest color of the xterm 256 color palette
+/// - either from the 6x6x6 color cube (16..=231) or the gray ramp (232..=255)
+/// - the 16 system colors are skipped, since terminals commonly redefine them
+pub fn ansi256(rgb: [u8; 3]) -> u8 {
+    let distance = |other: [u8; 3]| {
+        rgb.iter()
+            .zip(other)
+            .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+
+    // nearest level per channel
+    let level = |v: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - v as i32).abs())
+            .unwrap_or(0)
+    };
+    let [r, g, b] = rgb.map(level);
+    let cube = [r, g, b].map(|i| CUBE_LEVELS[i]);
+    let cube_index = 16 + 36 * r + 6 * g + b;
+
+    // gray ramp from 8 to 238 in steps of 10
+    let mean = rgb.iter().map(|&v| v as usize).sum::<usize>() / 3;
+    let gray_step = (mean.saturating_sub(3) / 10).min(23);
+    let gray = [8 + 10 * gray_step as u8; 3];
+
+    match distance(gray) < distance(cube) {
+        true => 232 + gray_step as u8,
+        false => cube_index as u8,
+    }
+}
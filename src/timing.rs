@@ -0,0 +1,81 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::messages;
+
+/// whether phase timings are recorded
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// recorded phases as (name, total duration, count), in order of first occurrence
+static PHASES: Mutex<Vec<(&'static str, Duration, usize)>> = Mutex::new(Vec::new());
+
+/// enable recording of phase timings
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// add a duration to a phase (no-op unless enabled)
+/// - repeated phases (eg. per iteration, or per file in batches) are summed
+pub fn record(phase: &'static str, duration: Duration) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut phases = PHASES.lock().expect("timing lock poisoned");
+    match phases.iter_mut().find(|(name, ..)| *name == phase) {
+        Some((_, total, count)) => {
+            *total += duration;
+            *count += 1;
+        }
+        None => phases.push((phase, duration, 1)),
+    }
+}
+
+/// run a function, recording its duration as a phase
+pub fn measure<T>(phase: &'static str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    record(phase, start.elapsed());
+    result
+}
+
+/// the recorded phases as (name, total duration, count), in order of first occurrence
+pub fn phases() -> Vec<(&'static str, Duration, usize)> {
+    PHASES.lock().expect("timing lock poisoned").clone()
+}
+
+/// print the recorded phases to stderr (no-op unless enabled)
+pub fn report(wall_clock: Duration) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let phases = PHASES.lock().expect("timing lock poisoned");
+    let total = messages::text("timing-total", &[]);
+    let width = (phases.iter().map(|(name, ..)| name.len()))
+        .max()
+        .unwrap_or(0)
+        .max(total.len());
+
+    let line = |name: &str, duration: Duration, count: usize| {
+        let args: &[(&str, &dyn std::fmt::Display)] = &[
+            ("name", &format!("{name:<width$}")),
+            (
+                "millis",
+                &format!("{:>10.2}", duration.as_secs_f64() * 1000.0),
+            ),
+            ("count", &count),
+        ];
+        match count {
+            1 => messages::text("timing-phase", args),
+            _ => messages::text("timing-phase-repeated", args),
+        }
+    };
+
+    eprintln!("{}", messages::text("timing", &[]));
+    for (name, total, count) in phases.iter() {
+        eprintln!("  {}", line(name, *total, *count));
+    }
+    eprintln!("  {}", line(&total, wall_clock, 1));
+}
@@ -0,0 +1,73 @@
+use image::*;
+use rayon::prelude::*;
+
+use crate::colors;
+
+/// tone mapping of high dynamic range images (eg. exr or radiance hdr) to srgb
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Tonemap {
+    /// Clip light brighter than white
+    #[default]
+    Clip,
+    /// Compress highlights by luminance (`L / (1 + L)`), keeping hues
+    Reinhard,
+    /// Filmic curve approximating the aces reference rendering
+    Aces,
+}
+
+impl Tonemap {
+    /// map linear light of any brightness to linear light in `[0, 1]`
+    fn map(self, rgb: [f32; 3]) -> [f32; 3] {
+        match self {
+            Tonemap::Clip => rgb,
+            Tonemap::Reinhard => {
+                let [r, g, b] = rgb.map(|v| v.max(0.0));
+                let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                let scale = 1.0 / (1.0 + luminance);
+                [r * scale, g * scale, b * scale]
+            }
+            // fit by krzysztof narkowicz
+            Tonemap::Aces => rgb.map(|v| {
+                let v = v.max(0.0);
+                (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14)
+            }),
+        }
+        .map(|v| v.clamp(0.0, 1.0))
+    }
+}
+
+/// whether an image has floating point channels of linear light, which may be brighter
+/// than white (high dynamic range)
+pub fn is_hdr(img: &DynamicImage) -> bool {
+    matches!(
+        img,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    )
+}
+
+/// tone map a high dynamic range image to an srgb image, or `None` for other images
+/// (see [`is_hdr`])
+/// - alpha is kept (clamped to `[0, 1]`)
+pub fn tonemap(img: &DynamicImage, operator: Tonemap) -> Option<DynamicImage> {
+    if !is_hdr(img) {
+        return None;
+    }
+
+    let (width, height) = img.dimensions();
+    let mapped = img
+        .to_rgba32f()
+        .par_pixels()
+        .flat_map_iter(|p| {
+            let [r, g, b, a] = p.0;
+            let rgb = operator.map([r, g, b]).map(|v| colors::gamma(v as f64));
+            let alpha = (a.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8;
+            [rgb[0], rgb[1], rgb[2], alpha]
+        })
+        .collect::<Vec<_>>();
+    let mapped = RgbaImage::from_vec(width, height, mapped)?;
+
+    Some(match img.color().has_alpha() {
+        true => DynamicImage::ImageRgba8(mapped),
+        false => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(mapped).to_rgb8()),
+    })
+}
@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use qtizer::colors::{self, Color};
+use qtizer::palette::{self, Palette};
+
+use crate::cli::Args;
+use crate::logger;
+use crate::messages;
+
+/// compare the palette of an input against a recorded palette file, see `--verify`
+/// - returns whether the palettes match, reporting mismatches as `verify-mismatch` errors
+/// - palettes differ if the number of colors differs, if a color of either palette is
+///   further than `--verify-tolerance` from every color of the other, or with
+///   `--deterministic`, if they are not equal
+/// - errors if the recorded palette cannot be read
+pub fn check(
+    args: &Args,
+    input: &Path,
+    expected: &Path,
+    palette: &[Color],
+) -> Result<bool, String> {
+    let recorded = Palette::from_file(expected)
+        .map_err(|err| {
+            messages::text(
+                "verify-read",
+                &[("expected", &expected.display()), ("error", &err)],
+            )
+        })?
+        .colors;
+    let mismatch = |message| {
+        logger::error("verify-mismatch", message);
+        Ok(false)
+    };
+    let (input, expected) = (input.display(), expected.display());
+
+    if recorded.len() != palette.len() {
+        return mismatch(messages::text(
+            "verify-count",
+            &[
+                ("input", &input),
+                ("expected", &expected),
+                ("colors", &palette.len()),
+                ("recorded", &recorded.len()),
+            ],
+        ));
+    }
+
+    if args.deterministic {
+        let sorted = |colors: &[Color]| {
+            let mut sorted = colors.to_vec();
+            palette::sort_by_luminance(&mut sorted);
+            sorted
+        };
+        if sorted(palette) != sorted(&recorded) {
+            return mismatch(messages::text(
+                "verify-unequal",
+                &[("input", &input), ("expected", &expected)],
+            ));
+        }
+        logger::note(
+            "verified",
+            messages::text(
+                "verified-equal",
+                &[("input", &input), ("expected", &expected)],
+            ),
+        );
+        return Ok(true);
+    }
+
+    let distance = distance(palette, &recorded);
+    let text = |key| {
+        messages::text(
+            key,
+            &[
+                ("input", &input),
+                ("expected", &expected),
+                ("distance", &format!("{distance:.2}")),
+                ("tolerance", &args.verify_tolerance),
+            ],
+        )
+    };
+    if distance > args.verify_tolerance {
+        return mismatch(text("verify-mismatch"));
+    }
+    logger::note("verified", text("verified"));
+    Ok(true)
+}
+
+/// largest delta-e of a color of either palette to its nearest color of the other
+fn distance(a: &[Color], b: &[Color]) -> f64 {
+    let furthest = |from: &[Color], to: &[Color]| {
+        (from.iter())
+            .map(|c| {
+                (to.iter())
+                    .map(|other| colors::delta_e(c, other))
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .fold(0.0, f64::max)
+    };
+    furthest(a, b).max(furthest(b, a))
+}
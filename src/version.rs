@@ -0,0 +1,103 @@
+use image::ImageFormat;
+
+use crate::cli;
+use crate::io;
+use crate::messages;
+
+/// optional cargo features (besides image codecs, listed as decoders and encoders), and
+/// whether they are compiled into this build
+const FEATURES: &[(&str, bool)] = &[
+    ("ase", cfg!(feature = "ase")),
+    ("edit", cfg!(feature = "edit")),
+    ("net", cfg!(feature = "net")),
+    ("capture", cfg!(feature = "capture")),
+];
+
+/// print the version, optionally with build and runtime details for bug reports
+pub fn print(verbose: bool) {
+    println!(
+        "{}",
+        messages::text("version", &[("version", &env!("CARGO_PKG_VERSION"))])
+    );
+
+    if !verbose {
+        return;
+    }
+
+    let features = FEATURES
+        .iter()
+        .map(|(name, enabled)| format!("{}{name}", if *enabled { "+" } else { "-" }))
+        .collect::<Vec<_>>();
+    let formats = |enabled: fn(&ImageFormat) -> bool| {
+        ImageFormat::all()
+            .filter(enabled)
+            .map(|f| format!("{f:?}").to_lowercase())
+            .collect::<Vec<_>>()
+    };
+    let simd = simd();
+
+    let target = format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS);
+    let profile = match cfg!(debug_assertions) {
+        true => messages::text("version-debug", &[]),
+        false => messages::text("version-release", &[]),
+    };
+    let lines: [(&str, &dyn std::fmt::Display); 7] = [
+        ("version-target", &target),
+        ("version-profile", &profile),
+        ("version-features", &list(&features)),
+        ("version-decoders", &list(&formats(|&f| io::decodable(f)))),
+        (
+            "version-encoders",
+            &list(&formats(ImageFormat::writing_enabled)),
+        ),
+        ("version-simd", &list(&simd)),
+        ("version-threads", &cli::default_jobs()),
+    ];
+
+    println!();
+    for (key, value) in lines {
+        println!("{}", messages::text(key, &[("value", value)]));
+    }
+}
+
+/// simd instruction sets supported by the cpu, detected at runtime
+fn simd() -> Vec<&'static str> {
+    #[allow(unused_mut)]
+    let mut supported = Vec::new();
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        macro_rules! detect {
+            ($($feature:tt),*) => {
+                $(if std::arch::is_x86_feature_detected!($feature) {
+                    supported.push($feature);
+                })*
+            };
+        }
+        detect!("sse2", "sse4.1", "sse4.2", "avx", "avx2", "fma", "avx512f");
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            supported.push("neon");
+        }
+        if std::arch::is_aarch64_feature_detected!("sve") {
+            supported.push("sve");
+        }
+    }
+
+    supported
+}
+
+/// comma separated list, or `none`
+fn list<T: AsRef<str>>(items: &[T]) -> String {
+    match items {
+        [] => messages::text("version-none", &[]),
+        items => items
+            .iter()
+            .map(|i| i.as_ref())
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
@@ -0,0 +1,59 @@
+use image::*;
+use std::path::Path;
+
+use crate::cli::Args;
+use crate::colorspace::ColorSpace;
+use crate::logger;
+use crate::messages;
+use crate::quantizer::Algorithm;
+
+/// above this many colors, palettes are unusually large
+const LARGE_K: usize = 4096;
+
+/// warn about arguments that are valid, but likely mistaken
+pub fn check_args(args: &Args) {
+    // colors and iterations are not used when remapping to a palette
+    if args.palette.is_some() {
+        return;
+    }
+
+    for &k in &args.levels {
+        match k {
+            0 => logger::warn("empty-palette", messages::text("empty-palette", &[])),
+            1 => logger::warn("single-color", messages::text("single-color", &[])),
+            k if k > LARGE_K => logger::warn("large-k", messages::text("large-k", &[("k", &k)])),
+            _ => {}
+        }
+    }
+
+    if args.algorithm != Algorithm::Kmeans && args.colorspace != ColorSpace::Srgb {
+        logger::warn(
+            "colorspace-ignored",
+            messages::text("colorspace-ignored", &[]),
+        );
+    }
+
+    match args.iterations {
+        _ if args.auto_iterations || args.algorithm != Algorithm::Kmeans => {}
+        1 => logger::warn("single-iteration", messages::text("single-iteration", &[])),
+        _ => {}
+    }
+}
+
+/// warn about arguments that are likely mistaken for a decoded image
+pub fn check_image(args: &Args, input: &Path, img: &DynamicImage) {
+    let pixels = img.width() as u64 * img.height() as u64;
+    if args.palette.is_some() {
+        return;
+    }
+
+    for k in args.levels.iter().filter(|&&k| k as u64 > pixels) {
+        logger::warn(
+            "k-exceeds-pixels",
+            messages::text(
+                "k-exceeds-pixels",
+                &[("input", &input.display()), ("k", &k), ("pixels", &pixels)],
+            ),
+        );
+    }
+}
@@ -0,0 +1,197 @@
+#[cfg(feature = "net")]
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+
+use image::DynamicImage;
+use qtizer::colors::Color;
+use qtizer::quantizer::{self, Thumbnail};
+use qtizer::{kmeans, pixels, timing, tonemap};
+
+use crate::cli::Args;
+use crate::io;
+use crate::messages;
+
+/// leds per packet of the wled realtime protocol DNRGB
+#[cfg(feature = "net")]
+const LEDS_PER_PACKET: usize = 489;
+
+/// grid of zones dividing an image, whose border cells are led zones, see `--zones`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Zones {
+    pub rows: u32,
+    pub columns: u32,
+}
+
+impl FromStr for Zones {
+    type Err = String;
+
+    /// parse `<rows>x<columns>`, eg. `9x16`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || messages::text("invalid-zones", &[("zones", &s)]);
+        let (rows, columns) = s.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+        let count = |n: &str| match n.trim().parse::<u32>() {
+            Ok(0) => Err(messages::text("zero-zones", &[("zones", &s)])),
+            Ok(n) => Ok(n),
+            Err(_) => Err(invalid()),
+        };
+        Ok(Zones {
+            rows: count(rows)?,
+            columns: count(columns)?,
+        })
+    }
+}
+
+impl Zones {
+    /// (row, column) of the border cells of the grid, clockwise from the top left
+    /// - top row left to right, right column downwards, bottom row right to left, left
+    ///   column upwards, like leds around a display seen from the front
+    pub fn cells(self) -> Vec<(u32, u32)> {
+        let Zones { rows, columns } = self;
+        let mut cells = (0..columns).map(|c| (0, c)).collect::<Vec<_>>();
+        cells.extend((1..rows).map(|r| (r, columns - 1)));
+        if rows > 1 {
+            cells.extend((0..columns - 1).rev().map(|c| (rows - 1, c)));
+        }
+        if columns > 1 {
+            cells.extend((1..rows - 1).rev().map(|r| (r, 0)));
+        }
+        cells
+    }
+}
+
+/// format of zone colors on stdout, see `--zones-format`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ZoneFormat {
+    /// Color codes of all zones on one line, separated by spaces (see `--format`)
+    #[default]
+    Codes,
+    /// State of the WLED JSON API, setting one led per zone, eg. for `curl -d @- <host>/json`
+    Wled,
+}
+
+/// decode the input and write the colors of its zones, see [`write`]
+pub fn run(args: &Args, seed: u64) -> Result<(), String> {
+    let decoded = io::decode_input(args, &args.inputs()[0])?;
+    let operator = args.tonemap.unwrap_or_default();
+    let img = tonemap::tonemap(&decoded.image, operator).unwrap_or(decoded.image);
+
+    let mut context = kmeans::Context::new(seed)
+        .with_progress(false)
+        .with_tolerance(args.tolerance)
+        .with_rounding(args.rounding)
+        .with_parameters(args.parameters());
+    let colors = colors(args, &mut context, &img)?;
+    write(args, &colors, false)
+}
+
+/// dominant colors of the zones of an image, clockwise from the top left (see
+/// [`Zones::cells`])
+/// - each zone is quantized to `-k` colors, and represented by the color most of its
+///   pixels are assigned to
+/// - alpha is ignored, leds cannot show it
+pub fn colors(
+    args: &Args,
+    context: &mut kmeans::Context,
+    img: &DynamicImage,
+) -> Result<Vec<Color>, String> {
+    let Some(zones) = args.zones else {
+        return Ok(Vec::new());
+    };
+    let (width, height) = (img.width(), img.height());
+    if width < zones.columns || height < zones.rows {
+        return Err(messages::text(
+            "zones-too-small",
+            &[
+                ("width", &width),
+                ("height", &height),
+                ("rows", &zones.rows),
+                ("columns", &zones.columns),
+            ],
+        ));
+    }
+
+    let mut colors = Vec::new();
+    for (row, column) in zones.cells() {
+        let (left, top) = (column * width / zones.columns, row * height / zones.rows);
+        let right = (column + 1) * width / zones.columns;
+        let bottom = (row + 1) * height / zones.rows;
+        let cell = img.crop_imm(left, top, right - left, bottom - top);
+
+        let pixels = timing::measure("preprocess", || {
+            let pixels = pixels(&cell, false);
+            match args.exact {
+                true => pixels,
+                false => {
+                    let width = cell.width() as usize;
+                    Thumbnail::pixels(&pixels, width, quantizer::THUMBNAIL_PIXELS)
+                }
+            }
+        });
+
+        let mut quantizer =
+            (args.algorithm).quantizer(context, args.colorspace, args.iterations, |_, _| {});
+        let (clusters, assignments) = quantizer.quantize(&pixels, args.number)?;
+
+        let mut counts = vec![0usize; clusters.len()];
+        for &assigned in &assignments {
+            counts[assigned] += 1;
+        }
+        let dominant = (0..clusters.len()).max_by_key(|&i| counts[i]).unwrap_or(0);
+        colors.push(clusters[dominant].clone());
+    }
+    Ok(colors)
+}
+
+/// print zone colors as one line in the format of `--zones-format`, and send them to
+/// the wled controller of `--zones-udp`
+/// - wled returns to its own effects after 2 seconds without `live` packets, and keeps
+///   the colors of a single run
+#[cfg_attr(not(feature = "net"), allow(unused_variables))]
+pub fn write(args: &Args, colors: &[Color], live: bool) -> Result<(), String> {
+    let line = match args.zones_format {
+        ZoneFormat::Codes => {
+            let codes = colors.iter().map(|c| args.color_codes().color_code(c));
+            codes.collect::<Vec<_>>().join(" ")
+        }
+        ZoneFormat::Wled => {
+            let leds = colors.iter().map(|c| {
+                let [r, g, b] = c.rgb();
+                format!("\"{r:02x}{g:02x}{b:02x}\"")
+            });
+            let leds = leds.collect::<Vec<_>>().join(",");
+            format!("{{\"on\":true,\"seg\":{{\"i\":[{leds}]}}}}")
+        }
+    };
+    println!("{line}");
+
+    match &args.zones_udp {
+        #[cfg(feature = "net")]
+        Some(address) => send(address, colors, live)
+            .map_err(|err| messages::text("send-zones", &[("address", address), ("error", &err)])),
+        // `--zones-udp` is rejected without the `net` feature, see `cli::validate`
+        _ => Ok(()),
+    }
+}
+
+/// send zone colors to a wled controller, as udp packets of the realtime protocol DNRGB
+#[cfg(feature = "net")]
+fn send(address: &str, colors: &[Color], live: bool) -> std::io::Result<()> {
+    let timeout = if live { 2 } else { u8::MAX };
+    let target = (address.to_socket_addrs()?.next())
+        .ok_or_else(|| std::io::Error::other(messages::text("address-not-found", &[])))?;
+    let local = if target.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(local)?;
+    for (i, leds) in colors.chunks(LEDS_PER_PACKET).enumerate() {
+        // DNRGB: protocol, timeout in seconds, index of the first led, then rgb values
+        let start = (i * LEDS_PER_PACKET) as u16;
+        let mut packet = vec![4, timeout];
+        packet.extend(start.to_be_bytes());
+        packet.extend(leds.iter().flat_map(Color::rgb));
+        socket.send_to(&packet, target)?;
+    }
+    Ok(())
+}